@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use lunchmoney_venmo::types::venmo::Amount;
+
+fuzz_target!(|data: &str| {
+    let _ = Amount::from_str(data);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lunchmoney_venmo::types::venmo::StatementFormat;
+use lunchmoney_venmo::venmo::parse_venmo_statement;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_venmo_statement(data, &StatementFormat::default());
+});
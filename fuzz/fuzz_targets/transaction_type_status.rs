@@ -0,0 +1,11 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use libfuzzer_sys::fuzz_target;
+use lunchmoney_venmo::types::venmo::{TransactionStatus, TransactionType};
+
+fuzz_target!(|data: &str| {
+    let _ = TransactionType::from_str(data);
+    let _ = TransactionStatus::from_str(data);
+});
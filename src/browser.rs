@@ -0,0 +1,383 @@
+//! Reading the API token the Venmo website keeps in its `api_access_token` cookie from a local
+//! browser profile, for `get-venmo-api-token --from-browser`, as an alternative to logging in.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use dialoguer::Confirm;
+use directories::BaseDirs;
+use rusqlite::{Connection, OpenFlags};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::http::HttpTransport;
+use crate::venmo::get_venmo_user;
+
+/// Name of the cookie the Venmo website keeps its API token in.
+const COOKIE_NAME: &str = "api_access_token";
+
+/// A browser whose cookies can be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+}
+
+impl FromStr for Browser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "firefox" => Browser::Firefox,
+            "chrome" => Browser::Chrome,
+            _ => bail!("Unknown browser '{}', expected firefox or chrome", s),
+        })
+    }
+}
+
+impl fmt::Display for Browser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Browser::Firefox => "Firefox",
+            Browser::Chrome => "Chrome",
+        })
+    }
+}
+
+/// Reads the Venmo API token from `browser`'s cookies, checks it with Venmo, and prints it and
+/// the profile ID it belongs to, like `cmd_get_venmo_api_token`. Asks before reading the
+/// cookies unless `accept_risk` is set. `profile_dir` picks the browser profile, defaulting to
+/// the most recently used one that's logged in to Venmo.
+pub async fn cmd_get_venmo_api_token_from_browser(
+    client: &impl HttpTransport,
+    browser: Browser,
+    profile_dir: Option<&Path>,
+    accept_risk: bool,
+) -> Result<()> {
+    let cookie_files = match profile_dir {
+        Some(profile_dir) => vec![cookie_file(browser, profile_dir)],
+        None => find_cookie_files(browser)?,
+    };
+    if cookie_files.is_empty() {
+        bail!(
+            "Found no {} profiles, pass --browser-profile with the profile directory",
+            browser
+        );
+    }
+
+    println!("** THE VENMO API TOKEN IN YOUR BROWSER HAS FULL ACCESS TO YOUR ACCOUNT, INCLUDING SENDING TRANSACTIONS. TREAT IT LIKE YOUR VENMO PASSWORD. IT STOPS WORKING WHEN YOU LOG OUT OF THE VENMO WEBSITE. **\n");
+
+    if !accept_risk {
+        if !io::stdin().is_terminal() {
+            bail!("Pass --accept-risk to read browser cookies when not running in a terminal.");
+        }
+
+        if !Confirm::new()
+            .with_prompt(format!(
+                "Copy your {} cookie database to a private temporary directory to read Venmo's \
+                 login from it? The copy is deleted afterwards.",
+                browser
+            ))
+            .default(false)
+            .wait_for_newline(true)
+            .interact()?
+        {
+            bail!("Not reading browser cookies.");
+        }
+    }
+
+    let mut api_token = None;
+    for path in &cookie_files {
+        if !path.exists() {
+            continue;
+        }
+
+        if let Some(token) = read_cookie(browser, path)? {
+            api_token = Some(token);
+            break;
+        }
+    }
+    let api_token = api_token.ok_or_else(|| {
+        anyhow!(
+            "No Venmo login found in {}, log in at https://venmo.com and try again",
+            browser
+        )
+    })?;
+
+    let user = get_venmo_user(client, &api_token).await?.ok_or_else(|| {
+        anyhow!("Venmo rejected the browser's API token, log in at https://venmo.com again")
+    })?;
+
+    println!("The API token belongs to @{}.", user.username);
+    println!("Venmo profile ID: {}", user.id);
+    println!("Venmo API token: {}", api_token);
+
+    Ok(())
+}
+
+/// The cookie database in `browser`'s profile at `profile_dir`.
+fn cookie_file(browser: Browser, profile_dir: &Path) -> PathBuf {
+    match browser {
+        Browser::Firefox => profile_dir.join("cookies.sqlite"),
+        Browser::Chrome => {
+            // Moved into `Network` on newer versions.
+            let network = profile_dir.join("Network").join("Cookies");
+            if network.exists() {
+                network
+            } else {
+                profile_dir.join("Cookies")
+            }
+        }
+    }
+}
+
+/// The cookie databases of every profile of `browser`, most recently used first.
+fn find_cookie_files(browser: Browser) -> Result<Vec<PathBuf>> {
+    let dirs = BaseDirs::new().ok_or_else(|| anyhow!("Failed to determine home directory"))?;
+
+    let profiles_dir = match browser {
+        Browser::Firefox if cfg!(target_os = "linux") => {
+            dirs.home_dir().join(".mozilla").join("firefox")
+        }
+        Browser::Firefox if cfg!(target_os = "windows") => dirs
+            .config_dir()
+            .join("Mozilla")
+            .join("Firefox")
+            .join("Profiles"),
+        Browser::Firefox => dirs.config_dir().join("Firefox").join("Profiles"),
+        Browser::Chrome if cfg!(target_os = "linux") => dirs.config_dir().join("google-chrome"),
+        Browser::Chrome if cfg!(target_os = "macos") => {
+            dirs.config_dir().join("Google").join("Chrome")
+        }
+        Browser::Chrome => bail!(
+            "Reading Chrome cookies is only supported on Linux and macOS, use --from-browser \
+             firefox or log in with a password"
+        ),
+    };
+
+    let entries = match fs::read_dir(&profiles_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| anyhow!("Failed to read {:?}", profiles_dir));
+        }
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = cookie_file(browser, &entry?.path());
+        if let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            files.push((modified, path));
+        }
+    }
+    files.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Reads the Venmo API token from `browser`'s cookie database at `path`, `None` if it isn't
+/// logged in to Venmo.
+fn read_cookie(browser: Browser, path: &Path) -> Result<Option<String>> {
+    // Browsers lock their cookie database while running, so a copy is read instead. It has every
+    // site's cookies, so it goes in a directory only we can read, deleted when this returns.
+    let dir = tempfile::Builder::new()
+        .prefix("lunchmoney-venmo-syncer-cookies-")
+        .tempdir()
+        .context("Failed to create a temporary directory for the cookie database")?;
+    let copy = dir.path().join("cookies.sqlite");
+    fs::copy(path, &copy).with_context(|| anyhow!("Failed to copy {:?}", path))?;
+    // Recent writes may still be in the write-ahead log.
+    let wal = PathBuf::from(format!("{}-wal", path.display()));
+    if wal.exists() {
+        fs::copy(&wal, dir.path().join("cookies.sqlite-wal"))
+            .with_context(|| anyhow!("Failed to copy {:?}", wal))?;
+    }
+
+    query_cookie(browser, &copy)
+        .with_context(|| anyhow!("Failed to read {} cookies from {:?}", browser, path))
+}
+
+fn query_cookie(browser: Browser, path: &Path) -> Result<Option<String>> {
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+
+    match browser {
+        Browser::Firefox => {
+            let mut statement = db.prepare(
+                "SELECT value FROM moz_cookies WHERE host LIKE '%venmo.com' AND name = ?1 \
+                 ORDER BY lastAccessed DESC LIMIT 1",
+            )?;
+            let mut rows = statement.query([COOKIE_NAME])?;
+
+            match rows.next()? {
+                Some(row) => Ok(Some(row.get(0)?)),
+                None => Ok(None),
+            }
+        }
+        Browser::Chrome => {
+            let mut statement = db.prepare(
+                "SELECT host_key, value, encrypted_value FROM cookies \
+                 WHERE host_key LIKE '%venmo.com' AND name = ?1 \
+                 ORDER BY last_access_utc DESC LIMIT 1",
+            )?;
+            let mut rows = statement.query([COOKIE_NAME])?;
+
+            let row = match rows.next()? {
+                Some(row) => row,
+                None => return Ok(None),
+            };
+            let host: String = row.get(0)?;
+            let value: String = row.get(1)?;
+            let encrypted: Vec<u8> = row.get(2)?;
+
+            if !value.is_empty() {
+                return Ok(Some(value));
+            }
+
+            let version = encrypted.get(..3).unwrap_or_default();
+            let password = chrome_password(version)?;
+            let decrypted = decrypt_chrome_cookie(&password, &host, &encrypted)?;
+
+            Ok(Some(decrypted))
+        }
+    }
+}
+
+/// The password Chrome encrypts cookies with, which depends on the platform and `version`
+/// prefix of the encrypted value.
+fn chrome_password(version: &[u8]) -> Result<String> {
+    if cfg!(target_os = "macos") {
+        return run_password_command(
+            Command::new("security").args(["find-generic-password", "-w", "-s"]),
+            "Chrome Safe Storage",
+        );
+    }
+
+    match version {
+        // Chrome's fallback when there's no desktop keyring.
+        b"v10" => Ok("peanuts".to_string()),
+        b"v11" => run_password_command(
+            Command::new("secret-tool").args(["lookup", "application"]),
+            "chrome",
+        ),
+        _ => bail!("Unsupported Chrome cookie encryption {:?}", version),
+    }
+}
+
+/// Runs `command` with `arg` to get Chrome's password from the OS keyring.
+fn run_password_command(command: &mut Command, arg: &str) -> Result<String> {
+    let output = command
+        .arg(arg)
+        .output()
+        .with_context(|| anyhow!("Failed to run {:?} to get Chrome's password", command))?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to get Chrome's password from the keyring, {:?} exited with {}",
+            command,
+            output.status
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)
+        .context("Chrome's password isn't UTF-8")?
+        .trim()
+        .to_string())
+}
+
+/// Decrypts a Chrome cookie value for `host` encrypted with `password`, as Chrome on Linux and
+/// macOS does.
+fn decrypt_chrome_cookie(password: &str, host: &str, encrypted: &[u8]) -> Result<String> {
+    let iterations = if cfg!(target_os = "macos") { 1003 } else { 1 };
+    let mut key = [0; 16];
+    pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), b"saltysalt", iterations, &mut key);
+
+    // Skip the version prefix.
+    let ciphertext = encrypted.get(3..).unwrap_or_default();
+    let mut decrypted = cbc::Decryptor::<aes::Aes128>::new(&key.into(), &[b' '; 16].into())
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt Chrome cookie, is the password right?"))?;
+
+    // Newer versions prefix the value with a hash of the host, to tie it to the domain.
+    if decrypted.starts_with(&Sha256::digest(host.as_bytes())) {
+        decrypted.drain(..32);
+    }
+
+    String::from_utf8(decrypted).context("Decrypted Chrome cookie isn't UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aes::cipher::BlockEncryptMut;
+
+    use crate::testing;
+
+    fn encrypt_chrome_cookie(password: &str, plaintext: &[u8]) -> Vec<u8> {
+        let iterations = if cfg!(target_os = "macos") { 1003 } else { 1 };
+        let mut key = [0; 16];
+        pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), b"saltysalt", iterations, &mut key);
+
+        let mut encrypted = b"v10".to_vec();
+        encrypted.extend(
+            cbc::Encryptor::<aes::Aes128>::new(&key.into(), &[b' '; 16].into())
+                .encrypt_padded_vec_mut::<Pkcs7>(plaintext),
+        );
+        encrypted
+    }
+
+    #[test]
+    fn decrypts_chrome_cookies() {
+        let encrypted = encrypt_chrome_cookie("peanuts", b"secret-venmo-token");
+        assert_eq!(
+            decrypt_chrome_cookie("peanuts", ".venmo.com", &encrypted).unwrap(),
+            "secret-venmo-token"
+        );
+        assert!(decrypt_chrome_cookie("wrong", ".venmo.com", &encrypted).is_err());
+
+        let mut plaintext = Sha256::digest(b".venmo.com").to_vec();
+        plaintext.extend(b"secret-venmo-token");
+        let encrypted = encrypt_chrome_cookie("peanuts", &plaintext);
+        assert_eq!(
+            decrypt_chrome_cookie("peanuts", ".venmo.com", &encrypted).unwrap(),
+            "secret-venmo-token"
+        );
+    }
+
+    #[test]
+    fn reads_firefox_cookies() {
+        let dir = testing::temp_dir();
+        let path = dir.path().join("cookies.sqlite");
+        let db = Connection::open(&path).unwrap();
+        db.execute_batch(
+            "CREATE TABLE moz_cookies (name TEXT, value TEXT, host TEXT, lastAccessed INTEGER);
+             INSERT INTO moz_cookies VALUES ('api_access_token', 'old-token', '.venmo.com', 1);
+             INSERT INTO moz_cookies VALUES ('api_access_token', 'new-token', '.venmo.com', 2);
+             INSERT INTO moz_cookies VALUES ('api_access_token', 'other', '.example.com', 3);",
+        )
+        .unwrap();
+        drop(db);
+
+        assert_eq!(
+            read_cookie(Browser::Firefox, &path).unwrap().as_deref(),
+            Some("new-token")
+        );
+
+        let db = Connection::open(&path).unwrap();
+        db.execute_batch("DELETE FROM moz_cookies WHERE host = '.venmo.com'")
+            .unwrap();
+        drop(db);
+
+        assert_eq!(read_cookie(Browser::Firefox, &path).unwrap(), None);
+    }
+}
@@ -0,0 +1,328 @@
+//! Parsing Cash App activity exports into the same `Statement` Venmo statements parse into, so
+//! they can be inserted into Lunch Money the same way. Cash App has no public API, so only the
+//! CSV export from the Cash App website (Activity > Statements > Export CSV) is supported.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::types::venmo::{Amount, Statement, Transaction, TransactionStatus, TransactionType};
+
+/// Names Cash App uses in the `Account` column for the Cash App balance itself, rather than a
+/// linked bank account or card.
+const BALANCE_ACCOUNTS: &[&str] = &["Your Cash", "Cash Balance", "Cash App", "Cash"];
+
+/// A row of a Cash App activity export.
+#[derive(Debug, Deserialize)]
+struct TransactionRecord {
+    #[serde(rename = "Transaction ID")]
+    id: String,
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Transaction Type")]
+    type_: String,
+    #[serde(rename = "Amount")]
+    amount: String,
+    #[serde(rename = "Net Amount", default)]
+    net_amount: String,
+    #[serde(rename = "Status")]
+    status: String,
+    #[serde(rename = "Notes", default)]
+    notes: String,
+    #[serde(rename = "Name of sender/receiver", default)]
+    name: String,
+    #[serde(rename = "Account", default)]
+    account: String,
+}
+
+/// Cash App transaction IDs are alphanumeric, e.g. `#rmgsrz`, and can be too long to decode
+/// into a `u64`. Returns the ID without the `#`, kept as the Lunch Money `external_id`, and a
+/// stable `Transaction::id` derived from a hash of it.
+fn parse_id(id: &str) -> Result<(u64, String)> {
+    let id = id.trim().trim_start_matches('#');
+
+    if id.is_empty() {
+        bail!("Missing Cash App transaction ID");
+    }
+
+    let digest = Sha256::digest(id.as_bytes());
+
+    Ok((
+        u64::from_be_bytes(digest[..8].try_into().unwrap()),
+        id.to_string(),
+    ))
+}
+
+/// Parses a date like `2023-01-15 14:23:45 EST`. Cash App writes US timezone abbreviations,
+/// which `chrono` can't parse on its own.
+fn parse_date(date: &str) -> Result<DateTime<FixedOffset>> {
+    let (datetime, abbreviation) = date
+        .trim()
+        .rsplit_once(' ')
+        .ok_or_else(|| anyhow!("Cash App date '{}' has no timezone", date))?;
+
+    let offset_hours = match abbreviation {
+        "UTC" | "GMT" => 0,
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" | "AKDT" => -8,
+        "AKST" => -9,
+        "HST" => -10,
+        _ => bail!(
+            "Unknown timezone '{}' in Cash App date '{}'",
+            abbreviation,
+            date
+        ),
+    };
+
+    let naive = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+        .map_err(|e| anyhow!("Failed to parse Cash App date '{}': {}", date, e))?;
+
+    FixedOffset::east_opt(offset_hours * 3600)
+        .unwrap()
+        .from_local_datetime(&naive)
+        .single()
+        .ok_or_else(|| anyhow!("Invalid Cash App date '{}'", date))
+}
+
+fn parse_status(status: &str) -> Result<TransactionStatus> {
+    Ok(match status.trim().to_ascii_uppercase().as_str() {
+        "COMPLETE" | "COMPLETED" => TransactionStatus::Complete,
+        "PENDING" => TransactionStatus::Pending,
+        "FAILED" => TransactionStatus::Failed,
+        "CANCELED" | "CANCELLED" => TransactionStatus::Cancelled,
+        _ => bail!("Unexpected Cash App transaction status '{}'", status),
+    })
+}
+
+/// `field`, or `None` if it's empty.
+fn non_empty(field: &str) -> Option<String> {
+    let field = field.trim();
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+/// Converts a Cash App record to the Venmo transaction type with the same meaning, so it's
+/// turned into Lunch Money transactions the same way.
+fn to_transaction(record: TransactionRecord, lenient: bool) -> Result<Transaction> {
+    let amount = if record.net_amount.trim().is_empty() {
+        &record.amount
+    } else {
+        &record.net_amount
+    };
+    let amount_total = Amount::from_str(amount)?;
+    let received = amount_total.val.is_sign_positive();
+
+    let name = non_empty(&record.name);
+    // Money moved to or from a linked bank account or card, rather than the Cash App balance.
+    let linked_account =
+        non_empty(&record.account).filter(|account| !BALANCE_ACCOUNTS.contains(&account.as_str()));
+
+    let (from, to) = if received {
+        (name.clone(), None)
+    } else {
+        (None, name.clone())
+    };
+
+    let (id, external_id) = parse_id(&record.id)?;
+
+    let mut transaction = Transaction {
+        id,
+        datetime: parse_date(&record.date)?,
+        type_: TransactionType::Payment,
+        status: parse_status(&record.status)?,
+        note: non_empty(&record.notes),
+        from,
+        to,
+        amount_total,
+        amount_tip: None,
+        amount_tax: None,
+        amount_fee: None,
+        funding_source: None,
+        destination: None,
+        external_id: Some(external_id),
+    };
+
+    match record.type_.trim() {
+        "P2P" | "Sent P2P" | "Received P2P" => {
+            transaction.funding_source = linked_account.filter(|_| !received);
+        }
+        "Cash Card" | "Cash Card Debit" => {
+            transaction.type_ = TransactionType::MerchantTransaction;
+            transaction.funding_source = linked_account.filter(|_| !received);
+        }
+        "Cash Out" | "Cash out" | "Withdrawal" => {
+            transaction.type_ = TransactionType::StandardTransfer;
+            transaction.destination = Some(
+                linked_account
+                    .or(name)
+                    .unwrap_or_else(|| "Bank".to_string()),
+            );
+        }
+        "Cash In" | "Deposits" | "Paper Money Deposit" | "Direct Deposit" => {
+            transaction.type_ = TransactionType::DirectDeposit;
+            transaction.from = name.or(linked_account);
+        }
+        "Refund" | "Cash Card Refund" => transaction.type_ = TransactionType::Refund,
+        "Boost" | "Boost Payment" | "Interest" => {
+            transaction.type_ = TransactionType::Cashback;
+            transaction.from = Some(name.unwrap_or_else(|| "Cash App".to_string()));
+        }
+        type_ => {
+            if !lenient {
+                bail!(
+                    "Transaction {} has a Cash App type this tool doesn't support yet ('{}'), pass --lenient to sync it like a payment",
+                    record.id,
+                    type_
+                );
+            }
+
+            warn!(
+                "Transaction {} has unknown type '{}', syncing it like a payment",
+                record.id, type_
+            );
+            transaction.type_ = TransactionType::Unknown(type_.to_string());
+        }
+    }
+
+    Ok(transaction)
+}
+
+/// Parses a Cash App activity export CSV. Exports don't include balances, so the statement's
+/// beginning balance is zero and its ending balance is how much its transactions moved the Cash
+/// App balance, leaving out payments funded by a linked card or bank account.
+/// Unrecognized transaction types fail to parse unless `lenient` is set.
+#[tracing::instrument(skip_all, fields(bytes = bytes.len()))]
+pub fn parse_cashapp_statement(bytes: &[u8], lenient: bool) -> Result<Statement> {
+    let body = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body);
+
+    let mut transactions = Vec::new();
+
+    for (i, record) in rdr.deserialize::<TransactionRecord>().enumerate() {
+        // Row 1 is the header.
+        let record =
+            record.with_context(|| anyhow!("Failed to read Cash App export row {}", i + 2))?;
+        let transaction = to_transaction(record, lenient)
+            .with_context(|| anyhow!("Failed to parse Cash App export row {}", i + 2))?;
+
+        transactions.push(transaction);
+    }
+
+    let currency = transactions
+        .first()
+        .map(|transaction| transaction.amount_total.currency.clone())
+        .unwrap_or_else(|| "$".to_string());
+    let net: Decimal = transactions
+        .iter()
+        .filter(|transaction| !TransactionStatus::DEFAULT_SKIPPED.contains(&transaction.status))
+        .map(Transaction::balance_change)
+        .sum();
+
+    Ok(Statement {
+        beginning_balance: Amount {
+            currency: currency.clone(),
+            val: Decimal::ZERO,
+        },
+        ending_balance: Amount { currency, val: net },
+        transactions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const EXPORT: &str = "\u{feff}Transaction ID,Date,Transaction Type,Currency,Amount,Fee,Net Amount,Asset Type,Asset Price,Asset Amount,Status,Notes,Name of sender/receiver,Account\n\
+        rmgsrz,2023-01-15 14:23:45 EST,Sent P2P,USD,-$12.50,$0,-$12.50,,,,COMPLETE,Lunch,Jane Doe,Visa Debit 1234\n\
+        #8kd2x9,2023-01-16 09:00:00 EST,Received P2P,USD,$40.00,$0,$40.00,,,,COMPLETE,Rent,John Smith,Your Cash\n\
+        qa1b2c,2023-01-17 18:30:00 EST,Cash Card Debit,USD,-$5.25,$0,-$5.25,,,,COMPLETE,,Coffee Shop,Your Cash\n\
+        zz9y8x,2023-01-18 12:00:00 EST,Cash out,USD,-$20.00,-$0.30,-$20.30,,,,COMPLETE,,,Chase Checking\n\
+        ab12cd,2023-01-19 12:00:00 EST,Sent P2P,USD,-$3.00,$0,-$3.00,,,,FAILED,,Jane Doe,Your Cash\n";
+
+    #[test]
+    fn parses_exports() {
+        let statement = parse_cashapp_statement(EXPORT.as_bytes(), false).unwrap();
+        let transactions = &statement.transactions;
+
+        assert_eq!(transactions.len(), 5);
+
+        assert_eq!(transactions[0].external_id(), "rmgsrz");
+        assert_eq!(transactions[0].type_, TransactionType::Payment);
+        assert_eq!(transactions[0].to.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            transactions[0].funding_source.as_deref(),
+            Some("Visa Debit 1234")
+        );
+        assert_eq!(transactions[0].note.as_deref(), Some("Lunch"));
+        assert_eq!(
+            transactions[0].datetime.to_rfc3339(),
+            "2023-01-15T14:23:45-05:00"
+        );
+
+        assert_ne!(transactions[0].id, transactions[1].id);
+        assert_eq!(
+            transactions[1],
+            Transaction {
+                id: transactions[1].id,
+                datetime: DateTime::parse_from_rfc3339("2023-01-16T09:00:00-05:00").unwrap(),
+                note: Some("Rent".to_string()),
+                from: Some("John Smith".to_string()),
+                to: None,
+                external_id: Some("8kd2x9".to_string()),
+                ..testing::transaction(0, TransactionType::Payment, Decimal::new(40, 0))
+            }
+        );
+
+        assert_eq!(transactions[2].type_, TransactionType::MerchantTransaction);
+        assert_eq!(transactions[2].funding_source, None);
+
+        assert_eq!(transactions[3].type_, TransactionType::StandardTransfer);
+        assert_eq!(
+            transactions[3].destination.as_deref(),
+            Some("Chase Checking")
+        );
+        assert_eq!(transactions[3].amount_total.val, Decimal::new(-2030, 2));
+
+        assert_eq!(transactions[4].status, TransactionStatus::Failed);
+
+        assert_eq!(statement.beginning_balance.val, Decimal::ZERO);
+        // The payment funded by the debit card doesn't move the balance.
+        assert_eq!(statement.ending_balance.val, Decimal::new(1445, 2));
+    }
+
+    #[test]
+    fn parses_long_ids() {
+        let export = "Transaction ID,Date,Transaction Type,Amount,Net Amount,Status,Notes,Name of sender/receiver,Account\n\
+            #d3kq9x2m7vbn4p8r,2023-01-15 14:23:45 PST,Sent P2P,-$10.00,-$10.00,COMPLETE,,Jane Doe,Your Cash\n";
+
+        let statement = parse_cashapp_statement(export.as_bytes(), false).unwrap();
+        assert_eq!(statement.transactions[0].external_id(), "d3kq9x2m7vbn4p8r");
+    }
+
+    #[test]
+    fn rejects_unknown_types_unless_lenient() {
+        let export = "Transaction ID,Date,Transaction Type,Amount,Net Amount,Status,Notes,Name of sender/receiver,Account\n\
+            rmgsrz,2023-01-15 14:23:45 PST,Bitcoin Buy,-$10.00,-$10.00,COMPLETE,,,Your Cash\n";
+
+        assert!(parse_cashapp_statement(export.as_bytes(), false).is_err());
+
+        let statement = parse_cashapp_statement(export.as_bytes(), true).unwrap();
+        assert_eq!(
+            statement.transactions[0].type_,
+            TransactionType::Unknown("Bitcoin Buy".to_string())
+        );
+    }
+}
@@ -0,0 +1,1054 @@
+//! The `config.toml` file, and the `config` subcommands for checking it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+use rust_decimal::Decimal;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::credentials::run_token_command;
+use crate::lunchmoney::{get_all_assets, get_all_categories, get_all_tags};
+use crate::notify::{validate_channel, validate_url};
+use crate::paths::Paths;
+use crate::payees::validate_rewrite;
+use crate::rules::validate_rule;
+use crate::types::venmo::{
+    is_known_column, ConvertOptions, StatementFormat, Template, Templates, TransactionFilter,
+    TransactionStatus, TransactionType, Transfers,
+};
+use crate::types::HttpsClient;
+
+/// Config file contents, see `config.example.toml`. Every value can be overridden by the
+/// corresponding CLI flag.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Venmo account to sync from.
+    #[serde(default)]
+    pub venmo: VenmoConfig,
+    /// Lunch Money account and asset to sync to.
+    #[serde(default)]
+    pub lunch_money: LunchMoneyConfig,
+    /// Default date range to list and sync transactions for.
+    #[serde(default)]
+    pub sync: SyncConfig,
+    /// Venmo accounts synced by `sync-all`, each to its own Lunch Money asset.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<AccountConfig>,
+    /// Rules assigning Lunch Money categories to synced transactions, the first match wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<CategoryRule>,
+    /// How to rewrite Venmo names into Lunch Money payees.
+    #[serde(default)]
+    pub payees: PayeeConfig,
+    /// Templates for the payees and notes of synced transactions.
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    /// URLs to ping after syncs.
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    /// PayPal account synced by `sync-paypal-transactions`.
+    #[serde(default)]
+    pub paypal: PayPalConfig,
+    /// YNAB account to sync to with `--target ynab`, instead of Lunch Money.
+    #[serde(default)]
+    pub ynab: YnabConfig,
+    /// Firefly III asset account to sync to with `--target firefly`, instead of Lunch Money.
+    #[serde(default)]
+    pub firefly: FireflyConfig,
+}
+
+/// URLs to ping after syncs, e.g. Healthchecks.io checks, see `notify`.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// URL to POST a JSON summary to after each successful sync.
+    pub on_success_url: Option<String>,
+    /// URL to POST a JSON error to after each failed sync.
+    pub on_failure_url: Option<String>,
+    /// Slack, Discord, and email channels to send a summary of each run to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<NotifyChannel>,
+}
+
+/// Where to send a summary of each run.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum NotifyChannel {
+    /// A Slack incoming webhook.
+    Slack {
+        /// Webhook URL, from the Slack app's "Incoming Webhooks" settings.
+        webhook_url: String,
+        #[serde(default)]
+        when: NotifyWhen,
+    },
+    /// A Discord webhook.
+    Discord {
+        /// Webhook URL, from the channel's "Integrations" settings.
+        webhook_url: String,
+        #[serde(default)]
+        when: NotifyWhen,
+    },
+    /// Email sent over SMTP.
+    Email(EmailChannel),
+}
+
+/// Where and how to send email notifications.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EmailChannel {
+    /// SMTP server to send through, e.g. `smtp.gmail.com`.
+    pub smtp_host: String,
+    /// Defaults to 587, or 465 with implicit TLS, or 25 without TLS.
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender address, e.g. `Venmo Sync <sync@example.com>`.
+    pub from: String,
+    /// Recipient addresses.
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub when: NotifyWhen,
+}
+
+impl NotifyChannel {
+    pub fn when(&self) -> NotifyWhen {
+        match self {
+            NotifyChannel::Slack { when, .. } | NotifyChannel::Discord { when, .. } => *when,
+            NotifyChannel::Email(email) => email.when,
+        }
+    }
+}
+
+/// Which runs to notify a channel of.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyWhen {
+    /// Every run.
+    #[default]
+    Always,
+    /// Only runs where a sync failed.
+    Errors,
+}
+
+/// How to secure the connection to an SMTP server.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS.
+    #[default]
+    Starttls,
+    /// Connect over TLS.
+    Implicit,
+    /// Don't encrypt the connection, e.g. for a local relay.
+    None,
+}
+
+/// How to rewrite Venmo names into Lunch Money payees.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PayeeConfig {
+    /// Remove emoji from payees.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_emoji: bool,
+    /// Capitalize payees that are all lowercase or all uppercase, e.g. `john SMITH` to `John
+    /// Smith`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub title_case: bool,
+    /// Payees to rewrite, the first match wins. Matched payees aren't otherwise normalized.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rewrites: Vec<PayeeRewrite>,
+}
+
+/// Templates for the payees and notes of generated Lunch Money transactions, e.g. `notes =
+/// "Venmo: {note} ({type})"`. Variables are in `{}`, see `config.example.toml` for the list.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TemplatesConfig {
+    /// Payee of Venmo transactions, defaults to `{payee}`, e.g. the other person.
+    pub payee: Option<String>,
+    /// Notes of Venmo transactions, defaults to `{notes}`, the Venmo note.
+    pub notes: Option<String>,
+    /// Payee of the shadow transfers generated for them, defaults to `{payee}`, e.g. `TRANSFER
+    /// FROM Chase Checking *1234`.
+    pub transfer_payee: Option<String>,
+    /// Notes of the shadow transfers generated for them, defaults to `{notes}`.
+    pub transfer_notes: Option<String>,
+}
+
+impl TemplatesConfig {
+    /// Parses the templates that are set.
+    pub fn templates(&self) -> Result<Templates> {
+        let parse = |name: &str, template: &Option<String>| {
+            template
+                .as_deref()
+                .map(Template::from_str)
+                .transpose()
+                .with_context(|| anyhow!("Failed to parse templates.{}", name))
+        };
+
+        Ok(Templates {
+            payee: parse("payee", &self.payee)?,
+            notes: parse("notes", &self.notes)?,
+            transfer_payee: parse("transfer_payee", &self.transfer_payee)?,
+            transfer_notes: parse("transfer_notes", &self.transfer_notes)?,
+        })
+    }
+}
+
+/// Replaces payees matching `name` or `pattern` with `replace`.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PayeeRewrite {
+    /// Venmo name to match exactly, ignoring case.
+    pub name: Option<String>,
+    /// Regex matched against the Venmo name, e.g. `(?i)^landlord`.
+    pub pattern: Option<String>,
+    /// Payee to use instead. With `pattern`, `$1` etc. are replaced with its capture groups.
+    pub replace: String,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VenmoConfig {
+    /// Venmo profile ID, as printed by `get-venmo-api-token`.
+    pub profile_id: Option<u64>,
+    /// Venmo API token, as printed by `get-venmo-api-token`.
+    pub api_token: Option<String>,
+    /// Command whose output is used as the API token when `api_token` isn't set, e.g.
+    /// `op read op://Private/Venmo/token`. Only run when the token is needed.
+    pub token_command: Option<String>,
+    /// Command run by `daemon` when Venmo rejects the API token, whose output is used as a new
+    /// one for the rest of the daemon's run. Without it, the daemon asks to log in again when
+    /// running in a terminal.
+    pub reauth_command: Option<String>,
+    /// ISO currency code of the Venmo account, defaults to USD.
+    pub currency: Option<String>,
+    /// Overrides for parsing Venmo statements.
+    #[serde(default)]
+    pub statement_format: StatementFormat,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LunchMoneyConfig {
+    /// Lunch Money API token, from <https://my.lunchmoney.app/developers>.
+    pub api_token: Option<String>,
+    /// Command whose output is used as the API token when `api_token` isn't set, e.g.
+    /// `op read op://Private/Lunch Money/token`. Only run when the token is needed.
+    pub token_command: Option<String>,
+    /// ID of the Lunch Money asset to sync to, as printed by `list-lunch-money-assets`.
+    pub asset_id: Option<u64>,
+    /// Names of Lunch Money tags to attach to every synced transaction.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Create tags in `tags` that don't exist yet, rather than failing the sync.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub create_tags: bool,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PayPalConfig {
+    /// Client ID of a PayPal REST API app with the Transaction Search feature, from
+    /// <https://developer.paypal.com/dashboard/applications>.
+    pub client_id: Option<String>,
+    /// Secret of the PayPal REST API app.
+    pub client_secret: Option<String>,
+    /// ISO currency code of the PayPal balance to sync, defaults to USD. Transactions in other
+    /// currencies are skipped.
+    pub currency: Option<String>,
+    /// ID of the Lunch Money asset to sync PayPal transactions to, defaults to
+    /// `lunch_money.asset_id`.
+    pub lunch_money_asset_id: Option<u64>,
+    /// Use PayPal's sandbox API instead of the live one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sandbox: bool,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct YnabConfig {
+    /// YNAB personal access token, from <https://app.ynab.com/settings/developer>.
+    pub api_token: Option<String>,
+    /// ID of the YNAB budget, the one in its URL, defaults to `last-used`.
+    pub budget_id: Option<String>,
+    /// ID of the YNAB account in the budget to sync to, the one in its URL.
+    pub account_id: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FireflyConfig {
+    /// URL of the Firefly III instance, e.g. `https://firefly.example.com`.
+    pub base_url: Option<String>,
+    /// Firefly III personal access token, from Options > Profile > OAuth.
+    pub api_token: Option<String>,
+    /// ID of the Firefly III asset account to sync to, the one in its URL.
+    pub account_id: Option<u64>,
+    /// Firefly III asset account IDs of funding sources and destinations by their name in Venmo
+    /// statements, e.g. `"Chase Checking *1234" = 3`. Money moved to or from them is inserted
+    /// as transfers rather than withdrawals and deposits.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub transfer_account_ids: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    /// Name to report results under, defaults to the Venmo profile ID.
+    pub name: Option<String>,
+    /// Venmo profile ID, as printed by `get-venmo-api-token`.
+    pub venmo_profile_id: u64,
+    /// Venmo API token, as printed by `get-venmo-api-token`.
+    pub venmo_api_token: String,
+    /// ISO currency code of the Venmo account, defaults to `venmo.currency` or USD.
+    pub currency: Option<String>,
+    /// Lunch Money API token, defaults to `lunch_money.api_token`.
+    pub lunch_money_api_token: Option<String>,
+    /// ID of the Lunch Money asset to sync this account to.
+    pub lunch_money_asset_id: u64,
+}
+
+/// `flag`, else `api_token`, else the output of `token_command`, if set.
+fn resolve_api_token(
+    flag: Option<String>,
+    api_token: &Option<String>,
+    token_command: &Option<String>,
+) -> Result<Option<String>> {
+    match flag.or_else(|| api_token.clone()) {
+        Some(api_token) => Ok(Some(api_token)),
+        None => token_command.as_deref().map(run_token_command).transpose(),
+    }
+}
+
+impl VenmoConfig {
+    /// The API token given as `flag`, else the configured one, running `token_command` if needed.
+    pub fn api_token_or(&self, flag: Option<String>) -> Result<Option<String>> {
+        resolve_api_token(flag, &self.api_token, &self.token_command)
+    }
+}
+
+impl LunchMoneyConfig {
+    /// The API token given as `flag`, else the configured one, running `token_command` if needed.
+    pub fn api_token_or(&self, flag: Option<String>) -> Result<Option<String>> {
+        resolve_api_token(flag, &self.api_token, &self.token_command)
+    }
+
+    /// Keeps the output of `token_command` as `api_token`, so it only runs once when the token
+    /// is needed for several accounts.
+    pub fn cache_api_token(&mut self) -> Result<()> {
+        self.api_token = self.api_token_or(None)?;
+        Ok(())
+    }
+}
+
+impl AccountConfig {
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.venmo_profile_id.to_string())
+    }
+}
+
+/// Assigns `category` to Venmo transactions matching all of the given conditions.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryRule {
+    /// Payee of the Lunch Money transaction, i.e. the other party after `[payees]` rewrites,
+    /// matched case-insensitively.
+    pub payee: Option<String>,
+    /// Regex matched against the Venmo note, e.g. `(?i)rent`.
+    pub note: Option<String>,
+    /// Venmo transaction type, e.g. `Payment`, `Charge`, `Standard Transfer`, `Merchant
+    /// Transaction`, or `Refund`.
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    /// Name of the Lunch Money category to assign.
+    pub category: String,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SyncConfig {
+    /// How far back to start, e.g. `30d`, defaults to 30 days.
+    pub start_from: Option<String>,
+    /// How far back to end, e.g. `1d`, defaults to now.
+    pub end_to: Option<String>,
+    /// Venmo transaction statuses not to sync, defaults to `["Failed", "Cancelled"]`. Set to
+    /// `[]` to sync every transaction.
+    pub skip_statuses: Option<Vec<String>>,
+    /// Venmo transaction statuses to insert into Lunch Money as cleared rather than uncleared,
+    /// e.g. `["Complete"]` to skip reviewing completed transactions. Defaults to none.
+    pub cleared_statuses: Option<Vec<String>>,
+    /// Only sync Venmo transactions of these types, e.g. `["Payment", "Charge"]`. Defaults to
+    /// every type.
+    pub include_types: Option<Vec<String>>,
+    /// Venmo transaction types not to sync, e.g. `["Standard Transfer"]` when bank transfers
+    /// are tracked elsewhere. Defaults to none.
+    pub exclude_types: Option<Vec<String>>,
+    /// Regexes matched against the counterparty of Venmo transactions not to sync, e.g.
+    /// `["(?i)^jane doe$"]` for a roommate's reimbursements tracked elsewhere. For transfers to a
+    /// bank, the counterparty is the bank account.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_payee_regex: Vec<String>,
+    /// Regexes matched against the notes of Venmo transactions not to sync, e.g.
+    /// `["(?i)utilities"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_note_regex: Vec<String>,
+    /// Smallest amount of Venmo transactions to sync, sent or received, e.g. `"1.00"` to skip
+    /// test payments of a few cents. Defaults to no minimum.
+    pub min_amount: Option<String>,
+    /// Largest amount of Venmo transactions to sync, sent or received. Defaults to no maximum.
+    pub max_amount: Option<String>,
+    /// Generate "shadow" transfers for payments funded by, and money deposited to, bank
+    /// accounts, so the asset's balance tracks the Venmo balance. Defaults to true.
+    pub shadow_transactions: Option<bool>,
+    /// Lunch Money asset IDs of funding sources and destinations by their name in Venmo
+    /// statements, e.g. `"Chase Checking *1234" = 54321`. The other side of transfers to or from
+    /// them is inserted into that asset. Names match ignoring case, spaces, and punctuation, and
+    /// however the account number is masked, e.g. `Chase Checking x1234`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub transfer_asset_ids: BTreeMap<String, u64>,
+    /// Insert tips and Venmo fees (e.g. for instant transfers) as their own transactions, with
+    /// ` (tip)` or ` (fee)` appended to the payee, rather than as part of the payment or transfer
+    /// they were paid with.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub split_tips_and_fees: bool,
+    /// Lunch Money asset ID of the Venmo Credit Card. Purchases and payments made with the card
+    /// are inserted into it rather than the Venmo asset, and paying off the card from the Venmo
+    /// balance inserts the other side of the payment into it.
+    pub credit_card_asset_id: Option<u64>,
+    /// Group each inserted payment with its shadow transfers in Lunch Money, so they show up as
+    /// one transaction.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub group_transfers: bool,
+    /// Max number of chunks of transactions to insert into Lunch Money at once, to speed up
+    /// large backfills. Defaults to 1.
+    pub parallelism: Option<usize>,
+    /// Fail syncs whose synced transactions don't add up to the statement's ending balance,
+    /// rather than just warning.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fail_on_balance_drift: bool,
+}
+
+impl SyncConfig {
+    /// Parses `start_from` as a duration, if set.
+    pub fn start_from(&self) -> Result<Option<Duration>> {
+        parse_duration_value("sync.start_from", &self.start_from)
+    }
+
+    /// Parses `end_to` as a duration, if set.
+    pub fn end_to(&self) -> Result<Option<Duration>> {
+        parse_duration_value("sync.end_to", &self.end_to)
+    }
+
+    /// Parses `skip_statuses`, defaulting to `TransactionStatus::DEFAULT_SKIPPED`.
+    pub fn skip_statuses(&self) -> Result<Vec<TransactionStatus>> {
+        match self.skip_statuses {
+            Some(ref statuses) => parse_statuses("sync.skip_statuses", statuses),
+            None => Ok(TransactionStatus::DEFAULT_SKIPPED.to_vec()),
+        }
+    }
+
+    /// Parses `include_types`, `exclude_types`, `exclude_payee_regex`, `exclude_note_regex`,
+    /// `min_amount`, and `max_amount`.
+    pub fn filter(&self) -> Result<TransactionFilter> {
+        let min_amount = parse_amount("sync.min_amount", &self.min_amount)?;
+        let max_amount = parse_amount("sync.max_amount", &self.max_amount)?;
+        if let (Some(min), Some(max)) = (min_amount, max_amount) {
+            if min > max {
+                bail!(
+                    "sync.min_amount {} is more than sync.max_amount {}",
+                    min,
+                    max
+                );
+            }
+        }
+
+        Ok(TransactionFilter {
+            include_types: parse_types(
+                "sync.include_types",
+                self.include_types.as_deref().unwrap_or_default(),
+            )?,
+            exclude_types: parse_types(
+                "sync.exclude_types",
+                self.exclude_types.as_deref().unwrap_or_default(),
+            )?,
+            exclude_payees: parse_regexes("sync.exclude_payee_regex", &self.exclude_payee_regex)?,
+            exclude_notes: parse_regexes("sync.exclude_note_regex", &self.exclude_note_regex)?,
+            min_amount,
+            max_amount,
+        })
+    }
+
+    /// Max number of concurrent inserts, at least 1.
+    pub fn parallelism(&self) -> Result<usize> {
+        match self.parallelism {
+            Some(0) => bail!("sync.parallelism must be at least 1"),
+            parallelism => Ok(parallelism.unwrap_or(1)),
+        }
+    }
+
+    /// Parses `cleared_statuses`, if set.
+    pub fn cleared_statuses(&self) -> Result<Vec<TransactionStatus>> {
+        parse_statuses(
+            "sync.cleared_statuses",
+            self.cleared_statuses.as_deref().unwrap_or_default(),
+        )
+    }
+}
+
+fn parse_statuses(name: &str, values: &[String]) -> Result<Vec<TransactionStatus>> {
+    values
+        .iter()
+        .map(|value| {
+            TransactionStatus::from_str(value)
+                .with_context(|| anyhow!("{} '{}' is not a Venmo status", name, value))
+        })
+        .collect()
+}
+
+fn parse_types(name: &str, values: &[String]) -> Result<Vec<TransactionType>> {
+    values
+        .iter()
+        .map(|value| {
+            TransactionType::parse_name(value)
+                .with_context(|| anyhow!("{} '{}' is not a Venmo transaction type", name, value))
+        })
+        .collect()
+}
+
+fn parse_regexes(name: &str, values: &[String]) -> Result<Vec<Regex>> {
+    values
+        .iter()
+        .map(|value| {
+            Regex::new(value).with_context(|| anyhow!("{} '{}' is not a valid regex", name, value))
+        })
+        .collect()
+}
+
+fn parse_amount(name: &str, value: &Option<String>) -> Result<Option<Decimal>> {
+    value
+        .as_deref()
+        .map(|value| match Decimal::from_str(value.trim()) {
+            Ok(amount) if amount.is_sign_negative() => {
+                bail!("{} '{}' must not be negative", name, value)
+            }
+            res => res.with_context(|| anyhow!("{} '{}' is not a valid amount", name, value)),
+        })
+        .transpose()
+}
+
+fn parse_duration_value(name: &str, value: &Option<String>) -> Result<Option<Duration>> {
+    value
+        .as_deref()
+        .map(|value| {
+            humantime::parse_duration(value)
+                .with_context(|| anyhow!("{} '{}' is not a valid duration", name, value))
+        })
+        .transpose()
+}
+
+/// Path of the config file in the config directory.
+pub fn config_file(paths: &Paths) -> PathBuf {
+    paths.config_dir.join("config.toml")
+}
+
+/// Masks all but the last 4 characters of `secret`, for printing.
+pub fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+
+    format!(
+        "{}{}",
+        "*".repeat(chars.len() - 4),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+impl Config {
+    /// How to convert Venmo transactions: record money moved between Venmo and other accounts,
+    /// name the generated transactions, and split out tips and fees.
+    pub fn convert_options(&self) -> Result<ConvertOptions> {
+        Ok(ConvertOptions {
+            transfers: Transfers {
+                shadow_transactions: self.sync.shadow_transactions.unwrap_or(true),
+                asset_ids: self.sync.transfer_asset_ids.clone(),
+            },
+            templates: self.templates.templates()?,
+            split_tips_and_fees: self.sync.split_tips_and_fees,
+            credit_card_asset_id: self.sync.credit_card_asset_id,
+        })
+    }
+
+    /// Returns a copy of this config with all secrets masked, for printing.
+    pub fn masked(&self) -> Self {
+        let mut config = self.clone();
+
+        // Ping URLs usually embed a secret check ID.
+        for secret in [
+            &mut config.venmo.api_token,
+            &mut config.lunch_money.api_token,
+            &mut config.paypal.client_secret,
+            &mut config.ynab.api_token,
+            &mut config.firefly.api_token,
+            &mut config.notify.on_success_url,
+            &mut config.notify.on_failure_url,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *secret = mask_secret(secret);
+        }
+
+        for channel in &mut config.notify.channels {
+            match channel {
+                NotifyChannel::Slack { webhook_url, .. }
+                | NotifyChannel::Discord { webhook_url, .. } => {
+                    *webhook_url = mask_secret(webhook_url);
+                }
+                NotifyChannel::Email(EmailChannel { password, .. }) => {
+                    if let Some(password) = password {
+                        *password = mask_secret(password);
+                    }
+                }
+            }
+        }
+
+        for account in &mut config.accounts {
+            account.venmo_api_token = mask_secret(&account.venmo_api_token);
+
+            if let Some(ref mut secret) = account.lunch_money_api_token {
+                *secret = mask_secret(secret);
+            }
+        }
+
+        config
+    }
+
+    /// Loads the config file from `path`, or an empty config if it does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| anyhow!("Failed to read config file {:?}", path))?;
+
+        toml::from_str(&contents).with_context(|| anyhow!("Failed to parse config file {:?}", path))
+    }
+}
+
+/// Deserializes the `name` section of `value`, recording a problem (and returning `None`) if it
+/// is malformed so the remaining sections can still be checked.
+fn check_section<T: DeserializeOwned + Default>(
+    value: &toml::Value,
+    name: &str,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    match value.get(name) {
+        Some(section) => match section.clone().try_into() {
+            Ok(section) => Some(section),
+            Err(e) => {
+                problems.push(format!("[{}]: {}", name, e));
+                None
+            }
+        },
+        None => Some(T::default()),
+    }
+}
+
+/// Prints a JSON Schema for the config file.
+pub fn cmd_config_schema() -> Result<()> {
+    let schema = schemars::schema_for!(Config);
+
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+
+    Ok(())
+}
+
+/// Validates the config file, printing every problem found, including Lunch Money assets,
+/// categories, and tags it references that don't exist.
+pub async fn cmd_config_check(client: &HttpsClient, paths: &Paths) -> Result<()> {
+    let path = config_file(paths);
+
+    if !path.exists() {
+        bail!("No config file found at {:?}", path);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| anyhow!("Failed to read config file {:?}", path))?;
+    let value: toml::Value = toml::from_str(&contents)
+        .with_context(|| anyhow!("Config file {:?} is not valid TOML", path))?;
+
+    let mut problems = Vec::new();
+
+    if let Some(table) = value.as_table() {
+        for key in table.keys() {
+            if ![
+                "venmo",
+                "lunch_money",
+                "sync",
+                "accounts",
+                "rules",
+                "payees",
+                "notify",
+                "paypal",
+                "ynab",
+                "firefly",
+            ]
+            .contains(&key.as_str())
+            {
+                problems.push(format!("unknown section or key '{}'", key));
+            }
+        }
+    }
+
+    let mut required = Vec::new();
+
+    if let Some(venmo) = check_section::<VenmoConfig>(&value, "venmo", &mut problems) {
+        required.push(("venmo.profile_id", venmo.profile_id.is_some()));
+        required.push((
+            "venmo.api_token",
+            venmo.api_token.is_some() || venmo.token_command.is_some(),
+        ));
+
+        if let Some(ref currency) = venmo.currency {
+            if rusty_money::iso::find(currency).is_none() {
+                problems.push(format!(
+                    "venmo.currency '{}' is not a valid currency",
+                    currency
+                ));
+            }
+        }
+
+        if let Err(e) = venmo.statement_format.timezone() {
+            problems.push(format!("venmo.statement_format.timezone: {}", e));
+        }
+
+        for problem in venmo.statement_format.request_headers().1 {
+            problems.push(format!(
+                "venmo.statement_format.request_headers: {}",
+                problem
+            ));
+        }
+
+        for (from, to) in &venmo.statement_format.headers {
+            if !is_known_column(to) {
+                problems.push(format!(
+                    "venmo.statement_format.headers maps '{}' to unknown column '{}'",
+                    from, to
+                ));
+            }
+        }
+    }
+
+    let mut lunch_money = check_section::<LunchMoneyConfig>(&value, "lunch_money", &mut problems);
+
+    if let Some(ref mut lunch_money) = lunch_money {
+        required.push((
+            "lunch_money.api_token",
+            lunch_money.api_token.is_some() || lunch_money.token_command.is_some(),
+        ));
+        required.push(("lunch_money.asset_id", lunch_money.asset_id.is_some()));
+
+        // Run `token_command` so the token it prints is checked against Lunch Money below.
+        match lunch_money.api_token_or(None) {
+            Ok(api_token) => lunch_money.api_token = api_token,
+            Err(e) => problems.push(format!("lunch_money.token_command: {:#}", e)),
+        }
+    }
+
+    let sync = check_section::<SyncConfig>(&value, "sync", &mut problems);
+
+    if let Some(ref sync) = sync {
+        for res in [sync.start_from(), sync.end_to()] {
+            if let Err(e) = res {
+                problems.push(format!("{:#}", e));
+            }
+        }
+
+        for res in [sync.skip_statuses(), sync.cleared_statuses()] {
+            if let Err(e) = res {
+                problems.push(format!("{:#}", e));
+            }
+        }
+
+        if let Err(e) = sync.filter() {
+            problems.push(format!("{:#}", e));
+        }
+
+        if let Err(e) = sync.parallelism() {
+            problems.push(format!("{:#}", e));
+        }
+    }
+
+    let accounts =
+        check_section::<Vec<AccountConfig>>(&value, "accounts", &mut problems).unwrap_or_default();
+
+    for account in &accounts {
+        if let Some(ref currency) = account.currency {
+            if rusty_money::iso::find(currency).is_none() {
+                problems.push(format!(
+                    "accounts '{}' currency '{}' is not a valid currency",
+                    account.name(),
+                    currency
+                ));
+            }
+        }
+    }
+
+    let rules =
+        check_section::<Vec<CategoryRule>>(&value, "rules", &mut problems).unwrap_or_default();
+
+    for (i, rule) in rules.iter().enumerate() {
+        if let Err(e) = validate_rule(rule) {
+            problems.push(format!("rules #{}: {:#}", i + 1, e));
+        }
+    }
+
+    if let Some(payees) = check_section::<PayeeConfig>(&value, "payees", &mut problems) {
+        for (i, rewrite) in payees.rewrites.iter().enumerate() {
+            if let Err(e) = validate_rewrite(rewrite) {
+                problems.push(format!("payees.rewrites #{}: {:#}", i + 1, e));
+            }
+        }
+    }
+
+    if let Some(templates) = check_section::<TemplatesConfig>(&value, "templates", &mut problems) {
+        if let Err(e) = templates.templates() {
+            problems.push(format!("{:#}", e));
+        }
+    }
+
+    if let Some(notify) = check_section::<NotifyConfig>(&value, "notify", &mut problems) {
+        for (name, url) in [
+            ("notify.on_success_url", &notify.on_success_url),
+            ("notify.on_failure_url", &notify.on_failure_url),
+        ] {
+            if let Some(url) = url {
+                if let Err(e) = validate_url(url) {
+                    problems.push(format!("{}: {:#}", name, e));
+                }
+            }
+        }
+
+        for (i, channel) in notify.channels.iter().enumerate() {
+            if let Err(e) = validate_channel(channel) {
+                problems.push(format!("notify.channels #{}: {:#}", i + 1, e));
+            }
+        }
+    }
+
+    if let Some(paypal) = check_section::<PayPalConfig>(&value, "paypal", &mut problems) {
+        if paypal.client_id.is_some() != paypal.client_secret.is_some() {
+            problems.push("paypal: client_id and client_secret must be set together".to_string());
+        }
+
+        if let Some(ref currency) = paypal.currency {
+            if rusty_money::iso::find(currency).is_none() {
+                problems.push(format!(
+                    "paypal.currency '{}' is not a valid currency",
+                    currency
+                ));
+            }
+        }
+    }
+
+    check_section::<YnabConfig>(&value, "ynab", &mut problems);
+    check_section::<FireflyConfig>(&value, "firefly", &mut problems);
+
+    // Accounts for `sync-all` don't need the single account values set.
+    if !accounts.is_empty() {
+        required.clear();
+    }
+
+    for (name, is_set) in required {
+        if !is_set {
+            problems.push(format!(
+                "{} is not set, it must be passed as a flag instead",
+                name
+            ));
+        }
+    }
+
+    if let Some(LunchMoneyConfig {
+        api_token: Some(ref api_token),
+        asset_id,
+        ref tags,
+        create_tags,
+        ..
+    }) = lunch_money
+    {
+        // Missing tags are created on the first sync with `create_tags`.
+        if !tags.is_empty() && !create_tags {
+            match get_all_tags(client, api_token).await {
+                Ok(existing) => {
+                    for tag in tags {
+                        if !existing
+                            .iter()
+                            .any(|existing| existing.name.eq_ignore_ascii_case(tag))
+                        {
+                            problems.push(format!(
+                                "lunch_money.tags: Lunch Money tag '{}' does not exist, set lunch_money.create_tags to create it",
+                                tag
+                            ));
+                        }
+                    }
+                }
+                Err(e) => problems.push(format!(
+                    "lunch_money.api_token could not be used to list tags: {:#}",
+                    anyhow::Error::from(e)
+                )),
+            }
+        }
+
+        if !rules.is_empty() {
+            match get_all_categories(client, api_token).await {
+                Ok(categories) => {
+                    for (i, rule) in rules.iter().enumerate() {
+                        if !categories
+                            .iter()
+                            .any(|category| category.name.eq_ignore_ascii_case(&rule.category))
+                        {
+                            problems.push(format!(
+                                "rules #{}: Lunch Money category '{}' does not exist",
+                                i + 1,
+                                rule.category
+                            ));
+                        }
+                    }
+                }
+                Err(e) => problems.push(format!(
+                    "lunch_money.api_token could not be used to list categories: {:#}",
+                    anyhow::Error::from(e)
+                )),
+            }
+        }
+
+        match get_all_assets(client, api_token).await {
+            Ok(assets) => {
+                if let Some(asset_id) = asset_id {
+                    if !assets.iter().any(|asset| asset.id == asset_id) {
+                        problems.push(format!(
+                            "lunch_money.asset_id {} does not match any Lunch Money asset",
+                            asset_id
+                        ));
+                    }
+                }
+
+                let transfer_asset_ids = sync.iter().flat_map(|sync| &sync.transfer_asset_ids);
+                for (account, asset_id) in transfer_asset_ids {
+                    if !assets.iter().any(|asset| asset.id == *asset_id) {
+                        problems.push(format!(
+                            "sync.transfer_asset_ids: asset {} for '{}' does not match any Lunch Money asset",
+                            asset_id, account
+                        ));
+                    }
+                }
+
+                let credit_card_asset_id = sync.as_ref().and_then(|sync| sync.credit_card_asset_id);
+                if let Some(asset_id) = credit_card_asset_id {
+                    if !assets.iter().any(|asset| asset.id == asset_id) {
+                        problems.push(format!(
+                            "sync.credit_card_asset_id {} does not match any Lunch Money asset",
+                            asset_id
+                        ));
+                    }
+                }
+            }
+            Err(e) => problems.push(format!(
+                "lunch_money.api_token could not be used to list assets: {:#}",
+                anyhow::Error::from(e)
+            )),
+        }
+    }
+
+    for account in &accounts {
+        let api_token = match account.lunch_money_api_token.as_ref().or_else(|| {
+            lunch_money
+                .as_ref()
+                .and_then(|lunch_money| lunch_money.api_token.as_ref())
+        }) {
+            Some(api_token) => api_token,
+            None => {
+                problems.push(format!(
+                    "accounts '{}' has no lunch_money_api_token and lunch_money.api_token is not set",
+                    account.name()
+                ));
+                continue;
+            }
+        };
+
+        match get_all_assets(client, api_token).await {
+            Ok(assets) => {
+                if !assets
+                    .iter()
+                    .any(|asset| asset.id == account.lunch_money_asset_id)
+                {
+                    problems.push(format!(
+                        "accounts '{}' lunch_money_asset_id {} does not match any Lunch Money asset",
+                        account.name(),
+                        account.lunch_money_asset_id
+                    ));
+                }
+            }
+            Err(e) => problems.push(format!(
+                "accounts '{}' Lunch Money API token could not be used to list assets: {:#}",
+                account.name(),
+                anyhow::Error::from(e)
+            )),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("Config file {:?} is valid.", path);
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("- {}", problem);
+    }
+
+    bail!(
+        "Found {} problem(s) in config file {:?}",
+        problems.len(),
+        path
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caches_lunch_money_token_command() {
+        let mut config = LunchMoneyConfig {
+            token_command: Some("echo secret-token".to_string()),
+            ..Default::default()
+        };
+
+        config.cache_api_token().unwrap();
+        config.token_command = Some("exit 1".to_string());
+        assert_eq!(
+            config.api_token_or(None).unwrap().as_deref(),
+            Some("secret-token")
+        );
+    }
+}
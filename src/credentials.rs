@@ -0,0 +1,278 @@
+//! Storing API tokens in the OS keyring, and reading them from files and commands.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use dialoguer::Password;
+use keyring::Entry;
+
+use crate::config::Config;
+
+/// Service name API tokens are stored under in the OS keyring.
+const SERVICE: &str = "lunchmoney-venmo-syncer";
+
+const VENMO_API_TOKEN: &str = "venmo-api-token";
+const LUNCH_MONEY_API_TOKEN: &str = "lunch-money-api-token";
+
+fn entry(name: &str) -> Result<Entry> {
+    Entry::new(SERVICE, name).with_context(|| anyhow!("Failed to open keyring entry {}", name))
+}
+
+/// Reads the secret `name` from the OS keyring, or `None` if it was never stored.
+fn get(name: &str) -> Result<Option<String>> {
+    match entry(name)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).with_context(|| anyhow!("Failed to read {} from keyring", name)),
+    }
+}
+
+fn set(name: &str, secret: &str) -> Result<()> {
+    entry(name)?
+        .set_password(secret)
+        .with_context(|| anyhow!("Failed to store {} in keyring", name))
+}
+
+/// Reads an API token from the file at `path`, or stdin if `-`, e.g. a Docker or Kubernetes
+/// secret mount or `pass show venmo | ...`. Surrounding whitespace, like a trailing newline, is
+/// trimmed.
+pub fn read_token_file(path: &str) -> Result<String> {
+    let mut token = String::new();
+    // Errors are shown by clap without their causes, so they're included in the message.
+    let res = if path == "-" {
+        std::io::stdin().read_to_string(&mut token).map(|_| ())
+    } else {
+        std::fs::read_to_string(path).map(|contents| token = contents)
+    };
+    res.map_err(|e| anyhow!("Failed to read API token from {}: {}", display(path), e))?;
+
+    let token = token.trim();
+    if token.is_empty() {
+        bail!(
+            "No API token in {}",
+            if path == "-" { "stdin" } else { path }
+        );
+    }
+
+    Ok(token.to_string())
+}
+
+/// Runs `command` with the shell and returns its output as an API token, for `token_command`.
+/// Stdin and stderr are inherited, so it can prompt, e.g. to unlock a password manager.
+pub fn run_token_command(command: &str) -> Result<String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    let output = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| anyhow!("Failed to run token command `{}`", command))?;
+
+    if !output.status.success() {
+        bail!("Token command `{}` failed with {}", command, output.status);
+    }
+
+    let token = String::from_utf8(output.stdout)
+        .with_context(|| anyhow!("Token command `{}` printed invalid UTF-8", command))?;
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("Token command `{}` printed no API token", command);
+    }
+
+    Ok(token.to_string())
+}
+
+/// `path` as given to `read_token_file`, for errors.
+fn display(path: &str) -> String {
+    if path == "-" {
+        "stdin".to_string()
+    } else {
+        format!("{:?}", path)
+    }
+}
+
+/// Fills in API tokens missing from `config` with the ones stored by `store-credentials`.
+pub fn fill_config(config: &mut Config) -> Result<()> {
+    if config.venmo.api_token.is_none() {
+        config.venmo.api_token = get(VENMO_API_TOKEN)?;
+    }
+
+    if config.lunch_money.api_token.is_none() {
+        config.lunch_money.api_token = get(LUNCH_MONEY_API_TOKEN)?;
+    }
+
+    Ok(())
+}
+
+/// Prompts for API tokens and stores them in the OS keyring, both unless `venmo` or
+/// `lunch_money` is set.
+pub fn cmd_store_credentials(venmo: bool, lunch_money: bool) -> Result<()> {
+    // Store both unless told to only store one.
+    let (venmo, lunch_money) = if !venmo && !lunch_money {
+        (true, true)
+    } else {
+        (venmo, lunch_money)
+    };
+
+    for (store, name, prompt) in [
+        (venmo, VENMO_API_TOKEN, "Venmo API token"),
+        (lunch_money, LUNCH_MONEY_API_TOKEN, "Lunch Money API token"),
+    ] {
+        if !store {
+            continue;
+        }
+
+        let secret: String = Password::new()
+            .with_prompt(format!("{} (leave empty to skip)", prompt))
+            .allow_empty_password(true)
+            .interact()?;
+
+        if secret.is_empty() {
+            continue;
+        }
+
+        set(name, &secret)?;
+        println!("Stored {} in the OS keyring.", prompt);
+    }
+
+    if get(VENMO_API_TOKEN)?.is_none() && get(LUNCH_MONEY_API_TOKEN)?.is_none() {
+        bail!("No API tokens are stored in the OS keyring");
+    }
+
+    println!("Pass --use-keyring to read stored API tokens instead of passing them as flags.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    use keyring::credential::{CredentialApi, CredentialBuilderApi};
+
+    use super::*;
+    use crate::testing;
+
+    /// Secrets stored in `MemoryKeyring`, by user. Unlike `keyring::mock`, these outlive the
+    /// entry that set them.
+    static SECRETS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+    #[derive(Debug)]
+    struct MemoryKeyring;
+
+    impl CredentialBuilderApi for MemoryKeyring {
+        fn build(
+            &self,
+            _target: Option<&str>,
+            _service: &str,
+            user: &str,
+        ) -> keyring::Result<Box<keyring::Credential>> {
+            Ok(Box::new(MemoryCredential(user.to_string())))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[derive(Debug)]
+    struct MemoryCredential(String);
+
+    impl CredentialApi for MemoryCredential {
+        fn set_password(&self, password: &str) -> keyring::Result<()> {
+            SECRETS
+                .lock()
+                .unwrap()
+                .insert(self.0.clone(), password.to_string());
+            Ok(())
+        }
+
+        fn get_password(&self) -> keyring::Result<String> {
+            SECRETS
+                .lock()
+                .unwrap()
+                .get(&self.0)
+                .cloned()
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_password(&self) -> keyring::Result<()> {
+            SECRETS
+                .lock()
+                .unwrap()
+                .remove(&self.0)
+                .map(|_| ())
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn fills_config_from_keyring() {
+        keyring::set_default_credential_builder(Box::new(MemoryKeyring));
+
+        assert_eq!(get(VENMO_API_TOKEN).unwrap(), None);
+        let mut config = Config::default();
+        fill_config(&mut config).unwrap();
+        assert_eq!(config.venmo.api_token, None);
+        assert_eq!(config.lunch_money.api_token, None);
+
+        set(VENMO_API_TOKEN, "stored-venmo-token").unwrap();
+        set(LUNCH_MONEY_API_TOKEN, "stored-lunch-money-token").unwrap();
+        assert_eq!(
+            get(VENMO_API_TOKEN).unwrap().as_deref(),
+            Some("stored-venmo-token")
+        );
+
+        // Tokens that are already configured, e.g. by flags, win over stored ones.
+        config.venmo.api_token = Some("flag-venmo-token".to_string());
+        fill_config(&mut config).unwrap();
+        assert_eq!(config.venmo.api_token.as_deref(), Some("flag-venmo-token"));
+        assert_eq!(
+            config.lunch_money.api_token.as_deref(),
+            Some("stored-lunch-money-token")
+        );
+    }
+
+    #[test]
+    fn reads_token_files() {
+        let dir = testing::temp_dir();
+        let path = dir.path().join("token");
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, "secret-token\n").unwrap();
+        assert_eq!(read_token_file(path).unwrap(), "secret-token");
+
+        std::fs::write(path, " \n").unwrap();
+        assert!(read_token_file(path).is_err());
+
+        std::fs::remove_file(path).unwrap();
+        assert!(read_token_file(path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_token_commands() {
+        assert_eq!(
+            run_token_command("echo ' secret-token'").unwrap(),
+            "secret-token"
+        );
+        assert!(run_token_command("true").is_err());
+        assert!(run_token_command("echo secret-token; exit 1").is_err());
+    }
+}
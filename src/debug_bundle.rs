@@ -0,0 +1,263 @@
+//! The `debug-bundle` subcommand, collecting anonymized diagnostics for bug reports.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::seq::SliceRandom;
+
+use crate::config::{config_file, Config};
+use crate::logging::read_last_run_log;
+use crate::paths::Paths;
+use crate::redact;
+use crate::state::{state_file, State};
+
+/// Statement columns holding names or free text, replaced with pseudonyms.
+const PERSONAL_COLUMNS: &[&str] = &[
+    "Note",
+    "From",
+    "To",
+    "Funding Source",
+    "Destination",
+    "Terminal Location",
+];
+
+/// Statement values that affect how transactions are synced, so they are kept as-is.
+const KEPT_VALUES: &[&str] = &["Venmo balance", "Venmo"];
+
+/// Statement columns holding amounts or IDs, whose digits are scrambled.
+const NUMERIC_COLUMNS: &[&str] = &[
+    "ID",
+    "Amount (total)",
+    "Amount (tip)",
+    "Amount (tax)",
+    "Amount (fee)",
+    "Beginning Balance",
+    "Ending Balance",
+    "Statement Period Venmo Fees",
+    "Year to Date Venmo Fees",
+];
+
+/// Scrambles a Venmo statement so it can be shared: names and notes are replaced with stable
+/// pseudonyms and the digits of amounts and IDs are substituted, while keeping the structure
+/// (preamble, columns, quoting, amount formats) that parsing bugs usually depend on.
+fn scramble_statement(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut digits: Vec<char> = ('0'..='9').collect();
+    digits.shuffle(&mut rand::thread_rng());
+
+    let mut pseudonyms: HashMap<String, String> = HashMap::new();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(bytes);
+    let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
+
+    let mut headers: Option<csv::StringRecord> = None;
+
+    for record in rdr.records() {
+        let record = record.context("Failed to read statement")?;
+
+        let header_row = match headers {
+            Some(ref header_row) => header_row,
+            None => {
+                if record.iter().any(|field| field == "ID") {
+                    headers = Some(record.clone());
+                    wtr.write_record(&record)?;
+                } else {
+                    // Preamble lines include the account's username.
+                    wtr.write_record(record.iter().map(|field| {
+                        if field.is_empty() {
+                            ""
+                        } else {
+                            "REDACTED"
+                        }
+                    }))?;
+                }
+
+                continue;
+            }
+        };
+
+        let scrambled: Vec<String> = header_row
+            .iter()
+            .chain(std::iter::repeat(""))
+            .zip(record.iter())
+            .map(|(header, field)| {
+                if field.is_empty() || KEPT_VALUES.contains(&field) {
+                    field.to_string()
+                } else if PERSONAL_COLUMNS.contains(&header) {
+                    // The same person shows up in both `From` and `To`.
+                    let kind = if header == "From" || header == "To" {
+                        "Person"
+                    } else {
+                        header
+                    };
+                    let next_id = pseudonyms.len() + 1;
+
+                    pseudonyms
+                        .entry(format!("{}: {}", kind, field))
+                        .or_insert_with(|| format!("{} {}", kind, next_id))
+                        .clone()
+                } else if NUMERIC_COLUMNS.contains(&header) {
+                    field
+                        .chars()
+                        .map(|c| {
+                            c.to_digit(10)
+                                .map(|digit| digits[digit as usize])
+                                .unwrap_or(c)
+                        })
+                        .collect()
+                } else {
+                    field.to_string()
+                }
+            })
+            .collect();
+
+        wtr.write_record(&scrambled)?;
+    }
+
+    Ok(wtr.into_inner()?)
+}
+
+fn append_file(
+    builder: &mut tar::Builder<GzEncoder<File>>,
+    name: &str,
+    contents: &[u8],
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+
+    builder
+        .append_data(&mut header, name, contents)
+        .with_context(|| anyhow!("Failed to add {} to debug bundle", name))
+}
+
+/// Writes a `.tar.gz` with version info, the masked config, a state summary, the last run's log
+/// with HTTP traces, and `statement` scrambled, to `output` or a timestamped file in the working directory.
+pub fn cmd_debug_bundle(
+    paths: &Paths,
+    statement: Option<&Path>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let output = output.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "lunchmoney-venmo-debug-{}.tar.gz",
+            Utc::now().format("%Y%m%dT%H%M%S")
+        ))
+    });
+
+    let file = File::create(&output)
+        .with_context(|| anyhow!("Failed to create debug bundle {:?}", output))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    append_file(
+        &mut builder,
+        "version.txt",
+        format!(
+            "{} {}\n{} {}\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            env::consts::OS,
+            env::consts::ARCH
+        )
+        .as_bytes(),
+    )?;
+
+    let config = match Config::load(&config_file(paths)) {
+        Ok(config) => toml::to_string_pretty(&config.masked())?,
+        Err(e) => format!("# Failed to load config: {:#}\n", e),
+    };
+    append_file(&mut builder, "config.toml", config.as_bytes())?;
+
+    // Only include a summary of the state, it holds account and transaction IDs.
+    let state = match State::load(&state_file(paths)) {
+        Ok(state) => format!(
+            "version: {}\nruns: {}\nwatermarks: {}\nlast synced at: {}\n",
+            state.version,
+            state.runs.len(),
+            state.watermarks.len(),
+            state
+                .runs
+                .last()
+                .map(|run| run.synced_at.to_rfc3339())
+                .unwrap_or_else(|| "never".to_string())
+        ),
+        Err(e) => format!("Failed to load state: {:#}\n", e),
+    };
+    append_file(&mut builder, "state.txt", state.as_bytes())?;
+
+    // It's redacted as it's written, but the patterns may have changed since.
+    if let Some(log) = read_last_run_log(paths) {
+        append_file(&mut builder, "last-run.log", redact::text(&log).as_bytes())?;
+    }
+
+    if let Some(statement) = statement {
+        let bytes = fs::read(statement)
+            .with_context(|| anyhow!("Failed to read statement {:?}", statement))?;
+        append_file(&mut builder, "statement.csv", &scramble_statement(&bytes)?)?;
+    }
+
+    builder.into_inner()?.finish()?;
+
+    println!(
+        "Wrote debug bundle to {:?}. Review its contents before attaching it to a bug report.",
+        output
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::venmo::StatementFormat;
+    use crate::venmo::parse_venmo_statement;
+
+    const STATEMENT: &[u8] = include_bytes!("../tests/fixtures/statement_basic.csv");
+
+    #[test]
+    fn scrambled_statements_still_parse() {
+        let scrambled = scramble_statement(STATEMENT).unwrap();
+
+        let original = parse_venmo_statement(STATEMENT, &StatementFormat::default()).unwrap();
+        let statement = parse_venmo_statement(&scrambled, &StatementFormat::default()).unwrap();
+        assert_eq!(statement.transactions.len(), original.transactions.len());
+        for (scrambled, original) in statement.transactions.iter().zip(&original.transactions) {
+            assert_eq!(scrambled.type_, original.type_);
+            assert_eq!(scrambled.status, original.status);
+            assert_eq!(scrambled.datetime, original.datetime);
+        }
+    }
+
+    #[test]
+    fn scrambles_personal_details() {
+        let scrambled = String::from_utf8(scramble_statement(STATEMENT).unwrap()).unwrap();
+
+        for personal in [
+            "Jane-Doe",
+            "John Smith",
+            "Jane Doe",
+            "Chase Checking",
+            "Dinner",
+        ] {
+            assert!(!scrambled.contains(personal), "{} was kept", personal);
+        }
+        assert!(scrambled.starts_with("REDACTED,"));
+        assert!(scrambled.contains("Venmo balance"));
+        assert!(scrambled.contains("Standard Transfer"));
+        // The same person is given the same pseudonym in `From` and `To`.
+        assert!(scrambled.contains(",Person 2,Person 3,"));
+        assert!(scrambled.contains(",Person 3,Person 5,"));
+    }
+}
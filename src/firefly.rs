@@ -0,0 +1,547 @@
+//! Client for the [Firefly III API](https://api-docs.firefly-iii.org), to sync transactions to a
+//! self-hosted Firefly III asset account instead of Lunch Money.
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use hyper::{body, Method, Request, StatusCode};
+use rusty_money::iso::Currency;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::{Config, PayeeConfig};
+use crate::http::{request_following_redirects, HttpTransport};
+use crate::payees::Payees;
+use crate::redact;
+use crate::sink::TransactionSink;
+use crate::sync::{balance_drift, SyncSummary};
+use crate::types::lunchmoney;
+use crate::types::venmo::{
+    ConvertOptions, Statement, TransactionFilter, TransactionStatus, Transfers,
+};
+use crate::types::HttpsClient;
+
+/// The Firefly III asset account to insert transactions into.
+pub struct FireflyDestination {
+    /// URL of the Firefly III instance, e.g. `https://firefly.example.com`.
+    pub base_url: String,
+    /// Personal access token, from Options > Profile > OAuth.
+    pub api_token: String,
+    /// ID of the asset account for the Venmo balance.
+    pub account_id: u64,
+    /// Firefly III asset account IDs of funding sources and destinations by their name in Venmo
+    /// statements. Money moved to or from them is inserted as transfers, money moved to or from
+    /// other banks as withdrawals and deposits.
+    pub transfer_account_ids: BTreeMap<String, u64>,
+    pub payees: PayeeConfig,
+    /// Venmo transactions with these statuses aren't synced.
+    pub skip_statuses: Vec<TransactionStatus>,
+    /// Venmo transactions of types it doesn't match aren't synced.
+    pub filter: TransactionFilter,
+    /// Only `transfers.shadow_transactions` applies, transfers go by `transfer_account_ids`, and
+    /// credit card payments aren't split out.
+    pub convert: ConvertOptions,
+}
+
+impl FireflyDestination {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let base_url = config
+            .firefly
+            .base_url
+            .clone()
+            .ok_or_else(|| anyhow!("--firefly-url or firefly.base_url must be set"))?;
+        let api_token = config
+            .firefly
+            .api_token
+            .clone()
+            .ok_or_else(|| anyhow!("--firefly-api-token or firefly.api_token must be set"))?;
+        let account_id = config
+            .firefly
+            .account_id
+            .ok_or_else(|| anyhow!("--firefly-account-id or firefly.account_id must be set"))?;
+
+        Ok(FireflyDestination {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_token,
+            account_id,
+            transfer_account_ids: config.firefly.transfer_account_ids.clone(),
+            payees: config.payees.clone(),
+            skip_statuses: config.sync.skip_statuses()?,
+            filter: config.sync.filter()?,
+            convert: {
+                let options = config.convert_options()?;
+                ConvertOptions {
+                    transfers: Transfers {
+                        asset_ids: BTreeMap::new(),
+                        ..options.transfers
+                    },
+                    credit_card_asset_id: None,
+                    ..options
+                }
+            },
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> hyper::http::request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(format!("{}/api/v1{}", self.base_url, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.api_token))
+            .header(ACCEPT, "application/vnd.api+json")
+    }
+}
+
+/// One split of a transaction to store, see
+/// <https://api-docs.firefly-iii.org/#/transactions/storeTransaction>.
+#[derive(Debug, Serialize)]
+struct TransactionSplit {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    date: NaiveDate,
+    /// Always positive, the direction is given by `type_` and the accounts.
+    amount: String,
+    currency_code: &'static str,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination_name: Option<String>,
+    notes: Option<String>,
+    external_id: Option<String>,
+}
+
+impl TransactionSplit {
+    /// `counterparty` is the payee, or the name of the bank for transfers, and `transfer_account`
+    /// the bank's asset account, if it's in Firefly III.
+    fn new(
+        transaction: &lunchmoney::Transaction,
+        currency: &Currency,
+        account_id: u64,
+        counterparty: &str,
+        transfer_account: Option<u64>,
+    ) -> Self {
+        let outflow = transaction.amount.0.is_sign_negative();
+        let venmo = Some(account_id.to_string());
+        let (other_id, other_name) = match transfer_account {
+            Some(id) => (Some(id.to_string()), None),
+            None => (None, Some(counterparty.to_string())),
+        };
+        let (source_id, source_name, destination_id, destination_name) = if outflow {
+            (venmo, None, other_id, other_name)
+        } else {
+            (other_id, other_name, venmo, None)
+        };
+
+        TransactionSplit {
+            type_: match (transfer_account, outflow) {
+                (Some(_), _) => "transfer",
+                (None, true) => "withdrawal",
+                (None, false) => "deposit",
+            },
+            date: transaction.date,
+            amount: format!("{:.2}", transaction.amount.0.abs()),
+            currency_code: currency.iso_alpha_code,
+            description: transaction
+                .payee
+                .clone()
+                .unwrap_or_else(|| counterparty.to_string()),
+            source_id,
+            source_name,
+            destination_id,
+            destination_name,
+            notes: transaction.notes.clone(),
+            external_id: transaction.external_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionsPage {
+    data: Vec<TransactionGroup>,
+    meta: Meta,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionGroup {
+    attributes: TransactionGroupAttributes,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionGroupAttributes {
+    transactions: Vec<StoredSplit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredSplit {
+    external_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    pagination: Pagination,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    current_page: u32,
+    total_pages: u32,
+}
+
+/// Lists the `external_id`s of the transactions in the asset account from `start_date` to
+/// `end_date`.
+async fn get_external_ids(
+    client: &impl HttpTransport,
+    destination: &FireflyDestination,
+    (start_date, end_date): (NaiveDate, NaiveDate),
+) -> Result<HashSet<String>> {
+    let mut external_ids = HashSet::new();
+    let mut page = 1;
+
+    loop {
+        let request = destination
+            .request(
+                Method::GET,
+                &format!(
+                    "/accounts/{}/transactions?start={}&end={}&page={}",
+                    destination.account_id,
+                    start_date.format("%Y-%m-%d"),
+                    end_date.format("%Y-%m-%d"),
+                    page
+                ),
+            )
+            .body(body::Body::empty())
+            .unwrap();
+
+        let response = request_following_redirects(client, request).await?;
+        let status = response.status();
+        let bytes = body::to_bytes(response).await?;
+        if !status.is_success() {
+            bail!(
+                "Failed to get Firefly III transactions, code {}, err:\n{}",
+                status,
+                redact::bytes(&bytes)
+            );
+        }
+
+        let response: TransactionsPage = serde_json::from_slice(&bytes)
+            .with_context(|| anyhow!("Failed to parse Firefly III transactions"))?;
+
+        external_ids.extend(
+            response
+                .data
+                .into_iter()
+                .flat_map(|group| group.attributes.transactions)
+                .filter_map(|split| split.external_id),
+        );
+
+        if response.meta.pagination.current_page >= response.meta.pagination.total_pages {
+            return Ok(external_ids);
+        }
+        page += 1;
+    }
+}
+
+/// Stores `split` as a new transaction. Returns whether it was stored, `false` if Firefly III
+/// rejected it as a duplicate of an existing one.
+async fn store_transaction(
+    client: &impl HttpTransport,
+    destination: &FireflyDestination,
+    split: &TransactionSplit,
+) -> Result<bool> {
+    let request = destination
+        .request(Method::POST, "/transactions")
+        .header(CONTENT_TYPE, "application/json")
+        .body(body::Body::from(serde_json::to_vec(&json!({
+            "error_if_duplicate_hash": true,
+            "apply_rules": true,
+            "transactions": [split],
+        }))?))
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+
+    if status == StatusCode::UNPROCESSABLE_ENTITY
+        && String::from_utf8_lossy(&bytes).contains("Duplicate of transaction")
+    {
+        return Ok(false);
+    }
+    if !status.is_success() {
+        bail!(
+            "Failed to store Firefly III transaction {}, code {}, err:\n{}",
+            split.external_id.as_deref().unwrap_or_default(),
+            status,
+            redact::bytes(&bytes)
+        );
+    }
+
+    Ok(true)
+}
+
+/// A converted transaction, with the bank it moves money to or from if it's a transfer.
+struct Converted {
+    transaction: lunchmoney::Transaction,
+    bank: Option<String>,
+}
+
+/// Converts `statement` into Lunch Money transactions in asset 0, which stands for the Firefly
+/// III asset account, as configured by `destination`. Returns them, the number of Venmo
+/// transactions skipped because of their status, the number left out because of their type, and
+/// the number of generated transfers.
+fn convert_statement(
+    destination: &FireflyDestination,
+    currency: &Currency,
+    statement: &Statement,
+) -> Result<(Vec<Converted>, usize, usize, usize)> {
+    let payees = Payees::new(&destination.payees)?;
+    let mut converted = Vec::new();
+    let mut skipped = 0;
+    let mut filtered = 0;
+    let mut transfers = 0;
+
+    for transaction in &statement.transactions {
+        if destination.skip_statuses.contains(&transaction.status) {
+            skipped += 1;
+            continue;
+        }
+
+        if !destination.filter.matches(transaction) {
+            filtered += 1;
+            continue;
+        }
+
+        for mut generated in
+            transaction.to_lunchmoney_transactions(*currency, 0, &destination.convert)?
+        {
+            if transaction.is_transfer(&generated) {
+                transfers += 1;
+            }
+            let bank = transaction.bank_account(&generated).map(str::to_string);
+            if bank.is_none() {
+                generated.payee = generated
+                    .payee
+                    .as_deref()
+                    .map(|payee| payees.rewrite(payee));
+            }
+
+            converted.push(Converted {
+                transaction: generated,
+                bank,
+            });
+        }
+    }
+
+    Ok((converted, skipped, filtered, transfers))
+}
+
+/// Inserts `statement` into the Firefly III asset account, skipping transactions whose
+/// `external_id` is already there. Category rules and tags are Lunch Money only, Firefly III's
+/// own rules are applied instead.
+async fn insert_statement(
+    client: &impl HttpTransport,
+    destination: &FireflyDestination,
+    currency: &Currency,
+    statement: Statement,
+    dry_run: bool,
+) -> Result<SyncSummary> {
+    let (transactions, skipped_by_status, filtered, transfers) =
+        convert_statement(destination, currency, &statement)?;
+    let venmo_transactions = statement.transactions.len();
+    let generated = transactions.len();
+
+    let dates = transactions
+        .iter()
+        .map(|converted| converted.transaction.date);
+    let existing = match (dates.clone().min(), dates.max()) {
+        (Some(start_date), Some(end_date)) => {
+            get_external_ids(client, destination, (start_date, end_date)).await?
+        }
+        _ => HashSet::new(),
+    };
+    let (synced, new): (Vec<_>, Vec<_>) = transactions.into_iter().partition(|converted| {
+        matches!(&converted.transaction.external_id, Some(external_id) if existing.contains(external_id))
+    });
+
+    let mut summary = SyncSummary {
+        beginning_balance: statement.beginning_balance.val,
+        ending_balance: statement.ending_balance.val,
+        venmo_transactions,
+        skipped_by_status,
+        filtered,
+        lunch_money_transactions: generated,
+        transfers,
+        new: new.len(),
+        already_synced: synced.len(),
+        changed: Vec::new(),
+        inserted: None,
+        inserted_ids: None,
+        grouped: 0,
+        would_insert: None,
+        net_amount: synced
+            .iter()
+            .chain(&new)
+            .map(|converted| converted.transaction.amount.0)
+            .sum(),
+        balance_drift: balance_drift(&statement, &destination.skip_statuses),
+        currency: *currency,
+    };
+
+    if dry_run {
+        summary.would_insert = Some(
+            new.into_iter()
+                .map(|converted| converted.transaction)
+                .collect(),
+        );
+        return Ok(summary);
+    }
+
+    let mut inserted = 0;
+    for Converted { transaction, bank } in &new {
+        let split = TransactionSplit::new(
+            transaction,
+            currency,
+            destination.account_id,
+            bank.as_deref()
+                .or(transaction.payee.as_deref())
+                .unwrap_or_default(),
+            bank.as_ref()
+                .and_then(|bank| destination.transfer_account_ids.get(bank))
+                .copied(),
+        );
+
+        if store_transaction(client, destination, &split).await? {
+            inserted += 1;
+        } else {
+            // Inserted since they were listed, e.g. by a concurrent sync.
+            summary.new -= 1;
+            summary.already_synced += 1;
+        }
+    }
+    summary.inserted = Some(inserted);
+
+    Ok(summary)
+}
+
+#[async_trait]
+impl TransactionSink for FireflyDestination {
+    fn name(&self) -> String {
+        format!("Firefly III account {}", self.account_id)
+    }
+
+    async fn insert_statement(
+        &self,
+        client: &HttpsClient,
+        currency: &Currency,
+        statement: Statement,
+        dry_run: bool,
+    ) -> Result<SyncSummary> {
+        insert_statement(client, self, currency, statement, dry_run).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::http::fake::FakeTransport;
+    use crate::testing::{amount, transaction};
+    use crate::types::venmo::TransactionType;
+
+    fn destination() -> FireflyDestination {
+        FireflyDestination {
+            base_url: "https://firefly.example.com".to_string(),
+            api_token: "token".to_string(),
+            account_id: 7,
+            transfer_account_ids: BTreeMap::from([("Chase Checking *1234".to_string(), 3)]),
+            payees: PayeeConfig::default(),
+            skip_statuses: TransactionStatus::DEFAULT_SKIPPED.to_vec(),
+            filter: TransactionFilter::default(),
+            convert: ConvertOptions::default(),
+        }
+    }
+
+    fn statement() -> Statement {
+        let mut payment = transaction(1, TransactionType::Payment, Decimal::new(-2050, 2));
+        payment.funding_source = Some("Chase Checking *1234".to_string());
+        let mut received = transaction(2, TransactionType::Payment, Decimal::new(15, 0));
+        received.from = Some("Sam Lee".to_string());
+
+        Statement {
+            beginning_balance: amount(Decimal::new(100, 0)),
+            ending_balance: amount(Decimal::new(115, 0)),
+            transactions: vec![payment, received],
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_new_transactions() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"data": [{"attributes": {"transactions": [{"external_id": "2"}]}}], "meta": {"pagination": {"current_page": 1, "total_pages": 1}}}"#,
+            ),
+            (StatusCode::OK, br#"{"data": {"id": "10"}}"#),
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                br#"{"message": "Duplicate of transaction #9."}"#,
+            ),
+        ]);
+
+        let summary = insert_statement(
+            &transport,
+            &destination(),
+            rusty_money::iso::USD,
+            statement(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.new, 1);
+        assert_eq!(summary.already_synced, 2);
+        assert_eq!(summary.inserted, Some(1));
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(
+            requests[0].uri,
+            "https://firefly.example.com/api/v1/accounts/7/transactions?start=2022-07-01&end=2022-07-01&page=1"
+        );
+        assert_eq!(requests[1].headers[AUTHORIZATION], "Bearer token");
+
+        let body = |i: usize| {
+            serde_json::from_slice::<serde_json::Value>(&requests[i].body).unwrap()["transactions"]
+                [0]
+            .clone()
+        };
+        assert_eq!(
+            body(1),
+            json!({
+                "type": "withdrawal",
+                "date": "2022-07-01",
+                "amount": "20.50",
+                "currency_code": "USD",
+                "description": "John Smith",
+                "source_id": "7",
+                "destination_name": "John Smith",
+                "notes": "Dinner",
+                "external_id": "1",
+            })
+        );
+        // The shadow transfer funding the payment, from the bank's asset account.
+        assert_eq!(body(2)["type"], "transfer");
+        assert_eq!(body(2)["source_id"], "3");
+        assert_eq!(body(2)["destination_id"], "7");
+    }
+}
@@ -0,0 +1,505 @@
+//! The HTTP transport API requests are made over, so they can be faked in tests, and following
+//! redirects on top of it.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::header::{
+    HeaderName, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, CONTENT_TYPE, COOKIE, LOCATION, RETRY_AFTER,
+    SET_COOKIE, USER_AGENT,
+};
+use hyper::{body, Body, HeaderMap, Method, Request, Response, StatusCode, Uri};
+use tokio::time::Instant;
+use tracing::{debug, trace};
+
+use crate::metrics;
+use crate::redact;
+use crate::types::HttpsClient;
+
+/// The cause of a typed API error, e.g. a failed request or a response that failed to parse.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Max number of redirects `request_following_redirects` follows.
+const MAX_REDIRECTS: usize = 5;
+
+/// Headers kept when redirected to another host. Everything else, e.g. cookies, `Authorization`,
+/// and Venmo's device and OTP headers, may be a credential for the original host only.
+const CROSS_HOST_HEADERS: [HeaderName; 4] = [ACCEPT, ACCEPT_LANGUAGE, CONTENT_TYPE, USER_AGENT];
+
+/// Sends HTTP requests. Implemented by `HttpsClient` for real requests, and by `FakeTransport` in
+/// tests to replay recorded responses.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    async fn request(&self, request: Request<Body>) -> Result<Response<Body>>;
+}
+
+#[async_trait]
+impl HttpTransport for HttpsClient {
+    async fn request(&self, request: Request<Body>) -> Result<Response<Body>> {
+        Ok(hyper::Client::request(self, request).await?)
+    }
+}
+
+/// Sends `request` with `client`, following up to `MAX_REDIRECTS` redirects. Cookies are kept
+/// across redirects on the same host, including ones set by the redirect responses, while all
+/// but `CROSS_HOST_HEADERS` are dropped when redirected to another host. Redirects from HTTPS to
+/// plain HTTP are refused. Like browsers, 303s and 301/302s of non-`GET` requests are followed
+/// with a `GET` without a body.
+pub async fn request_following_redirects(
+    client: &impl HttpTransport,
+    request: Request<Body>,
+) -> Result<Response<Body>> {
+    let (parts, request_body) = request.into_parts();
+    let mut method = parts.method;
+    let mut uri = parts.uri;
+    let mut headers = parts.headers;
+    let mut request_body = body::to_bytes(request_body).await?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let mut request = Request::builder()
+            .method(method.clone())
+            .uri(uri.clone())
+            .body(Body::from(request_body.clone()))?;
+        *request.headers_mut() = headers.clone();
+
+        trace!(method = %method, uri = %uri, headers = %redact::headers(&headers), "HTTP request headers");
+        let started = Instant::now();
+        let response = client.request(request).await;
+        if let Ok(response) = &response {
+            trace!(headers = %redact::headers(response.headers()), "HTTP response headers");
+        }
+        debug!(
+            method = %method,
+            uri = %uri,
+            status = response.as_ref().map(|response| response.status().as_u16()).ok(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "HTTP request"
+        );
+        metrics::record_request(
+            uri.host().unwrap_or_default(),
+            started.elapsed(),
+            response
+                .as_ref()
+                .map(|response| {
+                    response.status().is_client_error() || response.status().is_server_error()
+                })
+                .unwrap_or(true),
+        );
+
+        let response = response?;
+        let status = response.status();
+
+        if !status.is_redirection() || status == StatusCode::NOT_MODIFIED {
+            return Ok(response);
+        }
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| anyhow!("Got redirect without a location from {}", uri))?;
+        let next_uri = resolve_location(&uri, location)?;
+
+        if uri.scheme_str() == Some("https") && next_uri.scheme_str() != Some("https") {
+            bail!("Refusing redirect from {} to insecure {}", uri, next_uri);
+        }
+
+        if next_uri.authority() == uri.authority() {
+            add_set_cookies(&mut headers, response.headers());
+        } else {
+            headers = CROSS_HOST_HEADERS
+                .iter()
+                .filter_map(|name| Some((name.clone(), headers.get(name)?.clone())))
+                .collect();
+        }
+
+        if status == StatusCode::SEE_OTHER
+            || (method != Method::GET
+                && (status == StatusCode::MOVED_PERMANENTLY || status == StatusCode::FOUND))
+        {
+            method = Method::GET;
+            request_body = body::Bytes::new();
+        }
+
+        uri = next_uri;
+    }
+
+    bail!("Too many redirects while requesting {}", uri);
+}
+
+/// Resolves redirect `location` against the URI that was redirected from.
+fn resolve_location(from: &Uri, location: &str) -> Result<Uri> {
+    if location.contains("://") {
+        return location
+            .parse()
+            .map_err(|e| anyhow!("Got redirect to invalid location {}: {}", location, e));
+    }
+
+    let path_and_query = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        // Relative to the directory of the path redirected from.
+        let base = &from.path()[..=from.path().rfind('/').unwrap_or(0)];
+        format!("{}{}", base, location)
+    };
+
+    Ok(Uri::builder()
+        .scheme(from.scheme_str().unwrap_or("https"))
+        .authority(from.authority().map(|a| a.as_str()).unwrap_or_default())
+        .path_and_query(path_and_query)
+        .build()?)
+}
+
+/// Adds the cookies set in `response_headers` to the `Cookie` header of `headers`, replacing any
+/// with the same name.
+fn add_set_cookies(headers: &mut HeaderMap, response_headers: &HeaderMap) {
+    let mut cookies: Vec<(String, String)> = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+    let set_cookies = response_headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        // Attributes like `Path` come after the first `;`.
+        .filter_map(|value| value.split(';').next()?.trim().split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+
+    if set_cookies.is_empty() {
+        return;
+    }
+
+    for (name, value) in set_cookies {
+        cookies.retain(|(existing, _)| *existing != name);
+        cookies.push((name, value));
+    }
+
+    let cookie = cookies
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        headers.insert(COOKIE, cookie);
+    }
+}
+
+/// Limits requests to a sustained rate of `per_second`, allowing bursts of up to `burst`
+/// requests, as a token bucket.
+pub struct RateLimiter {
+    burst: f64,
+    per_second: f64,
+    /// Tokens available, and when they were last refilled.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        RateLimiter {
+            burst: burst as f64,
+            per_second,
+            bucket: Mutex::new((burst as f64, Instant::now())),
+        }
+    }
+
+    /// Waits until a request is allowed, and takes its token.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                let (tokens, refilled_at) = *bucket;
+                let tokens = (tokens
+                    + now.duration_since(refilled_at).as_secs_f64() * self.per_second)
+                    .min(self.burst);
+
+                if tokens >= 1.0 {
+                    *bucket = (tokens - 1.0, now);
+                    return;
+                }
+
+                *bucket = (tokens, now);
+                Duration::from_secs_f64((1.0 - tokens) / self.per_second)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// How long a 429 or 503 response asks to wait before retrying, from its `Retry-After` header
+/// in seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::collections::VecDeque;
+
+    use hyper::header::HeaderName;
+    use hyper::{body, Method, StatusCode};
+
+    use super::*;
+
+    /// A request sent to a `FakeTransport`.
+    #[derive(Debug)]
+    pub struct RecordedRequest {
+        pub method: Method,
+        pub uri: String,
+        pub headers: HeaderMap,
+        pub body: Vec<u8>,
+    }
+
+    /// Replays queued responses in order and records the requests it was sent.
+    #[derive(Default)]
+    pub struct FakeTransport {
+        responses: Mutex<VecDeque<(StatusCode, HeaderMap, Vec<u8>)>>,
+        pub requests: Mutex<Vec<RecordedRequest>>,
+    }
+
+    impl FakeTransport {
+        pub fn with_responses(responses: Vec<(StatusCode, &[u8])>) -> Self {
+            FakeTransport {
+                responses: Mutex::new(
+                    responses
+                        .into_iter()
+                        .map(|(status, body)| (status, HeaderMap::new(), body.to_vec()))
+                        .collect(),
+                ),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// Queues a redirect to `location`, setting cookies `set_cookies`.
+        pub fn push_redirect(&self, status: StatusCode, location: &str, set_cookies: &[&str]) {
+            let mut headers = HeaderMap::new();
+            headers.insert(LOCATION, location.parse().unwrap());
+
+            for set_cookie in set_cookies {
+                headers.append(SET_COOKIE, set_cookie.parse().unwrap());
+            }
+
+            self.responses
+                .lock()
+                .unwrap()
+                .push_back((status, headers, Vec::new()));
+        }
+
+        /// Queues a response without headers.
+        pub fn push_response(&self, status: StatusCode, body: &[u8]) {
+            self.responses
+                .lock()
+                .unwrap()
+                .push_back((status, HeaderMap::new(), body.to_vec()));
+        }
+
+        /// Queues a response with a single header `name: value`.
+        pub fn push_response_with_header(
+            &self,
+            status: StatusCode,
+            name: HeaderName,
+            value: &str,
+            body: &[u8],
+        ) {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+
+            self.responses
+                .lock()
+                .unwrap()
+                .push_back((status, headers, body.to_vec()));
+        }
+    }
+
+    #[async_trait]
+    impl HttpTransport for FakeTransport {
+        async fn request(&self, request: Request<Body>) -> Result<Response<Body>> {
+            let (parts, request_body) = request.into_parts();
+            let request_body = body::to_bytes(request_body).await?;
+
+            self.requests.lock().unwrap().push(RecordedRequest {
+                method: parts.method,
+                uri: parts.uri.to_string(),
+                headers: parts.headers,
+                body: request_body.to_vec(),
+            });
+
+            let (status, headers, response_body) = self
+                .responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("FakeTransport ran out of responses");
+
+            let mut response = Response::builder()
+                .status(status)
+                .body(Body::from(response_body))
+                .unwrap();
+            *response.headers_mut() = headers;
+
+            Ok(response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::AUTHORIZATION;
+
+    use super::fake::FakeTransport;
+    use super::*;
+
+    #[tokio::test]
+    async fn follows_redirects_keeping_cookies() {
+        let transport = FakeTransport::default();
+        transport.push_redirect(
+            StatusCode::MOVED_PERMANENTLY,
+            "https://venmo.com/statement?id=1",
+            &["session=abc; Path=/; HttpOnly"],
+        );
+        transport.push_redirect(StatusCode::FOUND, "download", &[]);
+        transport.push_response(StatusCode::OK, b"statement");
+
+        let request = Request::builder()
+            .uri("https://venmo.com/transaction-history/statement")
+            .header(COOKIE, "api_access_token=secret")
+            .header(AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = request_following_redirects(&transport, request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].uri, "https://venmo.com/statement?id=1");
+        assert_eq!(requests[2].uri, "https://venmo.com/download");
+        assert_eq!(
+            requests[2].headers[COOKIE],
+            "api_access_token=secret; session=abc"
+        );
+        assert_eq!(requests[2].headers[AUTHORIZATION], "Bearer secret");
+    }
+
+    #[tokio::test]
+    async fn drops_credentials_when_redirected_to_another_host() {
+        let transport = FakeTransport::default();
+        transport.push_redirect(
+            StatusCode::FOUND,
+            "https://cdn.example.com/statement.csv",
+            &["session=abc"],
+        );
+        transport.push_response(StatusCode::OK, b"statement");
+
+        let request = Request::builder()
+            .uri("https://venmo.com/transaction-history/statement")
+            .header(COOKIE, "api_access_token=secret")
+            .header(AUTHORIZATION, "Bearer secret")
+            .header("device-id", "device")
+            .header("venmo-otp-secret", "otp")
+            .header(USER_AGENT, "lunchmoney-venmo-syncer")
+            .body(Body::empty())
+            .unwrap();
+
+        request_following_redirects(&transport, request)
+            .await
+            .unwrap();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].uri, "https://cdn.example.com/statement.csv");
+        assert!(requests[1].headers.get(COOKIE).is_none());
+        assert_eq!(requests[1].headers.len(), 1);
+        assert_eq!(requests[1].headers[USER_AGENT], "lunchmoney-venmo-syncer");
+    }
+
+    #[tokio::test]
+    async fn refuses_redirects_to_plain_http() {
+        let transport = FakeTransport::default();
+        transport.push_redirect(StatusCode::FOUND, "http://venmo.com/statement", &[]);
+
+        let request = Request::builder()
+            .uri("https://venmo.com/transaction-history/statement")
+            .header(COOKIE, "api_access_token=secret")
+            .body(Body::empty())
+            .unwrap();
+
+        let err = request_following_redirects(&transport, request)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("insecure"));
+        assert_eq!(transport.requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_too_many_redirects() {
+        let transport = FakeTransport::default();
+
+        for _ in 0..=MAX_REDIRECTS {
+            transport.push_redirect(StatusCode::FOUND, "/again", &[]);
+        }
+
+        let request = Request::builder()
+            .uri("https://venmo.com/")
+            .body(Body::empty())
+            .unwrap();
+
+        assert!(request_following_redirects(&transport, request)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_limits_after_bursts() {
+        let limiter = RateLimiter::new(2, 50.0);
+        let started = Instant::now();
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert!(started.elapsed() < Duration::from_millis(10));
+
+        limiter.acquire().await;
+        assert!(started.elapsed() >= Duration::from_millis(15));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(30)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+}
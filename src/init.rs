@@ -0,0 +1,263 @@
+//! The `init` subcommand, a guided setup that writes a config file.
+
+use std::io::{self, IsTerminal, Write};
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use dialoguer::{Confirm, Input, Password, Select};
+use rust_decimal::Decimal;
+
+use crate::config::{config_file, Config, LunchMoneyConfig, VenmoConfig};
+use crate::http::HttpTransport;
+use crate::lunchmoney::{create_asset, get_all_assets};
+use crate::paths::{ensure_dir, Paths};
+use crate::sync::find_currency;
+use crate::types::lunchmoney::{Amount, CreateAssetRequest};
+use crate::venmo::{get_venmo_user, log_in, LoginOptions};
+
+/// Walks through getting a Venmo API token, choosing or creating the Lunch Money asset to sync
+/// to, and picking a currency, then writes them to the config file.
+pub async fn cmd_init(client: &impl HttpTransport, paths: &Paths) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        bail!("init prompts for answers, run it in a terminal");
+    }
+
+    let path = config_file(paths);
+    if path.exists()
+        && !Confirm::new()
+            .with_prompt(format!("{:?} already exists, overwrite it?", path))
+            .default(false)
+            .interact()?
+    {
+        bail!("Not overwriting {:?}", path);
+    }
+
+    println!("Step 1 of 4: Venmo API token\n");
+    let (profile_id, venmo_api_token) = venmo_api_token(client, paths).await?;
+
+    println!("\nStep 2 of 4: Lunch Money API token\n");
+    let lunch_money_api_token: String = Password::new()
+        .with_prompt("Lunch Money API token, from https://my.lunchmoney.app/developers")
+        .interact()?;
+    let assets = get_all_assets(client, &lunch_money_api_token)
+        .await
+        .context("Failed to list Lunch Money assets, check the API token")?;
+
+    println!("\nStep 3 of 4: currency\n");
+    let currency: String = Input::new()
+        .with_prompt("ISO currency code of the Venmo account")
+        .default("USD".to_string())
+        .validate_with(|code: &String| find_currency(Some(code)).map(|_| ()))
+        .interact_text()?;
+    let currency = find_currency(Some(&currency))?;
+
+    println!("\nStep 4 of 4: Lunch Money asset\n");
+    let mut items = assets
+        .iter()
+        .map(|asset| {
+            format!(
+                "{} (ID {}, {} {})",
+                asset.display_name.as_deref().unwrap_or(&asset.name),
+                asset.id,
+                asset.balance.0,
+                asset.currency.to_uppercase()
+            )
+        })
+        .collect::<Vec<_>>();
+    items.push("Create a new asset".to_string());
+
+    let default = assets
+        .iter()
+        .position(|asset| asset.name.to_lowercase().contains("venmo"))
+        .unwrap_or(assets.len());
+    let choice = Select::new()
+        .with_prompt("Asset to sync Venmo transactions to")
+        .items(&items)
+        .default(default)
+        .interact()?;
+
+    let asset_id = match assets.get(choice) {
+        Some(asset) => asset.id,
+        None => {
+            let name: String = Input::new()
+                .with_prompt("Name of the new asset")
+                .default("Venmo".to_string())
+                .interact_text()?;
+            let asset = create_asset(
+                client,
+                &lunch_money_api_token,
+                &CreateAssetRequest {
+                    type_name: "cash".to_string(),
+                    subtype_name: Some("digital wallet".to_string()),
+                    name,
+                    display_name: None,
+                    balance: Amount(Decimal::ZERO),
+                    currency: Some(currency.iso_alpha_code.to_lowercase()),
+                    institution_name: Some("Venmo".to_string()),
+                },
+            )
+            .await?;
+
+            println!("Created Lunch Money asset {}.", asset.id);
+            asset.id
+        }
+    };
+
+    let config = Config {
+        venmo: VenmoConfig {
+            profile_id: Some(profile_id),
+            api_token: Some(venmo_api_token),
+            currency: Some(currency.iso_alpha_code.to_string()),
+            ..Default::default()
+        },
+        lunch_money: LunchMoneyConfig {
+            api_token: Some(lunch_money_api_token),
+            asset_id: Some(asset_id),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    ensure_dir(&paths.config_dir)?;
+    write_private(&path, &config_toml(&config)?)?;
+
+    println!(
+        "\nWrote {:?}. Run `config check` to validate it, then `sync-venmo-transactions --dry-run` \
+         to preview the first sync.",
+        path
+    );
+
+    Ok(())
+}
+
+/// Logs in to Venmo for a new API token, or checks one the user already has. Returns the profile
+/// ID and API token.
+async fn venmo_api_token(client: &impl HttpTransport, paths: &Paths) -> Result<(u64, String)> {
+    let choice = Select::new()
+        .items(&[
+            "Log in to Venmo for a new API token",
+            "Use an API token I already have",
+        ])
+        .default(0)
+        .interact()?;
+
+    let (profile_id, api_token) = if choice == 0 {
+        let login = log_in(client, paths, LoginOptions::default()).await?;
+        (login.profile_id, login.api_token)
+    } else {
+        let api_token: String = Password::new().with_prompt("Venmo API token").interact()?;
+        let user = get_venmo_user(client, &api_token)
+            .await?
+            .ok_or_else(|| anyhow!("Venmo rejected the API token as invalid or expired"))?;
+
+        println!("The API token belongs to @{}.", user.username);
+        (user.id, api_token)
+    };
+
+    let profile_id = profile_id
+        .parse()
+        .with_context(|| anyhow!("Failed to parse Venmo profile ID {:?}", profile_id))?;
+
+    Ok((profile_id, api_token))
+}
+
+/// `config` as TOML, without the sections it leaves empty.
+fn config_toml(config: &Config) -> Result<String> {
+    let value = toml::Value::try_from(config)?;
+    Ok(toml::to_string_pretty(&without_empty_tables(value))?)
+}
+
+/// `value` without the tables, including nested ones, that are empty or only hold empty tables.
+fn without_empty_tables(value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, without_empty_tables(value)))
+                .filter(
+                    |(_, value)| !matches!(value, toml::Value::Table(table) if table.is_empty()),
+                )
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Writes `contents` to `path`, only readable by the current user on Unix since it holds API
+/// tokens. It's written to a new private file first and renamed into place, so the tokens are
+/// never readable by others, even briefly, and an existing config is replaced whole.
+fn write_private(path: &std::path::Path, contents: &str) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("{:?} has no directory", path))?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| anyhow!("Failed to create a temporary file in {:?}", dir))?;
+
+    file.write_all(contents.as_bytes())
+        .and_then(|()| file.as_file().sync_all())
+        .with_context(|| anyhow!("Failed to write {:?}", path))?;
+    file.persist(path)
+        .with_context(|| anyhow!("Failed to write {:?}", path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn writes_only_set_sections() {
+        let config = Config {
+            venmo: VenmoConfig {
+                profile_id: Some(1234),
+                api_token: Some("venmo-token".to_string()),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            },
+            lunch_money: LunchMoneyConfig {
+                api_token: Some("lunch-money-token".to_string()),
+                asset_id: Some(5678),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let toml = config_toml(&config).unwrap();
+
+        assert!(toml.contains("[venmo]"));
+        assert!(toml.contains("asset_id = 5678"));
+        assert!(!toml.contains("[sync]"));
+        assert!(!toml.contains("statement_format"));
+
+        let parsed: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.venmo.api_token.as_deref(), Some("venmo-token"));
+        assert_eq!(parsed.lunch_money.asset_id, Some(5678));
+    }
+
+    #[test]
+    fn writes_config_privately() {
+        let dir = testing::temp_dir();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "old").unwrap();
+
+        write_private(&path, "[venmo]\napi_token = \"secret\"\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[venmo]\napi_token = \"secret\"\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Converting statements to double-entry Beancount and Ledger journals, for plain text
+//! accounting.
+
+use std::fmt::Write;
+
+use anyhow::Result;
+use itertools::Itertools;
+use rust_decimal::Decimal;
+use rusty_money::iso::Currency;
+
+use crate::types::lunchmoney;
+use crate::types::venmo::{ConvertOptions, Statement, TransactionStatus};
+
+/// Which plain text accounting format to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalFormat {
+    Beancount,
+    Ledger,
+}
+
+/// Accounts the postings of exported transactions go to.
+#[derive(Debug, Clone)]
+pub struct JournalAccounts {
+    /// The Venmo balance.
+    pub venmo: String,
+    /// The other side of money sent from Venmo.
+    pub expenses: String,
+    /// The other side of money received in Venmo.
+    pub income: String,
+    /// Parent of the accounts for banks funding payments or receiving transfers, e.g.
+    /// `Assets:Banks` for `Assets:Banks:Chase-Checking-1234`.
+    pub banks: String,
+}
+
+impl Default for JournalAccounts {
+    fn default() -> Self {
+        JournalAccounts {
+            venmo: "Assets:Venmo".to_string(),
+            expenses: "Expenses:Venmo".to_string(),
+            income: "Income:Venmo".to_string(),
+            banks: "Assets:Banks".to_string(),
+        }
+    }
+}
+
+impl JournalAccounts {
+    /// The account for bank `name` as it appears in statements, e.g. `Chase Checking *1234`.
+    /// Account names can only contain letters, numbers, and dashes, and each component must
+    /// start with a capital letter or number.
+    fn bank(&self, name: &str) -> String {
+        let name = name
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                    .unwrap_or_default()
+            })
+            .join("-");
+
+        format!(
+            "{}:{}",
+            self.banks,
+            if name.is_empty() { "Unknown" } else { &name }
+        )
+    }
+}
+
+/// One balanced entry: money moving between the Venmo account and `other_account`.
+struct Entry {
+    transaction: lunchmoney::Transaction,
+    pending: bool,
+    other_account: String,
+}
+
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+fn amount(val: Decimal, currency: &Currency) -> String {
+    format!("{:.2} {}", val, currency.iso_alpha_code)
+}
+
+/// Writes `statement` as a Beancount or Ledger journal. Each Venmo transaction becomes an entry
+/// between `accounts.venmo` and `accounts.expenses` or `accounts.income`, and money moved to or
+/// from banks becomes entries with the banks' accounts, as shadow transfers do for Lunch Money.
+/// Failed and cancelled transactions are left out, pending ones are flagged `!`. Accounts aren't
+/// opened, Beancount users need `open` directives for them.
+pub fn statement_to_journal(
+    statement: &Statement,
+    currency: Currency,
+    accounts: &JournalAccounts,
+    format: JournalFormat,
+) -> Result<String> {
+    // Always generate the funding legs, unlike Lunch Money there's no bank feed recording them.
+    let options = ConvertOptions::default();
+    let mut entries = Vec::new();
+
+    for transaction in &statement.transactions {
+        if TransactionStatus::DEFAULT_SKIPPED.contains(&transaction.status) {
+            continue;
+        }
+
+        let pending = transaction.status == TransactionStatus::Pending;
+
+        for generated in transaction.to_lunchmoney_transactions(currency, 0, &options)? {
+            // The other sides of transfers are in other Lunch Money assets, here they're the
+            // bank postings of the entries below.
+            if generated.asset_id != Some(0) {
+                continue;
+            }
+
+            let other_account = if let Some(bank) = transaction.bank_account(&generated) {
+                accounts.bank(bank)
+            } else if generated.amount.0.is_sign_negative() {
+                accounts.expenses.clone()
+            } else {
+                accounts.income.clone()
+            };
+
+            entries.push(Entry {
+                transaction: generated,
+                pending,
+                other_account,
+            });
+        }
+    }
+
+    let mut journal = String::new();
+
+    for Entry {
+        transaction,
+        pending,
+        other_account,
+    } in entries
+    {
+        let flag = if pending { "!" } else { "*" };
+        let payee = single_line(transaction.payee.as_deref().unwrap_or_default());
+        let notes = transaction.notes.as_deref().map(single_line);
+        let external_id = transaction.external_id.unwrap_or_default();
+        let val = transaction.amount.0;
+
+        match format {
+            JournalFormat::Beancount => {
+                writeln!(
+                    journal,
+                    "{} {} {} {}",
+                    transaction.date.format("%Y-%m-%d"),
+                    flag,
+                    quoted(&payee),
+                    quoted(notes.as_deref().unwrap_or_default())
+                )?;
+                writeln!(journal, "  venmo_id: {}", quoted(&external_id))?;
+                writeln!(journal, "  {}  {}", accounts.venmo, amount(val, &currency))?;
+                writeln!(journal, "  {}  {}", other_account, amount(-val, &currency))?;
+            }
+            JournalFormat::Ledger => {
+                writeln!(
+                    journal,
+                    "{} {} {}",
+                    transaction.date.format("%Y/%m/%d"),
+                    flag,
+                    payee
+                )?;
+                if let Some(notes) = notes {
+                    writeln!(journal, "    ; {}", notes)?;
+                }
+                writeln!(journal, "    ; venmo_id: {}", external_id)?;
+                writeln!(
+                    journal,
+                    "    {}  {}",
+                    accounts.venmo,
+                    amount(val, &currency)
+                )?;
+                writeln!(
+                    journal,
+                    "    {}  {}",
+                    other_account,
+                    amount(-val, &currency)
+                )?;
+            }
+        }
+
+        writeln!(journal)?;
+    }
+
+    Ok(journal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{amount, transaction};
+    use crate::types::venmo::{Transaction, TransactionType};
+
+    fn statement() -> Statement {
+        let transaction = |id, type_, val| Transaction {
+            note: Some("Dinner \"downtown\"".to_string()),
+            ..transaction(id, type_, val)
+        };
+
+        let mut payment = transaction(1, TransactionType::Payment, Decimal::new(-2050, 2));
+        payment.funding_source = Some("Chase Checking *1234".to_string());
+        let mut withdrawal =
+            transaction(2, TransactionType::StandardTransfer, Decimal::new(-40, 0));
+        withdrawal.destination = Some("Chase Checking *1234".to_string());
+        withdrawal.note = None;
+
+        Statement {
+            beginning_balance: amount(Decimal::new(100, 0)),
+            ending_balance: amount(Decimal::new(60, 0)),
+            transactions: vec![payment, withdrawal],
+        }
+    }
+
+    #[test]
+    fn writes_beancount() {
+        let journal = statement_to_journal(
+            &statement(),
+            *rusty_money::iso::USD,
+            &JournalAccounts::default(),
+            JournalFormat::Beancount,
+        )
+        .unwrap();
+
+        assert_eq!(
+            journal,
+            "2022-07-01 * \"John Smith\" \"Dinner \\\"downtown\\\"\"\n  venmo_id: \"1\"\n  Assets:Venmo  -20.50 USD\n  Expenses:Venmo  20.50 USD\n\n\
+             2022-07-01 * \"TRANSFER FROM Chase Checking *1234\" \"To fund Venmo transaction with note: 'Dinner \\\"downtown\\\"'\"\n  venmo_id: \"1T\"\n  Assets:Venmo  20.50 USD\n  Assets:Banks:Chase-Checking-1234  -20.50 USD\n\n\
+             2022-07-01 * \"TRANSFER TO Chase Checking *1234\" \"\"\n  venmo_id: \"2\"\n  Assets:Venmo  -40.00 USD\n  Assets:Banks:Chase-Checking-1234  40.00 USD\n\n"
+        );
+    }
+
+    #[test]
+    fn writes_ledger() {
+        let journal = statement_to_journal(
+            &statement(),
+            *rusty_money::iso::USD,
+            &JournalAccounts::default(),
+            JournalFormat::Ledger,
+        )
+        .unwrap();
+
+        assert!(journal.starts_with(
+            "2022/07/01 * John Smith\n    ; Dinner \"downtown\"\n    ; venmo_id: 1\n    Assets:Venmo  -20.50 USD\n    Expenses:Venmo  20.50 USD\n\n"
+        ));
+        assert!(journal.ends_with(
+            "2022/07/01 * TRANSFER TO Chase Checking *1234\n    ; venmo_id: 2\n    Assets:Venmo  -40.00 USD\n    Assets:Banks:Chase-Checking-1234  40.00 USD\n\n"
+        ));
+    }
+}
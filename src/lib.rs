@@ -0,0 +1,102 @@
+//! Sync Venmo transactions to [Lunch Money](https://lunchmoney.app), using the unofficial Venmo
+//! API. This crate backs the `lunchmoney-venmo` CLI, but the fetching and inserting can be used
+//! on their own:
+//!
+//! ```no_run
+//! use lunchmoney_venmo::lunchmoney::{insert_transactions, InsertOptions};
+//! use lunchmoney_venmo::types::{https_client, ClientOptions};
+//! use lunchmoney_venmo::types::venmo::{AccountRecord, ConvertOptions, StatementFormat};
+//! use lunchmoney_venmo::venmo::{fetch_venmo_transactions, StatementStore};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = https_client(&ClientOptions::default())?;
+//! let account = AccountRecord {
+//!     profile_id: 1234567890,
+//!     api_token: "venmo api token".to_string(),
+//!     currency: *rusty_money::iso::find("USD").unwrap(),
+//! };
+//!
+//! let end_date = chrono::Utc::now();
+//! let start_date = end_date - chrono::Duration::days(30);
+//! let statement = fetch_venmo_transactions(
+//!     &client,
+//!     &account,
+//!     &StatementFormat::default(),
+//!     &StatementStore::default(),
+//!     &start_date,
+//!     &end_date,
+//! )
+//! .await?;
+//!
+//! let lunch_money_asset_id = 12345;
+//! let transactions = statement
+//!     .transactions
+//!     .iter()
+//!     .map(|transaction| {
+//!         transaction.to_lunchmoney_transactions(
+//!             account.currency,
+//!             lunch_money_asset_id,
+//!             &ConvertOptions::default(),
+//!         )
+//!     })
+//!     .collect::<Result<Vec<_>, _>>()?
+//!     .into_iter()
+//!     .flatten()
+//!     .collect();
+//!
+//! let ids = insert_transactions(
+//!     &client,
+//!     "lunch money api token",
+//!     transactions,
+//!     &InsertOptions::default(),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`venmo::fetch_venmo_transactions_stream`] returns the same transactions as a
+//! [`futures::Stream`] instead, fetching and parsing statements as it's polled, for long date
+//! ranges that shouldn't be held in memory at once.
+//!
+//! Requests to Venmo and Lunch Money fail with [`venmo::Error`] and [`lunchmoney::Error`], to
+//! tell e.g. a rejected API token from an outage. Statements that fail to parse carry a
+//! [`types::venmo::StatementError`] as the source.
+//!
+//! [`sync`] wraps this up with category rules, tags, and the sync state the CLI keeps between
+//! runs. Modules for CLI subcommands expose `cmd_*` functions, which print to and prompt on the
+//! terminal.
+
+pub mod browser;
+pub mod cashapp;
+pub mod config;
+pub mod credentials;
+pub mod debug_bundle;
+pub mod firefly;
+pub mod http;
+pub mod init;
+pub mod ledger;
+pub mod logging;
+pub mod lunchmoney;
+pub mod metrics;
+pub mod notify;
+pub mod ofx;
+pub mod output;
+pub mod paths;
+pub mod payees;
+pub mod paypal;
+pub mod progress;
+pub mod reconcile;
+pub mod redact;
+pub mod rules;
+pub mod self_update;
+pub mod serve;
+pub mod sink;
+pub mod state;
+pub mod sync;
+pub mod systemd;
+#[cfg(test)]
+mod testing;
+pub mod types;
+pub mod venmo;
+pub mod ynab;
@@ -0,0 +1,169 @@
+//! Diagnostic logging with `tracing`, written to stderr with secrets masked so logs from daemon
+//! and cron runs can be kept and shared.
+
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::paths::{ensure_dir, Paths};
+use crate::progress;
+use crate::redact;
+
+/// The last run's log stops growing past this many bytes, e.g. for a long running daemon.
+const MAX_LAST_RUN_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// What's written to the last run's log regardless of the log level, including HTTP traces.
+const LAST_RUN_LOG_FILTER: &str = "lunchmoney_venmo=trace";
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "text" => LogFormat::Text,
+            "json" => LogFormat::Json,
+            _ => bail!("Unknown log format '{}', expected text or json", s),
+        })
+    }
+}
+
+/// Masks secrets in each log line with `redact::text` before writing it to the inner writer. Log
+/// lines are written with a single `write` call each, so a secret is never split across calls.
+struct Redacting<W>(W);
+
+impl<W: Write> Write for Redacting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = redact::bytes(buf);
+        progress::suspend(|| self.0.write_all(line.as_bytes()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Stops writing to the inner writer once it has been written `limit` bytes.
+struct Capped<W> {
+    inner: W,
+    limit: u64,
+}
+
+impl<W: Write> Write for Capped<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 <= self.limit {
+            self.inner.write_all(buf)?;
+            self.limit -= buf.len() as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Path of the last run's log in the state directory, see `init`.
+pub fn last_run_log(paths: &Paths) -> PathBuf {
+    paths.state_dir.join("last-run.log")
+}
+
+/// Installs the global `tracing` subscriber, logging events at `level` and above to stderr.
+/// `level` is a level like `debug`, or `tracing_subscriber` filter directives like
+/// `lunchmoney_venmo=trace,hyper=info`. All of this crate's events, including HTTP traces, are
+/// also written to `last_run_log` if given, replacing the previous run's, for `debug-bundle`.
+pub fn init(level: &str, format: LogFormat, last_run_log: Option<&Path>) -> Result<()> {
+    let filter =
+        EnvFilter::try_new(level).map_err(|e| anyhow!("Invalid log level '{}': {}", level, e))?;
+    let stderr = fmt::layer()
+        .with_target(false)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(|| Redacting(io::stderr()));
+    let stderr = match format {
+        LogFormat::Text => stderr.boxed(),
+        LogFormat::Json => stderr.json().boxed(),
+    };
+
+    let file = match last_run_log {
+        Some(path) => {
+            if let Some(dir) = path.parent() {
+                ensure_dir(dir)?;
+            }
+            let file = File::create(path)
+                .with_context(|| anyhow!("Failed to create log file {:?}", path))?;
+
+            Some(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(Redacting(Capped {
+                        inner: file,
+                        limit: MAX_LAST_RUN_LOG_BYTES,
+                    })))
+                    .with_filter(EnvFilter::new(LAST_RUN_LOG_FILTER)),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr.with_filter(filter))
+        .with(file)
+        .try_init()
+        .map_err(|e| anyhow!("Failed to set up logging: {}", e))
+}
+
+/// The last run's log, see `init`, if there is one.
+pub fn read_last_run_log(paths: &Paths) -> Option<String> {
+    fs::read_to_string(last_run_log(paths)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_log_files() {
+        let mut writer = Capped {
+            inner: Vec::new(),
+            limit: 10,
+        };
+
+        writer.write_all(b"first\n").unwrap();
+        writer.write_all(b"second\n").unwrap();
+        writer.write_all(b"3rd\n").unwrap();
+
+        assert_eq!(writer.inner, b"first\n3rd\n");
+    }
+
+    #[test]
+    fn redacts_log_lines() {
+        let mut writer = Redacting(Vec::new());
+
+        writer
+            .write_all(b"DEBUG HTTP request uri=https://api.venmo.com/v1/me?access_token=abc123\n")
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(writer.0).unwrap(),
+            "DEBUG HTTP request uri=https://api.venmo.com/v1/me?access_token=[REDACTED]\n"
+        );
+    }
+}
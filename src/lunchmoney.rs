@@ -1,14 +1,213 @@
-use anyhow::bail;
-use anyhow::Result;
+//! Client for the parts of the [Lunch Money API](https://lunchmoney.dev) used for syncing.
+
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use futures::stream::{self, StreamExt};
 use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
-use hyper::{body, Method, Request, StatusCode};
+use hyper::{body, Method, Request, Response, StatusCode};
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use tracing::warn;
 
+use crate::http::{request_following_redirects, retry_after, BoxError, HttpTransport, RateLimiter};
+use crate::progress;
+use crate::redact;
 use crate::types::lunchmoney::{
-    Asset, GetAllAssetsResponse, InsertTransactionRequest, InsertTransactionResponse, Transaction,
+    Asset, Category, CreateAssetRequest, CreateTransactionGroupRequest,
+    DeleteTransactionGroupResponse, ExistingTransaction, GetAllAssetsResponse,
+    GetAllCategoriesResponse, GetAllTransactionsResponse, InsertTransactionRequest,
+    InsertTransactionResponse, Tag, Transaction, TransactionUpdate, UpdateTransactionRequest,
+    UpdateTransactionResponse,
 };
-use crate::types::HttpsClient;
 
-pub async fn get_all_assets(client: &HttpsClient, api_token: &str) -> Result<Vec<Asset>> {
+lazy_static! {
+    /// Shared by all Lunch Money requests, which are rate limited per API token.
+    static ref RATE_LIMITER: RateLimiter =
+        RateLimiter::new(RATE_LIMIT_BURST, RATE_LIMIT_PER_SECOND);
+}
+
+/// An error from the Lunch Money API.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Lunch Money rejected the API token.
+    #[error("{0}")]
+    AuthError(String),
+    /// Lunch Money kept rate limiting a request, or asked to wait too long before retrying it.
+    #[error("{0}")]
+    RateLimited(String),
+    /// Lunch Money served an HTML page instead of JSON, e.g. because the endpoint moved.
+    #[error("{0}")]
+    EndpointMoved(String),
+    /// A response didn't have the expected format.
+    #[error("{message}")]
+    ParseError {
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+    /// Lunch Money responded with an unexpected status.
+    #[error("Failed to {action}, code {status}, err:\n{body}")]
+    ApiError {
+        action: String,
+        status: StatusCode,
+        body: String,
+    },
+    /// The request failed to send, or the response failed to arrive.
+    #[error("Failed to send Lunch Money request")]
+    Http(#[source] BoxError),
+    /// Some chunks of transactions failed to insert after others were inserted.
+    #[error(transparent)]
+    PartialInsert(PartialInsertError),
+}
+
+/// Transactions inserted before inserting others failed, see `insert_transactions`. Wrapped in
+/// `anyhow::Error` by `sync::insert_transactions_from` so the sync can be resumed.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct PartialInsertError {
+    pub inserted_ids: Vec<u64>,
+    pub inserted_external_ids: Vec<String>,
+    message: String,
+}
+
+impl PartialInsertError {
+    pub fn new(
+        inserted_ids: Vec<u64>,
+        inserted_external_ids: Vec<String>,
+        message: String,
+    ) -> Self {
+        PartialInsertError {
+            inserted_ids,
+            inserted_external_ids,
+            message,
+        }
+    }
+}
+
+impl Error {
+    /// The error for an unexpected `status` response with `body`, from trying to `action`.
+    fn response(action: &str, status: StatusCode, body: &[u8]) -> Self {
+        if status == StatusCode::UNAUTHORIZED {
+            Error::AuthError(format!(
+                "Lunch Money rejected the API token, failed to {}, code {}, err:\n{}",
+                action,
+                status,
+                redact::bytes(body)
+            ))
+        } else if body.trim_ascii_start().starts_with(b"<") {
+            Error::EndpointMoved(format!(
+                "Failed to {}, Lunch Money returned an HTML page with code {} instead of JSON",
+                action, status
+            ))
+        } else {
+            Error::ApiError {
+                action: action.to_string(),
+                status,
+                body: redact::bytes(body),
+            }
+        }
+    }
+
+    fn http(e: impl Into<BoxError>) -> Self {
+        Error::Http(e.into())
+    }
+}
+
+/// Parses the JSON response `bytes` to `action`.
+fn parse_json<T: DeserializeOwned>(bytes: &[u8], action: &str) -> Result<T, Error> {
+    serde_json::from_slice(bytes).map_err(|e| Error::ParseError {
+        message: format!("Failed to parse Lunch Money response to {}", action),
+        source: e.into(),
+    })
+}
+
+/// Serializes the JSON body of a request, which can't fail for the request types here.
+fn to_json(value: &impl serde::Serialize) -> Vec<u8> {
+    serde_json::to_vec(value).expect("Lunch Money requests serialize to JSON")
+}
+
+/// Max number of Lunch Money requests sent back to back.
+const RATE_LIMIT_BURST: u32 = 10;
+
+/// Sustained Lunch Money requests per second, after a burst.
+const RATE_LIMIT_PER_SECOND: f64 = 2.0;
+
+/// Max number of times a rate limited (429) request is retried.
+const MAX_RATE_LIMITED_RETRIES: usize = 5;
+
+/// How long to wait before retrying a rate limited request without a `Retry-After`, doubled on
+/// each retry.
+const RATE_LIMITED_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest `Retry-After` to wait for, rather than failing.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Sends a Lunch Money API `request` once `RATE_LIMITER` allows, pausing and retrying when
+/// Lunch Money responds 429 Too Many Requests.
+async fn send(
+    client: &impl HttpTransport,
+    request: Request<body::Body>,
+) -> Result<Response<body::Body>, Error> {
+    let (parts, request_body) = request.into_parts();
+    let request_body = body::to_bytes(request_body).await.map_err(Error::http)?;
+    let mut backoff = RATE_LIMITED_BACKOFF;
+
+    for attempt in 0..=MAX_RATE_LIMITED_RETRIES {
+        RATE_LIMITER.acquire().await;
+
+        let mut request = Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .body(body::Body::from(request_body.clone()))
+            .map_err(Error::http)?;
+        *request.headers_mut() = parts.headers.clone();
+
+        let response = request_following_redirects(client, request)
+            .await
+            .map_err(Error::http)?;
+
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+        if attempt == MAX_RATE_LIMITED_RETRIES {
+            break;
+        }
+
+        let delay = retry_after(response.headers()).unwrap_or(backoff);
+        if delay > MAX_RETRY_AFTER {
+            return Err(Error::RateLimited(format!(
+                "Lunch Money rate limited {} {}, asking to retry in {}",
+                parts.method,
+                parts.uri.path(),
+                humantime::format_duration(delay)
+            )));
+        }
+
+        warn!(
+            "Lunch Money rate limited {} {}, retrying in {}",
+            parts.method,
+            parts.uri.path(),
+            humantime::format_duration(delay)
+        );
+        tokio::time::sleep(delay).await;
+        backoff *= 2;
+    }
+
+    Err(Error::RateLimited(format!(
+        "Lunch Money rate limited {} {} {} times in a row, giving up",
+        parts.method,
+        parts.uri.path(),
+        MAX_RATE_LIMITED_RETRIES + 1
+    )))
+}
+
+/// Lists all manually-managed assets in the Lunch Money account `api_token` belongs to.
+pub async fn get_all_assets(
+    client: &impl HttpTransport,
+    api_token: &str,
+) -> Result<Vec<Asset>, Error> {
     let request = Request::builder()
         .method(Method::GET)
         .uri("https://dev.lunchmoney.app/v1/assets")
@@ -16,36 +215,299 @@ pub async fn get_all_assets(client: &HttpsClient, api_token: &str) -> Result<Vec
         .body(body::Body::empty())
         .unwrap();
 
-    let response = client.request(request).await?;
+    let response = send(client, request).await?;
 
     let status = response.status();
-    let bytes = body::to_bytes(response).await?;
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
 
     if status != StatusCode::OK {
-        bail!(
-            "Failed to get Lunch Money assets, code {}, err:\n{:#?}",
-            status,
-            bytes
-        );
+        return Err(Error::response("get Lunch Money assets", status, &bytes));
     }
 
-    let response: GetAllAssetsResponse = serde_json::from_slice(&bytes)?;
+    let response: GetAllAssetsResponse = parse_json(&bytes, "get Lunch Money assets")?;
 
     Ok(response.assets)
 }
 
+/// Creates a manually-managed asset as described by `asset`, returning it.
+pub async fn create_asset(
+    client: &impl HttpTransport,
+    api_token: &str,
+    asset: &CreateAssetRequest,
+) -> Result<Asset, Error> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("https://dev.lunchmoney.app/v1/assets")
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(to_json(asset).into())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status != StatusCode::OK && status != StatusCode::CREATED {
+        return Err(Error::response("create Lunch Money asset", status, &bytes));
+    }
+
+    parse_json(&bytes, "create Lunch Money asset")
+}
+
+/// Lists all categories (including category groups) in the Lunch Money account.
+pub async fn get_all_categories(
+    client: &impl HttpTransport,
+    api_token: &str,
+) -> Result<Vec<Category>, Error> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("https://dev.lunchmoney.app/v1/categories")
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status != StatusCode::OK {
+        return Err(Error::response(
+            "get Lunch Money categories",
+            status,
+            &bytes,
+        ));
+    }
+
+    let response: GetAllCategoriesResponse = parse_json(&bytes, "get Lunch Money categories")?;
+
+    Ok(response.categories)
+}
+
+/// Lists all tags in the Lunch Money account.
+pub async fn get_all_tags(client: &impl HttpTransport, api_token: &str) -> Result<Vec<Tag>, Error> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("https://dev.lunchmoney.app/v1/tags")
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status != StatusCode::OK {
+        return Err(Error::response("get Lunch Money tags", status, &bytes));
+    }
+
+    // Unlike the other endpoints, tags are returned as a bare array.
+    parse_json(&bytes, "get Lunch Money tags")
+}
+
+/// Max number of transactions to request per page from the transactions endpoint.
+const TRANSACTIONS_PAGE_SIZE: usize = 500;
+
+/// Lists the transactions of asset `asset_id` dated `start_date` to `end_date` (inclusive).
+pub async fn get_transactions(
+    client: &impl HttpTransport,
+    api_token: &str,
+    asset_id: u64,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+) -> Result<Vec<ExistingTransaction>, Error> {
+    let mut transactions = Vec::new();
+
+    loop {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!(
+                "https://dev.lunchmoney.app/v1/transactions?asset_id={}&start_date={}&end_date={}&limit={}&offset={}",
+                asset_id,
+                start_date.format("%Y-%m-%d"),
+                end_date.format("%Y-%m-%d"),
+                TRANSACTIONS_PAGE_SIZE,
+                transactions.len()
+            ))
+            .header(AUTHORIZATION, format!("Bearer {}", api_token))
+            .body(body::Body::empty())
+            .unwrap();
+
+        let response = send(client, request).await?;
+
+        let status = response.status();
+        let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+        if status != StatusCode::OK {
+            return Err(Error::response(
+                "get Lunch Money transactions",
+                status,
+                &bytes,
+            ));
+        }
+
+        let response: GetAllTransactionsResponse =
+            parse_json(&bytes, "get Lunch Money transactions")?;
+        let page_size = response.transactions.len();
+        transactions.extend(response.transactions);
+
+        if !response.has_more || page_size == 0 {
+            return Ok(transactions);
+        }
+    }
+}
+
+/// How Lunch Money should process inserted transactions. Debits are always treated as negative
+/// amounts, since that's how transactions are converted from Venmo.
+#[derive(Debug, Clone)]
+pub struct InsertOptions {
+    /// Apply the account's Lunch Money rules.
+    pub apply_rules: bool,
+    /// Match transactions against recurring expenses.
+    pub check_for_recurring: bool,
+    /// Also skip transactions with the same date, payee, and amount as an existing one, on top
+    /// of ones with the same `external_id`.
+    pub skip_duplicates: bool,
+    /// Don't update the asset's balance.
+    pub skip_balance_update: bool,
+    /// Transactions to insert per request, at most `MAX_INSERT_CHUNK_SIZE`.
+    pub chunk_size: usize,
+    /// Max number of chunks to insert at once.
+    pub parallelism: usize,
+}
+
+impl Default for InsertOptions {
+    fn default() -> Self {
+        InsertOptions {
+            apply_rules: true,
+            check_for_recurring: true,
+            skip_duplicates: false,
+            skip_balance_update: false,
+            chunk_size: DEFAULT_INSERT_CHUNK_SIZE,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Default for `InsertOptions::chunk_size`.
+pub const DEFAULT_INSERT_CHUNK_SIZE: usize = 50;
+
+/// Most transactions Lunch Money takes in one insert request.
+pub const MAX_INSERT_CHUNK_SIZE: usize = 500;
+
+/// Inserts `transactions`, processed as `options` says, and returns the IDs of the inserted
+/// transactions. Lunch Money skips transactions whose `external_id` already exists on the same
+/// asset, so inserting the same statement twice is safe.
+///
+/// Transactions are sent in chunks of `options.chunk_size`, up to `options.parallelism` chunks at
+/// once. If some chunks fail to insert after others were inserted, the error is a
+/// `PartialInsert` listing which were.
+#[tracing::instrument(skip_all, fields(transactions = transactions.len()))]
 pub async fn insert_transactions(
-    client: &HttpsClient,
+    client: &impl HttpTransport,
     api_token: &str,
     transactions: Vec<Transaction>,
-) -> Result<Vec<u64>> {
+    options: &InsertOptions,
+) -> Result<Vec<u64>, Error> {
+    let chunks = transactions
+        .into_iter()
+        .chunks(options.chunk_size.clamp(1, MAX_INSERT_CHUNK_SIZE))
+        .into_iter()
+        .map(|chunk| chunk.collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let progress = progress::bar("Inserting into Lunch Money", chunks.len(), "chunks");
+    let progress = &progress;
+
+    let results = stream::iter(chunks.into_iter().enumerate())
+        .map(|(i, chunk)| async move {
+            let external_ids = chunk
+                .iter()
+                .filter_map(|transaction| transaction.external_id.clone())
+                .collect();
+            let res = insert_chunk(client, api_token, chunk, options).await;
+            progress.inc(1);
+
+            (i, external_ids, res)
+        })
+        .buffer_unordered(options.parallelism.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    collect_chunk_results(results)
+}
+
+/// The index, external IDs, and result of inserting a chunk of transactions.
+type ChunkResult = (usize, Vec<String>, Result<Vec<u64>, Error>);
+
+/// Orders the `(index, external IDs, result)` of each inserted chunk, finished in any order, into
+/// the inserted IDs. If any failed, the error is a `PartialInsert` listing which chunks were and
+/// weren't inserted, or the first chunk's error if none were.
+fn collect_chunk_results(mut results: Vec<ChunkResult>) -> Result<Vec<u64>, Error> {
+    results.sort_by_key(|(i, _, _)| *i);
+
+    let mut ids = Vec::new();
+    let mut external_ids = Vec::new();
+    let mut inserted = Vec::new();
+    let mut failed = Vec::new();
+
+    for (i, chunk_external_ids, res) in results {
+        let len = chunk_external_ids.len();
+
+        match res {
+            Ok(chunk_ids) => {
+                ids.extend(chunk_ids);
+                external_ids.extend(chunk_external_ids);
+                inserted.push(format!("{} ({} transactions)", i + 1, len));
+            }
+            Err(e) => failed.push((i, len, e)),
+        }
+    }
+
+    if failed.is_empty() {
+        return Ok(ids);
+    }
+    if inserted.is_empty() {
+        return Err(failed.swap_remove(0).2);
+    }
+
+    let failed = failed
+        .into_iter()
+        .map(|(i, len, e)| {
+            // Through `anyhow` to include the causes.
+            let e = anyhow::Error::from(e);
+            format!("chunk {} ({} transactions): {:#}", i + 1, len, e)
+        })
+        .collect::<Vec<_>>();
+
+    Err(Error::PartialInsert(PartialInsertError {
+        message: format!(
+            "Failed to insert {} of {} chunks of transactions.\nInserted chunks: {}\n{}",
+            failed.len(),
+            failed.len() + inserted.len(),
+            inserted.join(", "),
+            failed.join("\n")
+        ),
+        inserted_ids: ids,
+        inserted_external_ids: external_ids,
+    }))
+}
+
+/// Inserts `transactions` in a single request, see `insert_transactions`.
+async fn insert_chunk(
+    client: &impl HttpTransport,
+    api_token: &str,
+    transactions: Vec<Transaction>,
+    options: &InsertOptions,
+) -> Result<Vec<u64>, Error> {
     let request_body = InsertTransactionRequest {
         transactions,
-        apply_rules: Some(true),
-        check_for_recurring: Some(true),
+        apply_rules: Some(options.apply_rules),
+        check_for_recurring: Some(options.check_for_recurring),
         debit_as_negative: Some(true),
-        skip_balance_update: None,
-        skip_duplicates: None,
+        skip_balance_update: Some(options.skip_balance_update),
+        skip_duplicates: Some(options.skip_duplicates),
     };
 
     let request = Request::builder()
@@ -53,23 +515,534 @@ pub async fn insert_transactions(
         .uri("https://dev.lunchmoney.app/v1/transactions")
         .header(AUTHORIZATION, format!("Bearer {}", api_token))
         .header(CONTENT_TYPE, "application/json; charset=utf-8")
-        .body(serde_json::to_vec(&request_body)?.into())
+        .body(to_json(&request_body).into())
         .unwrap();
 
-    let response = client.request(request).await?;
+    let response = send(client, request).await?;
 
     let status = response.status();
-    let bytes = body::to_bytes(response).await?;
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
 
     if status != StatusCode::OK {
-        bail!(
-            "Failed to insert Lunch Money transactions, code {}, err:\n{:#?}",
+        return Err(Error::response(
+            "insert Lunch Money transactions",
             status,
-            bytes
-        );
+            &bytes,
+        ));
     }
 
-    let response: InsertTransactionResponse = serde_json::from_slice(&bytes)?;
+    let response: InsertTransactionResponse =
+        parse_json(&bytes, "insert Lunch Money transactions")?;
 
     Ok(response.ids)
 }
+
+/// Gets transaction `id`.
+pub async fn get_transaction(
+    client: &impl HttpTransport,
+    api_token: &str,
+    id: u64,
+) -> Result<ExistingTransaction, Error> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("https://dev.lunchmoney.app/v1/transactions/{}", id))
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status != StatusCode::OK {
+        return Err(Error::response(
+            "get Lunch Money transaction",
+            status,
+            &bytes,
+        ));
+    }
+
+    parse_json(&bytes, "get Lunch Money transaction")
+}
+
+/// Changes the fields of transaction `id` set in `update`.
+pub async fn update_transaction(
+    client: &impl HttpTransport,
+    api_token: &str,
+    id: u64,
+    update: TransactionUpdate,
+) -> Result<(), Error> {
+    let request_body = UpdateTransactionRequest {
+        transaction: update,
+        skip_balance_update: None,
+    };
+
+    let request = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("https://dev.lunchmoney.app/v1/transactions/{}", id))
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(to_json(&request_body).into())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    let updated = serde_json::from_slice::<UpdateTransactionResponse>(&bytes)
+        .map(|response| response.updated)
+        .unwrap_or(false);
+
+    if status != StatusCode::OK || !updated {
+        return Err(Error::response(
+            &format!("update Lunch Money transaction {}", id),
+            status,
+            &bytes,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Groups existing transactions as described by `group`, returning the ID of the new
+/// transaction group.
+pub async fn create_transaction_group(
+    client: &impl HttpTransport,
+    api_token: &str,
+    group: &CreateTransactionGroupRequest,
+) -> Result<u64, Error> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("https://dev.lunchmoney.app/v1/transactions/group")
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .header(CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(to_json(group).into())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status != StatusCode::OK {
+        return Err(Error::response(
+            "create Lunch Money transaction group",
+            status,
+            &bytes,
+        ));
+    }
+
+    // The ID of the new group is returned as a bare number.
+    parse_json(&bytes, "create Lunch Money transaction group")
+}
+
+/// Deletes transaction group `group_id`, returning the IDs of the transactions that were in it.
+/// The transactions themselves are kept.
+pub async fn delete_transaction_group(
+    client: &impl HttpTransport,
+    api_token: &str,
+    group_id: u64,
+) -> Result<Vec<u64>, Error> {
+    let request = Request::builder()
+        .method(Method::DELETE)
+        .uri(format!(
+            "https://dev.lunchmoney.app/v1/transactions/group/{}",
+            group_id
+        ))
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = send(client, request).await?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status != StatusCode::OK {
+        return Err(Error::response(
+            &format!("delete Lunch Money transaction group {}", group_id),
+            status,
+            &bytes,
+        ));
+    }
+
+    let response: DeleteTransactionGroupResponse =
+        parse_json(&bytes, "delete Lunch Money transaction group")?;
+
+    Ok(response.transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::http::fake::FakeTransport;
+    use crate::types::lunchmoney::{Amount, CreateAssetRequest, TagRef};
+
+    #[tokio::test]
+    async fn inserts_transactions() {
+        let transport =
+            FakeTransport::with_responses(vec![(StatusCode::OK, br#"{"ids": [101, 102]}"#)]);
+
+        let ids = insert_transactions(
+            &transport,
+            "token",
+            vec![Transaction {
+                payee: Some("John Smith".to_string()),
+                amount: Amount(Decimal::new(-2550, 2)),
+                asset_id: Some(12345),
+                external_id: Some("3581234567890123456".to_string()),
+                tags: Some(vec![TagRef::Id(7), TagRef::Name("venmo".to_string())]),
+                ..Default::default()
+            }],
+            &InsertOptions {
+                skip_duplicates: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(ids, vec![101, 102]);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].method, Method::POST);
+        assert_eq!(
+            requests[0].uri,
+            "https://dev.lunchmoney.app/v1/transactions"
+        );
+
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["transactions"][0]["amount"], "-25.5000");
+        assert_eq!(
+            body["transactions"][0]["external_id"],
+            "3581234567890123456"
+        );
+        assert_eq!(
+            body["transactions"][0]["tags"],
+            serde_json::json!([7, "venmo"])
+        );
+        assert_eq!(body["debit_as_negative"], true);
+        assert_eq!(body["apply_rules"], true);
+        assert_eq!(body["skip_duplicates"], true);
+    }
+
+    #[tokio::test]
+    async fn inserts_transactions_in_chunks() {
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, br#"{"ids": [101, 102]}"#),
+            (StatusCode::OK, br#"{"ids": [103]}"#),
+        ]);
+        let transaction = |external_id: &str| Transaction {
+            amount: Amount(Decimal::new(-2550, 2)),
+            external_id: Some(external_id.to_string()),
+            ..Default::default()
+        };
+
+        let ids = insert_transactions(
+            &transport,
+            "token",
+            vec![transaction("1"), transaction("2"), transaction("3")],
+            &InsertOptions {
+                chunk_size: 2,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(ids, vec![101, 102, 103]);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["transactions"][0]["external_id"], "3");
+    }
+
+    #[test]
+    fn collects_chunk_results_in_order() {
+        let external_ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let failed = || Err(Error::Http("code 500".into()));
+
+        let ids = collect_chunk_results(vec![
+            (2, external_ids(&["5"]), Ok(vec![5])),
+            (0, external_ids(&["1", "2"]), Ok(vec![1, 2])),
+            (1, external_ids(&["3", "4"]), Ok(vec![3, 4])),
+        ])
+        .unwrap();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+
+        let err = collect_chunk_results(vec![
+            (1, external_ids(&["2", "2T"]), failed()),
+            (0, external_ids(&["1"]), Ok(vec![1])),
+            (2, external_ids(&["3"]), Ok(vec![3])),
+        ])
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("Failed to insert 1 of 3 chunks"));
+        assert!(message.contains("Inserted chunks: 1 (1 transactions), 3 (1 transactions)"));
+        assert!(message
+            .contains("chunk 2 (2 transactions): Failed to send Lunch Money request: code 500"));
+
+        let partial = match err {
+            Error::PartialInsert(partial) => partial,
+            e => panic!("expected a partial insert, got {:?}", e),
+        };
+        assert_eq!(partial.inserted_ids, vec![1, 3]);
+        assert_eq!(partial.inserted_external_ids, external_ids(&["1", "3"]));
+
+        // Nothing was inserted, so there's nothing to resume.
+        let err = collect_chunk_results(vec![
+            (0, external_ids(&["1"]), failed()),
+            (1, external_ids(&["2"]), failed()),
+        ])
+        .unwrap_err();
+        assert!(matches!(err, Error::Http(_)));
+    }
+
+    #[tokio::test]
+    async fn creates_assets() {
+        let transport = FakeTransport::with_responses(vec![(
+            StatusCode::CREATED,
+            br#"{"id": 54321, "type_name": "cash", "subtype_name": "digital wallet", "name": "Venmo", "display_name": null, "balance": "0.0000", "balance_as_of": "2023-01-01T00:00:00.000Z", "closed_on": null, "currency": "usd", "institution_name": "Venmo", "exclude_transactions": false, "created_at": "2023-01-01T00:00:00.000Z"}"#,
+        )]);
+
+        let asset = create_asset(
+            &transport,
+            "token",
+            &CreateAssetRequest {
+                type_name: "cash".to_string(),
+                subtype_name: Some("digital wallet".to_string()),
+                name: "Venmo".to_string(),
+                display_name: None,
+                balance: Amount(Decimal::ZERO),
+                currency: Some("usd".to_string()),
+                institution_name: Some("Venmo".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(asset.id, 54321);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].method, Method::POST);
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type_name": "cash",
+                "subtype_name": "digital wallet",
+                "name": "Venmo",
+                "balance": "0.0000",
+                "currency": "usd",
+                "institution_name": "Venmo",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_through_transactions() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"transactions": [{"id": 1, "date": "2022-07-01", "payee": "John Smith", "amount": "-25.0000", "external_id": "3581234567890123456"}], "has_more": true}"#,
+            ),
+            (
+                StatusCode::OK,
+                br#"{"transactions": [{"id": 2, "date": "2022-07-03", "payee": null, "amount": "80.0000", "external_id": null}], "has_more": false}"#,
+            ),
+        ]);
+
+        let transactions = get_transactions(
+            &transport,
+            "token",
+            12345,
+            NaiveDate::from_ymd_opt(2022, 7, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2022, 7, 31).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            transactions.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(transactions[0].amount.0, Decimal::new(-25, 0));
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests[0]
+            .uri
+            .contains("asset_id=12345&start_date=2022-07-01&end_date=2022-07-31"));
+        assert!(requests[1].uri.ends_with("offset=1"));
+    }
+
+    #[tokio::test]
+    async fn creates_transaction_groups() {
+        let transport = FakeTransport::with_responses(vec![(StatusCode::OK, b"789")]);
+
+        let group_id = create_transaction_group(
+            &transport,
+            "token",
+            &CreateTransactionGroupRequest {
+                date: NaiveDate::from_ymd_opt(2022, 7, 3).unwrap(),
+                payee: "Alex Roe".to_string(),
+                category_id: None,
+                notes: Some("Concert tickets".to_string()),
+                tags: None,
+                transactions: vec![101, 102],
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(group_id, 789);
+
+        let requests = transport.requests.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["date"], "2022-07-03");
+        assert_eq!(body["transactions"], serde_json::json!([101, 102]));
+        assert!(body.get("category_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn deletes_transaction_groups() {
+        let transport = FakeTransport::with_responses(vec![(
+            StatusCode::OK,
+            br#"{"transactions": [101, 102]}"#,
+        )]);
+
+        let ids = delete_transaction_group(&transport, "token", 789)
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![101, 102]);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].method, Method::DELETE);
+        assert_eq!(
+            requests[0].uri,
+            "https://dev.lunchmoney.app/v1/transactions/group/789"
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_transactions() {
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, br#"{"updated": true}"#),
+            (
+                StatusCode::OK,
+                br#"{"error": ["Transaction ID not found."]}"#,
+            ),
+        ]);
+
+        update_transaction(
+            &transport,
+            "token",
+            101,
+            TransactionUpdate {
+                tags: Some(vec!["venmo-sync-undone".to_string()]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(
+            update_transaction(&transport, "token", 102, TransactionUpdate::default())
+                .await
+                .is_err()
+        );
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].method, Method::PUT);
+        assert_eq!(
+            requests[0].uri,
+            "https://dev.lunchmoney.app/v1/transactions/101"
+        );
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"transaction": {"tags": ["venmo-sync-undone"]}})
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_failed_requests() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::UNAUTHORIZED,
+                br#"{"error": "Access token does not exist."}"#,
+            ),
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                br#"{"error": "Something went wrong."}"#,
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                b"<html><body>Not Found</body></html>",
+            ),
+            (StatusCode::OK, br#"{"assets": "not a list"}"#),
+        ]);
+
+        let err = get_all_assets(&transport, "token").await.unwrap_err();
+        assert!(err.to_string().contains("401"));
+        assert!(matches!(err, Error::AuthError(_)));
+
+        let err = get_all_assets(&transport, "token").await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..
+            }
+        ));
+
+        let err = get_all_assets(&transport, "token").await.unwrap_err();
+        assert!(matches!(err, Error::EndpointMoved(_)));
+
+        let err = get_all_assets(&transport, "token").await.unwrap_err();
+        assert!(matches!(err, Error::ParseError { .. }));
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_requests() {
+        let transport = FakeTransport::default();
+        transport.push_response_with_header(
+            StatusCode::TOO_MANY_REQUESTS,
+            hyper::header::RETRY_AFTER,
+            "0",
+            b"",
+        );
+        transport.push_response(StatusCode::OK, br#"{"ids": [101]}"#);
+
+        let ids = insert_transactions(
+            &transport,
+            "token",
+            vec![Transaction::default()],
+            &InsertOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(ids, vec![101]);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body, requests[1].body);
+        assert_eq!(requests[1].headers[AUTHORIZATION], "Bearer token");
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_long_retry_after() {
+        let transport = FakeTransport::default();
+        transport.push_response_with_header(
+            StatusCode::TOO_MANY_REQUESTS,
+            hyper::header::RETRY_AFTER,
+            "3600",
+            b"",
+        );
+
+        assert!(get_all_tags(&transport, "token").await.is_err());
+    }
+}
@@ -1,219 +1,2718 @@
+use std::ffi::OsString;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
-use chrono::offset::{Local, Utc};
-use chrono::DateTime;
-use clap::{Args, Parser, Subcommand};
-use hyper::client::Client;
-use hyper_tls::HttpsConnector;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{
+    Arg, ArgEnum, ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+};
+use clap_complete::Shell;
+use hyper::Uri;
 use itertools::Itertools;
+use rust_decimal::Decimal;
 
-mod lunchmoney;
-mod types;
-mod venmo;
+use lunchmoney_venmo::{
+    browser, cashapp, config, credentials, debug_bundle, firefly, init, ledger, lunchmoney, notify,
+    ofx, output, paypal, progress, reconcile, self_update, serve, state, sync, systemd, venmo,
+    ynab,
+};
 
-use lunchmoney::{get_all_assets, insert_transactions};
-use types::venmo::AccountRecord;
-use types::HttpsClient;
-use venmo::fetch_venmo_transactions;
+use config::{Config, LunchMoneyConfig, SyncConfig, VenmoConfig};
+use lunchmoney_venmo::logging::{self, LogFormat};
+use lunchmoney_venmo::lunchmoney::{
+    create_asset, get_all_assets, get_all_tags, get_transactions, InsertOptions,
+    DEFAULT_INSERT_CHUNK_SIZE, MAX_INSERT_CHUNK_SIZE,
+};
+use lunchmoney_venmo::output::OutputFormat;
+use lunchmoney_venmo::paths::Paths;
+use lunchmoney_venmo::sink::TransactionSink;
+use lunchmoney_venmo::state::SyncLock;
+use lunchmoney_venmo::types::lunchmoney::{Amount, CreateAssetRequest};
+use lunchmoney_venmo::types::venmo::{AccountRecord, AccountType, Statement, Timezone};
+use lunchmoney_venmo::types::{self, https_client, ClientOptions, HttpsClient};
+use serde_json::json;
+use sync::{
+    LunchMoneyDestination, MetricsOptions, Schedule, SyncAccounts, SyncOptions, SyncSummary,
+};
+use tracing::warn;
+use venmo::{fetch_venmo_transactions, StatementStore};
+
+/// The date range to fetch, relative to now or as absolute dates.
+#[derive(Args)]
+struct DateRangeArgs {
+    /// Defaults to `sync.start_from` from the config file, or 30d. Syncs default to shortly
+    /// before the newest transaction already synced, if any.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    start_from: Option<Duration>,
+
+    /// Defaults to `sync.end_to` from the config file, or now.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    end_to: Option<Duration>,
+
+    /// First day to include, e.g. `2023-01-01`, instead of `--start-from`.
+    #[clap(long, conflicts_with = "start-from")]
+    start_date: Option<NaiveDate>,
+
+    /// Last day to include, e.g. `2023-01-31`, instead of `--end-to`.
+    #[clap(long, conflicts_with = "end-to")]
+    end_date: Option<NaiveDate>,
+}
+
+impl DateRangeArgs {
+    /// The range to fetch, falling back to the config file's `sync.start_from` and `sync.end_to`.
+    fn range(&self, config: &SyncConfig) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+        sync::with_absolute_dates(
+            sync::date_range(self.start_from, self.end_to, config)?,
+            self.start_date,
+            self.end_date,
+        )
+    }
+
+    /// Whether a start was given, otherwise syncs pick up where the last one left off.
+    fn has_start(&self) -> bool {
+        self.start_from.is_some() || self.start_date.is_some()
+    }
+}
+
+/// Lunch Money tags to attach to inserted transactions.
+#[derive(Args)]
+struct TagArgs {
+    /// Name of a Lunch Money tag to attach to every inserted transaction, can be repeated.
+    /// Defaults to `lunch_money.tags` from the config file.
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+
+    /// Create tags passed with `--tag` that don't exist in Lunch Money yet.
+    #[clap(long)]
+    create_tags: bool,
+}
+
+impl TagArgs {
+    /// Overrides values in `config` with the ones given as flags.
+    fn merge(self, config: &mut Config) {
+        if !self.tags.is_empty() {
+            config.lunch_money.tags = self.tags;
+        }
+        config.lunch_money.create_tags |= self.create_tags;
+    }
+}
+
+/// What to do when another sync to the same destination holds its lock.
+#[derive(Args)]
+struct LockArgs {
+    /// If another sync or import to the same Lunch Money asset (or `--target` account) is
+    /// running, wait for it to finish instead of skipping this one.
+    #[clap(long, env = "VENMO_SYNC_WAIT_FOR_LOCK")]
+    wait_for_lock: bool,
+}
 
 #[derive(Args)]
 struct ListVenmoTransactionsArgs {
-    #[clap(long, value_parser = humantime::parse_duration, default_value = "30d")]
-    start_from: Duration,
+    #[clap(flatten)]
+    dates: DateRangeArgs,
+
+    /// Defaults to `venmo.profile_id` from the config file.
+    #[clap(long, env = "VENMO_PROFILE_ID")]
+    profile_id: Option<u64>,
+
+    #[clap(flatten)]
+    api_token: TokenArgs<Venmo, false>,
+
+    /// Defaults to `venmo.currency` from the config file, or USD.
+    #[clap(long, env = "VENMO_CURRENCY")]
+    currency: Option<String>,
+
+    /// Order to list transactions in.
+    #[clap(long, arg_enum, default_value = "date")]
+    sort: TransactionSort,
+
+    /// Only show the first N transactions, after sorting.
+    #[clap(long)]
+    limit: Option<usize>,
+
+    /// Print directly to stdout instead of piping through `$PAGER`.
+    #[clap(long)]
+    no_pager: bool,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum TransactionSort {
+    /// Oldest first.
+    Date,
+    /// Largest outgoing amount first, largest incoming amount last.
+    Amount,
+}
+
+async fn cmd_list_venmo_transactions(
+    client: &HttpsClient,
+    config: Config,
+    statements: &StatementStore,
+    args: ListVenmoTransactionsArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    let (start_date, end_date) = args.dates.range(&config.sync)?;
+
+    let profile_id = args
+        .profile_id
+        .or(config.venmo.profile_id)
+        .ok_or_else(|| anyhow!("--profile-id or venmo.profile_id must be set"))?;
+    let api_token = config
+        .venmo
+        .api_token_or(args.api_token.token())?
+        .ok_or_else(|| {
+            anyhow!("--api-token, venmo.api_token, or venmo.token_command must be set")
+        })?;
+    let currency = args
+        .currency
+        .or(config.venmo.currency)
+        .unwrap_or_else(|| "USD".to_string());
+
+    let account = AccountRecord {
+        profile_id,
+        api_token,
+        currency: *rusty_money::iso::find(&currency)
+            .ok_or_else(|| anyhow!("Given currency {} is not valid", currency))?,
+    };
+
+    let mut transactions = fetch_venmo_transactions(
+        client,
+        &account,
+        &config.venmo.statement_format,
+        statements,
+        &start_date,
+        &end_date,
+    )
+    .await?;
+
+    match args.sort {
+        TransactionSort::Date => transactions
+            .transactions
+            .sort_by_key(|transaction| transaction.datetime),
+        TransactionSort::Amount => transactions
+            .transactions
+            .sort_by_key(|transaction| transaction.amount_total.val),
+    }
+
+    if let Some(limit) = args.limit {
+        transactions.transactions.truncate(limit);
+    }
+
+    match format {
+        OutputFormat::Text => output::page(
+            &output::transactions_table(&transactions, &account.currency),
+            args.no_pager,
+        ),
+        OutputFormat::Json => output::json(&transactions),
+    }
+}
+
+async fn cmd_list_lunch_money_assets(
+    client: &HttpsClient,
+    config: Config,
+    api_token: Option<String>,
+    limit: Option<usize>,
+    no_pager: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let api_token = config.lunch_money.api_token_or(api_token)?.ok_or_else(|| {
+        anyhow!("--api-token, lunch_money.api_token, or lunch_money.token_command must be set")
+    })?;
+    let mut assets = get_all_assets(client, &api_token).await?;
+
+    if let Some(limit) = limit {
+        assets.truncate(limit);
+    }
+
+    match format {
+        OutputFormat::Text => output::page(&format!("{:#?}\n", assets), no_pager),
+        OutputFormat::Json => output::json(&assets),
+    }
+}
+
+#[derive(Args)]
+struct ListLunchMoneyTransactionsArgs {
+    #[clap(flatten)]
+    dates: DateRangeArgs,
+
+    #[clap(flatten)]
+    api_token: TokenArgs<LunchMoney, false>,
+
+    /// Defaults to `lunch_money.asset_id` from the config file.
+    #[clap(long, env = "LUNCHMONEY_ASSET_ID")]
+    asset_id: Option<u64>,
+
+    /// Currency to show amounts in. Defaults to `venmo.currency` from the config file, or USD.
+    #[clap(long, env = "VENMO_CURRENCY")]
+    currency: Option<String>,
+
+    /// Only show the first N transactions.
+    #[clap(long)]
+    limit: Option<usize>,
+
+    /// Print directly to stdout instead of piping through `$PAGER`.
+    #[clap(long)]
+    no_pager: bool,
+}
+
+async fn cmd_list_lunch_money_transactions(
+    client: &HttpsClient,
+    config: Config,
+    args: ListLunchMoneyTransactionsArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    let (start_date, end_date) = args.dates.range(&config.sync)?;
+
+    let api_token = config
+        .lunch_money
+        .api_token_or(args.api_token.token())?
+        .ok_or_else(|| {
+            anyhow!("--api-token, lunch_money.api_token, or lunch_money.token_command must be set")
+        })?;
+    let asset_id = args
+        .asset_id
+        .or(config.lunch_money.asset_id)
+        .ok_or_else(|| anyhow!("--asset-id or lunch_money.asset_id must be set"))?;
+    let currency = sync::find_currency(args.currency.or(config.venmo.currency).as_deref())?;
+
+    let mut transactions = get_transactions(
+        client,
+        &api_token,
+        asset_id,
+        start_date.naive_utc().date(),
+        end_date.naive_utc().date(),
+    )
+    .await?;
+    transactions.sort_by_key(|transaction| transaction.date);
+
+    if let Some(limit) = args.limit {
+        transactions.truncate(limit);
+    }
+
+    match format {
+        OutputFormat::Text => output::page(
+            &output::lunch_money_transactions_table(&transactions, &currency),
+            args.no_pager,
+        ),
+        OutputFormat::Json => output::json(&transactions),
+    }
+}
+
+#[derive(Args)]
+struct CreateLunchMoneyAssetArgs {
+    #[clap(flatten)]
+    api_token: TokenArgs<LunchMoney, false>,
+
+    #[clap(long, default_value = "Venmo")]
+    name: String,
+
+    /// Lunch Money asset type, e.g. `cash` or `other asset`.
+    #[clap(long = "type", default_value = "cash")]
+    type_: String,
+
+    /// Lunch Money asset subtype, e.g. `digital wallet` for `cash`.
+    #[clap(long, default_value = "digital wallet")]
+    subtype: String,
+
+    /// Defaults to `venmo.currency` from the config file, or USD.
+    #[clap(long, env = "VENMO_CURRENCY")]
+    currency: Option<String>,
+
+    /// Current balance of the account. Syncs update it, so it can be left at zero.
+    #[clap(long, default_value = "0")]
+    balance: Decimal,
+
+    #[clap(long, default_value = "Venmo")]
+    institution_name: String,
+}
+
+/// Creates a Lunch Money asset to sync to and prints its ID.
+async fn cmd_create_lunch_money_asset(
+    client: &HttpsClient,
+    config: Config,
+    args: CreateLunchMoneyAssetArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    let api_token = config
+        .lunch_money
+        .api_token_or(args.api_token.token())?
+        .ok_or_else(|| {
+            anyhow!("--api-token, lunch_money.api_token, or lunch_money.token_command must be set")
+        })?;
+    let currency = sync::find_currency(args.currency.or(config.venmo.currency).as_deref())?;
+
+    let asset = create_asset(
+        client,
+        &api_token,
+        &CreateAssetRequest {
+            type_name: args.type_,
+            subtype_name: Some(args.subtype),
+            name: args.name,
+            display_name: None,
+            balance: Amount(args.balance),
+            currency: Some(currency.iso_alpha_code.to_lowercase()),
+            institution_name: Some(args.institution_name),
+        },
+    )
+    .await?;
+
+    match format {
+        OutputFormat::Text => {
+            println!(
+                "Created Lunch Money asset '{}' with ID {}. Pass --lunch-money-asset-id {} or set \
+                 lunch_money.asset_id = {} in the config file to sync to it.",
+                asset.name, asset.id, asset.id, asset.id
+            );
+            Ok(())
+        }
+        OutputFormat::Json => output::json(&asset),
+    }
+}
+
+async fn cmd_list_lunch_money_tags(
+    client: &HttpsClient,
+    config: Config,
+    api_token: Option<String>,
+    no_pager: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let api_token = config.lunch_money.api_token_or(api_token)?.ok_or_else(|| {
+        anyhow!("--api-token, lunch_money.api_token, or lunch_money.token_command must be set")
+    })?;
+    let tags = get_all_tags(client, &api_token).await?;
+
+    match format {
+        OutputFormat::Text => output::page(&format!("{:#?}\n", tags), no_pager),
+        OutputFormat::Json => output::json(&tags),
+    }
+}
+
+/// Checks each Venmo API token (`api_token`, or the ones in the config file) is still valid, and
+/// fails if any isn't.
+async fn cmd_check_venmo_token(
+    client: &HttpsClient,
+    config: Config,
+    api_token: Option<String>,
+) -> Result<()> {
+    // (name, expected profile ID, API token)
+    let mut tokens = Vec::new();
+
+    if let Some(api_token) = api_token {
+        tokens.push(("--api-token".to_string(), None, api_token));
+    } else {
+        if let Some(api_token) = config.venmo.api_token_or(None)? {
+            tokens.push(("venmo".to_string(), config.venmo.profile_id, api_token));
+        }
+
+        for account in config.accounts {
+            tokens.push((
+                account.name(),
+                Some(account.venmo_profile_id),
+                account.venmo_api_token,
+            ));
+        }
+    }
+
+    if tokens.is_empty() {
+        bail!("--api-token, venmo.api_token, or [[accounts]] must be set");
+    }
+
+    let mut invalid = 0;
+
+    for (name, profile_id, api_token) in tokens {
+        match venmo::get_venmo_user(client, &api_token).await? {
+            Some(user) => {
+                println!(
+                    "{}: valid, logged in as {} (@{}), profile ID {}",
+                    name,
+                    user.display_name.as_deref().unwrap_or("unknown"),
+                    user.username,
+                    user.id
+                );
+
+                if let Some(profile_id) = profile_id {
+                    if profile_id.to_string() != user.id {
+                        println!(
+                            "{}: configured profile ID {} does not match the token's",
+                            name, profile_id
+                        );
+                        invalid += 1;
+                    }
+                }
+            }
+            None => {
+                println!("{}: invalid or expired", name);
+                invalid += 1;
+            }
+        }
+    }
+
+    if invalid > 0 {
+        bail!(venmo::Error::AuthError(format!(
+            "{} Venmo API token(s) need attention",
+            invalid
+        )));
+    }
+
+    Ok(())
+}
+
+/// A service whose API token can be passed as a flag, see `TokenArgs`.
+trait TokenService {
+    /// The token and token file flags of commands that only talk to this service, then of ones
+    /// that talk to several.
+    const FLAGS: [(&'static str, &'static str); 2];
+    /// Environment variable with the token, and with the path of a file holding it.
+    const ENV: (&'static str, &'static str);
+    /// Help of the token and token file flags.
+    const HELP: (&'static str, &'static str);
+}
+
+struct Venmo;
+
+impl TokenService for Venmo {
+    const FLAGS: [(&'static str, &'static str); 2] = [
+        ("api-token", "api-token-file"),
+        ("venmo-api-token", "venmo-api-token-file"),
+    ];
+    const ENV: (&'static str, &'static str) = ("VENMO_API_TOKEN", "VENMO_API_TOKEN_FILE");
+    const HELP: (&'static str, &'static str) = (
+        "Defaults to `venmo.api_token` from the config file",
+        "Read the Venmo API token from this file, or stdin if `-`, e.g. a mounted Docker secret",
+    );
+}
+
+struct LunchMoney;
+
+impl TokenService for LunchMoney {
+    const FLAGS: [(&'static str, &'static str); 2] = [
+        ("api-token", "api-token-file"),
+        ("lunch-money-api-token", "lunch-money-api-token-file"),
+    ];
+    const ENV: (&'static str, &'static str) = ("LUNCHMONEY_API_TOKEN", "LUNCHMONEY_API_TOKEN_FILE");
+    const HELP: (&'static str, &'static str) = (
+        "Defaults to `lunch_money.api_token` from the config file",
+        "Read the Lunch Money API token from this file, or stdin if `-`, e.g. a mounted Docker \
+         secret",
+    );
+}
+
+/// An API token flag of service `S`, or a file to read it from. Commands that only talk to `S`
+/// take `--api-token`, ones that talk to several take e.g. `--venmo-api-token` (`PREFIXED`).
+struct TokenArgs<S, const PREFIXED: bool = true> {
+    token: Option<String>,
+    service: PhantomData<S>,
+}
+
+impl<S: TokenService, const PREFIXED: bool> TokenArgs<S, PREFIXED> {
+    const FLAGS: (&'static str, &'static str) = S::FLAGS[PREFIXED as usize];
+
+    fn token(self) -> Option<String> {
+        self.token
+    }
+}
+
+impl<S: TokenService, const PREFIXED: bool> Args for TokenArgs<S, PREFIXED> {
+    fn augment_args(cmd: clap::Command<'_>) -> clap::Command<'_> {
+        let (token, file) = Self::FLAGS;
+        cmd.arg(
+            Arg::new(token)
+                .long(token)
+                .env(S::ENV.0)
+                .hide_env_values(true)
+                .takes_value(true)
+                .value_name("TOKEN")
+                .help(S::HELP.0),
+        )
+        .arg(
+            Arg::new(file)
+                .long(file)
+                .env(S::ENV.1)
+                .takes_value(true)
+                .value_name("PATH")
+                .value_parser(credentials::read_token_file)
+                .conflicts_with(token)
+                .help(S::HELP.1),
+        )
+    }
+
+    fn augment_args_for_update(cmd: clap::Command<'_>) -> clap::Command<'_> {
+        Self::augment_args(cmd)
+    }
+}
+
+impl<S: TokenService, const PREFIXED: bool> FromArgMatches for TokenArgs<S, PREFIXED> {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let (token, file) = Self::FLAGS;
+        Ok(TokenArgs {
+            token: matches
+                .get_one::<String>(file)
+                .or_else(|| matches.get_one::<String>(token))
+                .cloned(),
+            service: PhantomData,
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct SyncAccountArgs {
+    /// Defaults to `venmo.profile_id` from the config file.
+    #[clap(long, env = "VENMO_PROFILE_ID")]
+    venmo_profile_id: Option<u64>,
+
+    #[clap(flatten)]
+    venmo_token: TokenArgs<Venmo>,
+
+    #[clap(flatten)]
+    lunch_money_token: TokenArgs<LunchMoney>,
+
+    /// Defaults to `lunch_money.asset_id` from the config file.
+    #[clap(long, env = "LUNCHMONEY_ASSET_ID")]
+    lunch_money_asset_id: Option<u64>,
+
+    /// Defaults to `venmo.currency` from the config file, or USD.
+    #[clap(long, env = "VENMO_CURRENCY")]
+    currency: Option<String>,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+}
+
+impl SyncAccountArgs {
+    /// Overrides values in `config` with the ones given as flags.
+    fn merge(self, config: Config) -> Config {
+        let mut config = Config {
+            venmo: VenmoConfig {
+                profile_id: self.venmo_profile_id.or(config.venmo.profile_id),
+                api_token: self.venmo_token.token().or(config.venmo.api_token),
+                token_command: config.venmo.token_command,
+                reauth_command: config.venmo.reauth_command,
+                currency: Some(
+                    self.currency
+                        .or(config.venmo.currency)
+                        .unwrap_or_else(|| "USD".to_string()),
+                ),
+                statement_format: config.venmo.statement_format,
+            },
+            lunch_money: LunchMoneyConfig {
+                api_token: self
+                    .lunch_money_token
+                    .token()
+                    .or(config.lunch_money.api_token),
+                token_command: config.lunch_money.token_command,
+                asset_id: self.lunch_money_asset_id.or(config.lunch_money.asset_id),
+                tags: config.lunch_money.tags,
+                create_tags: config.lunch_money.create_tags,
+            },
+            sync: config.sync,
+            accounts: config.accounts,
+            rules: config.rules,
+            payees: config.payees,
+            templates: config.templates,
+            notify: config.notify,
+            paypal: config.paypal,
+            ynab: config.ynab,
+            firefly: config.firefly,
+        };
+        self.tags.merge(&mut config);
+        config
+    }
+}
+
+/// How Lunch Money should process inserted transactions, shared by the syncing commands and the
+/// CSV imports.
+#[derive(Args)]
+struct InsertArgs {
+    /// Don't apply the Lunch Money account's rules to inserted transactions.
+    #[clap(long)]
+    no_apply_rules: bool,
+
+    /// Don't match inserted transactions against recurring expenses.
+    #[clap(long)]
+    no_check_for_recurring: bool,
+
+    /// Also skip transactions with the same date, payee, and amount as an existing transaction,
+    /// not just ones that were already synced.
+    #[clap(long)]
+    skip_duplicates: bool,
+
+    /// Don't update the Lunch Money asset's balance.
+    #[clap(long)]
+    skip_balance_update: bool,
+
+    /// Venmo status (e.g. `Complete`) of transactions to insert as cleared rather than
+    /// uncleared, can be repeated. Defaults to `sync.cleared_statuses` from the config file.
+    #[clap(long = "cleared-status")]
+    cleared_statuses: Vec<String>,
+
+    /// Only sync Venmo transactions of these types, comma separated, e.g. `Payment,Charge`.
+    /// Defaults to `sync.include_types` from the config file, or every type.
+    #[clap(long, value_delimiter = ',')]
+    include_types: Vec<String>,
+
+    /// Don't sync Venmo transactions of these types, comma separated, e.g. `StandardTransfer`
+    /// when bank transfers are tracked elsewhere. Defaults to `sync.exclude_types` from the
+    /// config file.
+    #[clap(long, value_delimiter = ',')]
+    exclude_types: Vec<String>,
+
+    /// Don't sync Venmo transactions whose counterparty (or bank account, for transfers)
+    /// matches this regex, can be repeated. Defaults to `sync.exclude_payee_regex` from the
+    /// config file.
+    #[clap(long)]
+    exclude_payee_regex: Vec<String>,
+
+    /// Don't sync Venmo transactions whose note matches this regex, can be repeated. Defaults to
+    /// `sync.exclude_note_regex` from the config file.
+    #[clap(long)]
+    exclude_note_regex: Vec<String>,
+
+    /// Don't sync Venmo transactions for less than this amount, sent or received, e.g. `1.00`
+    /// to skip test payments. Defaults to `sync.min_amount` from the config file.
+    #[clap(long)]
+    min_amount: Option<String>,
+
+    /// Don't sync Venmo transactions for more than this amount, sent or received. Defaults to
+    /// `sync.max_amount` from the config file.
+    #[clap(long)]
+    max_amount: Option<String>,
+
+    /// Don't generate "shadow" transfers for payments funded by, and money deposited to, bank
+    /// accounts, e.g. when the bank account is already in Lunch Money.
+    #[clap(long)]
+    no_shadow_transactions: bool,
+
+    /// Lunch Money asset ID of a bank account, by its name in Venmo statements, e.g. `"Chase
+    /// Checking *1234=54321"`, to insert the other side of transfers to or from it into, can be
+    /// repeated. Added to `sync.transfer_asset_ids` from the config file.
+    #[clap(long = "transfer-asset-id", value_parser = parse_transfer_asset_id)]
+    transfer_asset_ids: Vec<(String, u64)>,
+
+    /// Group each inserted payment with its shadow transfers in Lunch Money.
+    #[clap(long)]
+    group_transfers: bool,
+
+    /// Lunch Money asset ID of the Venmo Credit Card, to insert purchases and payments made
+    /// with it into. Defaults to `sync.credit_card_asset_id` from the config file.
+    #[clap(long)]
+    credit_card_asset_id: Option<u64>,
+
+    /// Insert tips and Venmo fees, e.g. for instant transfers, as their own transactions so fees
+    /// are tracked as spending.
+    #[clap(long)]
+    split_tips_and_fees: bool,
+
+    /// Insert up to this many chunks of transactions (see `--chunk-size`) into Lunch Money at
+    /// once, to speed up large backfills. Defaults to `sync.parallelism` from the config file,
+    /// or 1.
+    #[clap(long)]
+    parallelism: Option<usize>,
+
+    /// Insert this many transactions into Lunch Money per request, up to 500. Defaults to 50.
+    #[clap(long, value_parser = parse_chunk_size)]
+    chunk_size: Option<usize>,
+
+    /// Exit with an error if the synced transactions don't add up to the Venmo statement's
+    /// ending balance, e.g. because some were of unknown types.
+    #[clap(long)]
+    fail_on_balance_drift: bool,
+}
+
+impl InsertArgs {
+    fn options(&self) -> InsertOptions {
+        InsertOptions {
+            apply_rules: !self.no_apply_rules,
+            check_for_recurring: !self.no_check_for_recurring,
+            skip_duplicates: self.skip_duplicates,
+            skip_balance_update: self.skip_balance_update,
+            chunk_size: self.chunk_size.unwrap_or(DEFAULT_INSERT_CHUNK_SIZE),
+            ..Default::default()
+        }
+    }
+
+    /// Overrides values in the `[sync]` section of `config` with the ones given as flags.
+    fn merge(&self, config: &mut Config) {
+        if !self.cleared_statuses.is_empty() {
+            config.sync.cleared_statuses = Some(self.cleared_statuses.clone());
+        }
+        if !self.include_types.is_empty() {
+            config.sync.include_types = Some(self.include_types.clone());
+        }
+        if !self.exclude_types.is_empty() {
+            config.sync.exclude_types = Some(self.exclude_types.clone());
+        }
+        if !self.exclude_payee_regex.is_empty() {
+            config.sync.exclude_payee_regex = self.exclude_payee_regex.clone();
+        }
+        if !self.exclude_note_regex.is_empty() {
+            config.sync.exclude_note_regex = self.exclude_note_regex.clone();
+        }
+        config.sync.min_amount = self.min_amount.clone().or(config.sync.min_amount.take());
+        config.sync.max_amount = self.max_amount.clone().or(config.sync.max_amount.take());
+        if self.no_shadow_transactions {
+            config.sync.shadow_transactions = Some(false);
+        }
+        config
+            .sync
+            .transfer_asset_ids
+            .extend(self.transfer_asset_ids.iter().cloned());
+        config.sync.group_transfers |= self.group_transfers;
+        config.sync.split_tips_and_fees |= self.split_tips_and_fees;
+        config.sync.credit_card_asset_id = self
+            .credit_card_asset_id
+            .or(config.sync.credit_card_asset_id);
+        config.sync.parallelism = self.parallelism.or(config.sync.parallelism);
+        config.sync.fail_on_balance_drift |= self.fail_on_balance_drift;
+    }
+}
+
+/// Parses a `--transfer-asset-id` as a bank account name and Lunch Money asset ID.
+fn parse_transfer_asset_id(value: &str) -> Result<(String, u64)> {
+    let (account, asset_id) = value
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("Expected <bank account>=<asset id>, got '{}'", value))?;
+    let asset_id = asset_id
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid asset ID '{}': {}", asset_id, e))?;
+
+    Ok((account.trim().to_string(), asset_id))
+}
+
+/// Parses a `--chunk-size`, which Lunch Money caps.
+fn parse_chunk_size(value: &str) -> Result<usize> {
+    let chunk_size = value
+        .parse()
+        .map_err(|e| anyhow!("Invalid chunk size '{}': {}", value, e))?;
+
+    if !(1..=MAX_INSERT_CHUNK_SIZE).contains(&chunk_size) {
+        bail!(
+            "Chunk size must be between 1 and {}, Lunch Money's limit per request",
+            MAX_INSERT_CHUNK_SIZE
+        );
+    }
+
+    Ok(chunk_size)
+}
+
+/// Budgeting app to insert transactions into.
+#[derive(Clone, Copy, ArgEnum)]
+enum Target {
+    /// A Lunch Money asset, see `--lunch-money-asset-id`.
+    LunchMoney,
+    /// A YNAB account, see `--ynab-account-id`.
+    Ynab,
+    /// A Firefly III asset account, see `--firefly-account-id`.
+    Firefly,
+}
+
+/// Where to insert transactions, shared by `sync-venmo-transactions` and the CSV imports.
+#[derive(Args)]
+struct TargetArgs {
+    /// Budgeting app to insert the transactions into.
+    #[clap(
+        long,
+        env = "VENMO_SYNC_TARGET",
+        arg_enum,
+        default_value = "lunch-money"
+    )]
+    target: Target,
+
+    /// YNAB personal access token. Defaults to `ynab.api_token` from the config file.
+    #[clap(long, env = "YNAB_API_TOKEN", hide_env_values = true)]
+    ynab_api_token: Option<String>,
+
+    /// Defaults to `ynab.budget_id` from the config file, or the last used budget.
+    #[clap(long, env = "YNAB_BUDGET_ID")]
+    ynab_budget_id: Option<String>,
+
+    /// Defaults to `ynab.account_id` from the config file.
+    #[clap(long, env = "YNAB_ACCOUNT_ID")]
+    ynab_account_id: Option<String>,
+
+    /// URL of the Firefly III instance. Defaults to `firefly.base_url` from the config file.
+    #[clap(long, env = "FIREFLY_URL")]
+    firefly_url: Option<String>,
+
+    /// Firefly III personal access token. Defaults to `firefly.api_token` from the config file.
+    #[clap(long, env = "FIREFLY_API_TOKEN", hide_env_values = true)]
+    firefly_api_token: Option<String>,
+
+    /// Defaults to `firefly.account_id` from the config file.
+    #[clap(long, env = "FIREFLY_ACCOUNT_ID")]
+    firefly_account_id: Option<u64>,
+}
+
+impl TargetArgs {
+    /// Overrides values in the `[ynab]` and `[firefly]` sections of `config` with the ones given
+    /// as flags.
+    fn merge(&self, config: &mut Config) {
+        config.ynab.api_token = self.ynab_api_token.clone().or(config.ynab.api_token.take());
+        config.ynab.budget_id = self.ynab_budget_id.clone().or(config.ynab.budget_id.take());
+        config.ynab.account_id = self
+            .ynab_account_id
+            .clone()
+            .or(config.ynab.account_id.take());
+        config.firefly.base_url = self.firefly_url.clone().or(config.firefly.base_url.take());
+        config.firefly.api_token = self
+            .firefly_api_token
+            .clone()
+            .or(config.firefly.api_token.take());
+        config.firefly.account_id = self.firefly_account_id.or(config.firefly.account_id);
+    }
+
+    /// The account to insert into, `insert_options` only apply to Lunch Money.
+    fn sink(
+        &self,
+        config: &Config,
+        insert_options: InsertOptions,
+    ) -> Result<Box<dyn TransactionSink>> {
+        Ok(match self.target {
+            Target::LunchMoney => {
+                let mut destination = LunchMoneyDestination::from_config(config)?;
+                destination.insert_options = insert_options;
+                Box::new(destination)
+            }
+            Target::Ynab => Box::new(ynab::YnabDestination::from_config(config)?),
+            Target::Firefly => Box::new(firefly::FireflyDestination::from_config(config)?),
+        })
+    }
+}
+
+/// URLs to ping after syncs, shared by `sync-venmo-transactions`, `sync-all`, and `daemon`.
+#[derive(Args)]
+struct NotifyArgs {
+    /// URL to POST a JSON summary to after each successful sync, e.g. a Healthchecks.io ping URL.
+    /// Defaults to `notify.on_success_url` from the config file.
+    #[clap(long, env = "VENMO_SYNC_ON_SUCCESS_URL", hide_env_values = true)]
+    on_success_url: Option<String>,
+
+    /// URL to POST a JSON error to after each failed sync, e.g. a Healthchecks.io `/fail` URL.
+    /// Defaults to `notify.on_failure_url` from the config file.
+    #[clap(long, env = "VENMO_SYNC_ON_FAILURE_URL", hide_env_values = true)]
+    on_failure_url: Option<String>,
+}
+
+impl NotifyArgs {
+    /// Overrides values in the `[notify]` section of `config` with the ones given as flags.
+    fn merge(self, config: &mut Config) {
+        config.notify.on_success_url = self.on_success_url.or(config.notify.on_success_url.take());
+        config.notify.on_failure_url = self.on_failure_url.or(config.notify.on_failure_url.take());
+    }
+}
+
+/// Pings the `[notify]` URL for `result` and sends `results`, each the name of a synced account
+/// and the result of syncing it, to the `[notify]` channels. Failures are logged rather than
+/// returned, so a flaky notification doesn't turn a successful sync into a failed one.
+async fn notify<T: serde::Serialize>(
+    client: &HttpsClient,
+    config: &Config,
+    result: Result<T, &anyhow::Error>,
+    results: &[(&str, &Result<SyncSummary>)],
+) {
+    if let Err(e) = notify::notify_result(client, &config.notify, result).await {
+        warn!("{:#}", e);
+    }
+    if let Err(e) = notify::notify_channels(client, &config.notify, results).await {
+        warn!("{:#}", e);
+    }
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    #[clap(flatten)]
+    dates: DateRangeArgs,
+
+    /// Defaults to `venmo.profile_id` from the config file.
+    #[clap(long, env = "VENMO_PROFILE_ID")]
+    profile_id: Option<u64>,
+
+    #[clap(flatten)]
+    api_token: TokenArgs<Venmo, false>,
+
+    /// Defaults to `venmo.currency` from the config file, or USD.
+    #[clap(long, env = "VENMO_CURRENCY")]
+    currency: Option<String>,
+
+    /// Convert this statement CSV downloaded from the Venmo website instead of fetching one from
+    /// the Venmo API. The date range flags then only set the statement's reported range.
+    #[clap(long)]
+    statement: Option<PathBuf>,
+
+    #[clap(long, arg_enum, default_value = "ofx")]
+    format: ExportFormat,
+
+    /// The `INTU.BID` Quicken identifies the financial institution by, required for QFX.
+    #[clap(long, required_if_eq("format", "qfx"))]
+    intu_bid: Option<String>,
+
+    /// Beancount or Ledger account for the Venmo balance.
+    #[clap(long, default_value = "Assets:Venmo")]
+    venmo_account: String,
+
+    /// Beancount or Ledger account for the other side of money sent from Venmo.
+    #[clap(long, default_value = "Expenses:Venmo")]
+    expense_account: String,
+
+    /// Beancount or Ledger account for the other side of money received in Venmo.
+    #[clap(long, default_value = "Income:Venmo")]
+    income_account: String,
+
+    /// Beancount or Ledger account the accounts of banks funding payments or receiving transfers
+    /// go under, e.g. `Assets:Banks:Chase-Checking-1234`.
+    #[clap(long, default_value = "Assets:Banks")]
+    bank_accounts: String,
+
+    /// File to write to, defaults to stdout.
+    #[clap(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum ExportFormat {
+    /// OFX 1.0.2, for GnuCash, Banktivity, and most other finance tools.
+    Ofx,
+    /// Quicken's flavor of OFX.
+    Qfx,
+    /// A Beancount journal.
+    Beancount,
+    /// A Ledger journal, also read by hledger.
+    Ledger,
+}
+
+async fn cmd_export(
+    client: &HttpsClient,
+    config: Config,
+    statements: &StatementStore,
+    args: ExportArgs,
+) -> Result<()> {
+    let (start_date, end_date) = args.dates.range(&config.sync)?;
+    let currency = sync::find_currency(args.currency.or(config.venmo.currency.clone()).as_deref())?;
+    let profile_id = args.profile_id.or(config.venmo.profile_id);
+
+    let statement = match args.statement {
+        Some(path) => {
+            let bytes = std::fs::read(&path)
+                .with_context(|| anyhow!("Failed to read statement {:?}", path))?;
+
+            venmo::parse_venmo_statement(&bytes, &config.venmo.statement_format)
+                .with_context(|| anyhow!("Failed to parse statement {:?}", path))?
+        }
+        None => {
+            let account = AccountRecord {
+                profile_id: profile_id
+                    .ok_or_else(|| anyhow!("--profile-id or venmo.profile_id must be set"))?,
+                api_token: config
+                    .venmo
+                    .api_token_or(args.api_token.token())?
+                    .ok_or_else(|| {
+                        anyhow!("--api-token, venmo.api_token, or venmo.token_command must be set")
+                    })?,
+                currency,
+            };
+
+            fetch_venmo_transactions(
+                client,
+                &account,
+                &config.venmo.statement_format,
+                statements,
+                &start_date,
+                &end_date,
+            )
+            .await?
+        }
+    };
+
+    let ofx_account = || ofx::OfxAccount {
+        bank_id: "VENMO".to_string(),
+        account_id: profile_id.map_or_else(|| "VENMO".to_string(), |id| id.to_string()),
+        currency,
+    };
+    let accounts = ledger::JournalAccounts {
+        venmo: args.venmo_account,
+        expenses: args.expense_account,
+        income: args.income_account,
+        banks: args.bank_accounts,
+    };
+    let range = (start_date, end_date);
+    let options = config.convert_options()?;
+
+    let exported = match args.format {
+        ExportFormat::Ofx => ofx::statement_to_ofx(
+            &statement,
+            &ofx_account(),
+            &options,
+            range,
+            &ofx::OfxFormat::Ofx,
+        )?,
+        ExportFormat::Qfx => ofx::statement_to_ofx(
+            &statement,
+            &ofx_account(),
+            &options,
+            range,
+            &ofx::OfxFormat::Qfx {
+                // Required by clap for QFX.
+                intu_bid: args.intu_bid.unwrap_or_default(),
+            },
+        )?,
+        ExportFormat::Beancount => ledger::statement_to_journal(
+            &statement,
+            currency,
+            &accounts,
+            ledger::JournalFormat::Beancount,
+        )?,
+        ExportFormat::Ledger => ledger::statement_to_journal(
+            &statement,
+            currency,
+            &accounts,
+            ledger::JournalFormat::Ledger,
+        )?,
+    };
+
+    match args.out {
+        Some(path) => {
+            std::fs::write(&path, exported).with_context(|| anyhow!("Failed to write {:?}", path))
+        }
+        None => {
+            print!("{}", exported);
+            Ok(())
+        }
+    }
+}
+
+#[derive(Args)]
+struct SyncVenmoTransactionsArgs {
+    #[clap(flatten)]
+    dates: DateRangeArgs,
+
+    #[clap(flatten)]
+    accounts: SyncAccountArgs,
+
+    #[clap(flatten)]
+    insert: InsertArgs,
+
+    #[clap(flatten)]
+    target: TargetArgs,
+
+    #[clap(flatten)]
+    notify: NotifyArgs,
+
+    /// Print the effective configuration (config file merged with flags and environment
+    /// variables, secrets masked) and exit without syncing.
+    #[clap(long)]
+    print_config: bool,
+
+    /// Fetch and convert Venmo transactions, printing the Lunch Money transactions that would be
+    /// inserted without inserting them or updating the sync state.
+    #[clap(long, env = "VENMO_SYNC_DRY_RUN")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    lock: LockArgs,
+
+    /// Finish the last sync to the same asset that failed partway through inserting, over its
+    /// date range, skipping the transactions it already inserted.
+    #[clap(long, conflicts_with_all = &["start-from", "end-to", "start-date", "end-date"])]
+    resume: bool,
+}
+
+async fn cmd_sync_venmo_transactions(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: Config,
+    statements: &StatementStore,
+    args: SyncVenmoTransactionsArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut config = args.accounts.merge(config);
+
+    if let Some(start_from) = args.dates.start_from {
+        config.sync.start_from = Some(humantime::format_duration(start_from).to_string());
+    }
+    if let Some(end_to) = args.dates.end_to {
+        config.sync.end_to = Some(humantime::format_duration(end_to).to_string());
+    }
+
+    args.insert.merge(&mut config);
+    args.target.merge(&mut config);
+    args.notify.merge(&mut config);
+
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config.masked())?);
+        return Ok(());
+    }
+
+    if !matches!(args.target.target, Target::LunchMoney) {
+        if args.resume {
+            bail!("--resume is only supported with --target lunch-money");
+        }
+        let sink = args.target.sink(&config, args.insert.options())?;
+        let _lock = if args.dry_run {
+            None
+        } else {
+            match sync_lock(paths, &sink.name(), args.lock.wait_for_lock).await? {
+                Some(lock) => Some(lock),
+                None => return Ok(()),
+            }
+        };
+        return sync_venmo_to_sink(
+            client,
+            config,
+            statements,
+            sink.as_ref(),
+            (args.dates.start_date, args.dates.end_date),
+            args.dry_run,
+            format,
+        )
+        .await;
+    }
+
+    let mut accounts = SyncAccounts::from_config(&config)?;
+    accounts.statements = statements.clone();
+    accounts.lunch_money.insert_options = args.insert.options();
+    let _lock = if args.dry_run {
+        None
+    } else {
+        match sync_lock(paths, &accounts.lunch_money.name(), args.lock.wait_for_lock).await? {
+            Some(lock) => Some(lock),
+            None => return Ok(()),
+        }
+    };
+    let (mut start_date, mut end_date) = args.dates.range(&config.sync)?;
+
+    // Without an explicit `--start-from` or `--start-date`, pick up where the last sync left off.
+    if !args.dates.has_start() {
+        start_date = sync::next_start_date(paths, &accounts, &config.sync)?.min(end_date);
+    }
+
+    let interrupted = if args.resume {
+        let interrupted = sync::interrupted_sync(paths, &accounts)?;
+        start_date = interrupted.start_date;
+        end_date = interrupted.end_date;
+        Some(interrupted)
+    } else {
+        None
+    };
+
+    let res = sync::sync_venmo_transactions(
+        client,
+        paths,
+        &accounts,
+        start_date,
+        end_date,
+        args.dry_run,
+        interrupted.as_ref(),
+    )
+    .await;
+    let name = accounts.venmo.profile_id.to_string();
+    notify(
+        client,
+        &config,
+        res.as_ref().map(SyncSummary::counts),
+        &[(&name, &res)],
+    )
+    .await;
+
+    let summary = res?;
+    output::print(&summary, format)?;
+    sync::check_balance_drift(&config.sync, &summary)
+}
+
+/// Locks syncing to `destination`, waiting for another sync holding the lock if `wait`,
+/// otherwise warning and returning `None`.
+async fn sync_lock(paths: &Paths, destination: &str, wait: bool) -> Result<Option<SyncLock>> {
+    if wait {
+        return Ok(Some(state::lock_sync(paths, destination).await?));
+    }
+
+    let lock = state::try_lock_sync(paths, destination)?;
+    if lock.is_none() {
+        warn!(
+            "Another sync to {} is running, skipping this one. Pass --wait-for-lock to wait for \
+             it instead.",
+            destination
+        );
+    }
+    Ok(lock)
+}
+
+/// `sync-venmo-transactions` with a `--target` other than Lunch Money. The sync state only
+/// tracks Lunch Money assets, so the date range doesn't pick up where the last sync left off,
+/// targets skip transactions already synced by their IDs instead.
+async fn sync_venmo_to_sink(
+    client: &HttpsClient,
+    config: Config,
+    statements: &StatementStore,
+    sink: &dyn TransactionSink,
+    (start_date, end_date): (Option<NaiveDate>, Option<NaiveDate>),
+    dry_run: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let account = sync::venmo_account(&config)?;
+    let (start_date, end_date) = sync::with_absolute_dates(
+        sync::date_range(None, None, &config.sync)?,
+        start_date,
+        end_date,
+    )?;
+
+    let res = async {
+        let statement = fetch_venmo_transactions(
+            client,
+            &account,
+            &config.venmo.statement_format,
+            statements,
+            &start_date,
+            &end_date,
+        )
+        .await?;
+
+        sink.insert_statement(client, &account.currency, statement, dry_run)
+            .await
+    }
+    .await;
+    let name = account.profile_id.to_string();
+    notify(
+        client,
+        &config,
+        res.as_ref().map(SyncSummary::counts),
+        &[(&name, &res)],
+    )
+    .await;
+
+    let summary = res?;
+    output::print(&summary, format)?;
+    sync::check_balance_drift(&config.sync, &summary)
+}
+
+#[derive(Args)]
+struct ReconcileArgs {
+    #[clap(flatten)]
+    dates: DateRangeArgs,
+
+    #[clap(flatten)]
+    accounts: SyncAccountArgs,
+}
+
+/// Compares the Venmo statement to the Lunch Money asset it's synced to, and fails if they
+/// differ.
+async fn cmd_reconcile(
+    client: &HttpsClient,
+    config: Config,
+    statements: &StatementStore,
+    args: ReconcileArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    let config = args.accounts.merge(config);
+    let accounts = SyncAccounts::from_config(&config)?;
+    let (start_date, end_date) = args.dates.range(&config.sync)?;
+
+    let statement = fetch_venmo_transactions(
+        client,
+        &accounts.venmo,
+        &accounts.statement_format,
+        statements,
+        &start_date,
+        &end_date,
+    )
+    .await?;
+    let reconciliation = reconcile::reconcile(
+        client,
+        &accounts.lunch_money,
+        &accounts.venmo.currency,
+        &statement,
+        (start_date.naive_utc().date(), end_date.naive_utc().date()),
+    )
+    .await?;
+
+    output::print(&reconciliation, format)?;
+
+    let discrepancies = reconciliation.discrepancies();
+    if discrepancies > 0 {
+        bail!("Venmo and Lunch Money differ in {} place(s)", discrepancies);
+    }
+
+    Ok(())
+}
+
+#[derive(Args)]
+struct ImportCsvArgs {
+    /// Statement CSV downloaded from the Venmo website, or activity export from the Cash App
+    /// website.
+    #[clap(long)]
+    file: PathBuf,
+
+    #[clap(flatten)]
+    lunch_money_token: TokenArgs<LunchMoney>,
+
+    /// Defaults to `lunch_money.asset_id` from the config file.
+    #[clap(long, env = "LUNCHMONEY_ASSET_ID")]
+    lunch_money_asset_id: Option<u64>,
+
+    /// Defaults to `venmo.currency` from the config file, or USD.
+    #[clap(long, env = "VENMO_CURRENCY")]
+    currency: Option<String>,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+
+    #[clap(flatten)]
+    insert: InsertArgs,
+
+    #[clap(flatten)]
+    target: TargetArgs,
+
+    /// Print the transactions that would be inserted without inserting them.
+    #[clap(long, env = "VENMO_SYNC_DRY_RUN")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    lock: LockArgs,
+}
+
+async fn cmd_import_csv(
+    client: &HttpsClient,
+    paths: &Paths,
+    mut config: Config,
+    args: ImportCsvArgs,
+    format: OutputFormat,
+    parse: fn(&[u8], &Config) -> Result<Statement>,
+) -> Result<()> {
+    config.lunch_money.api_token = args
+        .lunch_money_token
+        .token()
+        .or(config.lunch_money.api_token);
+    config.lunch_money.asset_id = args.lunch_money_asset_id.or(config.lunch_money.asset_id);
+
+    args.tags.merge(&mut config);
+    args.insert.merge(&mut config);
+    args.target.merge(&mut config);
+
+    let destination = args.target.sink(&config, args.insert.options())?;
+    let currency = sync::find_currency(args.currency.or(config.venmo.currency.clone()).as_deref())?;
+    let _lock = if args.dry_run {
+        None
+    } else {
+        match sync_lock(paths, &destination.name(), args.lock.wait_for_lock).await? {
+            Some(lock) => Some(lock),
+            None => return Ok(()),
+        }
+    };
+
+    let bytes = std::fs::read(&args.file)
+        .with_context(|| anyhow!("Failed to read statement {:?}", args.file))?;
+    let statement = parse(&bytes, &config)
+        .with_context(|| anyhow!("Failed to parse statement {:?}", args.file))?;
+
+    let summary = destination
+        .insert_statement(client, &currency, statement, args.dry_run)
+        .await?;
+
+    output::print(&summary, format)?;
+    sync::check_balance_drift(&config.sync, &summary)
+}
+
+#[derive(Args)]
+struct SyncPayPalTransactionsArgs {
+    #[clap(flatten)]
+    dates: DateRangeArgs,
+
+    /// Client ID of a PayPal REST API app with Transaction Search enabled. Defaults to
+    /// `paypal.client_id` from the config file.
+    #[clap(long, env = "PAYPAL_CLIENT_ID")]
+    paypal_client_id: Option<String>,
+
+    /// Defaults to `paypal.client_secret` from the config file.
+    #[clap(long, env = "PAYPAL_CLIENT_SECRET", hide_env_values = true)]
+    paypal_client_secret: Option<String>,
+
+    /// Use PayPal's sandbox API, for sandbox app credentials.
+    #[clap(long, env = "PAYPAL_SANDBOX")]
+    paypal_sandbox: bool,
+
+    #[clap(flatten)]
+    lunch_money_token: TokenArgs<LunchMoney>,
+
+    /// Defaults to `paypal.lunch_money_asset_id` from the config file, then
+    /// `lunch_money.asset_id`.
+    #[clap(long, env = "PAYPAL_LUNCHMONEY_ASSET_ID")]
+    lunch_money_asset_id: Option<u64>,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+
+    #[clap(flatten)]
+    insert: InsertArgs,
+
+    #[clap(flatten)]
+    notify: NotifyArgs,
+
+    /// Print the Lunch Money transactions that would be inserted without inserting them.
+    #[clap(long, env = "VENMO_SYNC_DRY_RUN")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    lock: LockArgs,
+}
+
+async fn cmd_sync_paypal_transactions(
+    client: &HttpsClient,
+    paths: &Paths,
+    mut config: Config,
+    args: SyncPayPalTransactionsArgs,
+    format: OutputFormat,
+) -> Result<()> {
+    config.paypal.client_id = args.paypal_client_id.or(config.paypal.client_id);
+    config.paypal.client_secret = args.paypal_client_secret.or(config.paypal.client_secret);
+    config.paypal.sandbox |= args.paypal_sandbox;
+    config.lunch_money.api_token = args
+        .lunch_money_token
+        .token()
+        .or(config.lunch_money.api_token);
+    config.lunch_money.asset_id = args
+        .lunch_money_asset_id
+        .or(config.paypal.lunch_money_asset_id)
+        .or(config.lunch_money.asset_id);
+
+    args.tags.merge(&mut config);
+    args.insert.merge(&mut config);
+    args.notify.merge(&mut config);
+
+    let account = paypal::PayPalAccount::from_config(&config.paypal)?;
+    let mut destination = LunchMoneyDestination::from_config(&config)?;
+    destination.insert_options = args.insert.options();
+    let (start_date, end_date) = args.dates.range(&config.sync)?;
+    let _lock = if args.dry_run {
+        None
+    } else {
+        match sync_lock(paths, &destination.name(), args.lock.wait_for_lock).await? {
+            Some(lock) => Some(lock),
+            None => return Ok(()),
+        }
+    };
+
+    let res = async {
+        let statement = paypal::fetch_paypal_transactions(
+            client,
+            &account,
+            &start_date,
+            &end_date,
+            config.venmo.statement_format.lenient,
+        )
+        .await?;
+
+        sync::insert_statement(
+            client,
+            &destination,
+            &account.currency,
+            statement,
+            &[],
+            args.dry_run,
+        )
+        .await
+    }
+    .await;
+    notify(
+        client,
+        &config,
+        res.as_ref().map(SyncSummary::counts),
+        &[("PayPal", &res)],
+    )
+    .await;
+
+    let summary = res?;
+    output::print(&summary, format)?;
+    sync::check_balance_drift(&config.sync, &summary)
+}
+
+#[derive(Args)]
+struct UndoLastSyncArgs {
+    #[clap(flatten)]
+    lunch_money_token: TokenArgs<LunchMoney>,
+
+    /// Only undo the last sync to this asset, defaults to the last sync to any asset.
+    #[clap(long)]
+    lunch_money_asset_id: Option<u64>,
+
+    /// Tag to flag the inserted transactions with. Lunch Money's API can't delete transactions,
+    /// filter by this tag to delete them in Lunch Money.
+    #[clap(long, default_value = sync::UNDO_TAG)]
+    tag: String,
+
+    /// Print the sync that would be undone without changing anything.
+    #[clap(long, env = "VENMO_SYNC_DRY_RUN")]
+    dry_run: bool,
+}
+
+async fn cmd_undo_last_sync(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: Config,
+    args: UndoLastSyncArgs,
+) -> Result<()> {
+    let api_token = config
+        .lunch_money
+        .api_token_or(args.lunch_money_token.token())?
+        .ok_or_else(|| anyhow!("--lunch-money-api-token, lunch_money.api_token, or lunch_money.token_command must be set"))?;
+
+    let run = sync::undo_last_sync(
+        client,
+        paths,
+        &api_token,
+        args.lunch_money_asset_id,
+        &args.tag,
+        args.dry_run,
+    )
+    .await?
+    .ok_or_else(|| anyhow!("No recorded syncs to undo"))?;
+
+    println!(
+        "{} sync of Venmo profile {} to Lunch Money asset {} at {} ({} to {}), {} inserted transactions",
+        if args.dry_run { "Would undo" } else { "Undid" },
+        run.venmo_profile_id,
+        run.lunch_money_asset_id,
+        run.synced_at.format("%Y-%m-%d %H:%M"),
+        run.start_date.format("%Y-%m-%d"),
+        run.end_date.format("%Y-%m-%d"),
+        run.inserted_ids.len()
+    );
+
+    if !args.dry_run && !run.inserted_ids.is_empty() {
+        println!(
+            "Filter by the '{}' tag in Lunch Money to review and delete them",
+            args.tag
+        );
+    }
+
+    Ok(())
+}
 
-    #[clap(long, value_parser = humantime::parse_duration)]
-    end_to: Option<Duration>,
+#[derive(Args)]
+struct SyncAllArgs {
+    #[clap(flatten)]
+    dates: DateRangeArgs,
 
-    #[clap(long)]
-    profile_id: u64,
+    /// Print the Lunch Money transactions that would be inserted for each account without
+    /// inserting them or updating the sync state.
+    #[clap(long, env = "VENMO_SYNC_DRY_RUN")]
+    dry_run: bool,
 
-    #[clap(long)]
-    api_token: String,
+    #[clap(flatten)]
+    lock: LockArgs,
 
-    #[clap(long, default_value = "USD")]
-    currency: String,
+    #[clap(flatten)]
+    insert: InsertArgs,
+
+    #[clap(flatten)]
+    notify: NotifyArgs,
 }
 
-async fn cmd_list_venmo_transactions(
+async fn cmd_sync_all(
     client: &HttpsClient,
-    args: ListVenmoTransactionsArgs,
+    paths: &Paths,
+    mut config: Config,
+    statements: &StatementStore,
+    args: SyncAllArgs,
+    format: OutputFormat,
 ) -> Result<()> {
-    let end_date: DateTime<Utc> = {
-        let mut end_date = Local::now();
+    args.insert.merge(&mut config);
+    args.notify.merge(&mut config);
+
+    if config.accounts.is_empty() {
+        bail!("No [[accounts]] are set in the config file");
+    }
+    // Accounts without their own Lunch Money token share the one `token_command` prints.
+    if config
+        .accounts
+        .iter()
+        .any(|account| account.lunch_money_api_token.is_none())
+    {
+        config.lunch_money.cache_api_token()?;
+    }
 
-        if let Some(duration) = args.end_to {
-            end_date = end_date - chrono::Duration::from_std(duration).unwrap();
+    let mut results = Vec::new();
+
+    for account in &config.accounts {
+        if format == OutputFormat::Text {
+            println!("Syncing {}", account.name());
         }
 
-        end_date.into()
-    };
+        let res = async {
+            let mut accounts = SyncAccounts::from_account_config(&config, account)?;
+            accounts.statements = statements.clone();
+            accounts.lunch_money.insert_options = args.insert.options();
+            let _lock = if args.dry_run {
+                None
+            } else {
+                match sync_lock(paths, &accounts.lunch_money.name(), args.lock.wait_for_lock)
+                    .await?
+                {
+                    Some(lock) => Some(lock),
+                    None => return Ok(None),
+                }
+            };
+            let (mut start_date, end_date) = args.dates.range(&config.sync)?;
 
-    let start_date: DateTime<Utc> =
-        (Local::now() - chrono::Duration::from_std(args.start_from).unwrap()).into();
+            if !args.dates.has_start() {
+                start_date = sync::next_start_date(paths, &accounts, &config.sync)?.min(end_date);
+            }
 
-    let account = AccountRecord {
-        profile_id: args.profile_id,
-        api_token: args.api_token.clone(),
-        currency: *rusty_money::iso::find(&args.currency)
-            .ok_or_else(|| anyhow!("Given currency {} is not valid", args.currency))?,
-    };
+            sync::sync_venmo_transactions(
+                client,
+                paths,
+                &accounts,
+                start_date,
+                end_date,
+                args.dry_run,
+                None,
+            )
+            .await
+            .and_then(|summary| {
+                sync::check_balance_drift(&config.sync, &summary)?;
+                Ok(Some(summary))
+            })
+        }
+        .await;
+
+        let res = match res.transpose() {
+            Some(res) => res,
+            None => continue,
+        };
 
-    let transactions = fetch_venmo_transactions(client, &account, &start_date, &end_date).await?;
+        if let (OutputFormat::Text, Ok(summary)) = (format, &res) {
+            println!("{}", summary);
+        }
 
-    println!("{:#?}", transactions);
+        results.push((account.name(), res));
+    }
 
-    Ok(())
-}
+    let failures = results.iter().filter(|(_, res)| res.is_err()).count();
+    let json_results = results
+        .iter()
+        .map(|(name, res)| match res {
+            Ok(summary) => json!({ "name": name, "summary": summary }),
+            Err(e) => json!({ "name": name, "error": format!("{:#}", e) }),
+        })
+        .collect::<Vec<_>>();
+
+    match format {
+        OutputFormat::Text => {
+            println!();
+
+            for (name, res) in &results {
+                match res {
+                    Ok(summary) => println!("{}: {}", name, summary.short()),
+                    Err(e) => println!("{}: failed: {:#}", name, e),
+                }
+            }
+        }
+        OutputFormat::Json => output::json(&json_results)?,
+    }
+
+    let res = if failures > 0 {
+        let codes = results
+            .iter()
+            .filter_map(|(_, res)| res.as_ref().err().map(ExitCode::of))
+            .collect::<Vec<_>>();
+        // Exit as the accounts failed if they all failed the same way.
+        let code = if failures < results.len() {
+            ExitCode::PartialSync
+        } else if codes.iter().all_equal() {
+            codes[0]
+        } else {
+            ExitCode::Failure
+        };
 
-async fn cmd_list_lunch_money_assets(client: &HttpsClient, api_token: String) -> Result<()> {
-    let assets = get_all_assets(client, &api_token).await?;
+        Err(ExitError {
+            code,
+            message: format!(
+                "Failed to sync {} of {} accounts: {}",
+                failures,
+                results.len(),
+                results
+                    .iter()
+                    .filter_map(|(name, res)| res
+                        .as_ref()
+                        .err()
+                        .map(|e| format!("{}: {:#}", name, e)))
+                    .join("; ")
+            ),
+        }
+        .into())
+    } else {
+        Ok(json_results)
+    };
+    let counts = results
+        .iter()
+        .map(|(name, res)| match res {
+            Ok(summary) => json!({ "name": name, "summary": summary.counts() }),
+            Err(e) => json!({ "name": name, "error": format!("{:#}", e) }),
+        })
+        .collect::<Vec<_>>();
+    let results = results
+        .iter()
+        .map(|(name, res)| (name.as_str(), res))
+        .collect::<Vec<_>>();
+    notify(client, &config, res.as_ref().map(|_| counts), &results).await;
 
-    println!("{:#?}", assets);
+    res?;
+    let drifted = results
+        .iter()
+        .filter(|(_, res)| matches!(res, Ok(summary) if !summary.balance_drift.is_zero()))
+        .count();
+    if config.sync.fail_on_balance_drift && drifted > 0 {
+        bail!(
+            "Synced transactions are off from the Venmo ending balance for {} account(s)",
+            drifted
+        );
+    }
 
     Ok(())
 }
 
 #[derive(Args)]
-struct SyncVenmoTransactionsArgs {
-    #[clap(long, value_parser = humantime::parse_duration, default_value = "30d")]
-    start_from: Duration,
+#[clap(group(ArgGroup::new("schedule").required(true).args(&["every", "cron"])))]
+struct DaemonArgs {
+    /// Sync every interval, e.g. `6h`, starting immediately.
+    #[clap(long, env = "VENMO_SYNC_EVERY", value_parser = humantime::parse_duration)]
+    every: Option<Duration>,
 
-    #[clap(long, value_parser = humantime::parse_duration)]
-    end_to: Option<Duration>,
+    /// Sync on a cron schedule, e.g. `"0 */6 * * *"`, in local time.
+    #[clap(long, env = "VENMO_SYNC_CRON")]
+    cron: Option<String>,
 
-    #[clap(long)]
-    venmo_profile_id: u64,
+    /// Serve Prometheus metrics (last sync time, transactions synced, errors, and request
+    /// latencies) at `/metrics` on this address, e.g. `127.0.0.1:9184`.
+    #[clap(long, env = "VENMO_SYNC_METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
 
-    #[clap(long)]
-    venmo_api_token: String,
+    /// Write Prometheus metrics to this file after each sync, for node_exporter's textfile
+    /// collector. Should end in `.prom`.
+    #[clap(long, env = "VENMO_SYNC_METRICS_TEXTFILE")]
+    metrics_textfile: Option<PathBuf>,
 
-    #[clap(long)]
-    lunch_money_api_token: String,
+    #[clap(flatten)]
+    accounts: SyncAccountArgs,
 
-    #[clap(long)]
-    lunch_money_asset_id: u64,
+    #[clap(flatten)]
+    insert: InsertArgs,
 
-    #[clap(long, default_value = "USD")]
-    currency: String,
+    #[clap(flatten)]
+    notify: NotifyArgs,
 }
 
-async fn cmd_sync_venmo_transactions(
+async fn cmd_daemon(
     client: &HttpsClient,
-    args: SyncVenmoTransactionsArgs,
+    paths: &Paths,
+    config: Config,
+    statements: &StatementStore,
+    args: DaemonArgs,
 ) -> Result<()> {
-    let end_date: DateTime<Utc> = {
-        let mut end_date = Local::now();
+    let mut config = args.accounts.merge(config);
+    args.insert.merge(&mut config);
+    args.notify.merge(&mut config);
 
-        if let Some(duration) = args.end_to {
-            end_date = end_date - chrono::Duration::from_std(duration).unwrap();
-        }
+    let schedule = match (args.every, args.cron) {
+        (Some(every), _) => Schedule::Every(every),
+        (None, Some(cron)) => Schedule::cron(&cron)?,
+        (None, None) => unreachable!("clap requires --every or --cron"),
+    };
+
+    let metrics = MetricsOptions {
+        listen: args.metrics_listen,
+        textfile: args.metrics_textfile,
+    };
 
-        end_date.into()
+    let options = SyncOptions {
+        statements: statements.clone(),
+        insert: args.insert.options(),
     };
 
-    let start_date: DateTime<Utc> =
-        (Local::now() - chrono::Duration::from_std(args.start_from).unwrap()).into();
+    sync::run_daemon(client, paths, &config, schedule, &metrics, &options).await
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to serve the API on, e.g. `0.0.0.0:9185` to accept requests from other machines.
+    #[clap(
+        long,
+        env = "VENMO_SYNC_SERVE_LISTEN",
+        default_value = "127.0.0.1:9185"
+    )]
+    listen: SocketAddr,
+
+    /// Token requests must send in an `Authorization: Bearer` header.
+    #[clap(long, env = "VENMO_SYNC_SERVE_TOKEN", hide_env_values = true)]
+    token: String,
+
+    #[clap(flatten)]
+    accounts: SyncAccountArgs,
+
+    #[clap(flatten)]
+    insert: InsertArgs,
+
+    #[clap(flatten)]
+    notify: NotifyArgs,
+}
 
-    let currency = rusty_money::iso::find(&args.currency)
-        .ok_or_else(|| anyhow!("Given currency {} is not valid", args.currency))?;
+async fn cmd_serve(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: Config,
+    statements: StatementStore,
+    args: ServeArgs,
+) -> Result<()> {
+    let mut config = args.accounts.merge(config);
+    args.insert.merge(&mut config);
+    args.notify.merge(&mut config);
 
-    let venmo_account = AccountRecord {
-        profile_id: args.venmo_profile_id,
-        api_token: args.venmo_api_token.clone(),
-        currency: *currency,
+    let options = serve::ServeOptions {
+        listen: args.listen,
+        token: args.token,
+        sync: SyncOptions {
+            statements,
+            insert: args.insert.options(),
+        },
     };
 
-    let venmo_transactions =
-        fetch_venmo_transactions(client, &venmo_account, &start_date, &end_date).await?;
+    serve::cmd_serve(client, paths, config, &options).await
+}
 
-    println!(
-        "Beginning balance: {}",
-        venmo_transactions.beginning_balance
-    );
-    println!("Ending balance: {}", venmo_transactions.ending_balance);
+fn cmd_paths(paths: &Paths) -> Result<()> {
+    println!("Config directory: {}", paths.config_dir.display());
+    println!("Cache directory: {}", paths.cache_dir.display());
+    println!("State directory: {}", paths.state_dir.display());
 
-    let lunchmoney_transactions = venmo_transactions
-        .transactions
-        .into_iter()
-        .map(|transaction| {
-            transaction.to_lunchmoney_transactions(*currency, args.lunch_money_asset_id)
-        })
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .flatten();
+    Ok(())
+}
 
-    // println!("syncing:\n{:#?}", lunchmoney_transactions);
+#[derive(Args)]
+struct GenerateSystemdArgs {
+    /// When to sync, as a systemd calendar event, e.g. `daily` or `*-*-* 00/6:00:00`.
+    #[clap(long, default_value = "*-*-* 00/6:00:00")]
+    on_calendar: String,
 
-    let mut synced_transactions: Vec<u64> = Vec::new();
+    /// User to run the sync as, defaults to the current user, or the user running `sudo`. The
+    /// sync uses their config, state, and cache directories, unless overridden.
+    #[clap(long)]
+    user: Option<String>,
 
-    for transaction_chunk in &lunchmoney_transactions.into_iter().chunks(50) {
-        synced_transactions.extend(
-            insert_transactions(
-                client,
-                &args.lunch_money_api_token,
-                transaction_chunk.collect(),
-            )
-            .await?,
-        );
-    }
+    /// File with the Venmo API token, loaded with `LoadCredential` so only the sync can read it.
+    #[clap(long)]
+    venmo_api_token_file: Option<PathBuf>,
+
+    /// File with the Lunch Money API token, loaded with `LoadCredential` so only the sync can
+    /// read it.
+    #[clap(long)]
+    lunch_money_api_token_file: Option<PathBuf>,
+
+    /// Write `lunchmoney-venmo.service` and `lunchmoney-venmo.timer` to this directory, e.g.
+    /// `/etc/systemd/system`, instead of printing them.
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Arguments to pass to `sync-venmo-transactions`, after `--`.
+    #[clap(last = true)]
+    sync_args: Vec<String>,
+}
+
+fn cmd_generate_systemd(
+    paths: &Paths,
+    (config_dir, cache_dir, state_dir): (Option<PathBuf>, Option<PathBuf>, Option<PathBuf>),
+    args: GenerateSystemdArgs,
+) -> Result<()> {
+    let binary = std::env::current_exe().context("Failed to find the path of this binary")?;
+    let current_user = std::env::var("USER").ok();
+    // Under sudo, `$USER` is root.
+    let user = match args.user.or_else(|| std::env::var("SUDO_USER").ok()) {
+        Some(user) => user,
+        None => current_user
+            .clone()
+            .context("Failed to get the current user, pass --user")?,
+    };
+    let user = systemd::User::lookup(&user)?;
+    // `paths` are the current user's.
+    let paths = if current_user.as_deref() == Some(&user.name) {
+        paths.clone()
+    } else {
+        Paths::for_home(&user.home, config_dir, cache_dir, state_dir)
+    };
+    // `LoadCredential` needs absolute paths.
+    let absolute = |path: Option<PathBuf>| {
+        path.map(|path| {
+            path.canonicalize()
+                .with_context(|| anyhow!("Failed to find {:?}", path))
+        })
+        .transpose()
+    };
+
+    let options = systemd::UnitOptions {
+        binary,
+        user,
+        on_calendar: args.on_calendar,
+        venmo_api_token_file: absolute(args.venmo_api_token_file)?,
+        lunch_money_api_token_file: absolute(args.lunch_money_api_token_file)?,
+        sync_args: args.sync_args,
+    };
+
+    systemd::cmd_generate_systemd(&paths, &options, args.output_dir.as_deref())
+}
 
-    println!("inserted transactions: {:?}", synced_transactions);
+fn cmd_completions(shell: Shell) -> Result<()> {
+    let mut cmd = Cmd::command();
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
 
     Ok(())
 }
 
 /// A CLI to sync Venmo transactions to Lunch Money, using the unofficial Venmo API.
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Pass --env-file <PATH> before the subcommand to load environment variables \
+                  from a dotenv file, in addition to .env in the working directory."
+)]
 struct Cmd {
+    /// Directory to read the config file from, defaults to the platform config directory.
+    #[clap(long, global = true, env = "VENMO_SYNC_CONFIG_DIR")]
+    config_dir: Option<PathBuf>,
+
+    /// Directory to keep cached files in, e.g. statements saved with `--cache-statements`,
+    /// defaults to the platform cache directory.
+    #[clap(long, global = true, env = "VENMO_SYNC_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Directory to keep sync state in, defaults to the platform state directory.
+    #[clap(long, global = true, env = "VENMO_SYNC_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Read API tokens that aren't given as flags or in the config file from the OS keyring,
+    /// see `store-credentials`.
+    #[clap(long, global = true, env = "VENMO_SYNC_USE_KEYRING")]
+    use_keyring: bool,
+
+    /// Print results of listing and syncing commands as `text` or `json`.
+    #[clap(long, global = true, env = "VENMO_SYNC_OUTPUT", default_value = "text")]
+    output: OutputFormat,
+
+    /// Sync transactions with a type this tool doesn't know like payments instead of failing,
+    /// same as `lenient` under `[venmo.statement_format]`.
+    #[clap(long, global = true, env = "VENMO_SYNC_LENIENT")]
+    lenient: bool,
+
+    /// Save fetched Venmo statement CSVs under `statements` in the cache directory, to replay
+    /// them with `--from-cache`.
+    #[clap(long, global = true, env = "VENMO_SYNC_CACHE_STATEMENTS")]
+    cache_statements: bool,
+
+    /// Read Venmo statements saved with `--cache-statements` instead of fetching them, e.g. to
+    /// try out category rules with `--dry-run` without refetching.
+    #[clap(long, global = true, env = "VENMO_SYNC_FROM_CACHE")]
+    from_cache: bool,
+
+    /// Keep a copy of every fetched Venmo statement in this directory, as CSV and JSON.
+    #[clap(long, global = true, env = "VENMO_SYNC_ARCHIVE_DIR")]
+    archive_dir: Option<PathBuf>,
+
+    /// Send all Venmo and Lunch Money requests through this HTTP proxy, e.g.
+    /// `http://proxy.example.com:3128`. Defaults to `HTTPS_PROXY`, or `http_proxy` for HTTP URLs.
+    #[clap(long, global = true, env = "VENMO_SYNC_PROXY", value_parser = types::parse_proxy)]
+    proxy: Option<Uri>,
+
+    /// Also trust the root certificates in this PEM file, e.g. a TLS-inspecting corporate proxy's
+    /// or mitmproxy's.
+    #[clap(long, global = true, env = "VENMO_SYNC_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+
+    /// Show progress bars on stderr while fetching statements and inserting transactions in
+    /// several steps, e.g. when backfilling. Only drawn when stderr is a terminal.
+    #[clap(long, global = true, env = "VENMO_SYNC_PROGRESS")]
+    progress: bool,
+
+    /// Minimum level of diagnostic messages logged to stderr: error, warn, info, debug, or trace.
+    /// Also accepts filter directives like `lunchmoney_venmo=debug,hyper=info`.
+    #[clap(long, global = true, env = "RUST_LOG", default_value = "info")]
+    log_level: String,
+
+    /// Log to stderr as `text` or `json` lines.
+    #[clap(
+        long,
+        global = true,
+        env = "VENMO_SYNC_LOG_FORMAT",
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+
+    /// Timezone of Venmo statement datetimes, `local` or an IANA name like `America/New_York`,
+    /// same as `timezone` under `[venmo.statement_format]`. Defaults to local.
+    #[clap(long, global = true, env = "VENMO_SYNC_TIMEZONE")]
+    timezone: Option<Timezone>,
+
+    /// Kind of Venmo profile to fetch statements for, `personal` or `business`, same as
+    /// `account_type` under `[venmo.statement_format]`. Defaults to personal.
+    #[clap(long, global = true, env = "VENMO_SYNC_ACCOUNT_TYPE")]
+    account_type: Option<AccountType>,
+
     #[clap(subcommand)]
     verb: Verb,
 }
 
 #[derive(Subcommand)]
 enum Verb {
+    /// Set up a config file step by step: get a Venmo API token, choose or create the Lunch Money
+    /// asset to sync to, and pick a currency.
+    Init,
+
     /// List Venmo transactions for a given time period.
     ListVenmoTransactions(ListVenmoTransactionsArgs),
 
     /// List assets for your Lunch Money account, used to get the asset ID you care about.
     ListLunchMoneyAssets {
+        #[clap(flatten)]
+        api_token: TokenArgs<LunchMoney, false>,
+
+        /// Only show the first N assets.
         #[clap(long)]
-        api_token: String,
+        limit: Option<usize>,
+
+        /// Print directly to stdout instead of piping through `$PAGER`.
+        #[clap(long)]
+        no_pager: bool,
+    },
+
+    /// List the transactions in a Lunch Money asset.
+    ListLunchMoneyTransactions(ListLunchMoneyTransactionsArgs),
+
+    /// Create a Lunch Money asset to sync to, e.g. a "Venmo" cash asset, and print its ID.
+    CreateLunchMoneyAsset(CreateLunchMoneyAssetArgs),
+
+    /// List the tags in the Lunch Money account, to pass to `--tag`.
+    ListLunchMoneyTags {
+        #[clap(flatten)]
+        api_token: TokenArgs<LunchMoney, false>,
+
+        /// Print directly to stdout instead of piping through `$PAGER`.
+        #[clap(long)]
+        no_pager: bool,
+    },
+
+    /// Check Venmo API tokens are still valid and print who they belong to. Exits non-zero if
+    /// any isn't. Without `--api-token`, checks `venmo.api_token` and each `[[accounts]]` token
+    /// from the config file.
+    CheckVenmoToken {
+        #[clap(flatten)]
+        api_token: TokenArgs<Venmo, false>,
     },
 
     /// Sync Venmo transactions to Lunch Money asset.
     SyncVenmoTransactions(SyncVenmoTransactionsArgs),
 
+    /// Compare the Venmo statement to the transactions and balance of the Lunch Money asset it's
+    /// synced to, listing missing, extra, and mismatched transactions. Exits non-zero if they
+    /// differ.
+    Reconcile(ReconcileArgs),
+
+    /// Import a Venmo statement CSV downloaded from the Venmo website into a Lunch Money asset,
+    /// without using the Venmo API.
+    ImportVenmoCsv(ImportCsvArgs),
+
+    /// Import a Cash App activity CSV exported from the Cash App website into a Lunch Money
+    /// asset.
+    ImportCashAppCsv(ImportCsvArgs),
+
+    /// Sync PayPal transactions to a Lunch Money asset, using PayPal's Transaction Search API.
+    #[clap(name = "sync-paypal-transactions")]
+    SyncPayPalTransactions(SyncPayPalTransactionsArgs),
+
+    /// Export Venmo transactions as OFX, QFX, or a Beancount or Ledger journal, for finance tools
+    /// other than Lunch Money.
+    Export(ExportArgs),
+
+    /// Sync every Venmo account in the config file's `[[accounts]]` to its Lunch Money asset.
+    SyncAll(SyncAllArgs),
+
+    /// Roll back the last recorded sync: tag the transactions it inserted for deletion in Lunch
+    /// Money and forget the sync, so the next one starts from the configured range again.
+    UndoLastSync(UndoLastSyncArgs),
+
+    /// Keep running and sync Venmo transactions to Lunch Money on a schedule, only fetching
+    /// transactions since the last successful sync.
+    Daemon(DaemonArgs),
+
+    /// Serve an HTTP API to trigger syncs (`POST /sync`) and check on them (`GET /status`,
+    /// `GET /last-run`) remotely, e.g. from a phone shortcut.
+    Serve(ServeArgs),
+
     /// Get a Venmo API token for syncing use.
-    GetVenmoApiToken,
+    GetVenmoApiToken {
+        /// Device ID to log in with, which is remembered for later logins and logouts. Defaults
+        /// to the machine ID (or a generated ID if there is none).
+        #[clap(long, env = "VENMO_DEVICE_ID")]
+        device_id: Option<String>,
+
+        /// Venmo email or phone number, prompted for if not given.
+        #[clap(long, env = "VENMO_USERNAME")]
+        username: Option<String>,
+
+        /// Venmo password, prompted for if not given.
+        #[clap(long, env = "VENMO_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+
+        /// 2FA code to submit instead of having one sent. Without it, a sent code is prompted
+        /// for, or read from stdin when not running in a terminal.
+        #[clap(long, env = "VENMO_OTP_CODE", hide_env_values = true)]
+        otp_code: Option<String>,
+
+        /// How to get a 2FA code: sms, email, or app (an authenticator app). Defaults to asking
+        /// when Venmo offers several.
+        #[clap(long = "2fa-method", env = "VENMO_2FA_METHOD")]
+        twofa_method: Option<venmo::TwoFactorMethod>,
+
+        /// Acknowledge the risks of API tokens without being prompted.
+        #[clap(long)]
+        accept_risk: bool,
+
+        /// Instead of logging in, read the API token from the Venmo website's cookies in this
+        /// browser, `firefox` or `chrome`, where you're already logged in to Venmo.
+        #[clap(long, conflicts_with_all = &["username", "password", "otp-code", "twofa-method"])]
+        from_browser: Option<browser::Browser>,
+
+        /// The browser profile directory to read cookies from with `--from-browser`. Defaults to
+        /// the most recently used profile that's logged in to Venmo.
+        #[clap(long, requires = "from-browser")]
+        browser_profile: Option<PathBuf>,
+    },
+
+    /// Inspect the config file.
+    Config {
+        #[clap(subcommand)]
+        verb: ConfigVerb,
+    },
+
+    /// Back up or restore the sync state.
+    State {
+        #[clap(subcommand)]
+        verb: StateVerb,
+    },
+
+    /// Update this binary to the latest GitHub release, verifying its checksum.
+    SelfUpdate {
+        /// Only check whether a newer release is available.
+        #[clap(long)]
+        check: bool,
+    },
+
+    /// Collect version info, the masked config, a state summary, the last run's log with HTTP
+    /// traces, and optionally a scrambled statement into an archive to attach to bug reports.
+    DebugBundle {
+        /// A Venmo statement CSV to include, with names, notes, amounts, and IDs scrambled.
+        #[clap(long)]
+        statement: Option<PathBuf>,
+
+        /// Where to write the archive, defaults to a timestamped file in the working directory.
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Print the config, cache, and state directories in use.
+    Paths,
+
+    /// Generate a hardened systemd service and timer that run `sync-venmo-transactions` on a
+    /// schedule with this binary and config.
+    GenerateSystemd(GenerateSystemdArgs),
+
+    /// Print a completion script for bash, zsh, fish, or powershell, covering every subcommand
+    /// and flag.
+    Completions {
+        /// The shell to print completions for.
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+
+    /// Save Venmo and Lunch Money API tokens to the OS keyring (macOS Keychain, Windows
+    /// Credential Manager, or Secret Service on Linux), for use with `--use-keyring`.
+    StoreCredentials {
+        /// Only store the Venmo API token.
+        #[clap(long)]
+        venmo: bool,
+
+        /// Only store the Lunch Money API token.
+        #[clap(long)]
+        lunch_money: bool,
+    },
 
     /// Invalidate an existing Venmo API token.
     LogoutVenmoApiToken {
         /// The API token to invalidate
+        #[clap(env = "VENMO_API_TOKEN", hide_env_values = true)]
         api_token: String,
-    },
 
+        /// Device ID to log out with, defaults to the one `get-venmo-api-token` logged in with.
+        #[clap(long, env = "VENMO_DEVICE_ID")]
+        device_id: Option<String>,
+    },
     // TODO: add a one-off sync so users don't need to keep an API token around
 }
 
+/// Loads variables from the `--env-file` given in `args` (if any) and then `.env` in the working
+/// directory, without overriding variables that are already set, and returns `args` without
+/// `--env-file`. This has to happen before arguments are parsed so dotenv files can supply
+/// `env`-backed flags.
+///
+/// `--env-file` is only taken from before the subcommand, so the subcommand's arguments, e.g.
+/// `generate-systemd -- --env-file ...`, are passed through untouched.
+fn load_env_files(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let cmd = Cmd::command();
+    let mut args = args.into_iter();
+    // The binary name.
+    let mut rest: Vec<OsString> = args.next().into_iter().collect();
+    let mut env_file = None;
+
+    while let Some(arg) = args.next() {
+        let lossy = arg.to_string_lossy().into_owned();
+
+        if lossy == "--env-file" {
+            env_file = Some(PathBuf::from(
+                args.next()
+                    .ok_or_else(|| anyhow!("--env-file needs a path"))?,
+            ));
+            continue;
+        }
+        if let Some(path) = lossy.strip_prefix("--env-file=") {
+            env_file = Some(PathBuf::from(path));
+            continue;
+        }
+
+        rest.push(arg);
+        if lossy == "--" || !lossy.starts_with('-') {
+            // The subcommand, or arguments after `--`.
+            rest.extend(args);
+            break;
+        }
+
+        // Skip the value of global flags like `--config-dir <DIR>`, even if it's `--env-file`.
+        let takes_value = cmd
+            .get_arguments()
+            .find(|arg| arg.get_long() == lossy.strip_prefix("--"))
+            .is_some_and(|arg| arg.is_takes_value_set());
+        if takes_value {
+            rest.extend(args.next());
+        }
+    }
+
+    if let Some(path) = env_file {
+        dotenvy::from_path(&path).with_context(|| anyhow!("Failed to load env file {:?}", path))?;
+    }
+
+    match dotenvy::from_path(Path::new(".env")) {
+        Err(e) if e.not_found() => {}
+        res => res.context("Failed to load .env")?,
+    }
+
+    Ok(rest)
+}
+
+#[derive(Subcommand)]
+enum ConfigVerb {
+    /// Validate the config file, including that referenced Lunch Money assets exist.
+    Check,
+
+    /// Print a JSON Schema for the config file, for editor autocompletion and validation.
+    Schema,
+}
+
+#[derive(Subcommand)]
+enum StateVerb {
+    /// Copy the state file to a backup, migrating it to the current version if needed.
+    Backup {
+        /// Where to write the backup, defaults to a timestamped file in the state directory.
+        output: Option<PathBuf>,
+    },
+
+    /// Replace the state file with a backup, keeping a backup of the current state.
+    Restore {
+        /// The backup to restore.
+        input: PathBuf,
+    },
+}
+
+/// Process exit codes, so cron wrappers and systemd units can tell failures apart, e.g. to only
+/// alert when the Venmo API token needs replacing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitCode {
+    Failure = 1,
+    /// The Venmo API token was rejected, e.g. because it expired.
+    VenmoAuth = 2,
+    /// Venmo failed to return a statement, e.g. during an outage.
+    VenmoUnavailable = 3,
+    /// A Lunch Money request failed.
+    LunchMoney = 4,
+    /// Some transactions or accounts were synced before others failed.
+    PartialSync = 5,
+    /// The Lunch Money API token was rejected, e.g. because it was revoked.
+    LunchMoneyAuth = 6,
+}
+
+impl ExitCode {
+    fn of(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<ExitError>() {
+            return e.code;
+        }
+        if e.downcast_ref::<lunchmoney::PartialInsertError>().is_some() {
+            return ExitCode::PartialSync;
+        }
+
+        match e.downcast_ref::<venmo::Error>() {
+            Some(venmo::Error::AuthError(_)) => return ExitCode::VenmoAuth,
+            Some(
+                venmo::Error::RateLimited(_)
+                | venmo::Error::EndpointMoved(_)
+                | venmo::Error::ApiError { .. }
+                | venmo::Error::Unavailable(_)
+                | venmo::Error::Http(_),
+            ) => return ExitCode::VenmoUnavailable,
+            _ => {}
+        }
+
+        match e.downcast_ref::<lunchmoney::Error>() {
+            Some(lunchmoney::Error::AuthError(_)) => return ExitCode::LunchMoneyAuth,
+            Some(_) => return ExitCode::LunchMoney,
+            None => {}
+        }
+
+        ExitCode::Failure
+    }
+}
+
+/// An error that exits with `code` rather than the one its cause would, e.g. for `sync-all`.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+struct ExitError {
+    code: ExitCode,
+    message: String,
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cmd = Cmd::parse();
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(ExitCode::of(&e) as i32);
+    }
+}
+
+async fn run() -> Result<()> {
+    let args = load_env_files(std::env::args_os())?;
+
+    let cmd = Cmd::parse_from(args);
+    // Kept for `generate-systemd`, which may need another user's directories.
+    let dir_overrides = (
+        cmd.config_dir.clone(),
+        cmd.cache_dir.clone(),
+        cmd.state_dir.clone(),
+    );
+    let paths = Paths::new(cmd.config_dir, cmd.cache_dir, cmd.state_dir)?;
+    let statements = StatementStore {
+        cache_dir: (cmd.cache_statements || cmd.from_cache)
+            .then(|| paths.cache_dir.join("statements")),
+        from_cache: cmd.from_cache,
+        archive_dir: cmd.archive_dir.clone(),
+    };
+    // `debug-bundle` collects the previous run's log, it mustn't replace it.
+    let last_run_log = match cmd.verb {
+        Verb::DebugBundle { .. } => None,
+        _ => Some(logging::last_run_log(&paths)),
+    };
+    logging::init(&cmd.log_level, cmd.log_format, last_run_log.as_deref())?;
+    if cmd.progress {
+        progress::enable();
+    }
+    // Loaded lazily so `config check` can report problems in a config that fails to load.
+    let load_config = || -> Result<Config> {
+        let mut config = Config::load(&config::config_file(&paths))?;
+
+        if cmd.use_keyring {
+            credentials::fill_config(&mut config)?;
+        }
+
+        config.venmo.statement_format.lenient |= cmd.lenient;
 
-    let https = HttpsConnector::new();
-    let client = Client::builder().build::<_, hyper::Body>(https);
+        if let Some(timezone) = cmd.timezone {
+            config.venmo.statement_format.timezone = Some(timezone.to_string());
+        }
+        if let Some(account_type) = cmd.account_type {
+            config.venmo.statement_format.account_type = Some(account_type);
+        }
+
+        Ok(config)
+    };
+
+    let client = https_client(&ClientOptions {
+        proxy: cmd.proxy.clone(),
+        ca_cert: cmd.ca_cert.clone(),
+    })?;
 
     match cmd.verb {
-        Verb::ListVenmoTransactions(args) => cmd_list_venmo_transactions(&client, args).await,
-        Verb::ListLunchMoneyAssets { api_token } => {
-            cmd_list_lunch_money_assets(&client, api_token).await
-        }
-        Verb::SyncVenmoTransactions(args) => cmd_sync_venmo_transactions(&client, args).await,
-        Verb::GetVenmoApiToken => venmo::cmd_get_venmo_api_token(&client).await,
-        Verb::LogoutVenmoApiToken { api_token } => {
-            venmo::cmd_logout_venmo_api_token(&client, &api_token).await
+        Verb::Init => init::cmd_init(&client, &paths).await,
+        Verb::ListVenmoTransactions(args) => {
+            cmd_list_venmo_transactions(&client, load_config()?, &statements, args, cmd.output)
+                .await
+        }
+        Verb::ListLunchMoneyAssets {
+            api_token,
+            limit,
+            no_pager,
+        } => {
+            cmd_list_lunch_money_assets(
+                &client,
+                load_config()?,
+                api_token.token(),
+                limit,
+                no_pager,
+                cmd.output,
+            )
+            .await
+        }
+        Verb::ListLunchMoneyTransactions(args) => {
+            cmd_list_lunch_money_transactions(&client, load_config()?, args, cmd.output).await
+        }
+        Verb::CreateLunchMoneyAsset(args) => {
+            cmd_create_lunch_money_asset(&client, load_config()?, args, cmd.output).await
+        }
+        Verb::ListLunchMoneyTags {
+            api_token,
+            no_pager,
+        } => {
+            cmd_list_lunch_money_tags(
+                &client,
+                load_config()?,
+                api_token.token(),
+                no_pager,
+                cmd.output,
+            )
+            .await
+        }
+        Verb::CheckVenmoToken { api_token } => {
+            cmd_check_venmo_token(&client, load_config()?, api_token.token()).await
+        }
+        Verb::SyncVenmoTransactions(args) => {
+            cmd_sync_venmo_transactions(
+                &client,
+                &paths,
+                load_config()?,
+                &statements,
+                args,
+                cmd.output,
+            )
+            .await
+        }
+        Verb::Reconcile(args) => {
+            cmd_reconcile(&client, load_config()?, &statements, args, cmd.output).await
+        }
+        Verb::ImportVenmoCsv(args) => {
+            cmd_import_csv(
+                &client,
+                &paths,
+                load_config()?,
+                args,
+                cmd.output,
+                |bytes, config| {
+                    Ok(venmo::parse_venmo_statement(
+                        bytes,
+                        &config.venmo.statement_format,
+                    )?)
+                },
+            )
+            .await
+        }
+        Verb::ImportCashAppCsv(args) => {
+            cmd_import_csv(
+                &client,
+                &paths,
+                load_config()?,
+                args,
+                cmd.output,
+                |bytes, config| {
+                    cashapp::parse_cashapp_statement(bytes, config.venmo.statement_format.lenient)
+                },
+            )
+            .await
+        }
+        Verb::SyncPayPalTransactions(args) => {
+            cmd_sync_paypal_transactions(&client, &paths, load_config()?, args, cmd.output).await
+        }
+        Verb::Export(args) => cmd_export(&client, load_config()?, &statements, args).await,
+        Verb::SyncAll(args) => {
+            cmd_sync_all(
+                &client,
+                &paths,
+                load_config()?,
+                &statements,
+                args,
+                cmd.output,
+            )
+            .await
+        }
+        Verb::UndoLastSync(args) => cmd_undo_last_sync(&client, &paths, load_config()?, args).await,
+        Verb::Daemon(args) => cmd_daemon(&client, &paths, load_config()?, &statements, args).await,
+        Verb::Serve(args) => cmd_serve(&client, &paths, load_config()?, statements, args).await,
+        Verb::GetVenmoApiToken {
+            accept_risk,
+            from_browser: Some(browser),
+            browser_profile,
+            ..
+        } => {
+            browser::cmd_get_venmo_api_token_from_browser(
+                &client,
+                browser,
+                browser_profile.as_deref(),
+                accept_risk,
+            )
+            .await
+        }
+        Verb::GetVenmoApiToken {
+            device_id,
+            username,
+            password,
+            otp_code,
+            twofa_method,
+            accept_risk,
+            ..
+        } => {
+            let options = venmo::LoginOptions {
+                device_id,
+                username,
+                password,
+                otp_code,
+                twofa_method,
+                accept_risk,
+                headers: venmo::request_headers(&load_config()?.venmo.statement_format),
+            };
+
+            venmo::cmd_get_venmo_api_token(&client, &paths, options).await
+        }
+        Verb::LogoutVenmoApiToken {
+            api_token,
+            device_id,
+        } => venmo::cmd_logout_venmo_api_token(&client, &paths, &api_token, device_id).await,
+        Verb::Config {
+            verb: ConfigVerb::Check,
+        } => config::cmd_config_check(&client, &paths).await,
+        Verb::Config {
+            verb: ConfigVerb::Schema,
+        } => config::cmd_config_schema(),
+        Verb::State {
+            verb: StateVerb::Backup { output },
+        } => state::cmd_state_backup(&paths, output),
+        Verb::State {
+            verb: StateVerb::Restore { input },
+        } => state::cmd_state_restore(&paths, &input),
+        Verb::SelfUpdate { check } => self_update::cmd_self_update(&client, check).await,
+        Verb::DebugBundle { statement, output } => {
+            debug_bundle::cmd_debug_bundle(&paths, statement.as_deref(), output)
+        }
+        Verb::StoreCredentials { venmo, lunch_money } => {
+            credentials::cmd_store_credentials(venmo, lunch_money)
+        }
+        Verb::Paths => cmd_paths(&paths),
+        Verb::GenerateSystemd(args) => cmd_generate_systemd(&paths, dir_overrides, args),
+        Verb::Completions { shell } => cmd_completions(shell),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn loads_env_file_before_subcommand() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.env");
+        std::fs::write(&path, "LUNCHMONEY_VENMO_TEST_ENV_FILE=loaded\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(
+            load_env_files(args(&["lunchmoney-venmo", "--env-file", path, "paths"])).unwrap(),
+            args(&["lunchmoney-venmo", "paths"])
+        );
+        assert_eq!(
+            std::env::var("LUNCHMONEY_VENMO_TEST_ENV_FILE").as_deref(),
+            Ok("loaded")
+        );
+
+        // A flag's value isn't taken for `--env-file`.
+        let rest = args(&["lunchmoney-venmo", "--config-dir", "--env-file", "paths"]);
+        assert_eq!(load_env_files(rest.clone()).unwrap(), rest);
+    }
+
+    #[test]
+    fn reads_api_tokens_from_files() {
+        Cmd::command().debug_assert();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "file-token\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let cmd = Cmd::try_parse_from([
+            "lunchmoney-venmo",
+            "check-venmo-token",
+            "--api-token-file",
+            path,
+        ])
+        .unwrap();
+        match cmd.verb {
+            Verb::CheckVenmoToken { api_token } => {
+                assert_eq!(api_token.token().as_deref(), Some("file-token"))
+            }
+            _ => panic!("expected check-venmo-token"),
+        }
+
+        assert!(Cmd::try_parse_from([
+            "lunchmoney-venmo",
+            "sync-venmo-transactions",
+            "--lunch-money-api-token",
+            "flag-token",
+            "--lunch-money-api-token-file",
+            path,
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn passes_env_file_after_subcommand_through() {
+        let rest = args(&[
+            "lunchmoney-venmo",
+            "generate-systemd",
+            "--",
+            "--env-file",
+            "/etc/lunchmoney-venmo.env",
+        ]);
+        assert_eq!(load_env_files(rest.clone()).unwrap(), rest);
+
+        let cmd = Cmd::try_parse_from(rest).unwrap();
+        match cmd.verb {
+            Verb::GenerateSystemd(args) => assert_eq!(
+                args.sync_args,
+                vec!["--env-file", "/etc/lunchmoney-venmo.env"]
+            ),
+            _ => panic!("expected generate-systemd"),
         }
     }
 }
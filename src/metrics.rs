@@ -0,0 +1,202 @@
+//! Prometheus metrics for daemon mode, served over HTTP or written to a node_exporter textfile,
+//! so syncing that silently stops working can be alerted on.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use lazy_static::lazy_static;
+use tracing::error;
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+/// Metrics recorded since the process started.
+#[derive(Debug, Default)]
+struct Metrics {
+    last_sync_attempt: Option<DateTime<Utc>>,
+    last_successful_sync: Option<DateTime<Utc>>,
+    syncs: u64,
+    sync_errors: u64,
+    transactions_synced: u64,
+    /// Keyed by the host requested.
+    requests: BTreeMap<String, RequestMetrics>,
+}
+
+#[derive(Debug, Default)]
+struct RequestMetrics {
+    count: u64,
+    duration_seconds: f64,
+    errors: u64,
+}
+
+fn metrics() -> std::sync::MutexGuard<'static, Metrics> {
+    // Metrics are only ever updated in place, a panic mid-update can't leave them inconsistent.
+    METRICS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Records a sync that inserted `inserted` transactions, or failed if `None`.
+pub fn record_sync(inserted: Option<usize>) {
+    let mut metrics = metrics();
+    let now = Utc::now();
+
+    metrics.syncs += 1;
+    metrics.last_sync_attempt = Some(now);
+
+    match inserted {
+        Some(inserted) => {
+            metrics.last_successful_sync = Some(now);
+            metrics.transactions_synced += inserted as u64;
+        }
+        None => metrics.sync_errors += 1,
+    }
+}
+
+/// Records an HTTP request to `host` that took `duration`. `failed` is set for requests that
+/// got no response or an error status.
+pub fn record_request(host: &str, duration: Duration, failed: bool) {
+    let mut metrics = metrics();
+    let request = metrics.requests.entry(host.to_string()).or_default();
+
+    request.count += 1;
+    request.duration_seconds += duration.as_secs_f64();
+    if failed {
+        request.errors += 1;
+    }
+}
+
+/// Renders the recorded metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let metrics = metrics();
+    let mut out = String::new();
+
+    let timestamp = |date: Option<DateTime<Utc>>| date.map(|date| date.timestamp()).unwrap_or(0);
+
+    // Writing to a `String` can't fail.
+    let _ = write!(
+        out,
+        "# HELP lunchmoney_venmo_last_sync_timestamp_seconds When the last successful sync finished, 0 if none has.\n\
+         # TYPE lunchmoney_venmo_last_sync_timestamp_seconds gauge\n\
+         lunchmoney_venmo_last_sync_timestamp_seconds {}\n\
+         # HELP lunchmoney_venmo_last_sync_attempt_timestamp_seconds When the last sync finished, successful or not, 0 if none has.\n\
+         # TYPE lunchmoney_venmo_last_sync_attempt_timestamp_seconds gauge\n\
+         lunchmoney_venmo_last_sync_attempt_timestamp_seconds {}\n\
+         # HELP lunchmoney_venmo_syncs_total Syncs run, successful or not.\n\
+         # TYPE lunchmoney_venmo_syncs_total counter\n\
+         lunchmoney_venmo_syncs_total {}\n\
+         # HELP lunchmoney_venmo_sync_errors_total Syncs that failed.\n\
+         # TYPE lunchmoney_venmo_sync_errors_total counter\n\
+         lunchmoney_venmo_sync_errors_total {}\n\
+         # HELP lunchmoney_venmo_transactions_synced_total Transactions inserted into Lunch Money.\n\
+         # TYPE lunchmoney_venmo_transactions_synced_total counter\n\
+         lunchmoney_venmo_transactions_synced_total {}\n",
+        timestamp(metrics.last_successful_sync),
+        timestamp(metrics.last_sync_attempt),
+        metrics.syncs,
+        metrics.sync_errors,
+        metrics.transactions_synced,
+    );
+
+    out.push_str(
+        "# HELP lunchmoney_venmo_request_duration_seconds Time taken by HTTP requests, by host.\n\
+         # TYPE lunchmoney_venmo_request_duration_seconds summary\n",
+    );
+    for (host, request) in &metrics.requests {
+        let _ = write!(
+            out,
+            "lunchmoney_venmo_request_duration_seconds_sum{{host=\"{host}\"}} {}\n\
+             lunchmoney_venmo_request_duration_seconds_count{{host=\"{host}\"}} {}\n",
+            request.duration_seconds, request.count,
+        );
+    }
+
+    out.push_str(
+        "# HELP lunchmoney_venmo_request_errors_total HTTP requests that got no response or an error status, by host.\n\
+         # TYPE lunchmoney_venmo_request_errors_total counter\n",
+    );
+    for (host, request) in &metrics.requests {
+        let _ = writeln!(
+            out,
+            "lunchmoney_venmo_request_errors_total{{host=\"{}\"}} {}",
+            host, request.errors
+        );
+    }
+
+    out
+}
+
+/// Writes the recorded metrics to `path` for node_exporter's textfile collector, replacing it
+/// atomically so it's never scraped half-written.
+pub fn write_textfile(path: &Path) -> Result<()> {
+    let tmp_path = path.with_extension("prom.tmp");
+    fs::write(&tmp_path, render())
+        .with_context(|| anyhow!("Failed to write metrics file {:?}", tmp_path))?;
+    fs::rename(&tmp_path, path).with_context(|| anyhow!("Failed to write metrics file {:?}", path))
+}
+
+/// Serves the recorded metrics at `http://{addr}/metrics` in the background.
+pub fn serve(addr: &SocketAddr) -> Result<()> {
+    let server = Server::try_bind(addr)
+        .with_context(|| anyhow!("Failed to listen for metrics on {}", addr))?
+        .serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+                match (request.method(), request.uri().path()) {
+                    (&Method::GET, "/metrics") => Response::builder()
+                        .header("Content-Type", "text/plain; version=0.0.4")
+                        .body(Body::from(render())),
+                    _ => Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty()),
+                }
+            }))
+        }));
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Metrics server failed: {:#}", e);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_metrics() {
+        record_sync(Some(3));
+        record_sync(None);
+        record_request("metrics.test", Duration::from_millis(500), false);
+        record_request("metrics.test", Duration::from_millis(250), true);
+
+        let rendered = render();
+
+        assert!(rendered.contains("lunchmoney_venmo_syncs_total 2\n"));
+        assert!(rendered.contains("lunchmoney_venmo_sync_errors_total 1\n"));
+        assert!(rendered.contains("lunchmoney_venmo_transactions_synced_total 3\n"));
+        assert!(rendered.contains(
+            "lunchmoney_venmo_request_duration_seconds_sum{host=\"metrics.test\"} 0.75\n"
+        ));
+        assert!(rendered.contains(
+            "lunchmoney_venmo_request_duration_seconds_count{host=\"metrics.test\"} 2\n"
+        ));
+        assert!(
+            rendered.contains("lunchmoney_venmo_request_errors_total{host=\"metrics.test\"} 1\n")
+        );
+        assert!(!rendered.contains("lunchmoney_venmo_last_sync_timestamp_seconds 0\n"));
+    }
+}
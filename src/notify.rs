@@ -0,0 +1,376 @@
+//! Notifying of sync results, so unattended syncs that start failing get noticed: pinging URLs
+//! like [Healthchecks.io](https://healthchecks.io) checks, and sending summaries to Slack,
+//! Discord, or email channels.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+use hyper::header::CONTENT_TYPE;
+use hyper::{body, Method, Request, Uri};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::{EmailChannel, NotifyChannel, NotifyConfig, NotifyWhen, SmtpTls};
+use crate::http::{request_following_redirects, HttpTransport};
+use crate::redact;
+use crate::sync::SyncSummary;
+
+/// Discord rejects messages longer than this many characters.
+const DISCORD_MAX_LEN: usize = 2000;
+
+/// Checks that `url` is an absolute HTTP(S) URL.
+pub fn validate_url(url: &str) -> Result<()> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| anyhow!("'{}' is not a valid URL: {}", url, e))?;
+
+    match uri.scheme_str() {
+        Some("http" | "https") if uri.host().is_some() => Ok(()),
+        _ => bail!("'{}' is not an http or https URL", url),
+    }
+}
+
+/// Checks that `channel`'s URL or addresses are valid.
+pub fn validate_channel(channel: &NotifyChannel) -> Result<()> {
+    match channel {
+        NotifyChannel::Slack { webhook_url, .. } | NotifyChannel::Discord { webhook_url, .. } => {
+            validate_url(webhook_url)
+        }
+        NotifyChannel::Email(email) => {
+            if email.to.is_empty() {
+                bail!("'to' must list at least one address");
+            }
+
+            for address in std::iter::once(&email.from).chain(&email.to) {
+                address
+                    .parse::<Mailbox>()
+                    .map_err(|e| anyhow!("'{}' is not a valid email address: {}", address, e))?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Subject and body of the message summarizing `results`, each the name of a synced account and
+/// the result of syncing it.
+fn message(results: &[(&str, &Result<SyncSummary>)]) -> (String, String) {
+    let failures = results.iter().filter(|(_, res)| res.is_err()).count();
+
+    let subject = match (failures, results.len()) {
+        (0, _) => "Venmo sync succeeded".to_string(),
+        (_, 1) => "Venmo sync failed".to_string(),
+        (failures, total) => format!("Venmo sync failed for {} of {} accounts", failures, total),
+    };
+
+    let body = results
+        .iter()
+        .map(|(name, res)| match res {
+            Ok(summary) => format!("{}: {}", name, summary.short()),
+            Err(e) => format!("{}: failed: {:#}", name, e),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    (subject, body)
+}
+
+/// Sends a summary of `results` to each channel in `notify.channels` that wants it, trying every
+/// channel even if some fail.
+pub async fn notify_channels(
+    client: &impl HttpTransport,
+    config: &NotifyConfig,
+    results: &[(&str, &Result<SyncSummary>)],
+) -> Result<()> {
+    let failed = results.iter().any(|(_, res)| res.is_err());
+    let (subject, body) = message(results);
+    let mut errors = Vec::new();
+
+    for channel in &config.channels {
+        if channel.when() == NotifyWhen::Errors && !failed {
+            continue;
+        }
+
+        let res = match channel {
+            NotifyChannel::Slack { webhook_url, .. } => {
+                ping(
+                    client,
+                    webhook_url,
+                    &json!({ "text": format!("*{}*\n{}", subject, body) }),
+                )
+                .await
+            }
+            NotifyChannel::Discord { webhook_url, .. } => {
+                let content: String = format!("**{}**\n{}", subject, body)
+                    .chars()
+                    .take(DISCORD_MAX_LEN)
+                    .collect();
+
+                ping(client, webhook_url, &json!({ "content": content })).await
+            }
+            NotifyChannel::Email(email) => send_email(email, &subject, &body).await,
+        };
+
+        if let Err(e) = res {
+            errors.push(format!("{:#}", e));
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("Failed to send notifications: {}", errors.join("; "));
+    }
+
+    Ok(())
+}
+
+/// Emails `subject` and `body` over SMTP as configured by `email`.
+async fn send_email(email: &EmailChannel, subject: &str, body: &str) -> Result<()> {
+    let mut message = Message::builder()
+        .from(email.from.parse()?)
+        .subject(subject);
+    for address in &email.to {
+        message = message.to(address.parse()?);
+    }
+    let message = message.body(body.to_string())?;
+
+    let host = &email.smtp_host;
+    let mut transport = match email.tls {
+        SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+    };
+    if let Some(port) = email.smtp_port {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (&email.username, &email.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    transport
+        .build()
+        .send(message)
+        .await
+        .map_err(|e| anyhow!("Failed to send email via {}: {}", host, e))?;
+
+    Ok(())
+}
+
+/// POSTs the result of a sync to `notify.on_success_url` or `notify.on_failure_url`, if set. The
+/// JSON payload is `{"status": "success", "summary": ...}` with the serialized `result`, or
+/// `{"status": "failure", "error": "..."}`. Pass counts rather than full summaries, e.g.
+/// `SyncSummary::counts`, since these are usually third-party services.
+pub async fn notify_result<T: Serialize>(
+    client: &impl HttpTransport,
+    config: &NotifyConfig,
+    result: Result<T, &anyhow::Error>,
+) -> Result<()> {
+    let (url, payload) = match result {
+        Ok(summary) => (
+            &config.on_success_url,
+            json!({ "status": "success", "summary": summary }),
+        ),
+        Err(e) => (
+            &config.on_failure_url,
+            json!({ "status": "failure", "error": format!("{:#}", e) }),
+        ),
+    };
+
+    match url {
+        Some(url) => ping(client, url, &payload).await,
+        None => Ok(()),
+    }
+}
+
+/// POSTs `payload` as JSON to `url`, failing on non-2xx responses. Errors only name `url`'s host,
+/// see `redact_url`.
+pub async fn ping(
+    client: &impl HttpTransport,
+    url: &str,
+    payload: &serde_json::Value,
+) -> Result<()> {
+    let shown = redact_url(url);
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body::Body::from(serde_json::to_vec(payload)?))
+        .map_err(|e| anyhow!("Failed to build request to ping {}: {}", shown, e))?;
+
+    let response = request_following_redirects(client, request)
+        .await
+        .map_err(|e| {
+            anyhow!(
+                "Failed to ping {}: {}",
+                shown,
+                format!("{:#}", e).replace(url, &shown)
+            )
+        })?;
+
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+
+    if !status.is_success() {
+        bail!(
+            "Failed to ping {}, code {}, err:\n{}",
+            shown,
+            status,
+            redact::bytes(&bytes)
+        );
+    }
+
+    Ok(())
+}
+
+/// `url` with only its scheme and host, e.g. `https://hooks.slack.com/...`, since ping and
+/// webhook URLs carry their secret in the path.
+fn redact_url(url: &str) -> String {
+    let uri = url.parse::<Uri>().ok();
+
+    match uri
+        .as_ref()
+        .and_then(|uri| Some((uri.scheme_str()?, uri.host()?)))
+    {
+        Some((scheme, host)) => format!("{}://{}/...", scheme, host),
+        None => "the URL".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::StatusCode;
+
+    use super::*;
+    use crate::http::fake::FakeTransport;
+
+    #[tokio::test]
+    async fn pings_on_success_and_failure() {
+        let transport =
+            FakeTransport::with_responses(vec![(StatusCode::OK, b"OK"), (StatusCode::OK, b"OK")]);
+        let config = NotifyConfig {
+            on_success_url: Some("https://hc-ping.com/abc".to_string()),
+            on_failure_url: Some("https://hc-ping.com/abc/fail".to_string()),
+            ..Default::default()
+        };
+
+        notify_result(&transport, &config, Ok(json!({ "new": 2 })))
+            .await
+            .unwrap();
+        notify_result::<()>(&transport, &config, Err(&anyhow!("Venmo returned 404")))
+            .await
+            .unwrap();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].method, Method::POST);
+        assert_eq!(requests[0].uri, "https://hc-ping.com/abc");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[0].body).unwrap(),
+            json!({ "status": "success", "summary": { "new": 2 } })
+        );
+        assert_eq!(requests[1].uri, "https://hc-ping.com/abc/fail");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[1].body).unwrap(),
+            json!({ "status": "failure", "error": "Venmo returned 404" })
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_ping_urls_out_of_errors() {
+        let transport = FakeTransport::with_responses(vec![(StatusCode::NOT_FOUND, b"Not Found")]);
+
+        let err = ping(
+            &transport,
+            "https://hooks.slack.com/services/T000/B000/secret",
+            &json!({}),
+        )
+        .await
+        .unwrap_err();
+        let message = format!("{:#}", err);
+
+        assert!(message.contains("https://hooks.slack.com/..."));
+        assert!(!message.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn skips_unset_urls() {
+        let transport = FakeTransport::with_responses(vec![]);
+
+        notify_result::<()>(&transport, &NotifyConfig::default(), Ok(()))
+            .await
+            .unwrap();
+
+        assert!(transport.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sends_to_channels_by_severity() {
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, b"ok"),
+            (StatusCode::NO_CONTENT, b""),
+            (StatusCode::OK, b"ok"),
+        ]);
+        let config = NotifyConfig {
+            channels: vec![
+                NotifyChannel::Slack {
+                    webhook_url: "https://hooks.slack.com/services/abc".to_string(),
+                    when: NotifyWhen::Always,
+                },
+                NotifyChannel::Discord {
+                    webhook_url: "https://discord.com/api/webhooks/abc".to_string(),
+                    when: NotifyWhen::Errors,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let failed = Err(anyhow!("Venmo returned 404"));
+        notify_channels(&transport, &config, &[("personal", &failed)])
+            .await
+            .unwrap();
+
+        {
+            let requests = transport.requests.lock().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(&requests[0].body).unwrap(),
+                json!({ "text": "*Venmo sync failed*\npersonal: failed: Venmo returned 404" })
+            );
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(&requests[1].body).unwrap(),
+                json!({ "content": "**Venmo sync failed**\npersonal: failed: Venmo returned 404" })
+            );
+        }
+
+        notify_channels(&transport, &config, &[]).await.unwrap();
+        assert_eq!(transport.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn validates_channels() {
+        let email = |to: &[&str]| {
+            NotifyChannel::Email(EmailChannel {
+                smtp_host: "smtp.example.com".to_string(),
+                smtp_port: None,
+                tls: SmtpTls::Starttls,
+                username: None,
+                password: None,
+                from: "Venmo Sync <sync@example.com>".to_string(),
+                to: to.iter().map(|to| to.to_string()).collect(),
+                when: NotifyWhen::Always,
+            })
+        };
+
+        assert!(validate_channel(&email(&["me@example.com"])).is_ok());
+        assert!(validate_channel(&email(&[])).is_err());
+        assert!(validate_channel(&email(&["not an address"])).is_err());
+    }
+
+    #[test]
+    fn validates_urls() {
+        assert!(validate_url("https://hc-ping.com/abc").is_ok());
+        assert!(validate_url("hc-ping.com/abc").is_err());
+        assert!(validate_url("ftp://hc-ping.com/abc").is_err());
+    }
+}
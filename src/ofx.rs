@@ -0,0 +1,212 @@
+//! Converting statements to OFX (and Quicken's QFX flavor of it), for finance tools other than
+//! Lunch Money like GnuCash or Banktivity.
+
+use std::fmt::Write;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusty_money::iso::Currency;
+
+use crate::types::venmo::{ConvertOptions, Statement, TransactionStatus};
+
+/// OFX's limit on the length of a transaction's `NAME`.
+const MAX_NAME_LEN: usize = 32;
+
+/// Which flavor of OFX to write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OfxFormat {
+    Ofx,
+    /// OFX with the `INTU.BID` Quicken identifies the institution by.
+    Qfx {
+        intu_bid: String,
+    },
+}
+
+/// Account the statement is reported for.
+#[derive(Debug, Clone)]
+pub struct OfxAccount {
+    /// Identifies the institution, e.g. `VENMO`.
+    pub bank_id: String,
+    /// Identifies the account within the institution, e.g. the Venmo profile ID.
+    pub account_id: String,
+    pub currency: Currency,
+}
+
+/// Escapes the characters that are special in OFX's SGML.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace(['\r', '\n'], " ")
+}
+
+fn datetime(datetime: &DateTime<Utc>) -> String {
+    datetime.format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Writes `statement` for `start_date` to `end_date` as an OFX 1.0.2 bank statement. The
+/// transactions are converted as they'd be synced to Lunch Money, including shadow transfers as
+/// `options` says, so the running balance matches the statement's ending balance. Failed and
+/// cancelled transactions are left out.
+pub fn statement_to_ofx(
+    statement: &Statement,
+    account: &OfxAccount,
+    options: &ConvertOptions,
+    (start_date, end_date): (DateTime<Utc>, DateTime<Utc>),
+    format: &OfxFormat,
+) -> Result<String> {
+    let transactions = statement
+        .transactions
+        .iter()
+        .filter(|transaction| !TransactionStatus::DEFAULT_SKIPPED.contains(&transaction.status))
+        .map(|transaction| transaction.to_lunchmoney_transactions(account.currency, 0, options))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        // Only the asset's side of transfers, the other sides go to other accounts.
+        .filter(|transaction| transaction.asset_id == Some(0));
+
+    let mut ofx = String::new();
+
+    ofx.push_str(
+        "OFXHEADER:100\n\
+         DATA:OFXSGML\n\
+         VERSION:102\n\
+         SECURITY:NONE\n\
+         ENCODING:UTF-8\n\
+         CHARSET:NONE\n\
+         COMPRESSION:NONE\n\
+         OLDFILEUID:NONE\n\
+         NEWFILEUID:NONE\n\
+         \n",
+    );
+
+    writeln!(ofx, "<OFX>")?;
+    writeln!(ofx, "<SIGNONMSGSRSV1><SONRS>")?;
+    writeln!(ofx, "<STATUS><CODE>0<SEVERITY>INFO</STATUS>")?;
+    writeln!(ofx, "<DTSERVER>{}", datetime(&Utc::now()))?;
+    writeln!(ofx, "<LANGUAGE>ENG")?;
+    if let OfxFormat::Qfx { intu_bid } = format {
+        writeln!(ofx, "<INTU.BID>{}", escape(intu_bid))?;
+    }
+    writeln!(ofx, "</SONRS></SIGNONMSGSRSV1>")?;
+
+    writeln!(ofx, "<BANKMSGSRSV1><STMTTRNRS>")?;
+    writeln!(ofx, "<TRNUID>0")?;
+    writeln!(ofx, "<STATUS><CODE>0<SEVERITY>INFO</STATUS>")?;
+    writeln!(ofx, "<STMTRS>")?;
+    writeln!(ofx, "<CURDEF>{}", account.currency.iso_alpha_code)?;
+    writeln!(ofx, "<BANKACCTFROM>")?;
+    writeln!(ofx, "<BANKID>{}", escape(&account.bank_id))?;
+    writeln!(ofx, "<ACCTID>{}", escape(&account.account_id))?;
+    writeln!(ofx, "<ACCTTYPE>CHECKING")?;
+    writeln!(ofx, "</BANKACCTFROM>")?;
+    writeln!(ofx, "<BANKTRANLIST>")?;
+    writeln!(ofx, "<DTSTART>{}", datetime(&start_date))?;
+    writeln!(ofx, "<DTEND>{}", datetime(&end_date))?;
+
+    for transaction in transactions {
+        let amount = transaction.amount.0;
+
+        writeln!(ofx, "<STMTTRN>")?;
+        writeln!(
+            ofx,
+            "<TRNTYPE>{}",
+            if amount.is_sign_negative() {
+                "DEBIT"
+            } else {
+                "CREDIT"
+            }
+        )?;
+        writeln!(ofx, "<DTPOSTED>{}", transaction.date.format("%Y%m%d"))?;
+        writeln!(ofx, "<TRNAMT>{:.2}", amount)?;
+        if let Some(ref external_id) = transaction.external_id {
+            writeln!(ofx, "<FITID>{}", escape(external_id))?;
+        }
+        if let Some(ref payee) = transaction.payee {
+            let name: String = payee.chars().take(MAX_NAME_LEN).collect();
+            writeln!(ofx, "<NAME>{}", escape(&name))?;
+        }
+        if let Some(ref notes) = transaction.notes {
+            writeln!(ofx, "<MEMO>{}", escape(notes))?;
+        }
+        writeln!(ofx, "</STMTTRN>")?;
+    }
+
+    writeln!(ofx, "</BANKTRANLIST>")?;
+    writeln!(ofx, "<LEDGERBAL>")?;
+    writeln!(ofx, "<BALAMT>{:.2}", statement.ending_balance.val)?;
+    writeln!(ofx, "<DTASOF>{}", datetime(&end_date))?;
+    writeln!(ofx, "</LEDGERBAL>")?;
+    writeln!(ofx, "</STMTRS>")?;
+    writeln!(ofx, "</STMTTRNRS></BANKMSGSRSV1>")?;
+    writeln!(ofx, "</OFX>")?;
+
+    Ok(ofx)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::testing::{amount, transaction};
+    use crate::types::venmo::{Transaction, TransactionType};
+
+    #[test]
+    fn writes_ofx() {
+        let transaction = |id, status, val, note: &str| Transaction {
+            status,
+            note: Some(note.to_string()),
+            to: Some("Pizza & Pasta <Downtown>".to_string()),
+            funding_source: Some("Chase Checking".to_string()),
+            ..transaction(id, TransactionType::Payment, val)
+        };
+        let statement = Statement {
+            beginning_balance: amount(Decimal::new(100, 0)),
+            ending_balance: amount(Decimal::new(100, 0)),
+            transactions: vec![
+                transaction(
+                    1,
+                    TransactionStatus::Complete,
+                    Decimal::new(-2050, 2),
+                    "Dinner",
+                ),
+                transaction(2, TransactionStatus::Failed, Decimal::new(-5, 0), "Oops"),
+            ],
+        };
+        let account = OfxAccount {
+            bank_id: "VENMO".to_string(),
+            account_id: "1234".to_string(),
+            currency: *rusty_money::iso::USD,
+        };
+        let range = (
+            "2022-07-01T00:00:00Z".parse().unwrap(),
+            "2022-07-31T00:00:00Z".parse().unwrap(),
+        );
+
+        let ofx = statement_to_ofx(
+            &statement,
+            &account,
+            &ConvertOptions::default(),
+            range,
+            &OfxFormat::Qfx {
+                intu_bid: "12345".to_string(),
+            },
+        )
+        .unwrap();
+
+        assert!(ofx.starts_with("OFXHEADER:100\n"));
+        // Names are written as-is, so the header must not claim a narrower charset.
+        assert!(ofx.contains("ENCODING:UTF-8\nCHARSET:NONE\n"));
+        assert!(ofx.contains("<INTU.BID>12345\n"));
+        assert!(ofx.contains("<ACCTID>1234\n"));
+        assert!(ofx.contains(
+            "<STMTTRN>\n<TRNTYPE>DEBIT\n<DTPOSTED>20220701\n<TRNAMT>-20.50\n<FITID>1\n<NAME>Pizza &amp; Pasta &lt;Downtown&gt;\n<MEMO>Dinner\n</STMTTRN>\n"
+        ));
+        // The shadow transfer funding the payment.
+        assert!(ofx.contains("<TRNAMT>20.50\n<FITID>1T\n"));
+        assert!(!ofx.contains("Oops"));
+        assert!(ofx.contains("<BALAMT>100.00\n<DTASOF>20220731000000\n"));
+    }
+}
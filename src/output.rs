@@ -0,0 +1,370 @@
+//! Terminal output helpers for the CLI.
+
+use std::env;
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Result;
+use console::{measure_text_width, pad_str, style, Alignment};
+use rust_decimal::Decimal;
+use rusty_money::{iso::Currency, Money};
+use serde::Serialize;
+
+use crate::types::lunchmoney::ExistingTransaction;
+use crate::types::venmo::Statement;
+
+/// How commands print their results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable text.
+    Text,
+    /// A single JSON document on stdout, for `jq` and other automation.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "text" => OutputFormat::Text,
+            "json" => OutputFormat::Json,
+            _ => bail!("Unknown output format '{}', expected text or json", s),
+        })
+    }
+}
+
+/// Prints `value` to stdout as pretty-printed JSON.
+pub fn json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+
+    Ok(())
+}
+
+/// Prints `value` to stdout in `format`.
+pub fn print(value: &(impl Serialize + fmt::Display), format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            println!("{}", value);
+            Ok(())
+        }
+        OutputFormat::Json => json(value),
+    }
+}
+
+/// Writes `output` to stdout, piping it through `$PAGER` (defaulting to `less`) when stdout is
+/// attached to a terminal. Falls back to printing directly if the pager can't be started.
+pub fn page(output: &str, no_pager: bool) -> Result<()> {
+    if no_pager || !std::io::stdout().is_terminal() {
+        print!("{}", output);
+        return Ok(());
+    }
+
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+    let mut pager_args = pager.split_whitespace();
+
+    let program = match pager_args.next() {
+        Some(program) => program,
+        None => {
+            print!("{}", output);
+            return Ok(());
+        }
+    };
+
+    let child = Command::new(program)
+        .args(pager_args)
+        // Let `less` exit immediately if the output fits on one screen and keep colors intact.
+        .env(
+            "LESS",
+            env::var("LESS").unwrap_or_else(|_| "FRX".to_string()),
+        )
+        .stdin(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => {
+            print!("{}", output);
+            return Ok(());
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user quitting the pager early closes the pipe, which is not an error for us.
+        let _ = stdin.write_all(output.as_bytes());
+    }
+
+    child.wait()?;
+
+    Ok(())
+}
+
+/// Formats `val` using `currency`'s symbol and separators, with an explicit sign. Incoming
+/// (positive) amounts are rendered in green and outgoing (negative) amounts in red when the
+/// terminal supports colors.
+pub fn signed_amount(val: Decimal, currency: &Currency) -> String {
+    // Pad to the currency's minor units, e.g. `$120.50` rather than `$120.5`.
+    let mut rounded = val.round_dp(currency.exponent);
+    rounded.rescale(currency.exponent);
+    let money = Money::from_decimal(rounded, currency);
+
+    if val.is_sign_negative() {
+        style(money.to_string()).red().to_string()
+    } else {
+        style(format!("+{}", money)).green().to_string()
+    }
+}
+
+/// Lays out `rows` under `headers` in aligned columns, one line each. The `amount` column is
+/// right-aligned so decimal points line up, and the last column isn't padded.
+fn table_lines<const N: usize>(
+    headers: [&str; N],
+    rows: &[[String; N]],
+    amount: usize,
+) -> Vec<String> {
+    let mut widths = headers.map(measure_text_width);
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(measure_text_width(cell));
+        }
+    }
+
+    let format_row = |row: [&str; N]| {
+        row.iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                if i == amount {
+                    pad_str(cell, width, Alignment::Right, None).into_owned()
+                } else if i == N - 1 {
+                    cell.to_string()
+                } else {
+                    pad_str(cell, width, Alignment::Left, None).into_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![style(format_row(headers)).bold().to_string()];
+    lines.extend(
+        rows.iter()
+            .map(|row| format_row(row.each_ref().map(String::as_str))),
+    );
+
+    lines
+}
+
+/// Joins the words of `s`, so text spanning lines fits one row.
+fn one_line(s: Option<&str>) -> String {
+    s.unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Renders the transactions in `statement` as a table with aligned columns, followed by the
+/// totals in and out and the statement's balances.
+pub fn transactions_table(statement: &Statement, currency: &Currency) -> String {
+    let headers = ["Date", "Type", "Status", "From", "To", "Amount", "Note"];
+
+    let rows: Vec<[String; 7]> = statement
+        .transactions
+        .iter()
+        .map(|transaction| {
+            [
+                transaction.datetime.format("%Y-%m-%d %H:%M").to_string(),
+                transaction.type_.to_string(),
+                transaction.status.to_string(),
+                transaction.from.clone().unwrap_or_default(),
+                transaction.to.clone().unwrap_or_default(),
+                signed_amount(transaction.amount_total.val, currency),
+                // Notes can span lines, keep each transaction to one row.
+                one_line(transaction.note.as_deref()),
+            ]
+        })
+        .collect();
+
+    let mut table = table_lines(headers, &rows, 5);
+
+    let (incoming, outgoing): (Vec<Decimal>, Vec<Decimal>) = statement
+        .transactions
+        .iter()
+        .map(|transaction| transaction.amount_total.val)
+        .partition(|val| val.is_sign_positive());
+    let totals = [
+        ("Transactions", statement.transactions.len().to_string()),
+        ("Total in", signed_amount(incoming.iter().sum(), currency)),
+        ("Total out", signed_amount(outgoing.iter().sum(), currency)),
+        (
+            "Net",
+            signed_amount(incoming.iter().chain(&outgoing).sum(), currency),
+        ),
+        (
+            "Beginning balance",
+            signed_amount(statement.beginning_balance.val, currency),
+        ),
+        (
+            "Ending balance",
+            signed_amount(statement.ending_balance.val, currency),
+        ),
+    ];
+    let label_width = totals
+        .iter()
+        .map(|(label, _)| label.len() + 1)
+        .max()
+        .unwrap_or(0);
+    let value_width = totals
+        .iter()
+        .map(|(_, value)| measure_text_width(value))
+        .max()
+        .unwrap_or(0);
+
+    table.push(String::new());
+    table.extend(totals.iter().map(|(label, value)| {
+        format!(
+            "{}  {}",
+            pad_str(&format!("{}:", label), label_width, Alignment::Left, None),
+            pad_str(value, value_width, Alignment::Right, None)
+        )
+    }));
+
+    table.join("\n") + "\n"
+}
+
+/// Renders Lunch Money `transactions` as a table with aligned columns, with expenses negative as
+/// in Venmo statements.
+pub fn lunch_money_transactions_table(
+    transactions: &[ExistingTransaction],
+    currency: &Currency,
+) -> String {
+    let headers = [
+        "Date",
+        "ID",
+        "Status",
+        "Payee",
+        "Amount",
+        "External ID",
+        "Notes",
+    ];
+
+    let rows: Vec<[String; 7]> = transactions
+        .iter()
+        .map(|transaction| {
+            [
+                transaction.date.format("%Y-%m-%d").to_string(),
+                transaction.id.to_string(),
+                transaction.status.clone().unwrap_or_default(),
+                one_line(transaction.payee.as_deref()),
+                signed_amount(-transaction.amount.0, currency),
+                transaction.external_id.clone().unwrap_or_default(),
+                one_line(transaction.notes.as_deref()),
+            ]
+        })
+        .collect();
+
+    let mut table = table_lines(headers, &rows, 4);
+    table.push(String::new());
+    table.push(format!(
+        "Transactions: {}, net {}",
+        transactions.len(),
+        signed_amount(
+            transactions
+                .iter()
+                .map(|transaction| -transaction.amount.0)
+                .sum(),
+            currency
+        )
+    ));
+
+    table.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{amount, transaction};
+    use crate::types::venmo::{Transaction, TransactionType};
+
+    #[test]
+    fn renders_transactions_table() {
+        console::set_colors_enabled(false);
+
+        let transaction = |id, type_, val, note: &str| Transaction {
+            note: Some(note.to_string()),
+            ..transaction(id, type_, val)
+        };
+        let statement = Statement {
+            beginning_balance: amount(Decimal::new(12050, 2)),
+            ending_balance: amount(Decimal::new(6550, 2)),
+            transactions: vec![
+                transaction(1, TransactionType::Payment, Decimal::new(25, 0), "Dinner"),
+                transaction(
+                    2,
+                    TransactionType::Charge,
+                    Decimal::new(-80, 0),
+                    "Concert\ntickets",
+                ),
+            ],
+        };
+
+        let table = transactions_table(&statement, rusty_money::iso::USD);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "Date              Type     Status    From      To           Amount  Note"
+        );
+        assert_eq!(
+            lines[1],
+            "2022-07-01 18:30  Payment  Complete  Jane Doe  John Smith  +$25.00  Dinner"
+        );
+        assert_eq!(
+            lines[2],
+            "2022-07-01 18:30  Charge   Complete  Jane Doe  John Smith  -$80.00  Concert tickets"
+        );
+        assert!(lines.contains(&"Net:                 -$55.00"));
+        assert!(lines.contains(&"Ending balance:      +$65.50"));
+    }
+
+    #[test]
+    fn renders_lunch_money_transactions_table() {
+        console::set_colors_enabled(false);
+
+        let transaction = |id, val, external_id: &str| ExistingTransaction {
+            id,
+            date: chrono::NaiveDate::from_ymd_opt(2022, 7, 1).unwrap(),
+            payee: Some("John Smith".to_string()),
+            amount: crate::types::lunchmoney::Amount(val),
+            external_id: Some(external_id.to_string()),
+            notes: Some("Dinner".to_string()),
+            status: Some("cleared".to_string()),
+            tags: None,
+        };
+
+        let table = lunch_money_transactions_table(
+            &[
+                transaction(101, Decimal::new(2050, 2), "1"),
+                transaction(102, Decimal::new(-2050, 2), "1T"),
+            ],
+            rusty_money::iso::USD,
+        );
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "Date        ID   Status   Payee        Amount  External ID  Notes"
+        );
+        assert_eq!(
+            lines[1],
+            "2022-07-01  101  cleared  John Smith  -$20.50  1            Dinner"
+        );
+        assert_eq!(lines[4], "Transactions: 2, net +$0.00");
+    }
+}
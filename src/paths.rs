@@ -0,0 +1,110 @@
+//! Platform-specific config, cache, and state directories.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use directories::ProjectDirs;
+
+/// Directories used to store config, cache, and state files. Follows the platform conventions
+/// (XDG base directories on Linux, `Application Support` on macOS, `AppData` on Windows) unless
+/// overridden.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub config_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub state_dir: PathBuf,
+}
+
+impl Paths {
+    pub fn new(
+        config_dir: Option<PathBuf>,
+        cache_dir: Option<PathBuf>,
+        state_dir: Option<PathBuf>,
+    ) -> Result<Self> {
+        let project_dirs = ProjectDirs::from("", "", "lunchmoney-venmo-syncer");
+
+        Self::with_defaults(project_dirs.as_ref(), config_dir, cache_dir, state_dir)
+    }
+
+    /// The directories of `project_dirs`, unless overridden. Only fails if a directory that
+    /// isn't overridden has no default, e.g. when there's no home directory.
+    fn with_defaults(
+        project_dirs: Option<&ProjectDirs>,
+        config_dir: Option<PathBuf>,
+        cache_dir: Option<PathBuf>,
+        state_dir: Option<PathBuf>,
+    ) -> Result<Self> {
+        let or_default =
+            |dir: Option<PathBuf>, flag: &str, default: fn(&ProjectDirs) -> &Path| match (
+                dir,
+                project_dirs,
+            ) {
+                (Some(dir), _) => Ok(dir),
+                (None, Some(project_dirs)) => Ok(default(project_dirs).to_path_buf()),
+                (None, None) => Err(anyhow!(
+                    "Failed to determine home directory, pass --{}",
+                    flag
+                )),
+            };
+
+        Ok(Paths {
+            config_dir: or_default(config_dir, "config-dir", ProjectDirs::config_dir)?,
+            cache_dir: or_default(cache_dir, "cache-dir", ProjectDirs::cache_dir)?,
+            // Only Linux has a dedicated state directory, fall back to the local data directory
+            // everywhere else.
+            state_dir: or_default(state_dir, "state-dir", |project_dirs| {
+                project_dirs
+                    .state_dir()
+                    .unwrap_or_else(|| project_dirs.data_local_dir())
+            })?,
+        })
+    }
+
+    /// The default Linux directories of a user with home directory `home`, unless overridden,
+    /// e.g. for a unit running as another user than the one generating it.
+    pub fn for_home(
+        home: &Path,
+        config_dir: Option<PathBuf>,
+        cache_dir: Option<PathBuf>,
+        state_dir: Option<PathBuf>,
+    ) -> Self {
+        let dir = |base: &str| home.join(base).join("lunchmoney-venmo-syncer");
+
+        Paths {
+            config_dir: config_dir.unwrap_or_else(|| dir(".config")),
+            cache_dir: cache_dir.unwrap_or_else(|| dir(".cache")),
+            state_dir: state_dir.unwrap_or_else(|| dir(".local/state")),
+        }
+    }
+}
+
+/// Creates `dir` (and its parents) if it does not already exist.
+pub fn ensure_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| anyhow!("Failed to create directory {:?}", dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_missing_home_directory() {
+        let paths = Paths::with_defaults(
+            None,
+            Some("config".into()),
+            Some("cache".into()),
+            Some("state".into()),
+        )
+        .unwrap();
+        assert_eq!(paths.config_dir, Path::new("config"));
+        assert_eq!(paths.cache_dir, Path::new("cache"));
+        assert_eq!(paths.state_dir, Path::new("state"));
+
+        let err = Paths::with_defaults(None, Some("config".into()), None, Some("state".into()))
+            .unwrap_err();
+        assert!(err.to_string().contains("--cache-dir"), "{}", err);
+    }
+}
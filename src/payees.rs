@@ -0,0 +1,199 @@
+//! Rewriting Venmo names into the payees of synced Lunch Money transactions.
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+
+use crate::config::{PayeeConfig, PayeeRewrite};
+
+/// How a `PayeeRewrite` matches payees.
+enum Matcher {
+    Name(String),
+    Pattern(Regex),
+}
+
+/// A `PayeeRewrite` with its pattern compiled.
+struct CompiledRewrite {
+    matcher: Matcher,
+    replace: String,
+}
+
+/// Rewrites payees using the `[payees]` section of the config file. The first rewrite that
+/// matches a payee wins, otherwise the payee is normalized as configured.
+pub struct Payees {
+    rewrites: Vec<CompiledRewrite>,
+    strip_emoji: bool,
+    title_case: bool,
+}
+
+impl Payees {
+    pub fn new(config: &PayeeConfig) -> Result<Self> {
+        let rewrites = config
+            .rewrites
+            .iter()
+            .enumerate()
+            .map(|(i, rewrite)| {
+                compile_rewrite(rewrite)
+                    .with_context(|| anyhow!("Invalid payee rewrite #{}", i + 1))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Payees {
+            rewrites,
+            strip_emoji: config.strip_emoji,
+            title_case: config.title_case,
+        })
+    }
+
+    /// Returns the payee to use for Venmo name `payee`.
+    pub fn rewrite(&self, payee: &str) -> String {
+        for rewrite in &self.rewrites {
+            match rewrite.matcher {
+                Matcher::Name(ref name) if name.eq_ignore_ascii_case(payee) => {
+                    return rewrite.replace.clone();
+                }
+                Matcher::Pattern(ref pattern) if pattern.is_match(payee) => {
+                    return pattern
+                        .replace(payee, rewrite.replace.as_str())
+                        .into_owned();
+                }
+                _ => {}
+            }
+        }
+
+        let mut payee = payee.to_string();
+
+        if self.strip_emoji {
+            payee = strip_emoji(&payee);
+        }
+
+        if self.title_case {
+            payee = title_case(&payee);
+        }
+
+        payee
+    }
+}
+
+/// Checks `rewrite` can be compiled. Used by `config check`.
+pub fn validate_rewrite(rewrite: &PayeeRewrite) -> Result<()> {
+    compile_rewrite(rewrite).map(|_| ())
+}
+
+fn compile_rewrite(rewrite: &PayeeRewrite) -> Result<CompiledRewrite> {
+    let matcher = match (&rewrite.name, &rewrite.pattern) {
+        (Some(name), None) => Matcher::Name(name.clone()),
+        (None, Some(pattern)) => Matcher::Pattern(
+            Regex::new(pattern)
+                .with_context(|| anyhow!("Failed to parse payee regex '{}'", pattern))?,
+        ),
+        _ => {
+            return Err(anyhow!(
+                "Payee rewrite to '{}' must set exactly one of name or pattern",
+                rewrite.replace
+            ))
+        }
+    };
+
+    Ok(CompiledRewrite {
+        matcher,
+        replace: rewrite.replace.clone(),
+    })
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        // Pictographs, emoticons, transport and map symbols, flags, and skin tones.
+        0x1F000..=0x1FAFF
+        // Miscellaneous symbols and dingbats.
+        | 0x2600..=0x27BF
+        // Arrows, stars, and other symbols used as emoji.
+        | 0x2B00..=0x2BFF
+        // Variation selectors, zero width joiners, and tags combining emoji.
+        | 0xFE00..=0xFE0F
+        | 0x200D
+        | 0xE0020..=0xE007F)
+}
+
+/// Removes emoji from `s`, collapsing the whitespace left behind.
+fn strip_emoji(s: &str) -> String {
+    s.chars()
+        .filter(|c| !is_emoji(*c))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Capitalizes words of `s` that are all lowercase or all uppercase, leaving ones like `McDonald`
+/// alone.
+fn title_case(s: &str) -> String {
+    s.split(' ')
+        .map(|word| {
+            let has_lower = word.chars().any(char::is_lowercase);
+            let has_upper = word.chars().any(char::is_uppercase);
+
+            if has_lower && has_upper {
+                return word.to_string();
+            }
+
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(name: Option<&str>, pattern: Option<&str>, replace: &str) -> PayeeRewrite {
+        PayeeRewrite {
+            name: name.map(str::to_string),
+            pattern: pattern.map(str::to_string),
+            replace: replace.to_string(),
+        }
+    }
+
+    #[test]
+    fn rewrites_payees() {
+        let payees = Payees::new(&PayeeConfig {
+            strip_emoji: true,
+            title_case: true,
+            rewrites: vec![
+                rewrite(Some("john smith"), None, "Rent - John"),
+                rewrite(None, Some(r"^(.*) \(Business\)$"), "$1"),
+            ],
+        })
+        .unwrap();
+
+        assert_eq!(payees.rewrite("John Smith"), "Rent - John");
+        assert_eq!(payees.rewrite("Corner Cafe (Business)"), "Corner Cafe");
+        assert_eq!(payees.rewrite("alex roe 🍕"), "Alex Roe");
+        assert_eq!(payees.rewrite("JANE DOE"), "Jane Doe");
+        assert_eq!(payees.rewrite("Ronald McDonald"), "Ronald McDonald");
+        assert_eq!(payees.rewrite("🇺🇸 Team ❤️ Fund"), "Team Fund");
+    }
+
+    #[test]
+    fn leaves_payees_alone_by_default() {
+        let payees = Payees::new(&PayeeConfig::default()).unwrap();
+
+        assert_eq!(payees.rewrite("alex roe 🍕"), "alex roe 🍕");
+    }
+
+    #[test]
+    fn rejects_ambiguous_rewrites() {
+        assert!(validate_rewrite(&rewrite(Some("a"), Some("b"), "c")).is_err());
+        assert!(validate_rewrite(&rewrite(None, None, "c")).is_err());
+        assert!(validate_rewrite(&rewrite(None, Some("("), "c")).is_err());
+    }
+}
@@ -0,0 +1,608 @@
+//! Client for PayPal's official Transaction Search API, converting PayPal activity into the same
+//! `Statement` Venmo statements parse into so it's inserted into Lunch Money the same way.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use base64::Engine;
+use chrono::{DateTime, FixedOffset, Utc};
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use hyper::{body, Method, Request, StatusCode};
+use rust_decimal::Decimal;
+use rusty_money::iso::Currency;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::config::PayPalConfig;
+use crate::http::{request_following_redirects, HttpTransport};
+use crate::redact;
+use crate::sync::find_currency;
+use crate::types::venmo::{Amount, Statement, Transaction, TransactionStatus, TransactionType};
+
+const LIVE_API: &str = "https://api-m.paypal.com";
+const SANDBOX_API: &str = "https://api-m.sandbox.paypal.com";
+
+/// Longest date range, in days, PayPal searches transactions over in one request.
+const MAX_SEARCH_DAYS: i64 = 31;
+
+/// Max number of transactions PayPal returns per page.
+const PAGE_SIZE: u32 = 500;
+
+/// A PayPal REST API app's credentials, and the balance to sync.
+#[derive(Debug, Clone)]
+pub struct PayPalAccount {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Transactions in other currencies are skipped.
+    pub currency: Currency,
+    pub sandbox: bool,
+}
+
+impl PayPalAccount {
+    pub fn from_config(config: &PayPalConfig) -> Result<Self> {
+        let client_id = config
+            .client_id
+            .clone()
+            .ok_or_else(|| anyhow!("--paypal-client-id or paypal.client_id must be set"))?;
+        let client_secret = config
+            .client_secret
+            .clone()
+            .ok_or_else(|| anyhow!("--paypal-client-secret or paypal.client_secret must be set"))?;
+
+        Ok(PayPalAccount {
+            client_id,
+            client_secret,
+            currency: find_currency(config.currency.as_deref())?,
+            sandbox: config.sandbox,
+        })
+    }
+
+    fn api(&self) -> &'static str {
+        if self.sandbox {
+            SANDBOX_API
+        } else {
+            LIVE_API
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    #[serde(default)]
+    transaction_details: Vec<TransactionDetail>,
+    #[serde(default)]
+    total_pages: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionDetail {
+    transaction_info: TransactionInfo,
+    payer_info: Option<PayerInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionInfo {
+    transaction_id: String,
+    transaction_event_code: String,
+    transaction_initiation_date: String,
+    transaction_amount: Money,
+    fee_amount: Option<Money>,
+    ending_balance: Option<Money>,
+    transaction_status: String,
+    transaction_subject: Option<String>,
+    transaction_note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Money {
+    currency_code: String,
+    value: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct PayerInfo {
+    email_address: Option<String>,
+    payer_name: Option<PayerName>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PayerName {
+    alternate_full_name: Option<String>,
+    given_name: Option<String>,
+    surname: Option<String>,
+}
+
+impl PayerInfo {
+    /// The payer's full name, falling back to their email address.
+    fn name(&self) -> Option<String> {
+        let name = self.payer_name.as_ref().and_then(|name| {
+            name.alternate_full_name.clone().or_else(|| {
+                let parts = [&name.given_name, &name.surname]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+
+                (!parts.is_empty()).then(|| parts.join(" "))
+            })
+        });
+
+        name.or_else(|| self.email_address.clone())
+    }
+}
+
+/// Gets an access token for the PayPal REST API with the OAuth client credentials flow.
+pub async fn get_paypal_access_token(
+    client: &impl HttpTransport,
+    account: &PayPalAccount,
+) -> Result<String> {
+    let credentials = base64::engine::general_purpose::STANDARD
+        .encode(format!("{}:{}", account.client_id, account.client_secret));
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/v1/oauth2/token", account.api()))
+        .header(AUTHORIZATION, format!("Basic {}", credentials))
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(ACCEPT, "application/json")
+        .body(body::Body::from("grant_type=client_credentials"))
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+
+    if status == StatusCode::UNAUTHORIZED {
+        bail!(
+            "PayPal rejected the client ID and secret{}",
+            if account.sandbox {
+                ""
+            } else {
+                ", pass --paypal-sandbox if they're for a sandbox app"
+            }
+        );
+    }
+
+    if status != StatusCode::OK {
+        bail!(
+            "Failed to get PayPal access token, code {}, body:\n{}",
+            status,
+            redact::bytes(&bytes)
+        );
+    }
+
+    let response: TokenResponse = serde_json::from_slice(&bytes)
+        .with_context(|| anyhow!("Failed to parse PayPal access token response"))?;
+
+    Ok(response.access_token)
+}
+
+/// Splits `start_date..=end_date` into consecutive windows of at most `MAX_SEARCH_DAYS` days.
+fn search_windows(
+    start_date: &DateTime<Utc>,
+    end_date: &DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut windows = Vec::new();
+    let mut window_start = *start_date;
+
+    loop {
+        let window_end = window_start + chrono::Duration::days(MAX_SEARCH_DAYS);
+
+        if window_end >= *end_date {
+            windows.push((window_start, *end_date));
+            return windows;
+        }
+
+        windows.push((window_start, window_end));
+        window_start = window_end;
+    }
+}
+
+/// GETs page `page` of the balance affecting transactions from `start_date` to `end_date`.
+async fn search_transactions(
+    client: &impl HttpTransport,
+    account: &PayPalAccount,
+    access_token: &str,
+    (start_date, end_date): (DateTime<Utc>, DateTime<Utc>),
+    page: u32,
+) -> Result<SearchResponse> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{}/v1/reporting/transactions?start_date={}&end_date={}&fields=transaction_info,payer_info&balance_affecting_records_only=Y&page_size={}&page={}",
+            account.api(),
+            start_date.format("%Y-%m-%dT%H:%M:%SZ"),
+            end_date.format("%Y-%m-%dT%H:%M:%SZ"),
+            PAGE_SIZE,
+            page
+        ))
+        .header(AUTHORIZATION, format!("Bearer {}", access_token))
+        .header(ACCEPT, "application/json")
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+
+    if status == StatusCode::FORBIDDEN {
+        bail!("PayPal denied the transaction search, enable Transaction Search for the app at https://developer.paypal.com/dashboard/applications");
+    }
+
+    if status != StatusCode::OK {
+        bail!(
+            "Failed to search PayPal transactions, code {}, body:\n{}",
+            status,
+            redact::bytes(&bytes)
+        );
+    }
+
+    serde_json::from_slice(&bytes)
+        .with_context(|| anyhow!("Failed to parse PayPal transaction search response"))
+}
+
+/// PayPal transaction IDs are alphanumeric, e.g. `5TY05013RG002845M`, and too long to decode
+/// into a `u64`. Derives a stable `Transaction::id` from a hash of it instead, the ID itself is
+/// kept as the Lunch Money `external_id`.
+fn transaction_id(id: &str) -> u64 {
+    let digest = Sha256::digest(id.as_bytes());
+
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+fn parse_status(status: &str) -> Result<TransactionStatus> {
+    Ok(match status {
+        // Success, and partially refunded.
+        "S" | "F" => TransactionStatus::Complete,
+        "P" => TransactionStatus::Pending,
+        "D" => TransactionStatus::Failed,
+        // Reversed.
+        "V" => TransactionStatus::Cancelled,
+        _ => bail!("Unexpected PayPal transaction status '{}'", status),
+    })
+}
+
+/// Converts a PayPal transaction to the Venmo transaction type with the same meaning, so it's
+/// turned into Lunch Money transactions the same way. See
+/// <https://developer.paypal.com/docs/transaction-search/transaction-event-codes/>.
+fn to_transaction(
+    detail: TransactionDetail,
+    currency: &Currency,
+    lenient: bool,
+) -> Result<Transaction> {
+    let info = detail.transaction_info;
+    let fee = info.fee_amount.map_or(Decimal::ZERO, |fee| fee.value);
+    let val = info.transaction_amount.value + fee;
+    let received = val.is_sign_positive();
+
+    let counterparty = detail.payer_info.as_ref().and_then(PayerInfo::name);
+    let (from, to) = if received {
+        (counterparty, None)
+    } else {
+        (None, counterparty)
+    };
+
+    let mut transaction = Transaction {
+        id: transaction_id(&info.transaction_id),
+        datetime: DateTime::parse_from_str(
+            &info.transaction_initiation_date,
+            "%Y-%m-%dT%H:%M:%S%z",
+        )
+        .with_context(|| {
+            anyhow!(
+                "Failed to parse PayPal date '{}'",
+                info.transaction_initiation_date
+            )
+        })?,
+        type_: TransactionType::Payment,
+        status: parse_status(&info.transaction_status)?,
+        note: info.transaction_note.or(info.transaction_subject),
+        from,
+        to,
+        amount_total: Amount {
+            currency: currency.symbol.to_string(),
+            val,
+        },
+        amount_tip: None,
+        amount_tax: None,
+        amount_fee: None,
+        funding_source: None,
+        destination: None,
+        external_id: Some(info.transaction_id.clone()),
+    };
+
+    let code = &info.transaction_event_code;
+
+    match code.get(..3).unwrap_or_default() {
+        // Payments.
+        "T00" => {
+            if transaction.from.is_none() && transaction.to.is_none() {
+                transaction.to = Some("PayPal".to_string());
+            }
+        }
+        // Fees not tied to a payment.
+        "T01" => transaction.to = Some("PayPal".to_string()),
+        // Bank deposits into PayPal.
+        "T03" => {
+            transaction.type_ = TransactionType::DirectDeposit;
+            transaction.from = Some("Bank".to_string());
+        }
+        // Bank withdrawals from PayPal.
+        "T04" => {
+            transaction.type_ = TransactionType::StandardTransfer;
+            transaction.destination = Some("Bank".to_string());
+        }
+        // PayPal debit card purchases.
+        "T05" => transaction.type_ = TransactionType::MerchantTransaction,
+        // Rewards and cashback.
+        "T08" => {
+            transaction.type_ = TransactionType::Cashback;
+            transaction.from = Some("PayPal".to_string());
+        }
+        // Refunds and reversals.
+        "T11" => transaction.type_ = TransactionType::Refund,
+        _ => {
+            if !lenient {
+                bail!(
+                    "Transaction {} has a PayPal event code this tool doesn't support yet ({}), pass --lenient to sync it like a payment",
+                    info.transaction_id,
+                    code
+                );
+            }
+
+            warn!(
+                "Transaction {} has unknown PayPal event code {}, syncing it like a payment",
+                info.transaction_id, code
+            );
+            transaction.type_ = TransactionType::Unknown(code.clone());
+        }
+    }
+
+    Ok(transaction)
+}
+
+/// Fetches the PayPal transactions in `account.currency` from `start_date` to `end_date`,
+/// searching in windows as long as PayPal allows. PayPal doesn't report the balance at the start
+/// of a range, so the statement's beginning balance is worked back from the newest transaction's
+/// ending balance. Unrecognized transaction event codes fail the fetch unless `lenient` is set.
+#[tracing::instrument(skip_all, fields(%start_date, %end_date))]
+pub async fn fetch_paypal_transactions(
+    client: &impl HttpTransport,
+    account: &PayPalAccount,
+    start_date: &DateTime<Utc>,
+    end_date: &DateTime<Utc>,
+    lenient: bool,
+) -> Result<Statement> {
+    let access_token = get_paypal_access_token(client, account).await?;
+    let currency_code = account.currency.iso_alpha_code;
+
+    let mut transactions = Vec::new();
+    let mut ending_balance: Option<(DateTime<FixedOffset>, Decimal)> = None;
+    let mut skipped = 0;
+
+    for window in search_windows(start_date, end_date) {
+        let mut page = 1;
+
+        loop {
+            let response = search_transactions(client, account, &access_token, window, page)
+                .await
+                .with_context(|| {
+                    anyhow!(
+                        "Failed to fetch PayPal transactions from {} to {}",
+                        window.0.format("%Y-%m-%d"),
+                        window.1.format("%Y-%m-%d")
+                    )
+                })?;
+
+            for detail in response.transaction_details {
+                if detail.transaction_info.transaction_amount.currency_code != currency_code {
+                    skipped += 1;
+                    continue;
+                }
+
+                let balance = detail
+                    .transaction_info
+                    .ending_balance
+                    .as_ref()
+                    .filter(|balance| balance.currency_code == currency_code)
+                    .map(|balance| balance.value);
+                let transaction = to_transaction(detail, &account.currency, lenient)?;
+
+                if let Some(balance) = balance {
+                    if !matches!(ending_balance, Some((datetime, _)) if datetime > transaction.datetime)
+                    {
+                        ending_balance = Some((transaction.datetime, balance));
+                    }
+                }
+
+                transactions.push(transaction);
+            }
+
+            if page >= response.total_pages {
+                break;
+            }
+            page += 1;
+        }
+    }
+
+    if skipped > 0 {
+        debug!(
+            skipped,
+            "Skipped PayPal transactions not in {}", currency_code
+        );
+    }
+
+    transactions.sort_by_key(|transaction| transaction.datetime);
+
+    let net: Decimal = transactions
+        .iter()
+        .filter(|transaction| !TransactionStatus::DEFAULT_SKIPPED.contains(&transaction.status))
+        .map(|transaction| transaction.amount_total.val)
+        .sum();
+    let ending_balance = ending_balance.map_or(net, |(_, balance)| balance);
+    let amount = |val| Amount {
+        currency: account.currency.symbol.to_string(),
+        val,
+    };
+
+    Ok(Statement {
+        beginning_balance: amount(ending_balance - net),
+        ending_balance: amount(ending_balance),
+        transactions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::fake::FakeTransport;
+    use crate::testing;
+
+    fn date(date: &str) -> DateTime<Utc> {
+        format!("{}T00:00:00Z", date).parse().unwrap()
+    }
+
+    fn account() -> PayPalAccount {
+        PayPalAccount {
+            client_id: "id".to_string(),
+            client_secret: "secret".to_string(),
+            currency: *rusty_money::iso::USD,
+            sandbox: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_transactions() {
+        let search = br#"{
+            "transaction_details": [
+                {
+                    "transaction_info": {
+                        "transaction_id": "5TY05013RG002845M",
+                        "transaction_event_code": "T0006",
+                        "transaction_initiation_date": "2023-01-15T14:23:45+0000",
+                        "transaction_amount": { "currency_code": "USD", "value": "-25.00" },
+                        "ending_balance": { "currency_code": "USD", "value": "75.00" },
+                        "transaction_status": "S",
+                        "transaction_subject": "Order #1234"
+                    },
+                    "payer_info": { "payer_name": { "alternate_full_name": "Example Store" } }
+                },
+                {
+                    "transaction_info": {
+                        "transaction_id": "1FN09943UV455731N",
+                        "transaction_event_code": "T0000",
+                        "transaction_initiation_date": "2023-01-10T09:00:00+0000",
+                        "transaction_amount": { "currency_code": "USD", "value": "20.00" },
+                        "fee_amount": { "currency_code": "USD", "value": "-0.89" },
+                        "ending_balance": { "currency_code": "USD", "value": "100.00" },
+                        "transaction_status": "S"
+                    },
+                    "payer_info": {
+                        "email_address": "jane@example.com",
+                        "payer_name": { "given_name": "Jane", "surname": "Doe" }
+                    }
+                },
+                {
+                    "transaction_info": {
+                        "transaction_id": "7KL12345AB678901C",
+                        "transaction_event_code": "T0000",
+                        "transaction_initiation_date": "2023-01-11T09:00:00+0000",
+                        "transaction_amount": { "currency_code": "EUR", "value": "5.00" },
+                        "transaction_status": "S"
+                    }
+                }
+            ],
+            "total_pages": 1
+        }"#;
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, br#"{"access_token": "token"}"#),
+            (StatusCode::OK, search),
+        ]);
+        let start_date = date("2023-01-01");
+        let end_date = date("2023-01-20");
+
+        let statement =
+            fetch_paypal_transactions(&transport, &account(), &start_date, &end_date, false)
+                .await
+                .unwrap();
+
+        let transactions = &statement.transactions;
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            transactions[0],
+            Transaction {
+                id: transaction_id("1FN09943UV455731N"),
+                datetime: DateTime::parse_from_rfc3339("2023-01-10T09:00:00Z").unwrap(),
+                note: None,
+                to: None,
+                external_id: Some("1FN09943UV455731N".to_string()),
+                ..testing::transaction(0, TransactionType::Payment, Decimal::new(1911, 2))
+            }
+        );
+        assert_eq!(transactions[1].external_id(), "5TY05013RG002845M");
+        assert_eq!(transactions[1].to.as_deref(), Some("Example Store"));
+        assert_eq!(transactions[1].note.as_deref(), Some("Order #1234"));
+        assert_eq!(statement.ending_balance.val, Decimal::new(75, 0));
+        assert_eq!(statement.beginning_balance.val, Decimal::new(8089, 2));
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(
+            requests[0].uri,
+            "https://api-m.sandbox.paypal.com/v1/oauth2/token"
+        );
+        assert_eq!(requests[0].headers[AUTHORIZATION], "Basic aWQ6c2VjcmV0");
+        assert_eq!(requests[0].body, b"grant_type=client_credentials");
+        assert!(requests[1]
+            .uri
+            .contains("start_date=2023-01-01T00:00:00Z&end_date=2023-01-20T00:00:00Z"));
+        assert_eq!(requests[1].headers[AUTHORIZATION], "Bearer token");
+    }
+
+    #[test]
+    fn rejects_unknown_event_codes_unless_lenient() {
+        let detail = || TransactionDetail {
+            transaction_info: TransactionInfo {
+                transaction_id: "5TY05013RG002845M".to_string(),
+                transaction_event_code: "T9900".to_string(),
+                transaction_initiation_date: "2023-01-15T14:23:45+0000".to_string(),
+                transaction_amount: Money {
+                    currency_code: "USD".to_string(),
+                    value: Decimal::new(-5, 0),
+                },
+                fee_amount: None,
+                ending_balance: None,
+                transaction_status: "S".to_string(),
+                transaction_subject: None,
+                transaction_note: None,
+            },
+            payer_info: None,
+        };
+
+        assert!(to_transaction(detail(), rusty_money::iso::USD, false).is_err());
+        assert_eq!(
+            to_transaction(detail(), rusty_money::iso::USD, true)
+                .unwrap()
+                .type_,
+            TransactionType::Unknown("T9900".to_string())
+        );
+    }
+
+    #[test]
+    fn splits_searches_into_windows() {
+        let start_date = date("2023-01-01");
+        let end_date = date("2023-03-01");
+
+        let windows = search_windows(&start_date, &end_date);
+
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[0].1, windows[1].0);
+        assert_eq!(windows[1].1, end_date);
+    }
+}
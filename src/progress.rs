@@ -0,0 +1,61 @@
+//! Progress bars for long backfills, drawn on stderr once enabled with `--progress`.
+
+use std::ops::Deref;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Draws all progress bars, hidden until `enable` is called.
+    static ref BARS: MultiProgress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+}
+
+/// Draws progress bars on stderr from now on, unless it isn't a terminal.
+pub fn enable() {
+    BARS.set_draw_target(ProgressDrawTarget::stderr());
+}
+
+/// Runs `f` with progress bars cleared, so what it writes to stderr isn't drawn over.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    BARS.suspend(f)
+}
+
+/// A progress bar, cleared when dropped.
+pub struct Progress(ProgressBar);
+
+impl Deref for Progress {
+    type Target = ProgressBar;
+
+    fn deref(&self) -> &ProgressBar {
+        &self.0
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+    }
+}
+
+/// A progress bar labeled `message` counting up to `len` `unit`s, with an ETA. Nothing is drawn
+/// for a single step.
+pub fn bar(message: &'static str, len: usize, unit: &str) -> Progress {
+    if len <= 1 {
+        return Progress(ProgressBar::hidden());
+    }
+
+    let style = ProgressStyle::with_template(&format!(
+        "{{msg}} [{{bar:30}}] {{pos}}/{{len}} {}, ETA {{eta}}",
+        unit
+    ))
+    .expect("progress bar template is valid")
+    .progress_chars("=> ");
+
+    Progress(
+        BARS.add(
+            ProgressBar::new(len as u64)
+                .with_style(style)
+                .with_message(message),
+        ),
+    )
+}
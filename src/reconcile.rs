@@ -0,0 +1,360 @@
+//! Comparing a Venmo statement to the Lunch Money asset it's synced to, for `reconcile`.
+
+use std::fmt;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use rusty_money::iso::Currency;
+use serde::Serialize;
+
+use crate::lunchmoney::{get_all_assets, get_transactions};
+use crate::output;
+use crate::sync::{differences, serialize_currency, ChangedTransaction, LunchMoneyDestination};
+use crate::types::lunchmoney::{ExistingTransaction, Transaction as LunchMoneyTransaction};
+use crate::types::venmo::Statement;
+use crate::types::HttpsClient;
+
+/// How a Venmo statement and the Lunch Money asset it's synced to differ.
+#[derive(Debug, Serialize)]
+pub struct Reconciliation {
+    /// Transactions the statement converts into that are in the asset, by `external_id`.
+    pub matched: usize,
+    /// Converted transactions without a match by `external_id`, but with a transaction in the
+    /// asset with the same date and amount, e.g. one entered by hand.
+    pub matched_by_amount: usize,
+    /// Converted transactions that aren't in the asset, e.g. because they weren't synced yet.
+    pub missing: Vec<LunchMoneyTransaction>,
+    /// Transactions in the asset that don't match any converted transaction.
+    pub extra: Vec<ExistingTransaction>,
+    /// Transactions in the asset whose amount or date differ from the converted transaction with
+    /// the same `external_id`.
+    pub mismatched: Vec<ChangedTransaction>,
+    /// Venmo balance at the end of the statement.
+    pub ending_balance: Decimal,
+    /// Balance of the Lunch Money asset at the end of the statement, see `balance_at`.
+    pub asset_balance: Decimal,
+    #[serde(serialize_with = "serialize_currency")]
+    pub currency: Currency,
+}
+
+impl Reconciliation {
+    /// Number of missing, extra, and mismatched transactions, plus one if the balances differ.
+    pub fn discrepancies(&self) -> usize {
+        self.missing.len()
+            + self.extra.len()
+            + self.mismatched.len()
+            + usize::from(self.ending_balance != self.asset_balance)
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Matched: {}", self.matched)?;
+        if self.matched_by_amount > 0 {
+            writeln!(f, "Matched by date and amount: {}", self.matched_by_amount)?;
+        }
+
+        writeln!(f, "Missing from Lunch Money: {}", self.missing.len())?;
+        for transaction in &self.missing {
+            writeln!(
+                f,
+                "  {} {} {} {}",
+                transaction.external_id.as_deref().unwrap_or_default(),
+                transaction.date,
+                transaction.payee.as_deref().unwrap_or_default(),
+                output::signed_amount(transaction.amount.0, &self.currency)
+            )?;
+        }
+
+        writeln!(f, "Not in the Venmo statement: {}", self.extra.len())?;
+        for transaction in &self.extra {
+            writeln!(
+                f,
+                "  Lunch Money transaction {} {} {} {}",
+                transaction.id,
+                transaction.date,
+                transaction.payee.as_deref().unwrap_or_default(),
+                output::signed_amount(-transaction.amount.0, &self.currency)
+            )?;
+        }
+
+        writeln!(f, "Mismatched: {}", self.mismatched.len())?;
+        for changed in &self.mismatched {
+            writeln!(
+                f,
+                "  {} (Lunch Money transaction {}): {}",
+                changed.external_id,
+                changed.id,
+                changed.differences.join(", ")
+            )?;
+        }
+
+        writeln!(
+            f,
+            "Venmo ending balance: {}",
+            output::signed_amount(self.ending_balance, &self.currency)
+        )?;
+        write!(
+            f,
+            "Lunch Money asset balance at the statement's end: {}{}",
+            output::signed_amount(self.asset_balance, &self.currency),
+            if self.ending_balance == self.asset_balance {
+                ""
+            } else {
+                " (differs)"
+            }
+        )
+    }
+}
+
+/// The transaction lists of a `Reconciliation`.
+struct Matches {
+    matched: usize,
+    matched_by_amount: usize,
+    missing: Vec<LunchMoneyTransaction>,
+    extra: Vec<ExistingTransaction>,
+    mismatched: Vec<ChangedTransaction>,
+}
+
+/// Matches the `expected` transactions a statement converts into against the asset's `existing`
+/// transactions, first by `external_id`, then the rest by date and amount.
+fn match_transactions(
+    expected: Vec<LunchMoneyTransaction>,
+    mut existing: Vec<ExistingTransaction>,
+) -> Matches {
+    let mut matched = 0;
+    let mut mismatched = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for transaction in expected {
+        let found = existing.iter().position(|existing| {
+            transaction.external_id.is_some() && existing.external_id == transaction.external_id
+        });
+
+        match found {
+            Some(i) => {
+                let found = existing.swap_remove(i);
+                let differences = differences(&transaction, &found);
+
+                if differences.is_empty() {
+                    matched += 1;
+                } else {
+                    mismatched.push(ChangedTransaction {
+                        external_id: transaction.external_id.unwrap_or_default(),
+                        id: found.id,
+                        differences,
+                    });
+                }
+            }
+            None => unmatched.push(transaction),
+        }
+    }
+
+    let mut matched_by_amount = 0;
+    let mut missing = Vec::new();
+
+    for transaction in unmatched {
+        let found = existing.iter().position(|existing| {
+            existing.date == transaction.date && -existing.amount.0 == transaction.amount.0
+        });
+
+        match found {
+            Some(i) => {
+                existing.swap_remove(i);
+                matched_by_amount += 1;
+            }
+            None => missing.push(transaction),
+        }
+    }
+
+    existing.sort_by_key(|existing| (existing.date, existing.id));
+
+    Matches {
+        matched,
+        matched_by_amount,
+        missing,
+        extra: existing,
+        mismatched,
+    }
+}
+
+/// The balance an asset had before the `later` transactions, given its `balance` after them.
+fn balance_before(balance: Decimal, later: &[ExistingTransaction]) -> Decimal {
+    // Expenses are positive, and took away from the balance.
+    balance
+        + later
+            .iter()
+            .map(|transaction| transaction.amount.0)
+            .sum::<Decimal>()
+}
+
+/// Compares `statement`, converted as syncing to `destination` would, to the transactions in
+/// the Lunch Money asset from `start_date` to `end_date`, and its ending balance to the asset's
+/// balance on `end_date`: its current balance without the transactions dated after. Only the
+/// asset's side of transfers is compared, and category rules, tags, and payees aren't.
+pub async fn reconcile(
+    client: &HttpsClient,
+    destination: &LunchMoneyDestination,
+    currency: &Currency,
+    statement: &Statement,
+    (start_date, end_date): (NaiveDate, NaiveDate),
+) -> Result<Reconciliation> {
+    let mut expected = Vec::new();
+
+    for transaction in &statement.transactions {
+        if destination.skip_statuses.contains(&transaction.status)
+            || !destination.filter.matches(transaction)
+        {
+            continue;
+        }
+
+        expected.extend(
+            transaction
+                .to_lunchmoney_transactions(*currency, destination.asset_id, &destination.convert)?
+                .into_iter()
+                .filter(|transaction| transaction.asset_id == Some(destination.asset_id)),
+        );
+    }
+
+    let existing = get_transactions(
+        client,
+        &destination.api_token,
+        destination.asset_id,
+        start_date,
+        end_date,
+    )
+    .await?;
+    let asset = get_all_assets(client, &destination.api_token)
+        .await?
+        .into_iter()
+        .find(|asset| asset.id == destination.asset_id)
+        .ok_or_else(|| anyhow!("Lunch Money asset {} not found", destination.asset_id))?;
+
+    let balance_as_of = asset.balance_as_of.date_naive();
+    let later = match end_date.succ_opt() {
+        Some(after_end) if after_end <= balance_as_of => {
+            get_transactions(
+                client,
+                &destination.api_token,
+                destination.asset_id,
+                after_end,
+                balance_as_of,
+            )
+            .await?
+        }
+        _ => Vec::new(),
+    };
+
+    let Matches {
+        matched,
+        matched_by_amount,
+        missing,
+        extra,
+        mismatched,
+    } = match_transactions(expected, existing);
+
+    Ok(Reconciliation {
+        matched,
+        matched_by_amount,
+        missing,
+        extra,
+        mismatched,
+        ending_balance: statement.ending_balance.val,
+        asset_balance: balance_before(asset.balance.0, &later),
+        currency: *currency,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::types::lunchmoney::Amount;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 7, day).unwrap()
+    }
+
+    fn expected(external_id: &str, day: u32, val: Decimal) -> LunchMoneyTransaction {
+        LunchMoneyTransaction {
+            date: date(day),
+            amount: Amount(val),
+            external_id: Some(external_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn existing(id: u64, external_id: Option<&str>, day: u32, val: Decimal) -> ExistingTransaction {
+        ExistingTransaction {
+            id,
+            date: date(day),
+            payee: None,
+            // Lunch Money returns expenses as positive amounts.
+            amount: Amount(-val),
+            external_id: external_id.map(str::to_string),
+            notes: None,
+            status: None,
+            tags: None,
+        }
+    }
+
+    #[test]
+    fn matches_by_external_id_then_amount() {
+        let matches = match_transactions(
+            vec![
+                expected("1", 1, Decimal::new(-25, 0)),
+                expected("2", 2, Decimal::new(-30, 0)),
+                expected("3", 3, Decimal::new(80, 0)),
+                expected("4", 4, Decimal::new(-5, 0)),
+            ],
+            vec![
+                existing(101, Some("1"), 1, Decimal::new(-25, 0)),
+                existing(102, Some("2"), 2, Decimal::new(-35, 0)),
+                existing(103, None, 3, Decimal::new(80, 0)),
+                existing(104, None, 5, Decimal::new(-12, 0)),
+            ],
+        );
+
+        assert_eq!(matches.matched, 1);
+        assert_eq!(matches.matched_by_amount, 1);
+        assert_eq!(matches.mismatched.len(), 1);
+        assert_eq!(matches.mismatched[0].id, 102);
+        assert_eq!(
+            matches
+                .missing
+                .iter()
+                .map(|transaction| transaction.external_id.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("4")]
+        );
+        assert_eq!(
+            matches
+                .extra
+                .iter()
+                .map(|transaction| transaction.id)
+                .collect::<Vec<_>>(),
+            vec![104]
+        );
+    }
+
+    #[test]
+    fn undoes_later_transactions_from_balance() {
+        // Spent 12 and received 80 after the statement ended.
+        let later = [
+            existing(105, None, 20, Decimal::new(-12, 0)),
+            existing(106, None, 21, Decimal::new(80, 0)),
+        ];
+
+        assert_eq!(
+            balance_before(Decimal::new(168, 0), &later),
+            Decimal::new(100, 0)
+        );
+        assert_eq!(
+            balance_before(Decimal::new(100, 0), &[]),
+            Decimal::new(100, 0)
+        );
+    }
+}
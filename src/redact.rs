@@ -0,0 +1,136 @@
+//! Masking secrets in error and debug output.
+
+use hyper::header::HeaderName;
+use hyper::HeaderMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Header and JSON key names (lowercase) whose values are secrets.
+const SECRET_NAMES: &[&str] = &[
+    "authorization",
+    "cookie",
+    "set-cookie",
+    "venmo-otp-secret",
+    "venmo-otp",
+    "password",
+];
+
+lazy_static! {
+    static ref SECRET_TEXT_RES: Vec<Regex> = vec![
+        // `api_access_token=...` in cookies and query strings.
+        Regex::new(r"(?i)([a-z_]*token=)[^;&\s]+").unwrap(),
+        // `"access_token": "..."` in JSON bodies.
+        Regex::new(r#"(?i)("[a-z_]*(?:token|secret|password)"\s*:\s*")[^"]*(")"#).unwrap(),
+        Regex::new(r"(?i)(bearer\s+)\S+").unwrap(),
+    ];
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+
+    SECRET_NAMES.contains(&name.as_str()) || name.contains("token") || name.contains("secret")
+}
+
+/// Masks tokens, passwords, and bearer credentials in free-form `text`. Each pattern captures
+/// what comes before (and optionally after) the secret, which is kept.
+pub fn text(text: &str) -> String {
+    SECRET_TEXT_RES.iter().fold(text.to_string(), |text, re| {
+        re.replace_all(&text, format!("${{1}}{}${{2}}", REDACTED).as_str())
+            .into_owned()
+    })
+}
+
+/// Like `text`, for a response body that may not be UTF-8.
+pub fn bytes(bytes: &[u8]) -> String {
+    text(&String::from_utf8_lossy(bytes))
+}
+
+/// Returns a copy of `value` with the values of secret keys masked, at any depth.
+pub fn json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    if is_secret_name(key) {
+                        (key.clone(), Value::String(REDACTED.to_string()))
+                    } else {
+                        (key.clone(), json(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(json).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Formats `headers` one per line, with cookies, authorization, and token headers masked.
+pub fn headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value): (&HeaderName, _)| {
+            if is_secret_name(name.as_str()) {
+                format!("{}: {}", name, REDACTED)
+            } else {
+                format!(
+                    "{}: {}",
+                    name,
+                    text(&String::from_utf8_lossy(value.as_bytes()))
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets_in_text() {
+        assert_eq!(
+            text("Cookie: api_access_token=abc123; other=1"),
+            "Cookie: api_access_token=[REDACTED]; other=1"
+        );
+        assert_eq!(
+            text(r#"{"access_token": "abc123", "user": {"id": "1"}}"#),
+            r#"{"access_token": "[REDACTED]", "user": {"id": "1"}}"#
+        );
+        assert_eq!(
+            text("Authorization: Bearer abc123"),
+            "Authorization: Bearer [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn redacts_secrets_in_json() {
+        let value = serde_json::json!({
+            "access_token": "abc123",
+            "user": {"id": "1", "password": "hunter2"},
+        });
+
+        assert_eq!(
+            json(&value),
+            serde_json::json!({
+                "access_token": REDACTED,
+                "user": {"id": "1", "password": REDACTED},
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_secret_headers() {
+        let mut map = HeaderMap::new();
+        map.insert("set-cookie", "api_access_token=abc123".parse().unwrap());
+        map.insert("content-type", "text/csv".parse().unwrap());
+
+        let formatted = headers(&map);
+
+        assert!(!formatted.contains("abc123"));
+        assert!(formatted.contains("content-type: text/csv"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Rules assigning Lunch Money categories to Venmo transactions.
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+
+use crate::config::CategoryRule;
+use crate::types::lunchmoney::Category;
+use crate::types::venmo::{Transaction, TransactionType};
+
+/// A `CategoryRule` with its note regex compiled and category resolved to an ID.
+struct CompiledRule {
+    payee: Option<String>,
+    note: Option<Regex>,
+    type_: Option<TransactionType>,
+    category_id: u64,
+}
+
+/// Assigns Lunch Money categories to Venmo transactions using the `[[rules]]` from the config
+/// file. The first rule that matches a transaction wins.
+pub struct Rules {
+    rules: Vec<CompiledRule>,
+}
+
+impl Rules {
+    /// Compiles `rules`, resolving their category names against `categories`.
+    pub fn new(rules: &[CategoryRule], categories: &[Category]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                compile_rule(rule, categories).with_context(|| anyhow!("Invalid rule #{}", i + 1))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Rules { rules })
+    }
+
+    /// Returns the category ID of the first rule matching `transaction`, whose Lunch Money payee
+    /// is `payee`.
+    pub fn category_for(&self, transaction: &Transaction, payee: &str) -> Option<u64> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(transaction, payee))
+            .map(|rule| rule.category_id)
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, transaction: &Transaction, payee: &str) -> bool {
+        if let Some(ref rule_payee) = self.payee {
+            if !rule_payee.eq_ignore_ascii_case(payee) {
+                return false;
+            }
+        }
+
+        if let Some(ref note) = self.note {
+            match transaction.note {
+                Some(ref transaction_note) if note.is_match(transaction_note) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref type_) = self.type_ {
+            if *type_ != transaction.type_ {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Checks `rule` can be compiled, without resolving its category. Used by `config check`.
+pub fn validate_rule(rule: &CategoryRule) -> Result<()> {
+    compile_matchers(rule).map(|_| ())
+}
+
+fn compile_matchers(rule: &CategoryRule) -> Result<(Option<Regex>, Option<TransactionType>)> {
+    if rule.payee.is_none() && rule.note.is_none() && rule.type_.is_none() {
+        return Err(anyhow!(
+            "Rule for category '{}' must set at least one of payee, note, or type",
+            rule.category
+        ));
+    }
+
+    let note = rule
+        .note
+        .as_deref()
+        .map(|note| {
+            Regex::new(note).with_context(|| anyhow!("Failed to parse note regex '{}'", note))
+        })
+        .transpose()?;
+    let type_ = rule
+        .type_
+        .as_deref()
+        .map(TransactionType::from_str)
+        .transpose()?;
+
+    if let Some(TransactionType::Unknown(ref type_)) = type_ {
+        return Err(anyhow!(
+            "Rule for category '{}' has unknown transaction type '{}'",
+            rule.category,
+            type_
+        ));
+    }
+
+    Ok((note, type_))
+}
+
+fn compile_rule(rule: &CategoryRule, categories: &[Category]) -> Result<CompiledRule> {
+    let (note, type_) = compile_matchers(rule)?;
+
+    let category_id = categories
+        .iter()
+        .find(|category| category.name.eq_ignore_ascii_case(&rule.category))
+        .map(|category| category.id)
+        .ok_or_else(|| anyhow!("Lunch Money category '{}' does not exist", rule.category))?;
+
+    Ok(CompiledRule {
+        payee: rule.payee.clone(),
+        note,
+        type_,
+        category_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::testing;
+
+    fn category(id: u64, name: &str) -> Category {
+        Category {
+            id,
+            name: name.to_string(),
+            description: None,
+            is_income: false,
+            exclude_from_budget: false,
+            exclude_from_totals: false,
+            is_group: None,
+        }
+    }
+
+    fn transaction(type_: TransactionType, note: &str) -> Transaction {
+        Transaction {
+            note: Some(note.to_string()),
+            ..testing::transaction(1, type_, Decimal::new(-10, 0))
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = Rules::new(
+            &[
+                CategoryRule {
+                    note: Some("(?i)rent".to_string()),
+                    category: "Housing".to_string(),
+                    ..Default::default()
+                },
+                CategoryRule {
+                    payee: Some("spotify".to_string()),
+                    category: "Subscriptions".to_string(),
+                    ..Default::default()
+                },
+                CategoryRule {
+                    type_: Some("Merchant Transaction".to_string()),
+                    category: "shopping".to_string(),
+                    ..Default::default()
+                },
+            ],
+            &[
+                category(1, "Housing"),
+                category(2, "Subscriptions"),
+                category(3, "Shopping"),
+            ],
+        )
+        .unwrap();
+
+        let payment = transaction(TransactionType::Payment, "July Rent");
+        assert_eq!(rules.category_for(&payment, "Spotify"), Some(1));
+
+        let payment = transaction(TransactionType::Payment, "music");
+        assert_eq!(rules.category_for(&payment, "Spotify"), Some(2));
+        assert_eq!(rules.category_for(&payment, "John Smith"), None);
+
+        let purchase = transaction(TransactionType::MerchantTransaction, "order");
+        assert_eq!(rules.category_for(&purchase, "Some Store"), Some(3));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let categories = [category(1, "Housing")];
+        let rule = |note: &str, category: &str| CategoryRule {
+            note: Some(note.to_string()),
+            category: category.to_string(),
+            ..Default::default()
+        };
+
+        assert!(Rules::new(&[rule("(", "Housing")], &categories).is_err());
+        assert!(Rules::new(&[rule("rent", "Groceries")], &categories).is_err());
+        assert!(Rules::new(
+            &[CategoryRule {
+                category: "Housing".to_string(),
+                ..Default::default()
+            }],
+            &categories
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! The `self-update` subcommand, updating the binary from GitHub releases.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use hyper::header::{ACCEPT, USER_AGENT};
+use hyper::{body, Method, Request, StatusCode};
+use minisign_verify::{PublicKey, Signature};
+use serde::Deserialize;
+use tempfile::NamedTempFile;
+
+use crate::http::request_following_redirects;
+use crate::types::HttpsClient;
+
+const RELEASES_URL: &str =
+    "https://api.github.com/repos/emersonford/lunchmoney-venmo-syncer/releases/latest";
+
+/// The minisign public key release binaries are signed with, set when building releases. Builds
+/// without one can check for updates but not install them.
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("LUNCHMONEY_VENMO_RELEASE_PUBLIC_KEY");
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Parses a version like `v1.2.3` into its numeric components for comparison.
+fn parse_version(version: &str) -> Result<Vec<u64>> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| {
+            part.parse()
+                .with_context(|| anyhow!("Failed to parse version {}", version))
+        })
+        .collect()
+}
+
+/// Name of the release asset built for the platform we're running on, e.g.
+/// `lunchmoney-venmo-x86_64-linux`.
+fn platform_asset_name() -> String {
+    format!(
+        "lunchmoney-venmo-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+/// GETs `url`, following redirects since GitHub serves release assets from a separate host.
+async fn download(client: &HttpsClient, url: &str) -> Result<body::Bytes> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(url)
+        .header(USER_AGENT, "lunchmoney-venmo-syncer")
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+
+    if status != StatusCode::OK {
+        bail!(
+            "Failed to download {}, code {}, err:\n{:#?}",
+            url,
+            status,
+            bytes
+        );
+    }
+
+    Ok(bytes)
+}
+
+/// Replaces the running binary with the latest GitHub release if it is newer, or only reports
+/// whether there is one with `check_only`.
+pub async fn cmd_self_update(client: &HttpsClient, check_only: bool) -> Result<()> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(RELEASES_URL)
+        .header(USER_AGENT, "lunchmoney-venmo-syncer")
+        .header(ACCEPT, "application/vnd.github+json")
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+
+    if status != StatusCode::OK {
+        bail!(
+            "Failed to get latest release, code {}, err:\n{:#?}",
+            status,
+            bytes
+        );
+    }
+
+    let release: Release = serde_json::from_slice(&bytes)?;
+    let current_version = env!("CARGO_PKG_VERSION");
+
+    if parse_version(&release.tag_name)? <= parse_version(current_version)? {
+        println!("Already up to date (version {}).", current_version);
+        return Ok(());
+    }
+
+    println!(
+        "New version available: {} (current version {})",
+        release.tag_name, current_version
+    );
+
+    if check_only {
+        return Ok(());
+    }
+
+    let asset_name = platform_asset_name();
+    let find_asset = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| anyhow!("Release {} has no asset named {}", release.tag_name, name))
+    };
+
+    let public_key = RELEASE_PUBLIC_KEY.ok_or_else(|| {
+        anyhow!(
+            "This build has no release signing key to verify updates with, download {} from \
+             GitHub instead",
+            release.tag_name
+        )
+    })?;
+
+    let binary_asset = find_asset(&asset_name)?;
+    let signature_asset = find_asset(&format!("{}.minisig", asset_name))?;
+
+    let signature = download(client, &signature_asset.browser_download_url).await?;
+    let binary = download(client, &binary_asset.browser_download_url).await?;
+    verify_signature(&binary, std::str::from_utf8(&signature)?, public_key)
+        .with_context(|| anyhow!("Refusing to install {}", binary_asset.name))?;
+
+    let current_exe = env::current_exe().context("Failed to find the current binary")?;
+    let new_binary = write_new_binary(
+        current_exe
+            .parent()
+            .ok_or_else(|| anyhow!("Current binary {:?} has no directory", current_exe))?,
+        &binary,
+    )?;
+
+    self_replace::self_replace(new_binary.path()).context("Failed to replace current binary")?;
+
+    println!("Updated to {}.", release.tag_name);
+
+    Ok(())
+}
+
+/// Checks `binary` was signed by the release signing key `public_key`, with the minisign
+/// `signature` published next to it.
+fn verify_signature(binary: &[u8], signature: &str, public_key: &str) -> Result<()> {
+    let public_key = PublicKey::from_base64(public_key.trim())
+        .map_err(|e| anyhow!("Invalid release public key: {}", e))?;
+    let signature =
+        Signature::decode(signature).map_err(|e| anyhow!("Invalid release signature: {}", e))?;
+
+    public_key
+        .verify(binary, &signature, false)
+        .map_err(|e| anyhow!("Release signature doesn't match: {}", e))
+}
+
+/// Writes `binary` to a new executable file in `dir`, only created if it didn't exist and only
+/// writable by us, so it can't be swapped out before it replaces the current binary. Removed when
+/// dropped.
+fn write_new_binary(dir: &Path, binary: &[u8]) -> Result<NamedTempFile> {
+    let mut file = tempfile::Builder::new()
+        .prefix(".lunchmoney-venmo-update-")
+        .tempfile_in(dir)
+        .with_context(|| anyhow!("Failed to create the new binary in {:?}", dir))?;
+    file.write_all(binary)
+        .and_then(|()| file.as_file().sync_all())
+        .with_context(|| anyhow!("Failed to write the new binary to {:?}", file.path()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(file.path(), fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// A key and signature of `test` from minisign's test vectors.
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+
+    #[test]
+    fn verifies_release_signatures() {
+        verify_signature(b"test", SIGNATURE, PUBLIC_KEY).unwrap();
+
+        assert!(verify_signature(b"tampered", SIGNATURE, PUBLIC_KEY).is_err());
+        assert!(verify_signature(b"test", "not a signature", PUBLIC_KEY).is_err());
+        assert!(verify_signature(
+            b"test",
+            SIGNATURE,
+            "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO4"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn writes_new_binary_privately() {
+        let dir = testing::temp_dir();
+
+        let file = write_new_binary(dir.path(), b"binary").unwrap();
+        assert_eq!(file.path().parent(), Some(dir.path()));
+        assert_eq!(fs::read(file.path()).unwrap(), b"binary");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(file.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        let path = file.path().to_path_buf();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn compares_versions() {
+        assert!(parse_version("v1.10.0").unwrap() > parse_version("1.9.3").unwrap());
+        assert!(parse_version("v1.2").is_ok());
+        assert!(parse_version("v1.2-beta").is_err());
+    }
+}
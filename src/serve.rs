@@ -0,0 +1,350 @@
+//! The `serve` subcommand, a small authenticated HTTP API to trigger syncs and check on them
+//! remotely, e.g. from a home automation setup or a phone shortcut.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::config::Config;
+use crate::paths::Paths;
+use crate::sync::{report_sync, sync_since_last_run, SyncOptions};
+use crate::types::HttpsClient;
+
+/// Where and how to serve the API.
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub listen: SocketAddr,
+    /// Requests must send this in an `Authorization: Bearer` header.
+    pub token: String,
+    /// Options of the syncs requests start.
+    pub sync: SyncOptions,
+}
+
+/// The most recent sync triggered with `POST /sync`.
+#[derive(Debug, Clone, Serialize)]
+struct LastRun {
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>,
+    succeeded: bool,
+    /// The `SyncSummary`, if it succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    /// When the running sync started, if one is.
+    running_since: Option<DateTime<Utc>>,
+    last_run: Option<LastRun>,
+}
+
+/// Everything requests are handled with.
+struct Api {
+    client: HttpsClient,
+    paths: Paths,
+    config: Config,
+    token: String,
+    sync: SyncOptions,
+    state: Mutex<ServerState>,
+}
+
+impl Api {
+    fn state(&self) -> MutexGuard<'_, ServerState> {
+        // The state is only ever replaced field by field, a panic can't leave it inconsistent.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Serves `POST /sync`, `GET /status`, and `GET /last-run` on `options.listen` until the
+/// process is killed. Syncs run in the background, one at a time, like `daemon` runs them.
+pub async fn cmd_serve(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: Config,
+    options: &ServeOptions,
+) -> Result<()> {
+    if options.token.trim().is_empty() {
+        bail!("An API token is required to serve, pass --token");
+    }
+
+    let api = Arc::new(Api {
+        client: client.clone(),
+        paths: paths.clone(),
+        config,
+        token: options.token.clone(),
+        sync: options.sync.clone(),
+        state: Mutex::new(ServerState::default()),
+    });
+
+    let service = make_service_fn(move |_| {
+        let api = api.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let api = api.clone();
+                async move { Ok::<_, Infallible>(handle(&api, request)) }
+            }))
+        }
+    });
+
+    let http = Server::try_bind(&options.listen)
+        .with_context(|| anyhow!("Failed to listen on {}", options.listen))?
+        .serve(service);
+    info!("Listening on http://{}", options.listen);
+
+    http.await.context("Server failed")
+}
+
+/// Routes `request`, rejecting it unless it has the token.
+fn handle(api: &Arc<Api>, request: Request<Body>) -> Response<Body> {
+    if !authorized(&request, &api.token) {
+        let mut response = json_response(
+            StatusCode::UNAUTHORIZED,
+            &json!({ "error": "Missing or wrong API token" }),
+        );
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return response;
+    }
+
+    match (request.method(), request.uri().path()) {
+        (&Method::POST, "/sync") => start_sync(api),
+        (&Method::GET, "/status") => {
+            let state = api.state();
+            json_response(
+                StatusCode::OK,
+                &json!({
+                    "running": state.running_since.is_some(),
+                    "running_since": state.running_since,
+                    "last_finished_at": state.last_run.as_ref().map(|run| run.finished_at),
+                    "last_succeeded": state.last_run.as_ref().map(|run| run.succeeded),
+                }),
+            )
+        }
+        (&Method::GET, "/last-run") => match &api.state().last_run {
+            Some(last_run) => json_response(StatusCode::OK, last_run),
+            None => json_response(
+                StatusCode::NOT_FOUND,
+                &json!({ "error": "No sync has run yet" }),
+            ),
+        },
+        (_, "/sync" | "/status" | "/last-run") => json_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            &json!({ "error": "Method not allowed" }),
+        ),
+        _ => json_response(StatusCode::NOT_FOUND, &json!({ "error": "Not found" })),
+    }
+}
+
+/// Starts a sync in the background unless one is already running.
+fn start_sync(api: &Arc<Api>) -> Response<Body> {
+    let started_at = Utc::now();
+
+    {
+        let mut state = api.state();
+        if let Some(running_since) = state.running_since {
+            return json_response(
+                StatusCode::CONFLICT,
+                &json!({
+                    "error": "A sync is already running",
+                    "running_since": running_since,
+                }),
+            );
+        }
+        state.running_since = Some(started_at);
+    }
+
+    info!("Starting sync requested over HTTP");
+    let sync_api = api.clone();
+    spawn_sync(api.clone(), started_at, async move {
+        let res = sync_since_last_run(
+            &sync_api.client,
+            &sync_api.paths,
+            &sync_api.config,
+            &sync_api.sync,
+            None,
+        )
+        .await;
+        report_sync(&sync_api.client, &sync_api.config, &res).await;
+
+        res.map(|summary| serde_json::to_value(summary).unwrap_or_default())
+    });
+
+    json_response(StatusCode::ACCEPTED, &json!({ "started_at": started_at }))
+}
+
+/// Runs `sync`, started at `started_at`, in the background, recording it as the last run once it
+/// finishes, or panics.
+fn spawn_sync(
+    api: Arc<Api>,
+    started_at: DateTime<Utc>,
+    sync: impl Future<Output = Result<serde_json::Value>> + Send + 'static,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Awaited as its own task, so a panicking sync doesn't leave `running_since` set and
+        // every later `POST /sync` refused.
+        let (summary, error) = match tokio::spawn(sync).await {
+            Ok(Ok(summary)) => (Some(summary), None),
+            Ok(Err(e)) => (None, Some(format!("{:#}", e))),
+            Err(e) => (None, Some(format!("Sync failed: {}", e))),
+        };
+
+        let mut state = api.state();
+        state.running_since = None;
+        state.last_run = Some(LastRun {
+            started_at,
+            finished_at: Utc::now(),
+            succeeded: error.is_none(),
+            summary,
+            error,
+        });
+    })
+}
+
+/// Whether `request` has `token` in its `Authorization: Bearer` header.
+fn authorized(request: &Request<Body>, token: &str) -> bool {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    // Compared by hash so the time taken doesn't reveal how much of the token matched.
+    Sha256::digest(given.as_bytes()) == Sha256::digest(token.as_bytes())
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        // Serializing these can't fail.
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::body;
+
+    use crate::types::{https_client, ClientOptions};
+
+    fn api() -> Arc<Api> {
+        Arc::new(Api {
+            client: https_client(&ClientOptions::default()).unwrap(),
+            paths: Paths {
+                config_dir: "config".into(),
+                cache_dir: "cache".into(),
+                state_dir: "state".into(),
+            },
+            config: Config::default(),
+            token: "secret".to_string(),
+            sync: SyncOptions::default(),
+            state: Mutex::new(ServerState::default()),
+        })
+    }
+
+    fn request(method: Method, path: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        request.body(Body::empty()).unwrap()
+    }
+
+    async fn json(response: Response<Body>) -> serde_json::Value {
+        serde_json::from_slice(&body::to_bytes(response).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_the_token() {
+        let api = api();
+
+        for token in [None, Some("wrong")] {
+            let response = handle(&api, request(Method::GET, "/status", token));
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_status_and_last_run() {
+        let api = api();
+
+        let response = handle(&api, request(Method::GET, "/status", Some("secret")));
+        assert_eq!(response.status(), StatusCode::OK);
+        let status = json(response).await;
+        assert_eq!(status["running"], false);
+        assert!(status["last_finished_at"].is_null());
+
+        let response = handle(&api, request(Method::GET, "/last-run", Some("secret")));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let now = Utc::now();
+        api.state().last_run = Some(LastRun {
+            started_at: now,
+            finished_at: now,
+            succeeded: false,
+            summary: None,
+            error: Some("Venmo API token is invalid".to_string()),
+        });
+
+        let response = handle(&api, request(Method::GET, "/last-run", Some("secret")));
+        assert_eq!(response.status(), StatusCode::OK);
+        let last_run = json(response).await;
+        assert_eq!(last_run["succeeded"], false);
+        assert_eq!(last_run["error"], "Venmo API token is invalid");
+        assert!(last_run.get("summary").is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_overlapping_syncs() {
+        let api = api();
+        api.state().running_since = Some(Utc::now());
+
+        let response = handle(&api, request(Method::POST, "/sync", Some("secret")));
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = handle(&api, request(Method::GET, "/sync", Some("secret")));
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = handle(&api, request(Method::GET, "/nope", Some("secret")));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn finishes_panicking_syncs() {
+        let api = api();
+        let started_at = Utc::now();
+        api.state().running_since = Some(started_at);
+
+        spawn_sync(api.clone(), started_at, async { panic!("oops") })
+            .await
+            .unwrap();
+
+        let state = api.state();
+        assert!(state.running_since.is_none());
+        let last_run = state.last_run.as_ref().unwrap();
+        assert!(!last_run.succeeded);
+        assert!(last_run.error.as_deref().unwrap().contains("panicked"));
+    }
+}
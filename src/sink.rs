@@ -0,0 +1,44 @@
+//! Where synced transactions are inserted: Lunch Money, or YNAB or Firefly III with `--target`.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rusty_money::iso::Currency;
+
+use crate::sync::{asset_name, insert_statement, LunchMoneyDestination, SyncSummary};
+use crate::types::venmo::Statement;
+use crate::types::HttpsClient;
+
+/// A budgeting app account to insert the transactions of statements into.
+#[async_trait]
+pub trait TransactionSink: Send + Sync {
+    /// Names the account, e.g. `Lunch Money asset 1234`, for messages and the lock syncs to it
+    /// hold (see `state::SyncLock`).
+    fn name(&self) -> String;
+
+    /// Converts `statement`'s transactions and inserts the ones not already in the account. With
+    /// `dry_run`, nothing is inserted and the summary lists what would be.
+    async fn insert_statement(
+        &self,
+        client: &HttpsClient,
+        currency: &Currency,
+        statement: Statement,
+        dry_run: bool,
+    ) -> Result<SyncSummary>;
+}
+
+#[async_trait]
+impl TransactionSink for LunchMoneyDestination {
+    fn name(&self) -> String {
+        asset_name(self.asset_id)
+    }
+
+    async fn insert_statement(
+        &self,
+        client: &HttpsClient,
+        currency: &Currency,
+        statement: Statement,
+        dry_run: bool,
+    ) -> Result<SyncSummary> {
+        insert_statement(client, self, currency, statement, &[], dry_run).await
+    }
+}
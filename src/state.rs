@@ -0,0 +1,511 @@
+//! Sync history and watermarks persisted between runs.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::paths::{ensure_dir, Paths};
+
+/// Version of the state file layout written by this build. Bump this and add a step to
+/// `migrate` whenever the layout changes in a way older state files can't be read as.
+const STATE_VERSION: u64 = 1;
+
+/// Runs kept per Venmo profile and Lunch Money asset pair, older ones are dropped so the state
+/// file doesn't grow forever. Only the newest runs can be undone anyway.
+const MAX_RUNS: usize = 100;
+
+/// Sync history persisted across runs, stored as JSON in the state directory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    pub version: u64,
+    #[serde(default)]
+    pub runs: Vec<SyncRun>,
+    #[serde(default)]
+    pub watermarks: Vec<Watermark>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interrupted: Vec<InterruptedSync>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            version: STATE_VERSION,
+            runs: Vec::new(),
+            watermarks: Vec::new(),
+            interrupted: Vec::new(),
+        }
+    }
+}
+
+/// A successful sync of a Venmo profile to a Lunch Money asset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRun {
+    pub venmo_profile_id: u64,
+    pub lunch_money_asset_id: u64,
+    pub synced_at: DateTime<Utc>,
+    pub start_date: DateTime<Utc>,
+    pub end_date: DateTime<Utc>,
+    pub inserted_ids: Vec<u64>,
+    /// IDs of the transaction groups created for the run's transfers, see `sync.group_transfers`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_ids: Vec<u64>,
+}
+
+/// The newest Venmo transaction synced from a Venmo profile to a Lunch Money asset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Watermark {
+    pub venmo_profile_id: u64,
+    pub lunch_money_asset_id: u64,
+    pub transaction_id: u64,
+    pub transaction_date: DateTime<Utc>,
+}
+
+/// A sync of a Venmo profile to a Lunch Money asset that failed partway through inserting
+/// transactions, to finish with `--resume`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterruptedSync {
+    pub venmo_profile_id: u64,
+    pub lunch_money_asset_id: u64,
+    pub failed_at: DateTime<Utc>,
+    pub start_date: DateTime<Utc>,
+    pub end_date: DateTime<Utc>,
+    /// IDs of the transactions inserted before the sync failed.
+    pub inserted_ids: Vec<u64>,
+    /// External IDs of the transactions inserted before the sync failed, which resuming skips.
+    pub inserted_external_ids: Vec<String>,
+}
+
+/// Path of the state file in the state directory.
+pub fn state_file(paths: &Paths) -> PathBuf {
+    paths.state_dir.join("state.json")
+}
+
+/// The version of a state file `value`, 0 for unversioned ones.
+fn version(value: &Value) -> Result<u64> {
+    match value.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("State file version {} is not a number", version)),
+    }
+}
+
+/// Upgrades a state file `value` to `STATE_VERSION`, one version at a time.
+fn migrate(mut value: Value) -> Result<State> {
+    if !value.is_object() {
+        bail!("State file is not a JSON object");
+    }
+
+    let mut version = version(&value)?;
+
+    if version > STATE_VERSION {
+        bail!(
+            "State file is version {}, but this build only supports up to version {}, please upgrade",
+            version,
+            STATE_VERSION
+        );
+    }
+
+    while version < STATE_VERSION {
+        match version {
+            // Unversioned state files have the same fields, just no version.
+            0 => {}
+            _ => bail!("Don't know how to migrate state file version {}", version),
+        }
+
+        version += 1;
+        value["version"] = version.into();
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+impl State {
+    /// Reads state from `path`, migrating it to the current version if needed. The original
+    /// file is backed up before a migrated state is ever written back.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        let contents =
+            fs::read(path).with_context(|| anyhow!("Failed to read state file {:?}", path))?;
+        let value: Value = serde_json::from_slice(&contents)
+            .with_context(|| anyhow!("Failed to parse state file {:?}", path))?;
+
+        let version =
+            version(&value).with_context(|| anyhow!("Failed to read state file {:?}", path))?;
+        let state =
+            migrate(value).with_context(|| anyhow!("Failed to migrate state file {:?}", path))?;
+
+        if version != STATE_VERSION {
+            let backup = path.with_extension(format!("v{}.bak", version));
+            fs::copy(path, &backup)
+                .with_context(|| anyhow!("Failed to back up state file to {:?}", backup))?;
+        }
+
+        Ok(state)
+    }
+
+    /// Returns the watermark for syncing `venmo_profile_id` to `lunch_money_asset_id`, if they
+    /// have been synced before.
+    pub fn watermark(
+        &self,
+        venmo_profile_id: u64,
+        lunch_money_asset_id: u64,
+    ) -> Option<&Watermark> {
+        self.watermarks.iter().find(|watermark| {
+            watermark.venmo_profile_id == venmo_profile_id
+                && watermark.lunch_money_asset_id == lunch_money_asset_id
+        })
+    }
+
+    /// Sets the watermark for its profile and asset pair, unless the existing one is newer.
+    pub fn update_watermark(&mut self, watermark: Watermark) {
+        match self.watermarks.iter_mut().find(|existing| {
+            existing.venmo_profile_id == watermark.venmo_profile_id
+                && existing.lunch_money_asset_id == watermark.lunch_money_asset_id
+        }) {
+            Some(existing) => {
+                if existing.transaction_date <= watermark.transaction_date {
+                    *existing = watermark;
+                }
+            }
+            None => self.watermarks.push(watermark),
+        }
+    }
+
+    /// Returns the interrupted sync of `venmo_profile_id` to `lunch_money_asset_id`, if any.
+    pub fn interrupted(
+        &self,
+        venmo_profile_id: u64,
+        lunch_money_asset_id: u64,
+    ) -> Option<&InterruptedSync> {
+        self.interrupted.iter().find(|interrupted| {
+            interrupted.venmo_profile_id == venmo_profile_id
+                && interrupted.lunch_money_asset_id == lunch_money_asset_id
+        })
+    }
+
+    /// Records `interrupted`, replacing any earlier interrupted sync of the same pair.
+    pub fn set_interrupted(&mut self, interrupted: InterruptedSync) {
+        self.clear_interrupted(
+            interrupted.venmo_profile_id,
+            interrupted.lunch_money_asset_id,
+        );
+        self.interrupted.push(interrupted);
+    }
+
+    /// Forgets the interrupted sync of `venmo_profile_id` to `lunch_money_asset_id`, once it has
+    /// been finished.
+    pub fn clear_interrupted(&mut self, venmo_profile_id: u64, lunch_money_asset_id: u64) {
+        self.interrupted.retain(|interrupted| {
+            interrupted.venmo_profile_id != venmo_profile_id
+                || interrupted.lunch_money_asset_id != lunch_money_asset_id
+        });
+    }
+
+    /// Records `run`, dropping the oldest run of the same pair if it has more than `MAX_RUNS`.
+    pub fn push_run(&mut self, run: SyncRun) {
+        let same_pair = |other: &SyncRun| {
+            other.venmo_profile_id == run.venmo_profile_id
+                && other.lunch_money_asset_id == run.lunch_money_asset_id
+        };
+        let mut excess = (self.runs.iter().filter(|other| same_pair(other)).count() + 1)
+            .saturating_sub(MAX_RUNS);
+
+        // Runs are recorded in order, the first ones are the oldest.
+        self.runs.retain(|other| {
+            let drop = excess > 0 && same_pair(other);
+            excess -= usize::from(drop);
+            !drop
+        });
+        self.runs.push(run);
+    }
+
+    /// Sets the IDs of the transaction groups created for `run`, if it's still recorded.
+    pub fn set_run_groups(&mut self, run: &SyncRun, group_ids: Vec<u64>) {
+        if let Some(recorded) = self.runs.iter_mut().find(|recorded| {
+            recorded.venmo_profile_id == run.venmo_profile_id
+                && recorded.lunch_money_asset_id == run.lunch_money_asset_id
+                && recorded.synced_at == run.synced_at
+        }) {
+            recorded.group_ids = group_ids;
+        }
+    }
+
+    /// Removes and returns the newest run, of asset `lunch_money_asset_id` if given, along with
+    /// its watermark so the next sync of the pair starts from the configured range again.
+    pub fn pop_run(&mut self, lunch_money_asset_id: Option<u64>) -> Option<SyncRun> {
+        let index = self
+            .runs
+            .iter()
+            .enumerate()
+            .filter(|(_, run)| {
+                lunch_money_asset_id.is_none()
+                    || lunch_money_asset_id == Some(run.lunch_money_asset_id)
+            })
+            .max_by_key(|(_, run)| run.synced_at)
+            .map(|(index, _)| index)?;
+        let run = self.runs.remove(index);
+
+        self.watermarks.retain(|watermark| {
+            watermark.venmo_profile_id != run.venmo_profile_id
+                || watermark.lunch_money_asset_id != run.lunch_money_asset_id
+        });
+
+        Some(run)
+    }
+
+    /// Atomically writes state to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            ensure_dir(dir)?;
+        }
+
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| anyhow!("Failed to write state file {:?}", tmp_path))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| anyhow!("Failed to write state file {:?}", path))?;
+
+        Ok(())
+    }
+}
+
+/// Advisory lock on syncing to a destination, e.g. a Lunch Money asset, released when dropped,
+/// so overlapping runs (e.g. a cron run while the previous one is still going, or an import
+/// during a sync) don't insert the same transactions twice.
+#[derive(Debug)]
+pub struct SyncLock {
+    _file: File,
+}
+
+fn lock_file(paths: &Paths, destination: &str) -> Result<File> {
+    ensure_dir(&paths.state_dir)?;
+
+    let name: String = destination
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let path = paths.state_dir.join(format!("sync-{}.lock", name));
+    File::create(&path).with_context(|| anyhow!("Failed to open lock file {:?}", path))
+}
+
+/// Locks syncing to `destination`, e.g. `Lunch Money asset 1234`, or returns `None` if another
+/// sync holds the lock.
+pub fn try_lock_sync(paths: &Paths, destination: &str) -> Result<Option<SyncLock>> {
+    let file = lock_file(paths, destination)?;
+
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(SyncLock { _file: file })),
+        Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Ok(None),
+        Err(e) => Err(e).context("Failed to lock sync"),
+    }
+}
+
+/// Locks syncing to `destination`, waiting for other syncs holding the lock to finish.
+pub async fn lock_sync(paths: &Paths, destination: &str) -> Result<SyncLock> {
+    if let Some(lock) = try_lock_sync(paths, destination)? {
+        return Ok(lock);
+    }
+
+    info!("Waiting for another sync to {} to finish", destination);
+
+    let file = lock_file(paths, destination)?;
+    tokio::task::spawn_blocking(move || {
+        file.lock_exclusive().context("Failed to lock sync")?;
+        Ok(SyncLock { _file: file })
+    })
+    .await?
+}
+
+fn backups_dir(paths: &Paths) -> PathBuf {
+    paths.state_dir.join("backups")
+}
+
+fn timestamped_backup(paths: &Paths) -> PathBuf {
+    backups_dir(paths).join(format!("state-{}.json", Utc::now().format("%Y%m%dT%H%M%S")))
+}
+
+/// Copies the (migrated) state file to `output`, or a timestamped file in the state directory.
+pub fn cmd_state_backup(paths: &Paths, output: Option<PathBuf>) -> Result<()> {
+    let path = state_file(paths);
+
+    if !path.exists() {
+        bail!("No state file found at {:?}", path);
+    }
+
+    let output = output.unwrap_or_else(|| timestamped_backup(paths));
+
+    // Loading first makes sure we only ever back up a readable, current state file.
+    State::load(&path)?.save(&output)?;
+
+    println!("Backed up state to {:?}", output);
+
+    Ok(())
+}
+
+/// Replaces the state file with the backup at `input`, backing up the current state first.
+pub fn cmd_state_restore(paths: &Paths, input: &Path) -> Result<()> {
+    let state = State::load(input)?;
+    let path = state_file(paths);
+
+    if path.exists() {
+        let backup = timestamped_backup(paths);
+        ensure_dir(&backups_dir(paths))?;
+        fs::copy(&path, &backup)
+            .with_context(|| anyhow!("Failed to back up current state to {:?}", backup))?;
+        println!("Backed up current state to {:?}", backup);
+    }
+
+    state.save(&path)?;
+
+    println!("Restored state from {:?}", input);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn run(lunch_money_asset_id: u64, synced_at: &str) -> SyncRun {
+        let synced_at = DateTime::parse_from_rfc3339(synced_at)
+            .unwrap()
+            .with_timezone(&Utc);
+
+        SyncRun {
+            venmo_profile_id: 1,
+            lunch_money_asset_id,
+            synced_at,
+            start_date: synced_at,
+            end_date: synced_at,
+            inserted_ids: vec![lunch_money_asset_id],
+            group_ids: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pops_newest_run() {
+        let mut state = State {
+            runs: vec![
+                run(10, "2022-07-03T00:00:00Z"),
+                run(20, "2022-07-02T00:00:00Z"),
+                run(10, "2022-07-01T00:00:00Z"),
+            ],
+            watermarks: vec![Watermark {
+                venmo_profile_id: 1,
+                lunch_money_asset_id: 20,
+                transaction_id: 100,
+                transaction_date: Utc::now(),
+            }],
+            ..Default::default()
+        };
+
+        let popped = state.pop_run(Some(20)).unwrap();
+        assert_eq!(popped.lunch_money_asset_id, 20);
+        assert!(state.watermark(1, 20).is_none());
+
+        assert_eq!(
+            state.pop_run(None).unwrap().synced_at,
+            run(10, "2022-07-03T00:00:00Z").synced_at
+        );
+        assert_eq!(state.runs.len(), 1);
+        assert!(state.pop_run(Some(20)).is_none());
+    }
+
+    #[test]
+    fn caps_runs_per_pair() {
+        let mut state = State::default();
+        state.push_run(run(20, "2022-06-01T00:00:00Z"));
+
+        for day in 1..=MAX_RUNS + 1 {
+            let synced_at = Utc::now() + chrono::Duration::days(day as i64);
+            state.push_run(run(10, &synced_at.to_rfc3339()));
+        }
+
+        assert_eq!(state.runs.len(), MAX_RUNS + 1);
+        assert_eq!(state.runs[0].lunch_money_asset_id, 20);
+        assert!(state.runs[1].synced_at > Utc::now() + chrono::Duration::days(1));
+    }
+
+    #[test]
+    fn migrates_state_files() {
+        let state = migrate(serde_json::json!({
+            "runs": [serde_json::to_value(run(10, "2022-07-01T00:00:00Z")).unwrap()],
+        }))
+        .unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.runs.len(), 1);
+
+        assert!(migrate(serde_json::json!({ "version": STATE_VERSION + 1 })).is_err());
+        assert!(migrate(serde_json::json!({ "version": "1" })).is_err());
+        assert!(migrate(serde_json::json!([])).is_err());
+    }
+
+    #[test]
+    fn replaces_interrupted_syncs() {
+        let interrupted = |lunch_money_asset_id: u64, inserted_ids: Vec<u64>| InterruptedSync {
+            venmo_profile_id: 1,
+            lunch_money_asset_id,
+            failed_at: Utc::now(),
+            start_date: Utc::now(),
+            end_date: Utc::now(),
+            inserted_ids,
+            inserted_external_ids: Vec::new(),
+        };
+        let mut state = State::default();
+
+        state.set_interrupted(interrupted(10, vec![1]));
+        state.set_interrupted(interrupted(20, vec![2]));
+        state.set_interrupted(interrupted(10, vec![1, 3]));
+
+        assert_eq!(state.interrupted.len(), 2);
+        assert_eq!(state.interrupted(1, 10).unwrap().inserted_ids, vec![1, 3]);
+
+        state.clear_interrupted(1, 10);
+        assert!(state.interrupted(1, 10).is_none());
+        assert!(state.interrupted(1, 20).is_some());
+    }
+
+    #[test]
+    fn locks_syncs() {
+        let state_dir = testing::temp_dir();
+        let paths = testing::paths(state_dir.path());
+
+        let lock = try_lock_sync(&paths, "Lunch Money asset 10").unwrap();
+        assert!(lock.is_some());
+        assert!(try_lock_sync(&paths, "Lunch Money asset 10")
+            .unwrap()
+            .is_none());
+        assert!(try_lock_sync(&paths, "Lunch Money asset 20")
+            .unwrap()
+            .is_some());
+        assert!(paths
+            .state_dir
+            .join("sync-lunch-money-asset-10.lock")
+            .exists());
+
+        drop(lock);
+        assert!(try_lock_sync(&paths, "Lunch Money asset 10")
+            .unwrap()
+            .is_some());
+    }
+}
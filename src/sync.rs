@@ -0,0 +1,2146 @@
+//! Syncing Venmo statements into Lunch Money assets, once or on a schedule.
+
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::offset::{Local, TimeZone, Utc};
+use chrono::{DateTime, NaiveDate};
+use rust_decimal::Decimal;
+use rusty_money::iso::Currency;
+use serde::{Serialize, Serializer};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{AccountConfig, CategoryRule, Config, PayeeConfig, SyncConfig};
+use crate::credentials::run_token_command;
+use crate::http::HttpTransport;
+use crate::lunchmoney::{
+    create_transaction_group, delete_transaction_group, get_all_categories, get_all_tags,
+    get_transaction, get_transactions, insert_transactions, update_transaction,
+    Error as LunchMoneyError, InsertOptions, PartialInsertError,
+};
+use crate::metrics;
+use crate::notify;
+use crate::output;
+use crate::paths::Paths;
+use crate::payees::Payees;
+use crate::rules::Rules;
+use crate::state::{lock_sync, state_file, InterruptedSync, State, SyncRun, Watermark};
+use crate::types::lunchmoney::{
+    Amount, CreateTransactionGroupRequest, ExistingTransaction, TagRef,
+    Transaction as LunchMoneyTransaction, TransactionStatus as LunchMoneyStatus, TransactionUpdate,
+};
+use crate::types::venmo::{
+    AccountRecord, Amount as VenmoAmount, ConvertOptions, Statement, StatementFormat,
+    Transaction as VenmoTransaction, TransactionFilter, TransactionStatus,
+};
+use crate::types::HttpsClient;
+use crate::venmo::{
+    log_in, request_headers, Error as VenmoClientError, LoginOptions, StatementStore,
+    StatementStream,
+};
+
+/// Default for `--start-from` when neither it nor `sync.start_from` is set.
+pub const DEFAULT_START_FROM: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How far before the end of the last sync to start the next one from, to pick up transactions
+/// that showed up in Venmo late. Lunch Money skips the ones that were already inserted.
+const WATERMARK_OVERLAP: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Resolves the `--start-from` and `--end-to` flags, falling back to the `[sync]` config section,
+/// into the date range to fetch transactions for.
+pub fn date_range(
+    start_from: Option<Duration>,
+    end_to: Option<Duration>,
+    config: &SyncConfig,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let start_from = match start_from {
+        Some(start_from) => start_from,
+        None => config.start_from()?.unwrap_or(DEFAULT_START_FROM),
+    };
+    let end_to = match end_to {
+        Some(end_to) => Some(end_to),
+        None => config.end_to()?,
+    };
+
+    let now = Local::now();
+
+    let end_date: DateTime<Utc> = match end_to {
+        Some(duration) => (now - chrono::Duration::from_std(duration)?).into(),
+        None => now.into(),
+    };
+    let start_date: DateTime<Utc> = (now - chrono::Duration::from_std(start_from)?).into();
+
+    Ok((start_date, end_date))
+}
+
+/// Overrides the start and end of `range` with whole (UTC) days `start_date` and `end_date`, as
+/// given by `--start-date` and `--end-date`.
+pub fn with_absolute_dates(
+    range: (DateTime<Utc>, DateTime<Utc>),
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = match start_date {
+        Some(date) => Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()),
+        None => range.0,
+    };
+    let end = match end_date {
+        Some(date) => Utc.from_utc_datetime(&date.and_hms_opt(23, 59, 59).unwrap()),
+        None => range.1,
+    };
+
+    if start > end {
+        bail!(
+            "Start date {} is after end date {}",
+            start.format("%Y-%m-%d"),
+            end.format("%Y-%m-%d")
+        );
+    }
+
+    Ok((start, end))
+}
+
+/// The Lunch Money asset to insert transactions into, and how to categorize and tag them.
+pub struct LunchMoneyDestination {
+    pub api_token: String,
+    pub asset_id: u64,
+    pub rules: Vec<CategoryRule>,
+    pub payees: PayeeConfig,
+    pub tags: Vec<String>,
+    /// Create tags in `tags` that don't exist in Lunch Money, rather than failing.
+    pub create_tags: bool,
+    pub insert_options: InsertOptions,
+    /// Venmo transactions with these statuses aren't synced.
+    pub skip_statuses: Vec<TransactionStatus>,
+    /// Venmo transactions with these statuses are inserted as cleared.
+    pub cleared_statuses: Vec<TransactionStatus>,
+    /// Venmo transactions of types it doesn't match aren't synced.
+    pub filter: TransactionFilter,
+    pub convert: ConvertOptions,
+    /// Group inserted payments with their shadow transfers.
+    pub group_transfers: bool,
+    /// Max number of chunks of transactions to insert at once.
+    pub parallelism: usize,
+}
+
+impl LunchMoneyDestination {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let api_token = config.lunch_money.api_token_or(None)?.ok_or_else(|| {
+            anyhow!("--lunch-money-api-token, lunch_money.api_token, or lunch_money.token_command must be set")
+        })?;
+        let asset_id = config
+            .lunch_money
+            .asset_id
+            .ok_or_else(|| anyhow!("--lunch-money-asset-id or lunch_money.asset_id must be set"))?;
+
+        Ok(LunchMoneyDestination {
+            api_token,
+            asset_id,
+            rules: config.rules.clone(),
+            payees: config.payees.clone(),
+            tags: config.lunch_money.tags.clone(),
+            create_tags: config.lunch_money.create_tags,
+            insert_options: InsertOptions::default(),
+            skip_statuses: config.sync.skip_statuses()?,
+            cleared_statuses: config.sync.cleared_statuses()?,
+            filter: config.sync.filter()?,
+            convert: config.convert_options()?,
+            group_transfers: config.sync.group_transfers,
+            parallelism: config.sync.parallelism()?,
+        })
+    }
+}
+
+/// The Venmo account and Lunch Money asset to sync between, resolved from a config.
+pub struct SyncAccounts {
+    pub venmo: AccountRecord,
+    pub statement_format: StatementFormat,
+    /// Where fetched statements are cached and archived, none by default.
+    pub statements: StatementStore,
+    pub lunch_money: LunchMoneyDestination,
+}
+
+impl SyncAccounts {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(SyncAccounts {
+            venmo: venmo_account(config)?,
+            statement_format: config.venmo.statement_format.clone(),
+            statements: StatementStore::default(),
+            lunch_money: LunchMoneyDestination::from_config(config)?,
+        })
+    }
+
+    /// Resolves an entry of `accounts` in `config`, falling back to the single account sections
+    /// for values it leaves unset.
+    pub fn from_account_config(config: &Config, account: &AccountConfig) -> Result<Self> {
+        let lunch_money_api_token = config
+            .lunch_money
+            .api_token_or(account.lunch_money_api_token.clone())?
+            .ok_or_else(|| {
+                anyhow!(
+                    "accounts '{}' has no lunch_money_api_token and lunch_money.api_token is not set",
+                    account.name()
+                )
+            })?;
+
+        Ok(SyncAccounts {
+            venmo: AccountRecord {
+                profile_id: account.venmo_profile_id,
+                api_token: account.venmo_api_token.clone(),
+                currency: find_currency(
+                    account
+                        .currency
+                        .as_deref()
+                        .or(config.venmo.currency.as_deref()),
+                )?,
+            },
+            statement_format: config.venmo.statement_format.clone(),
+            statements: StatementStore::default(),
+            lunch_money: LunchMoneyDestination {
+                api_token: lunch_money_api_token,
+                asset_id: account.lunch_money_asset_id,
+                rules: config.rules.clone(),
+                payees: config.payees.clone(),
+                tags: config.lunch_money.tags.clone(),
+                create_tags: config.lunch_money.create_tags,
+                insert_options: InsertOptions::default(),
+                skip_statuses: config.sync.skip_statuses()?,
+                cleared_statuses: config.sync.cleared_statuses()?,
+                filter: config.sync.filter()?,
+                convert: config.convert_options()?,
+                group_transfers: config.sync.group_transfers,
+                parallelism: config.sync.parallelism()?,
+            },
+        })
+    }
+}
+
+/// The Venmo account in the `[venmo]` section of `config`.
+pub fn venmo_account(config: &Config) -> Result<AccountRecord> {
+    let profile_id = config
+        .venmo
+        .profile_id
+        .ok_or_else(|| anyhow!("--venmo-profile-id or venmo.profile_id must be set"))?;
+    let api_token = config.venmo.api_token_or(None)?.ok_or_else(|| {
+        anyhow!("--venmo-api-token, venmo.api_token, or venmo.token_command must be set")
+    })?;
+
+    Ok(AccountRecord {
+        profile_id,
+        api_token,
+        currency: find_currency(config.venmo.currency.as_deref())?,
+    })
+}
+
+/// Names Lunch Money asset `asset_id` for messages and the lock syncs to it hold, see
+/// `state::SyncLock`.
+pub fn asset_name(asset_id: u64) -> String {
+    format!("Lunch Money asset {}", asset_id)
+}
+
+/// Looks up the ISO currency `code`, defaulting to USD.
+pub fn find_currency(code: Option<&str>) -> Result<Currency> {
+    let code = code.unwrap_or("USD");
+
+    rusty_money::iso::find(code)
+        .copied()
+        .ok_or_else(|| anyhow!("Given currency {} is not valid", code))
+}
+
+/// Resolves tag `names` to the IDs of existing Lunch Money tags, matched case-insensitively.
+/// Names that don't match a tag are kept as names with `create`, so inserting creates them, and
+/// fail otherwise.
+async fn resolve_tags(
+    client: &impl HttpTransport,
+    api_token: &str,
+    names: &[String],
+    create: bool,
+) -> Result<Vec<TagRef>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let tags = get_all_tags(client, api_token).await?;
+
+    names
+        .iter()
+        .map(|name| {
+            match tags.iter().find(|tag| tag.name.eq_ignore_ascii_case(name)) {
+                Some(tag) => Ok(TagRef::Id(tag.id)),
+                None if create => Ok(TagRef::Name(name.clone())),
+                None => Err(anyhow!(
+                    "Lunch Money tag '{}' does not exist, create it in Lunch Money or pass --create-tags",
+                    name
+                )),
+            }
+        })
+        .collect()
+}
+
+/// What a sync fetched and inserted.
+#[derive(Debug, Serialize)]
+pub struct SyncSummary {
+    /// Venmo balance at the start of the synced date range.
+    pub beginning_balance: Decimal,
+    /// Venmo balance at the end of the synced date range.
+    pub ending_balance: Decimal,
+    /// Transactions in the Venmo statement.
+    pub venmo_transactions: usize,
+    /// Venmo transactions not synced because of their status, see `sync.skip_statuses`.
+    pub skipped_by_status: usize,
+    /// Venmo transactions not synced because of `sync.include_types`, `sync.exclude_types`,
+    /// `sync.exclude_payee_regex`, or `sync.exclude_note_regex`.
+    pub filtered: usize,
+    /// Lunch Money transactions generated from them, including transfers.
+    pub lunch_money_transactions: usize,
+    /// Generated transfers to or from bank accounts funding Venmo payments or receiving
+    /// withdrawals, or the Venmo Credit Card.
+    pub transfers: usize,
+    /// Generated transactions not yet in the Lunch Money asset, these are inserted.
+    pub new: usize,
+    /// Generated transactions already in the Lunch Money asset, as identified by their
+    /// `external_id`.
+    pub already_synced: usize,
+    /// Generated transactions already in the Lunch Money asset, but which differ from what's
+    /// there, e.g. because they were edited in Lunch Money. These are not inserted or updated.
+    pub changed: Vec<ChangedTransaction>,
+    /// Number of inserted transactions, `None` on dry runs.
+    pub inserted: Option<usize>,
+    /// IDs of the inserted transactions, `None` on dry runs and for targets other than Lunch
+    /// Money.
+    pub inserted_ids: Option<Vec<u64>>,
+    /// Transaction groups created for inserted payments and their shadow transfers, see
+    /// `sync.group_transfers`.
+    pub grouped: usize,
+    /// The new transactions that would have been inserted, only set on dry runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub would_insert: Option<Vec<LunchMoneyTransaction>>,
+    /// Sum of the generated Lunch Money transactions in the asset, i.e. the change in its
+    /// balance.
+    pub net_amount: Decimal,
+    /// Ending balance minus the beginning balance plus the synced transactions, non-zero if the
+    /// synced transactions don't account for how the Venmo balance changed, see `balance_drift`.
+    pub balance_drift: Decimal,
+    #[serde(serialize_with = "serialize_currency")]
+    pub currency: Currency,
+}
+
+/// How many transactions a sync fetched and inserted, without their details or amounts, see
+/// `SyncSummary::counts`.
+#[derive(Debug, Serialize)]
+pub struct SyncCounts {
+    pub venmo_transactions: usize,
+    pub skipped_by_status: usize,
+    pub filtered: usize,
+    pub lunch_money_transactions: usize,
+    pub new: usize,
+    pub already_synced: usize,
+    pub changed: usize,
+    pub inserted: Option<usize>,
+    pub grouped: usize,
+}
+
+/// A generated transaction whose `external_id` is already in Lunch Money with different values.
+#[derive(Debug, Serialize)]
+pub struct ChangedTransaction {
+    pub external_id: String,
+    /// ID of the existing Lunch Money transaction.
+    pub id: u64,
+    /// What differs, e.g. `amount -25.0000 -> -30.0000`.
+    pub differences: Vec<String>,
+}
+
+pub(crate) fn serialize_currency<S: Serializer>(
+    currency: &Currency,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(currency.iso_alpha_code)
+}
+
+impl SyncSummary {
+    /// The counts of the summary, e.g. for notifications sent to third parties, which shouldn't
+    /// get payees, notes, or amounts.
+    pub fn counts(&self) -> SyncCounts {
+        SyncCounts {
+            venmo_transactions: self.venmo_transactions,
+            skipped_by_status: self.skipped_by_status,
+            filtered: self.filtered,
+            lunch_money_transactions: self.lunch_money_transactions,
+            new: self.new,
+            already_synced: self.already_synced,
+            changed: self.changed.len(),
+            inserted: self.inserted,
+            grouped: self.grouped,
+        }
+    }
+
+    /// One line summary of the sync, e.g. for `sync-all` and notifications.
+    pub fn short(&self) -> String {
+        let mut short = match self.inserted {
+            Some(inserted) => format!(
+                "inserted {} transactions, {} already synced, {} changed, net {}",
+                inserted,
+                self.already_synced,
+                self.changed.len(),
+                output::signed_amount(self.net_amount, &self.currency)
+            ),
+            None => "dry run, nothing inserted".to_string(),
+        };
+
+        if !self.balance_drift.is_zero() {
+            short += &format!(
+                ", balance drifted by {}",
+                output::signed_amount(self.balance_drift, &self.currency)
+            );
+        }
+
+        short
+    }
+
+    /// Generated transactions that weren't inserted: already synced, changed, or new ones Lunch
+    /// Money deduplicated anyway (e.g. with `skip_duplicates`).
+    pub fn skipped(&self) -> Option<usize> {
+        self.inserted
+            .map(|inserted| self.lunch_money_transactions.saturating_sub(inserted))
+    }
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Beginning balance: {}",
+            output::signed_amount(self.beginning_balance, &self.currency)
+        )?;
+        writeln!(
+            f,
+            "Ending balance: {}",
+            output::signed_amount(self.ending_balance, &self.currency)
+        )?;
+
+        if let Some(ref would_insert) = self.would_insert {
+            writeln!(
+                f,
+                "would insert {} transactions:\n{:#?}",
+                would_insert.len(),
+                would_insert
+            )?;
+        }
+
+        writeln!(f, "Venmo transactions fetched: {}", self.venmo_transactions)?;
+
+        if self.skipped_by_status > 0 {
+            writeln!(f, "Skipped by status: {}", self.skipped_by_status)?;
+        }
+
+        if self.filtered > 0 {
+            writeln!(f, "Filtered out: {}", self.filtered)?;
+        }
+
+        writeln!(
+            f,
+            "Lunch Money transactions generated: {} ({} transfers)",
+            self.lunch_money_transactions, self.transfers
+        )?;
+
+        writeln!(f, "New: {}", self.new)?;
+        writeln!(f, "Already synced: {}", self.already_synced)?;
+        writeln!(f, "Changed since synced: {}", self.changed.len())?;
+
+        for changed in &self.changed {
+            writeln!(
+                f,
+                "  {} (Lunch Money transaction {}): {}",
+                changed.external_id,
+                changed.id,
+                changed.differences.join(", ")
+            )?;
+        }
+
+        match self.inserted {
+            Some(inserted) => {
+                writeln!(f, "Inserted: {}", inserted)?;
+
+                let deduplicated = self.new.saturating_sub(inserted);
+                if deduplicated > 0 {
+                    writeln!(f, "Skipped by Lunch Money as duplicates: {}", deduplicated)?;
+                }
+
+                if self.grouped > 0 {
+                    writeln!(f, "Grouped with transfers: {}", self.grouped)?;
+                }
+            }
+            None => writeln!(f, "Inserted: none, dry run")?,
+        }
+
+        write!(
+            f,
+            "Net amount: {}",
+            output::signed_amount(self.net_amount, &self.currency)
+        )?;
+
+        if !self.balance_drift.is_zero() {
+            write!(
+                f,
+                "\n{}",
+                console::style(format!(
+                    "WARNING: the synced transactions don't add up to the ending balance, it's off \
+                     by {}. Some transactions may not have been synced, run `reconcile` to check.",
+                    output::signed_amount(self.balance_drift, &self.currency)
+                ))
+                .yellow()
+                .bold()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How far `statement`'s ending balance is from its beginning balance plus the transactions
+/// synced, i.e. not in `skip_statuses`. Non-zero when transactions that moved the balance weren't
+/// synced, e.g. ones of unknown types skipped by the statement parser, or the statement is
+/// inconsistent. Transactions left out by a `TransactionFilter` count as synced, since they're
+/// left out on purpose.
+pub fn balance_drift(statement: &Statement, skip_statuses: &[TransactionStatus]) -> Decimal {
+    let synced = statement
+        .transactions
+        .iter()
+        .filter(|transaction| !skip_statuses.contains(&transaction.status))
+        .map(|transaction| transaction.balance_change())
+        .sum();
+
+    drift(
+        &statement.beginning_balance,
+        &statement.ending_balance,
+        synced,
+    )
+}
+
+/// How far `ending_balance` is from `beginning_balance` plus the `synced` balance changes, see
+/// `balance_drift`.
+fn drift(
+    beginning_balance: &VenmoAmount,
+    ending_balance: &VenmoAmount,
+    synced: Decimal,
+) -> Decimal {
+    let drift = ending_balance.val - (beginning_balance.val + synced);
+    if !drift.is_zero() {
+        warn!(%drift, "Synced transactions don't add up to the statement's ending balance");
+    }
+
+    drift
+}
+
+/// Generated transactions split by how they compare to the asset's existing transactions.
+struct ExistingDiff {
+    new: Vec<LunchMoneyTransaction>,
+    already_synced: usize,
+    changed: Vec<ChangedTransaction>,
+}
+
+/// How the `existing` Lunch Money transaction differs from the generated `transaction` with the
+/// same `external_id`, e.g. `amount -25.0000 -> -30.0000`. Payees aren't compared since Lunch
+/// Money rules may rename them.
+pub(crate) fn differences(
+    transaction: &LunchMoneyTransaction,
+    existing: &ExistingTransaction,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    // Lunch Money returns expenses as positive amounts, we insert them as negative ones.
+    let existing_amount = -existing.amount.0;
+
+    if existing_amount != transaction.amount.0 {
+        differences.push(format!(
+            "amount {} -> {}",
+            Amount(existing_amount),
+            transaction.amount
+        ));
+    }
+
+    if existing.date != transaction.date {
+        differences.push(format!("date {} -> {}", existing.date, transaction.date));
+    }
+
+    differences
+}
+
+/// Compares generated `transactions` to the asset's `existing` transactions by `external_id`.
+fn diff_existing(
+    transactions: Vec<LunchMoneyTransaction>,
+    existing: &[ExistingTransaction],
+) -> ExistingDiff {
+    let mut diff = ExistingDiff {
+        new: Vec::new(),
+        already_synced: 0,
+        changed: Vec::new(),
+    };
+
+    for transaction in transactions {
+        let found = transaction.external_id.as_deref().and_then(|external_id| {
+            existing
+                .iter()
+                .find(|existing| existing.external_id.as_deref() == Some(external_id))
+        });
+
+        let found = match found {
+            Some(found) => found,
+            None => {
+                diff.new.push(transaction);
+                continue;
+            }
+        };
+
+        let differences = differences(&transaction, found);
+
+        if differences.is_empty() {
+            diff.already_synced += 1;
+        } else {
+            diff.changed.push(ChangedTransaction {
+                external_id: transaction.external_id.unwrap_or_default(),
+                id: found.id,
+                differences,
+            });
+        }
+    }
+
+    diff
+}
+
+/// Venmo transactions to sync, read one at a time so the whole statement doesn't need to be held
+/// in memory, see `insert_transactions_from`.
+#[async_trait]
+pub trait TransactionSource: Send {
+    /// The next transaction, or `None` once all of them are read.
+    async fn next_transaction(&mut self) -> Result<Option<VenmoTransaction>>;
+
+    /// The statement's beginning and ending balances, once every transaction is read.
+    fn balances(&self) -> Option<(VenmoAmount, VenmoAmount)>;
+
+    /// Dates the transactions fall on, to look up the asset's existing transactions in. `None`
+    /// if there are no transactions.
+    fn date_range(&self) -> Option<(NaiveDate, NaiveDate)>;
+}
+
+/// The transactions of a statement already in memory, e.g. one imported from a file.
+struct StatementSource {
+    transactions: std::vec::IntoIter<VenmoTransaction>,
+    balances: (VenmoAmount, VenmoAmount),
+    date_range: Option<(NaiveDate, NaiveDate)>,
+}
+
+impl From<Statement> for StatementSource {
+    fn from(statement: Statement) -> Self {
+        let dates = statement
+            .transactions
+            .iter()
+            .map(|transaction| transaction.datetime.naive_local().date());
+
+        StatementSource {
+            date_range: dates.clone().min().zip(dates.max()),
+            transactions: statement.transactions.into_iter(),
+            balances: (statement.beginning_balance, statement.ending_balance),
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionSource for StatementSource {
+    async fn next_transaction(&mut self) -> Result<Option<VenmoTransaction>> {
+        Ok(self.transactions.next())
+    }
+
+    fn balances(&self) -> Option<(VenmoAmount, VenmoAmount)> {
+        Some(self.balances.clone())
+    }
+
+    fn date_range(&self) -> Option<(NaiveDate, NaiveDate)> {
+        self.date_range
+    }
+}
+
+/// The transactions of the Venmo statement for `start_date` to `end_date`, as they're fetched.
+struct FetchedStatement<'a> {
+    stream: StatementStream<'a, HttpsClient>,
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+    /// ID and date of the newest transaction read so far, for the sync's watermark.
+    newest: Option<(u64, DateTime<Utc>)>,
+}
+
+#[async_trait]
+impl TransactionSource for FetchedStatement<'_> {
+    async fn next_transaction(&mut self) -> Result<Option<VenmoTransaction>> {
+        let transaction = self.stream.next().await?;
+
+        if let Some(transaction) = &transaction {
+            let date = transaction.datetime.with_timezone(&Utc);
+            if self.newest.map_or(true, |(_, newest)| date >= newest) {
+                self.newest = Some((transaction.id, date));
+            }
+        }
+
+        Ok(transaction)
+    }
+
+    fn balances(&self) -> Option<(VenmoAmount, VenmoAmount)> {
+        self.stream
+            .balances()
+            .map(|(beginning, ending)| (beginning.clone(), ending.clone()))
+    }
+
+    fn date_range(&self) -> Option<(NaiveDate, NaiveDate)> {
+        // Transactions are dated in the statement's timezone, a day either way of UTC.
+        let day = chrono::Duration::days(1);
+        Some((
+            (self.start_date - day).date_naive(),
+            (self.end_date + day).date_naive(),
+        ))
+    }
+}
+
+/// Converts the transactions in `statement` and inserts the ones not already in Lunch Money, see
+/// `insert_transactions_from`.
+pub async fn insert_statement(
+    client: &impl HttpTransport,
+    destination: &LunchMoneyDestination,
+    currency: &Currency,
+    statement: Statement,
+    already_inserted: &[String],
+    dry_run: bool,
+) -> Result<SyncSummary> {
+    insert_transactions_from(
+        client,
+        destination,
+        currency,
+        &mut StatementSource::from(statement),
+        already_inserted,
+        dry_run,
+    )
+    .await
+}
+
+/// Converts the transactions read from `source`, categorized and tagged as `destination` says,
+/// and inserts the ones not already in its Lunch Money asset, returning a summary. Transactions
+/// are inserted in batches as they're read, so only a batch is held in memory. With `dry_run`,
+/// nothing is inserted and the summary has the Lunch Money transactions that would have been.
+/// Transactions with external IDs in `already_inserted`, inserted by an interrupted sync being
+/// resumed, are counted as already synced even if Lunch Money doesn't list them yet. If
+/// inserting fails after some transactions were inserted, the error is (or has the context of) a
+/// `PartialInsertError`. Payments are grouped with their shadow transfers last, see
+/// `group_transfers`.
+pub async fn insert_transactions_from(
+    client: &impl HttpTransport,
+    destination: &LunchMoneyDestination,
+    currency: &Currency,
+    source: &mut impl TransactionSource,
+    already_inserted: &[String],
+    dry_run: bool,
+) -> Result<SyncSummary> {
+    let (mut summary, groups) = insert_ungrouped(
+        client,
+        destination,
+        currency,
+        source,
+        already_inserted,
+        dry_run,
+    )
+    .await?;
+    group_transfers(client, destination, groups, &mut summary, &mut Vec::new()).await?;
+
+    Ok(summary)
+}
+
+/// `insert_transactions_from`, without grouping the inserted transactions yet, so the sync can
+/// be recorded first. Returns the groups to create.
+#[tracing::instrument(skip_all, fields(asset_id = destination.asset_id, dry_run))]
+async fn insert_ungrouped(
+    client: &impl HttpTransport,
+    destination: &LunchMoneyDestination,
+    currency: &Currency,
+    source: &mut impl TransactionSource,
+    already_inserted: &[String],
+    dry_run: bool,
+) -> Result<(SyncSummary, TransferGroups)> {
+    let LunchMoneyDestination {
+        ref api_token,
+        asset_id,
+        ref rules,
+        ref payees,
+        ref tags,
+        create_tags,
+        ref insert_options,
+        ref skip_statuses,
+        ref cleared_statuses,
+        ref filter,
+        ref convert,
+        group_transfers,
+        parallelism,
+    } = *destination;
+
+    let rules = if rules.is_empty() {
+        Rules::new(&[], &[])?
+    } else {
+        Rules::new(rules, &get_all_categories(client, api_token).await?)?
+    };
+
+    let payees = Payees::new(payees)?;
+    let tag_ids = resolve_tags(client, api_token, tags, create_tags).await?;
+
+    let mut summary = SyncSummary {
+        beginning_balance: Decimal::ZERO,
+        ending_balance: Decimal::ZERO,
+        venmo_transactions: 0,
+        skipped_by_status: 0,
+        filtered: 0,
+        lunch_money_transactions: 0,
+        transfers: 0,
+        new: 0,
+        already_synced: 0,
+        changed: Vec::new(),
+        inserted: None,
+        inserted_ids: None,
+        grouped: 0,
+        would_insert: dry_run.then(Vec::new),
+        net_amount: Decimal::ZERO,
+        balance_drift: Decimal::ZERO,
+        currency: *currency,
+    };
+    let mut inserter = Inserter {
+        client,
+        api_token,
+        options: InsertOptions {
+            parallelism,
+            ..insert_options.clone()
+        },
+        batch: Vec::new(),
+        inserted_ids: Vec::new(),
+        inserted_external_ids: Vec::new(),
+    };
+    let mut groups = TransferGroups {
+        groups: Vec::new(),
+        date_range: source.date_range(),
+    };
+    let mut existing = Vec::new();
+    let mut fetched_asset_ids = Vec::new();
+    // Sum of the balance changes of the synced Venmo transactions, see `balance_drift`.
+    let mut synced = Decimal::ZERO;
+
+    let res: Result<()> = async {
+        while let Some(transaction) = source.next_transaction().await? {
+            summary.venmo_transactions += 1;
+
+            if skip_statuses.contains(&transaction.status) {
+                summary.skipped_by_status += 1;
+                continue;
+            }
+            synced += transaction.balance_change();
+
+            if !filter.matches(&transaction) {
+                summary.filtered += 1;
+                continue;
+            }
+
+            let mut generated =
+                transaction.to_lunchmoney_transactions(*currency, asset_id, convert)?;
+
+            if !tag_ids.is_empty() {
+                for lunchmoney_transaction in &mut generated {
+                    lunchmoney_transaction.tags = Some(tag_ids.clone());
+                }
+            }
+
+            if cleared_statuses.contains(&transaction.status) {
+                for lunchmoney_transaction in &mut generated {
+                    lunchmoney_transaction.status = LunchMoneyStatus::Cleared;
+                }
+            }
+
+            // Only the first transaction is the payment itself, the rest are transfers.
+            if let Some(primary) = generated.first_mut() {
+                primary.payee = primary.payee.as_deref().map(|payee| payees.rewrite(payee));
+                primary.category_id = primary
+                    .payee
+                    .as_deref()
+                    .and_then(|payee| rules.category_for(&transaction, payee));
+            }
+
+            summary.lunch_money_transactions += generated.len();
+            summary.transfers += generated
+                .iter()
+                .filter(|generated| transaction.is_transfer(generated))
+                .count();
+            summary.net_amount += generated
+                .iter()
+                .filter(|transaction| transaction.asset_id == Some(asset_id))
+                .map(|transaction| transaction.amount.0)
+                .sum::<Decimal>();
+
+            // Includes the assets in `convert.transfers.asset_ids` the other sides of transfers go to,
+            // each looked up the first time a transaction goes to it.
+            if let Some((start_date, end_date)) = source.date_range() {
+                for transaction_asset_id in generated.iter().filter_map(|t| t.asset_id) {
+                    if !fetched_asset_ids.contains(&transaction_asset_id) {
+                        fetched_asset_ids.push(transaction_asset_id);
+                        existing.extend(
+                            get_transactions(
+                                client,
+                                api_token,
+                                transaction_asset_id,
+                                start_date,
+                                end_date,
+                            )
+                            .await?,
+                        );
+                    }
+                }
+            }
+
+            let diff = diff_existing(generated, &existing);
+            summary.already_synced += diff.already_synced;
+            summary.changed.extend(diff.changed);
+
+            for transaction in diff.new {
+                let resumed = matches!(&transaction.external_id, Some(external_id) if already_inserted.contains(external_id));
+                if resumed {
+                    summary.already_synced += 1;
+                } else {
+                    summary.new += 1;
+                    inserter.batch.push(transaction);
+                }
+            }
+
+            // Batches end between Venmo transactions, so payments and their shadow transfers are
+            // inserted, and grouped, together.
+            if inserter.batch.len() >= inserter.batch_size() {
+                groups
+                    .groups
+                    .extend(inserter.flush(&mut summary, group_transfers, asset_id).await?);
+            }
+        }
+
+        groups
+            .groups
+            .extend(inserter.flush(&mut summary, group_transfers, asset_id).await?);
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = res {
+        return Err(inserter.interrupted(e));
+    }
+
+    let (beginning_balance, ending_balance) =
+        source.balances().expect("every transaction has been read");
+    summary.beginning_balance = beginning_balance.val;
+    summary.ending_balance = ending_balance.val;
+    summary.balance_drift = drift(&beginning_balance, &ending_balance, synced);
+
+    if !dry_run {
+        summary.inserted = Some(inserter.inserted_ids.len());
+        summary.inserted_ids = Some(inserter.inserted_ids);
+    }
+
+    Ok((summary, groups))
+}
+
+/// Generated transactions waiting to be inserted, and the ones inserted so far, see
+/// `insert_transactions_from`.
+struct Inserter<'a, C> {
+    client: &'a C,
+    api_token: &'a str,
+    options: InsertOptions,
+    batch: Vec<LunchMoneyTransaction>,
+    inserted_ids: Vec<u64>,
+    inserted_external_ids: Vec<String>,
+}
+
+impl<C: HttpTransport> Inserter<'_, C> {
+    /// Transactions to insert at once, enough to keep `options.parallelism` chunks busy.
+    fn batch_size(&self) -> usize {
+        self.options.chunk_size.max(1) * self.options.parallelism.max(1)
+    }
+
+    /// Inserts the batch, or adds it to `summary.would_insert` on dry runs. Returns the groups to
+    /// create for its payments and their shadow transfers, with `group_transfers`.
+    async fn flush(
+        &mut self,
+        summary: &mut SyncSummary,
+        group_transfers: bool,
+        asset_id: u64,
+    ) -> Result<Vec<TransferGroup>> {
+        let batch = std::mem::take(&mut self.batch);
+
+        if let Some(would_insert) = &mut summary.would_insert {
+            would_insert.extend(batch);
+            return Ok(Vec::new());
+        }
+        if batch.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let groups = if group_transfers {
+            transfer_groups(&batch, asset_id)
+        } else {
+            Vec::new()
+        };
+        let external_ids = batch
+            .iter()
+            .filter_map(|transaction| transaction.external_id.clone())
+            .collect::<Vec<_>>();
+
+        match insert_transactions(self.client, self.api_token, batch, &self.options).await {
+            Ok(ids) => {
+                self.inserted_ids.extend(ids);
+                self.inserted_external_ids.extend(external_ids);
+                Ok(groups)
+            }
+            Err(LunchMoneyError::PartialInsert(mut partial)) => {
+                partial
+                    .inserted_ids
+                    .splice(0..0, self.inserted_ids.drain(..));
+                partial
+                    .inserted_external_ids
+                    .splice(0..0, self.inserted_external_ids.drain(..));
+                Err(partial.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// `e`, with the context of a `PartialInsertError` if earlier batches were inserted, so the
+    /// sync can be resumed.
+    fn interrupted(self, e: anyhow::Error) -> anyhow::Error {
+        if self.inserted_ids.is_empty() || e.is::<PartialInsertError>() {
+            return e;
+        }
+
+        let message = format!(
+            "Failed after inserting {} transactions",
+            self.inserted_ids.len()
+        );
+        e.context(PartialInsertError::new(
+            self.inserted_ids,
+            self.inserted_external_ids,
+            message,
+        ))
+    }
+}
+
+/// A new payment and its shadow transfers, to group once inserted.
+struct TransferGroup {
+    /// The group to create, without its tags and transactions yet.
+    request: CreateTransactionGroupRequest,
+    /// External IDs of the payment and its shadow transfers.
+    external_ids: Vec<String>,
+}
+
+/// The groups to create for the transactions a sync inserted, dated within `date_range`.
+struct TransferGroups {
+    groups: Vec<TransferGroup>,
+    date_range: Option<(NaiveDate, NaiveDate)>,
+}
+
+/// Finds the new transactions in asset `asset_id` with shadow transfers to group them with.
+/// Transfers in other assets (see `Transfers::asset_ids`) can't be grouped with the payment.
+fn transfer_groups(new: &[LunchMoneyTransaction], asset_id: u64) -> Vec<TransferGroup> {
+    new.iter()
+        .filter(|transaction| transaction.asset_id == Some(asset_id))
+        .filter_map(|payment| {
+            let external_id = payment.external_id.as_deref()?;
+            let transfer_external_ids = [
+                format!("{}T", external_id),
+                format!("{}TDEPOSIT", external_id),
+            ]
+            .into_iter()
+            .filter(|transfer_external_id| {
+                new.iter().any(|transaction| {
+                    transaction.asset_id == Some(asset_id)
+                        && transaction.external_id.as_ref() == Some(transfer_external_id)
+                })
+            })
+            .collect::<Vec<_>>();
+
+            if transfer_external_ids.is_empty() {
+                return None;
+            }
+
+            Some(TransferGroup {
+                request: CreateTransactionGroupRequest {
+                    date: payment.date,
+                    payee: payment.payee.clone().unwrap_or_default(),
+                    category_id: payment.category_id,
+                    notes: payment.notes.clone(),
+                    tags: None,
+                    transactions: Vec::new(),
+                },
+                external_ids: std::iter::once(external_id.to_string())
+                    .chain(transfer_external_ids)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Groups the payments inserted into `destination` with their shadow transfers, as `groups`
+/// says, counting them in `summary.grouped`. The inserted transactions are looked up by external
+/// ID since Lunch Money doesn't say which inserted ID is which. IDs of the created groups are
+/// pushed to `group_ids` as they're created, so they're kept if grouping fails partway.
+async fn group_transfers(
+    client: &impl HttpTransport,
+    destination: &LunchMoneyDestination,
+    groups: TransferGroups,
+    summary: &mut SyncSummary,
+    group_ids: &mut Vec<u64>,
+) -> Result<()> {
+    let (start_date, end_date) = match groups.date_range {
+        Some(date_range) if !groups.groups.is_empty() => date_range,
+        _ => return Ok(()),
+    };
+    let api_token = &destination.api_token;
+
+    // Groups only take tag IDs, look up the tags inserting may have just created.
+    let tags = if destination.tags.is_empty() {
+        None
+    } else {
+        let tags = resolve_tags(client, api_token, &destination.tags, false).await?;
+        Some(tags.iter().filter_map(TagRef::id).collect::<Vec<_>>())
+    };
+    let inserted = get_transactions(
+        client,
+        api_token,
+        destination.asset_id,
+        start_date,
+        end_date,
+    )
+    .await?;
+    let id_of = |external_id: &str| {
+        inserted
+            .iter()
+            .find(|transaction| transaction.external_id.as_deref() == Some(external_id))
+            .map(|transaction| transaction.id)
+    };
+
+    for group in groups.groups {
+        let ids = group
+            .external_ids
+            .iter()
+            .map(|external_id| id_of(external_id))
+            .collect::<Option<Vec<_>>>();
+
+        // Lunch Money skipped some of them, e.g. as duplicates.
+        let ids = match ids {
+            Some(ids) => ids,
+            None => continue,
+        };
+
+        let group_id = create_transaction_group(
+            client,
+            api_token,
+            &CreateTransactionGroupRequest {
+                tags: tags.clone(),
+                transactions: ids,
+                ..group.request
+            },
+        )
+        .await?;
+
+        group_ids.push(group_id);
+        summary.grouped += 1;
+    }
+
+    Ok(())
+}
+
+/// Default tag `undo_last_sync` flags transactions with.
+pub const UNDO_TAG: &str = "venmo-sync-undone";
+
+/// Adds `tag` to transaction `id` and marks it uncleared. Updates replace a transaction's tags,
+/// so the ones it already has are kept.
+async fn flag_undone(
+    client: &impl HttpTransport,
+    api_token: &str,
+    id: u64,
+    tag: &str,
+) -> Result<(), LunchMoneyError> {
+    let mut tags: Vec<String> = get_transaction(client, api_token, id)
+        .await?
+        .tags
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tag| tag.name)
+        .collect();
+    if !tags.iter().any(|existing| existing == tag) {
+        tags.push(tag.to_string());
+    }
+
+    update_transaction(
+        client,
+        api_token,
+        id,
+        TransactionUpdate {
+            status: Some(LunchMoneyStatus::Uncleared),
+            tags: Some(tags),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Rolls back the newest recorded sync, of asset `lunch_money_asset_id` if given: the groups it
+/// created for transfers are deleted, its inserted transactions are tagged `tag` and left
+/// uncleared (Lunch Money's API can't delete transactions, filter by the tag to bulk delete them
+/// in Lunch Money), then the run and its watermark are dropped from the sync state. With
+/// `dry_run`, nothing is changed. Returns the undone run, if there was one.
+pub async fn undo_last_sync(
+    client: &impl HttpTransport,
+    paths: &Paths,
+    api_token: &str,
+    lunch_money_asset_id: Option<u64>,
+    tag: &str,
+    dry_run: bool,
+) -> Result<Option<SyncRun>> {
+    let state_path = state_file(paths);
+
+    let run = match State::load(&state_path)?.pop_run(lunch_money_asset_id) {
+        Some(run) => run,
+        None => return Ok(None),
+    };
+
+    if dry_run {
+        return Ok(Some(run));
+    }
+
+    // Reloaded once locked, so a sync to the asset finishing meanwhile isn't lost.
+    let _lock = lock_sync(paths, &asset_name(run.lunch_money_asset_id)).await?;
+    let mut state = State::load(&state_path)?;
+    let run = match state.pop_run(Some(run.lunch_money_asset_id)) {
+        Some(run) => run,
+        None => return Ok(None),
+    };
+
+    if !run.group_ids.is_empty() {
+        for &group_id in &run.group_ids {
+            delete_transaction_group(client, api_token, group_id)
+                .await
+                .with_context(|| {
+                    anyhow!(
+                        "Failed to delete transaction group {}, state left unchanged",
+                        group_id
+                    )
+                })?;
+        }
+
+        // Forgotten right away, so retrying a failed undo doesn't delete them again.
+        let mut ungrouped = State::load(&state_path)?;
+        ungrouped.set_run_groups(&run, Vec::new());
+        ungrouped.save(&state_path)?;
+    }
+
+    for &id in &run.inserted_ids {
+        flag_undone(client, api_token, id, tag)
+            .await
+            .with_context(|| anyhow!("Failed to flag transaction {}, state left unchanged", id))?;
+    }
+
+    // Only dropped once every transaction is flagged, so a failed undo can be retried.
+    state.save(&state_path)?;
+
+    Ok(Some(run))
+}
+
+/// Inserts Venmo transactions between `start_date` and `end_date` into Lunch Money, recording
+/// the run in the sync state. With `dry_run`, nothing is inserted or recorded, see
+/// `insert_statement`.
+///
+/// If inserting fails partway, the transactions inserted so far are recorded as an
+/// `InterruptedSync`. Passing it as `resume` (with its date range) skips those transactions, and
+/// it's forgotten once a sync covering its date range succeeds.
+#[tracing::instrument(skip_all, fields(
+    profile_id = accounts.venmo.profile_id,
+    asset_id = accounts.lunch_money.asset_id,
+))]
+pub async fn sync_venmo_transactions(
+    client: &HttpsClient,
+    paths: &Paths,
+    accounts: &SyncAccounts,
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+    dry_run: bool,
+    resume: Option<&InterruptedSync>,
+) -> Result<SyncSummary> {
+    let (profile_id, asset_id) = (accounts.venmo.profile_id, accounts.lunch_money.asset_id);
+    let mut statement = FetchedStatement {
+        stream: StatementStream::new(
+            client,
+            &accounts.venmo,
+            &accounts.statement_format,
+            &accounts.statements,
+            &start_date,
+            &end_date,
+        ),
+        start_date,
+        end_date,
+        newest: None,
+    };
+
+    let (resumed_ids, resumed_external_ids) = match resume {
+        Some(resume) => (
+            resume.inserted_ids.clone(),
+            resume.inserted_external_ids.clone(),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+
+    let state_path = state_file(paths);
+    let res = insert_ungrouped(
+        client,
+        &accounts.lunch_money,
+        &accounts.venmo.currency,
+        &mut statement,
+        &resumed_external_ids,
+        dry_run,
+    )
+    .await;
+
+    let (mut summary, groups) = match res {
+        Ok(res) => res,
+        Err(e) => {
+            if let Some(partial) = e.downcast_ref::<PartialInsertError>() {
+                let mut state = State::load(&state_path)?;
+                state.set_interrupted(InterruptedSync {
+                    venmo_profile_id: profile_id,
+                    lunch_money_asset_id: asset_id,
+                    failed_at: Utc::now(),
+                    start_date,
+                    end_date,
+                    inserted_ids: resumed_ids
+                        .into_iter()
+                        .chain(partial.inserted_ids.iter().copied())
+                        .collect(),
+                    inserted_external_ids: resumed_external_ids
+                        .into_iter()
+                        .chain(partial.inserted_external_ids.iter().cloned())
+                        .collect(),
+                });
+                state.save(&state_path)?;
+
+                return Err(e.context(
+                    "Sync was interrupted, pass --resume to insert the remaining transactions",
+                ));
+            }
+
+            return Err(e);
+        }
+    };
+
+    let inserted_ids = match summary.inserted_ids {
+        Some(ref inserted_ids) => inserted_ids.clone(),
+        None => return Ok(summary),
+    };
+
+    let run = SyncRun {
+        venmo_profile_id: profile_id,
+        lunch_money_asset_id: asset_id,
+        synced_at: Utc::now(),
+        start_date,
+        end_date,
+        // Include the transactions inserted before being interrupted, so undoing this run undoes
+        // them too.
+        inserted_ids: resumed_ids.into_iter().chain(inserted_ids).collect(),
+        group_ids: Vec::new(),
+    };
+
+    let mut state = State::load(&state_path)?;
+    state.push_run(run.clone());
+
+    if let Some((transaction_id, transaction_date)) = statement.newest {
+        state.update_watermark(Watermark {
+            venmo_profile_id: profile_id,
+            lunch_money_asset_id: asset_id,
+            transaction_id,
+            transaction_date,
+        });
+    }
+
+    if let Some(interrupted) = state.interrupted(profile_id, asset_id) {
+        if start_date <= interrupted.start_date && end_date >= interrupted.end_date {
+            state.clear_interrupted(profile_id, asset_id);
+        }
+    }
+
+    state.save(&state_path)?;
+
+    // Grouped once the sync is recorded, so a failure here can't lose track of the inserted
+    // transactions.
+    let mut group_ids = Vec::new();
+    let grouped = group_transfers(
+        client,
+        &accounts.lunch_money,
+        groups,
+        &mut summary,
+        &mut group_ids,
+    )
+    .await;
+
+    // Recorded even if grouping failed partway, so undoing the run deletes the groups.
+    if !group_ids.is_empty() {
+        let mut state = State::load(&state_path)?;
+        state.set_run_groups(&run, group_ids);
+        state.save(&state_path)?;
+    }
+
+    grouped.context("Inserted the transactions but failed to group them with their transfers")?;
+
+    Ok(summary)
+}
+
+/// The interrupted sync of `accounts` to resume, see `sync_venmo_transactions`.
+pub fn interrupted_sync(paths: &Paths, accounts: &SyncAccounts) -> Result<InterruptedSync> {
+    let state = State::load(&state_file(paths))?;
+
+    state
+        .interrupted(accounts.venmo.profile_id, accounts.lunch_money.asset_id)
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "No interrupted sync of Venmo profile {} to Lunch Money asset {} to resume",
+                accounts.venmo.profile_id,
+                accounts.lunch_money.asset_id
+            )
+        })
+}
+
+/// When to re-run the sync in daemon mode.
+pub enum Schedule {
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// Parses a cron expression, accepting the standard 5-field form as well as the 6 and 7-field
+    /// forms (with seconds and years) the `cron` crate uses.
+    pub fn cron(expression: &str) -> Result<Self> {
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_string()
+        };
+
+        let schedule = cron::Schedule::from_str(&expression)
+            .with_context(|| anyhow!("Failed to parse cron expression '{}'", expression))?;
+
+        Ok(Schedule::Cron(Box::new(schedule)))
+    }
+
+    /// How long to wait until the next run.
+    fn next_delay(&self) -> Result<Duration> {
+        match self {
+            Schedule::Every(every) => Ok(*every),
+            Schedule::Cron(schedule) => {
+                let next = schedule
+                    .upcoming(Local)
+                    .next()
+                    .ok_or_else(|| anyhow!("Cron expression has no upcoming runs"))?;
+
+                Ok((next - Local::now()).to_std().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Start of the date range for the next sync of `accounts`: shortly before the newest
+/// transaction synced so far (or the end of the last sync, if it had no transactions), or
+/// `start_from` back if they have never been synced.
+pub fn next_start_date(
+    paths: &Paths,
+    accounts: &SyncAccounts,
+    config: &SyncConfig,
+) -> Result<DateTime<Utc>> {
+    let state = State::load(&state_file(paths))?;
+    let (profile_id, asset_id) = (accounts.venmo.profile_id, accounts.lunch_money.asset_id);
+
+    let last_synced = state
+        .watermark(profile_id, asset_id)
+        .map(|watermark| watermark.transaction_date)
+        .or_else(|| {
+            state
+                .runs
+                .iter()
+                .rev()
+                .find(|run| {
+                    run.venmo_profile_id == profile_id && run.lunch_money_asset_id == asset_id
+                })
+                .map(|run| run.end_date)
+        });
+
+    match last_synced {
+        Some(date) => Ok(date - chrono::Duration::from_std(WATERMARK_OVERLAP)?),
+        None => Ok(date_range(None, None, config)?.0),
+    }
+}
+
+/// Where daemon mode exposes its metrics, see `metrics`.
+#[derive(Debug, Default)]
+pub struct MetricsOptions {
+    /// Address to serve metrics on at `/metrics`.
+    pub listen: Option<SocketAddr>,
+    /// File to write metrics to after each sync, for node_exporter's textfile collector.
+    pub textfile: Option<PathBuf>,
+}
+
+/// Options of `daemon` and `serve` syncs given on the command line rather than in the config
+/// file.
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Where fetched statements are cached and archived.
+    pub statements: StatementStore,
+    /// How Lunch Money should process inserted transactions.
+    pub insert: InsertOptions,
+}
+
+/// Syncs transactions since shortly before the last successful sync of the account in `config`,
+/// as `daemon` and `serve` do, using `accounts` if they're already resolved from it.
+pub async fn sync_since_last_run(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: &Config,
+    options: &SyncOptions,
+    accounts: Option<SyncAccounts>,
+) -> Result<SyncSummary> {
+    let mut accounts = match accounts {
+        Some(accounts) => accounts,
+        None => SyncAccounts::from_config(config)?,
+    };
+    accounts.statements = options.statements.clone();
+    accounts.lunch_money.insert_options = options.insert.clone();
+    // Wait out a manual sync of the same account rather than skipping the run.
+    let _lock = lock_sync(paths, &asset_name(accounts.lunch_money.asset_id)).await?;
+    let start_date = next_start_date(paths, &accounts, &config.sync)?;
+
+    let summary = sync_venmo_transactions(
+        client,
+        paths,
+        &accounts,
+        start_date,
+        Utc::now(),
+        false,
+        None,
+    )
+    .await?;
+    check_balance_drift(&config.sync, &summary)?;
+
+    Ok(summary)
+}
+
+/// Fails if `summary` doesn't add up to the Venmo statement's ending balance and `config` says
+/// to fail on that, rather than just warning.
+pub fn check_balance_drift(config: &SyncConfig, summary: &SyncSummary) -> Result<()> {
+    if config.fail_on_balance_drift && !summary.balance_drift.is_zero() {
+        bail!(
+            "Synced transactions are off from the Venmo ending balance by {}",
+            output::signed_amount(summary.balance_drift, &summary.currency)
+        );
+    }
+
+    Ok(())
+}
+
+/// Logs, sends notifications for, and records metrics of the result of a
+/// `sync_since_last_run`.
+pub async fn report_sync(client: &HttpsClient, config: &Config, res: &Result<SyncSummary>) {
+    let name = config.venmo.profile_id.unwrap_or_default().to_string();
+
+    let counts = res.as_ref().map(SyncSummary::counts);
+    if let Err(e) = notify::notify_result(client, &config.notify, counts).await {
+        warn!("{:#}", e);
+    }
+    if let Err(e) = notify::notify_channels(client, &config.notify, &[(&name, res)]).await {
+        warn!("{:#}", e);
+    }
+
+    match res {
+        Ok(summary) => {
+            info!("Sync succeeded: {}", summary.short());
+            debug!("{}", summary);
+            metrics::record_sync(Some(summary.inserted.unwrap_or_default()));
+        }
+        Err(e) => {
+            error!("Sync failed: {:#}", e);
+            metrics::record_sync(None);
+        }
+    }
+}
+
+/// `sync_since_last_run` with `venmo_api_token`, if given, instead of the configured Venmo API
+/// token.
+async fn sync_with_venmo_api_token(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: &Config,
+    options: &SyncOptions,
+    accounts: Option<SyncAccounts>,
+    venmo_api_token: Option<&str>,
+) -> Result<SyncSummary> {
+    let mut accounts = match accounts {
+        Some(accounts) => accounts,
+        None => SyncAccounts::from_config(config)?,
+    };
+    if let Some(api_token) = venmo_api_token {
+        accounts.venmo.api_token = api_token.to_string();
+    }
+
+    sync_since_last_run(client, paths, config, options, Some(accounts)).await
+}
+
+/// Whether `e` is Venmo rejecting the API token.
+fn is_venmo_auth_error(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<VenmoClientError>(),
+        Some(VenmoClientError::AuthError(_))
+    )
+}
+
+/// Gets a new Venmo API token after Venmo rejected the configured one, by running
+/// `venmo.reauth_command`, or else logging in again if running in a terminal. `None` if neither
+/// is possible.
+async fn reauthenticate(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: &Config,
+) -> Result<Option<String>> {
+    if let Some(command) = &config.venmo.reauth_command {
+        info!("Venmo rejected the API token, running venmo.reauth_command");
+        return run_token_command(command).map(Some);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    warn!("Venmo rejected the API token, log in again to keep syncing");
+    let options = LoginOptions {
+        headers: request_headers(&config.venmo.statement_format),
+        ..Default::default()
+    };
+    let login = log_in(client, paths, options).await?;
+
+    if let Some(profile_id) = config.venmo.profile_id {
+        if login.profile_id != profile_id.to_string() {
+            bail!(
+                "Logged in to Venmo profile {}, but the config syncs profile {}",
+                login.profile_id,
+                profile_id
+            );
+        }
+    }
+
+    println!(
+        "Using the new API token until the daemon restarts. Update the config with it to keep \
+         using it after."
+    );
+
+    Ok(Some(login.api_token))
+}
+
+/// Syncs on `schedule` until the process is killed. `--every` schedules sync immediately, cron
+/// schedules wait for the first matching time. Failed syncs are logged and retried on the next
+/// run.
+pub async fn run_daemon(
+    client: &HttpsClient,
+    paths: &Paths,
+    config: &Config,
+    schedule: Schedule,
+    metrics_options: &MetricsOptions,
+    options: &SyncOptions,
+) -> Result<()> {
+    // Resolved again for later runs, so `token_command`s run at sync time and pick up rotated
+    // tokens.
+    let mut resolved = Some(SyncAccounts::from_config(config)?);
+
+    if let Some(addr) = &metrics_options.listen {
+        metrics::serve(addr)?;
+    }
+
+    if let Schedule::Cron(_) = schedule {
+        tokio::time::sleep(schedule.next_delay()?).await;
+    }
+
+    // A new Venmo API token from re-authenticating, used instead of the configured one from then
+    // on.
+    let mut venmo_api_token = None;
+
+    loop {
+        info!("Starting sync");
+
+        let mut res = sync_with_venmo_api_token(
+            client,
+            paths,
+            config,
+            options,
+            resolved.take(),
+            venmo_api_token.as_deref(),
+        )
+        .await;
+
+        if matches!(&res, Err(e) if is_venmo_auth_error(e)) {
+            res = match reauthenticate(client, paths, config).await {
+                Ok(Some(api_token)) => {
+                    info!("Re-authenticated with Venmo, retrying the sync");
+                    let res = sync_with_venmo_api_token(
+                        client,
+                        paths,
+                        config,
+                        options,
+                        None,
+                        Some(&api_token),
+                    )
+                    .await;
+                    venmo_api_token = Some(api_token);
+                    res
+                }
+                Ok(None) => res.context(
+                    "Venmo rejected the API token, get a new one with get-venmo-api-token and \
+                     update the config, or set venmo.reauth_command to get one automatically",
+                ),
+                Err(e) => res.with_context(|| {
+                    format!(
+                        "Venmo rejected the API token, and re-authenticating failed: {:#}",
+                        e
+                    )
+                }),
+            };
+        }
+
+        report_sync(client, config, &res).await;
+
+        if let Some(path) = &metrics_options.textfile {
+            if let Err(e) = metrics::write_textfile(path) {
+                warn!("{:#}", e);
+            }
+        }
+
+        let delay = schedule.next_delay()?;
+        info!(
+            "Next sync in {}",
+            humantime::format_duration(Duration::from_secs(delay.as_secs()))
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+
+    use super::*;
+    use crate::http::fake::FakeTransport;
+    use crate::testing;
+    use crate::venmo::parse_venmo_statement;
+
+    /// Asset 1, inserting a transaction per request, a request at a time.
+    fn destination() -> LunchMoneyDestination {
+        LunchMoneyDestination {
+            api_token: "token".to_string(),
+            asset_id: 1,
+            rules: Vec::new(),
+            payees: PayeeConfig::default(),
+            tags: Vec::new(),
+            create_tags: false,
+            insert_options: InsertOptions {
+                chunk_size: 1,
+                ..Default::default()
+            },
+            skip_statuses: Vec::new(),
+            cleared_statuses: Vec::new(),
+            filter: TransactionFilter::default(),
+            convert: ConvertOptions::default(),
+            group_transfers: false,
+            parallelism: 1,
+        }
+    }
+
+    fn basic_statement() -> Statement {
+        parse_venmo_statement(
+            include_bytes!("../tests/fixtures/statement_basic.csv"),
+            &StatementFormat::default(),
+        )
+        .unwrap()
+    }
+
+    fn generated(external_id: &str, val: Decimal) -> LunchMoneyTransaction {
+        LunchMoneyTransaction {
+            date: NaiveDate::from_ymd_opt(2022, 7, 1).unwrap(),
+            amount: Amount(val),
+            external_id: Some(external_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn existing(id: u64, external_id: &str, val: Decimal) -> ExistingTransaction {
+        ExistingTransaction {
+            id,
+            date: NaiveDate::from_ymd_opt(2022, 7, 1).unwrap(),
+            payee: None,
+            amount: Amount(val),
+            external_id: Some(external_id.to_string()),
+            notes: None,
+            status: None,
+            tags: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_existing_tags_when_undoing() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"id": 101, "date": "2022-07-01", "payee": "Jane Doe", "amount": "25.0000",
+                    "external_id": "1", "tags": [{"id": 7, "name": "shared"}]}"#,
+            ),
+            (StatusCode::OK, br#"{"updated": true}"#),
+        ]);
+
+        flag_undone(&transport, "token", 101, UNDO_TAG)
+            .await
+            .unwrap();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].method, Method::GET);
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(
+            body["transaction"]["tags"],
+            serde_json::json!(["shared", UNDO_TAG])
+        );
+        assert_eq!(body["transaction"]["status"], "uncleared");
+    }
+
+    #[tokio::test]
+    async fn deletes_groups_when_undoing() {
+        let dir = testing::temp_dir();
+        let paths = testing::paths(dir.path());
+        let run = SyncRun {
+            venmo_profile_id: 1,
+            lunch_money_asset_id: 10,
+            synced_at: Utc::now(),
+            start_date: Utc::now(),
+            end_date: Utc::now(),
+            inserted_ids: vec![101],
+            group_ids: vec![789],
+        };
+        let mut state = State::default();
+        state.push_run(run.clone());
+        state.save(&state_file(&paths)).unwrap();
+
+        let transaction: &[u8] = br#"{"id": 101, "date": "2022-07-01", "payee": "Jane Doe",
+            "amount": "25.0000", "external_id": "1"}"#;
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, br#"{"transactions": [101, 102]}"#),
+            (StatusCode::INTERNAL_SERVER_ERROR, b"{}"),
+        ]);
+        assert!(
+            undo_last_sync(&transport, &paths, "token", None, UNDO_TAG, false)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            transport.requests.lock().unwrap()[0].uri,
+            "https://dev.lunchmoney.app/v1/transactions/group/789"
+        );
+
+        // The group is already deleted, so retrying only flags the transaction.
+        let state = State::load(&state_file(&paths)).unwrap();
+        assert_eq!(state.runs[0].group_ids, Vec::<u64>::new());
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, transaction),
+            (StatusCode::OK, br#"{"updated": true}"#),
+        ]);
+        let undone = undo_last_sync(&transport, &paths, "token", None, UNDO_TAG, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(undone.inserted_ids, vec![101]);
+        assert_eq!(transport.requests.lock().unwrap()[0].method, Method::GET);
+        assert!(State::load(&state_file(&paths)).unwrap().runs.is_empty());
+    }
+
+    #[test]
+    fn diffs_against_existing_transactions() {
+        let diff = diff_existing(
+            vec![
+                generated("1", Decimal::new(-25, 0)),
+                generated("2", Decimal::new(-30, 0)),
+                generated("3", Decimal::new(80, 0)),
+            ],
+            &[
+                existing(101, "1", Decimal::new(25, 0)),
+                existing(102, "2", Decimal::new(25, 0)),
+            ],
+        );
+
+        assert_eq!(diff.already_synced, 1);
+        assert_eq!(
+            diff.new
+                .iter()
+                .map(|transaction| transaction.external_id.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("3")]
+        );
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].id, 102);
+        assert_eq!(
+            diff.changed[0].differences,
+            vec!["amount -25.0000 -> -30.0000"]
+        );
+    }
+
+    #[test]
+    fn finds_transfers_to_group() {
+        let in_asset = |external_id: &str, asset_id: u64| LunchMoneyTransaction {
+            asset_id: Some(asset_id),
+            ..generated(external_id, Decimal::new(-80, 0))
+        };
+
+        let groups = transfer_groups(
+            &[
+                in_asset("1", 12345),
+                in_asset("1T", 12345),
+                in_asset("1TPAIR", 54321),
+                in_asset("2", 12345),
+                in_asset("3", 12345),
+                in_asset("3TDEPOSIT", 12345),
+            ],
+            12345,
+        );
+
+        assert_eq!(
+            groups
+                .iter()
+                .map(|group| group.external_ids.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                vec!["1".to_string(), "1T".to_string()],
+                vec!["3".to_string(), "3TDEPOSIT".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides_range_with_absolute_dates() {
+        let now = Utc::now();
+        let relative = (now - chrono::Duration::days(30), now);
+
+        let (start, end) = with_absolute_dates(
+            relative,
+            NaiveDate::from_ymd_opt(2023, 1, 1),
+            NaiveDate::from_ymd_opt(2023, 1, 31),
+        )
+        .unwrap();
+        assert_eq!(start.to_rfc3339(), "2023-01-01T00:00:00+00:00");
+        assert_eq!(end.to_rfc3339(), "2023-01-31T23:59:59+00:00");
+
+        assert_eq!(with_absolute_dates(relative, None, None).unwrap(), relative);
+        assert!(with_absolute_dates(relative, NaiveDate::from_ymd_opt(2099, 1, 1), None).is_err());
+    }
+
+    #[test]
+    fn computes_balance_drift() {
+        use crate::testing::{amount, transaction};
+        use crate::types::venmo::{Transaction, TransactionType};
+
+        let transaction = |id, status, val| Transaction {
+            status,
+            ..transaction(id, TransactionType::Payment, val)
+        };
+
+        // Funded by a bank account, so it doesn't change the Venmo balance.
+        let mut payment = transaction(1, TransactionStatus::Complete, Decimal::new(-2050, 2));
+        payment.funding_source = Some("Chase Checking *1234".to_string());
+        let received = transaction(2, TransactionStatus::Complete, Decimal::new(15, 0));
+        let pending = transaction(3, TransactionStatus::Pending, Decimal::new(-10, 0));
+
+        let mut statement = Statement {
+            beginning_balance: amount(Decimal::new(100, 0)),
+            ending_balance: amount(Decimal::new(115, 0)),
+            transactions: vec![payment, received, pending],
+        };
+        let skip_statuses = [TransactionStatus::Pending];
+        assert_eq!(balance_drift(&statement, &skip_statuses), Decimal::ZERO);
+
+        // E.g. a transaction of an unknown type the parser dropped.
+        statement.transactions.remove(1);
+        assert_eq!(
+            balance_drift(&statement, &skip_statuses),
+            Decimal::new(15, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn inserts_transactions_as_they_are_read() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"transactions": [], "has_more": false}"#,
+            ),
+            (StatusCode::OK, br#"{"ids": [101]}"#),
+            (StatusCode::OK, br#"{"ids": [102]}"#),
+            (StatusCode::OK, br#"{"ids": [103]}"#),
+            (StatusCode::OK, br#"{"ids": [104]}"#),
+        ]);
+
+        let summary = insert_statement(
+            &transport,
+            &destination(),
+            rusty_money::iso::USD,
+            basic_statement(),
+            &[],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.venmo_transactions, 3);
+        assert_eq!(summary.lunch_money_transactions, 4);
+        // The shadow transfer funding the concert tickets.
+        assert_eq!(summary.transfers, 1);
+        assert_eq!(summary.new, 4);
+        assert_eq!(summary.inserted_ids, Some(vec![101, 102, 103, 104]));
+        assert_eq!(summary.balance_drift, Decimal::ZERO);
+        let counts = serde_json::to_value(summary.counts()).unwrap();
+        assert_eq!(counts["inserted"], 4);
+        assert!(counts.get("net_amount").is_none());
+
+        // The asset's existing transactions are only looked up once.
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[0].uri.contains("/transactions?asset_id=1"));
+    }
+
+    #[tokio::test]
+    async fn summarizes_already_synced_transactions() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"transactions": [{"id": 1, "date": "2022-07-01", "payee": "John Smith", "amount": "-25.0000", "external_id": "3581234567890123456"}], "has_more": false}"#,
+            ),
+            (StatusCode::OK, br#"{"ids": [102]}"#),
+            (StatusCode::OK, br#"{"ids": [103]}"#),
+            (StatusCode::OK, br#"{"ids": []}"#),
+        ]);
+
+        let summary = insert_statement(
+            &transport,
+            &destination(),
+            rusty_money::iso::USD,
+            basic_statement(),
+            &[],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.lunch_money_transactions, 4);
+        assert_eq!(summary.transfers, 1);
+        assert_eq!(summary.already_synced, 1);
+        assert_eq!(summary.new, 3);
+        assert_eq!(summary.inserted, Some(2));
+        assert_eq!(summary.skipped(), Some(2));
+        assert_eq!(summary.net_amount, Decimal::new(-15, 0));
+
+        let summary = console::strip_ansi_codes(&summary.to_string()).to_string();
+        assert!(summary.contains("Lunch Money transactions generated: 4 (1 transfers)\n"));
+        assert!(summary.contains("Skipped by Lunch Money as duplicates: 1\n"));
+        assert!(summary.ends_with("Net amount: -$15.00"), "{}", summary);
+    }
+
+    #[tokio::test]
+    async fn groups_payments_with_their_transfers() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"transactions": [], "has_more": false}"#,
+            ),
+            (StatusCode::OK, br#"{"ids": [101]}"#),
+            (StatusCode::OK, br#"{"ids": [102]}"#),
+            (StatusCode::OK, br#"{"ids": [103]}"#),
+            (StatusCode::OK, br#"{"ids": [104]}"#),
+            (
+                StatusCode::OK,
+                br#"{"transactions": [
+                    {"id": 101, "date": "2022-07-01", "payee": "John Smith", "amount": "25.0000", "external_id": "3581234567890123456"},
+                    {"id": 102, "date": "2022-07-03", "payee": "Alex Roe", "amount": "-80.0000", "external_id": "3581234567890123457"},
+                    {"id": 103, "date": "2022-07-03", "payee": "Chase Checking", "amount": "80.0000", "external_id": "3581234567890123457T"},
+                    {"id": 104, "date": "2022-07-05", "payee": "Chase Checking *1234", "amount": "-40.0000", "external_id": "3581234567890123458"}
+                ], "has_more": false}"#,
+            ),
+            (StatusCode::OK, b"789"),
+        ]);
+
+        let summary = insert_statement(
+            &transport,
+            &LunchMoneyDestination {
+                group_transfers: true,
+                ..destination()
+            },
+            rusty_money::iso::USD,
+            basic_statement(),
+            &[],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.grouped, 1);
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 7);
+        let body: serde_json::Value = serde_json::from_slice(&requests[6].body).unwrap();
+        assert_eq!(body["payee"], "Alex Roe");
+        assert_eq!(body["date"], "2022-07-03");
+        assert_eq!(body["transactions"], serde_json::json!([102, 103]));
+    }
+
+    #[tokio::test]
+    async fn keeps_earlier_batches_when_interrupted() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"transactions": [], "has_more": false}"#,
+            ),
+            (StatusCode::OK, br#"{"ids": [101]}"#),
+            (StatusCode::OK, br#"{"ids": [102]}"#),
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                br#"{"error": "Something went wrong."}"#,
+            ),
+        ]);
+
+        let err = insert_statement(
+            &transport,
+            &destination(),
+            rusty_money::iso::USD,
+            basic_statement(),
+            &[],
+            false,
+        )
+        .await
+        .unwrap_err();
+
+        // The first batch was the first Venmo transaction, the second its payment and transfer.
+        let partial = err.downcast_ref::<PartialInsertError>().unwrap();
+        assert_eq!(partial.inserted_ids, vec![101, 102]);
+        assert_eq!(
+            partial.inserted_external_ids,
+            vec!["3581234567890123456", "3581234567890123457"]
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! The `generate-systemd` subcommand, writing a service and timer to run syncs on a schedule.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::paths::{ensure_dir, Paths};
+
+/// Name of the generated units, without the `.service` or `.timer` suffix.
+const UNIT_NAME: &str = "lunchmoney-venmo";
+
+/// Names of the credentials the tokens are loaded as, read from `$CREDENTIALS_DIRECTORY`.
+const VENMO_CREDENTIAL: &str = "venmo-api-token";
+const LUNCH_MONEY_CREDENTIAL: &str = "lunch-money-api-token";
+
+/// What to put in the generated units.
+#[derive(Debug, Clone)]
+pub struct UnitOptions {
+    /// The binary to run, usually the one running now.
+    pub binary: PathBuf,
+    /// User to run the sync as.
+    pub user: User,
+    /// When to sync, as a systemd calendar event, e.g. `*-*-* 00/6:00:00`.
+    pub on_calendar: String,
+    /// Files with the Venmo and Lunch Money API tokens, passed to the sync with `LoadCredential`
+    /// so they're only readable by it.
+    pub venmo_api_token_file: Option<PathBuf>,
+    pub lunch_money_api_token_file: Option<PathBuf>,
+    /// Arguments to pass to `sync-venmo-transactions`.
+    pub sync_args: Vec<String>,
+}
+
+/// A user to run the sync as, from the passwd database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+impl User {
+    /// Looks up `name` with `getent`, so users from LDAP and the like are found too.
+    pub fn lookup(name: &str) -> Result<Self> {
+        let output = Command::new("getent")
+            .args(["passwd", name])
+            .output()
+            .context("Failed to run getent to look up the user")?;
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| parse_passwd_entry(line).filter(|user| user.name == name))
+            .ok_or_else(|| anyhow!("User {} does not exist", name))
+    }
+}
+
+/// Parses a passwd line, `name:password:uid:gid:gecos:home:shell`.
+fn parse_passwd_entry(line: &str) -> Option<User> {
+    match line.split(':').collect::<Vec<_>>()[..] {
+        [name, _, uid, gid, _, home, _] => Some(User {
+            name: name.to_string(),
+            uid: uid.parse().ok()?,
+            gid: gid.parse().ok()?,
+            home: PathBuf::from(home),
+        }),
+        _ => None,
+    }
+}
+
+/// Prints a hardened service and a timer that runs it on `options.on_calendar`, or writes them
+/// to `output_dir` if given. `paths` are the directories of `options.user`.
+pub fn cmd_generate_systemd(
+    paths: &Paths,
+    options: &UnitOptions,
+    output_dir: Option<&Path>,
+) -> Result<()> {
+    // Both end up in the unit as-is, a newline would start another setting.
+    for (flag, value) in [
+        ("--on-calendar", &options.on_calendar),
+        ("--user", &options.user.name),
+    ] {
+        if value.is_empty() || value.contains(|c: char| c.is_control()) {
+            bail!("{} {:?} is not valid", flag, value);
+        }
+    }
+
+    // They have to exist to be made writable, and the sync may not have created them yet. They're
+    // created as whoever runs this, e.g. root under sudo, so hand them to the sync's user.
+    ensure_dir_owned_by(&paths.state_dir, &options.user)?;
+    ensure_dir_owned_by(&paths.cache_dir, &options.user)?;
+
+    let service = service_unit(paths, options);
+    let timer = timer_unit(options);
+
+    let output_dir = match output_dir {
+        Some(output_dir) => output_dir,
+        None => {
+            println!("# {}.service\n{}", UNIT_NAME, service);
+            println!("# {}.timer\n{}", UNIT_NAME, timer);
+            return Ok(());
+        }
+    };
+
+    ensure_dir(output_dir)?;
+    for (suffix, contents) in [("service", service), ("timer", timer)] {
+        let path = output_dir.join(format!("{}.{}", UNIT_NAME, suffix));
+        fs::write(&path, contents).with_context(|| anyhow!("Failed to write {:?}", path))?;
+        println!("Wrote {:?}", path);
+    }
+
+    println!(
+        "\nEnable it with `systemctl daemon-reload && systemctl enable --now {}.timer`.",
+        UNIT_NAME
+    );
+
+    Ok(())
+}
+
+/// Creates `dir` like `ensure_dir`, making `user` the owner of the directories it creates if they
+/// aren't created as `user`.
+fn ensure_dir_owned_by(dir: &Path, user: &User) -> Result<()> {
+    let missing = dir
+        .ancestors()
+        .take_while(|dir| !dir.exists())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    ensure_dir(dir)?;
+
+    #[cfg(unix)]
+    for dir in missing.iter().rev() {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::metadata(dir).with_context(|| anyhow!("Failed to read {:?}", dir))?;
+        if metadata.uid() != user.uid {
+            std::os::unix::fs::chown(dir, Some(user.uid), Some(user.gid))
+                .with_context(|| anyhow!("Failed to make {} the owner of {:?}", user.name, dir))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (missing, user);
+
+    Ok(())
+}
+
+/// The `.service` unit, a oneshot sync with the directories in `paths`.
+fn service_unit(paths: &Paths, options: &UnitOptions) -> String {
+    let mut exec_start = vec![
+        options.binary.display().to_string(),
+        "--config-dir".to_string(),
+        paths.config_dir.display().to_string(),
+        "--cache-dir".to_string(),
+        paths.cache_dir.display().to_string(),
+        "--state-dir".to_string(),
+        paths.state_dir.display().to_string(),
+        "sync-venmo-transactions".to_string(),
+    ];
+    exec_start.extend(options.sync_args.iter().cloned());
+
+    let mut credentials = String::new();
+    for (name, env, file) in [
+        (
+            VENMO_CREDENTIAL,
+            "VENMO_API_TOKEN_FILE",
+            &options.venmo_api_token_file,
+        ),
+        (
+            LUNCH_MONEY_CREDENTIAL,
+            "LUNCHMONEY_API_TOKEN_FILE",
+            &options.lunch_money_api_token_file,
+        ),
+    ] {
+        if let Some(file) = file {
+            credentials += &format!(
+                "LoadCredential={}:{}\nEnvironment={}=%d/{}\n",
+                name,
+                escape_specifiers(&file.display().to_string()),
+                env,
+                name
+            );
+        }
+    }
+
+    format!(
+        "[Unit]
+Description=Sync Venmo transactions to Lunch Money
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+User={user}
+ExecStart={exec_start}
+{credentials}
+# Only the state and cache directories are writable, the config is read-only.
+ProtectSystem=strict
+ProtectHome=read-only
+ReadWritePaths={state_dir} {cache_dir}
+PrivateTmp=true
+PrivateDevices=true
+NoNewPrivileges=true
+CapabilityBoundingSet=
+RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX
+RestrictNamespaces=true
+RestrictRealtime=true
+RestrictSUIDSGID=true
+LockPersonality=true
+MemoryDenyWriteExecute=true
+ProtectClock=true
+ProtectHostname=true
+ProtectKernelLogs=true
+ProtectKernelModules=true
+ProtectKernelTunables=true
+ProtectControlGroups=true
+SystemCallArchitectures=native
+SystemCallFilter=@system-service
+UMask=0077
+",
+        user = options.user.name,
+        exec_start = exec_start
+            .iter()
+            .map(|arg| escape(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+        credentials = credentials,
+        state_dir = escape(&paths.state_dir.display().to_string()),
+        cache_dir = escape(&paths.cache_dir.display().to_string()),
+    )
+}
+
+/// The `.timer` unit, catching up on syncs missed while the machine was off.
+fn timer_unit(options: &UnitOptions) -> String {
+    format!(
+        "[Unit]
+Description=Sync Venmo transactions to Lunch Money on a schedule
+
+[Timer]
+OnCalendar={}
+Persistent=true
+RandomizedDelaySec=10m
+
+[Install]
+WantedBy=timers.target
+",
+        options.on_calendar
+    )
+}
+
+/// Quotes `arg` for a unit file if needed, escaping specifiers (`%`) and variables (`$`) so it's
+/// passed as-is.
+fn escape(arg: &str) -> String {
+    let escaped = escape_specifiers(arg).replace('$', "$$");
+    let needs_quotes = |c: char| c.is_whitespace() || "\"'\\;".contains(c);
+
+    if escaped.is_empty() || escaped.contains(needs_quotes) {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+/// Escapes specifiers (`%`) in `value`, for settings that take the rest of the line as-is.
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> UnitOptions {
+        UnitOptions {
+            binary: PathBuf::from("/usr/local/bin/lunchmoney-venmo"),
+            user: User {
+                name: "alex".to_string(),
+                uid: 1000,
+                gid: 1000,
+                home: PathBuf::from("/home/alex"),
+            },
+            on_calendar: "daily".to_string(),
+            venmo_api_token_file: Some(PathBuf::from("/etc/lunchmoney-venmo/venmo token")),
+            lunch_money_api_token_file: None,
+            sync_args: vec!["--tag".to_string(), "50% off".to_string()],
+        }
+    }
+
+    #[test]
+    fn generates_service() {
+        let paths = Paths::for_home(Path::new("/home/alex"), None, None, None);
+
+        let service = service_unit(&paths, &options());
+
+        assert!(service.contains("User=alex\n"));
+        assert!(service.contains(
+            "ExecStart=/usr/local/bin/lunchmoney-venmo \
+             --config-dir /home/alex/.config/lunchmoney-venmo-syncer \
+             --cache-dir /home/alex/.cache/lunchmoney-venmo-syncer \
+             --state-dir /home/alex/.local/state/lunchmoney-venmo-syncer \
+             sync-venmo-transactions --tag \"50%% off\"\n"
+        ));
+        assert!(service.contains(
+            "LoadCredential=venmo-api-token:/etc/lunchmoney-venmo/venmo token\n\
+             Environment=VENMO_API_TOKEN_FILE=%d/venmo-api-token\n"
+        ));
+        assert!(!service.contains("LUNCHMONEY_API_TOKEN_FILE"));
+        assert!(service.contains(
+            "ReadWritePaths=/home/alex/.local/state/lunchmoney-venmo-syncer \
+             /home/alex/.cache/lunchmoney-venmo-syncer\n"
+        ));
+    }
+
+    #[test]
+    fn generates_timer() {
+        let timer = timer_unit(&options());
+
+        assert!(timer.contains("OnCalendar=daily\n"));
+        assert!(timer.contains("WantedBy=timers.target\n"));
+    }
+
+    #[test]
+    fn escapes_arguments() {
+        assert_eq!(escape("/usr/bin/sync"), "/usr/bin/sync");
+        assert_eq!(escape("$HOME"), "$$HOME");
+        assert_eq!(escape("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(escape(""), "\"\"");
+    }
+
+    #[test]
+    fn parses_passwd_entries() {
+        assert_eq!(
+            parse_passwd_entry("alex:x:1000:1001:Alex,,,:/home/alex:/bin/bash"),
+            Some(User {
+                name: "alex".to_string(),
+                uid: 1000,
+                gid: 1001,
+                home: PathBuf::from("/home/alex"),
+            })
+        );
+        assert_eq!(parse_passwd_entry("alex:x:1000"), None);
+    }
+
+    #[test]
+    fn rejects_settings_with_newlines() {
+        let options = UnitOptions {
+            on_calendar: "daily\nExecStartPre=/bin/sh".to_string(),
+            ..options()
+        };
+
+        let err = cmd_generate_systemd(
+            &Paths::for_home(Path::new("/nonexistent"), None, None, None),
+            &options,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("--on-calendar"));
+    }
+}
@@ -0,0 +1,56 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::Path;
+
+use chrono::DateTime;
+use rust_decimal::Decimal;
+use tempfile::TempDir;
+
+use crate::paths::Paths;
+use crate::types::venmo::{Amount, Transaction, TransactionStatus, TransactionType};
+
+/// An amount in dollars, as Venmo statements write them.
+pub fn amount(val: Decimal) -> Amount {
+    Amount {
+        currency: "$".to_string(),
+        val,
+    }
+}
+
+/// A completed transaction from Jane Doe to John Smith for dinner, on July 1st 2022. Tests
+/// override the fields they care about.
+pub fn transaction(id: u64, type_: TransactionType, val: Decimal) -> Transaction {
+    Transaction {
+        id,
+        datetime: DateTime::parse_from_rfc3339("2022-07-01T18:30:12Z").unwrap(),
+        type_,
+        status: TransactionStatus::Complete,
+        note: Some("Dinner".to_string()),
+        from: Some("Jane Doe".to_string()),
+        to: Some("John Smith".to_string()),
+        amount_total: amount(val),
+        amount_tip: None,
+        amount_tax: None,
+        amount_fee: None,
+        funding_source: None,
+        destination: None,
+        external_id: None,
+    }
+}
+
+/// A fresh scratch directory, removed when the returned `TempDir` is dropped.
+pub fn temp_dir() -> TempDir {
+    tempfile::Builder::new()
+        .prefix("lunchmoney-venmo-syncer-test-")
+        .tempdir()
+        .unwrap()
+}
+
+/// Paths keeping config, cache, and state all in `dir`.
+pub fn paths(dir: &Path) -> Paths {
+    Paths {
+        config_dir: dir.to_path_buf(),
+        cache_dir: dir.to_path_buf(),
+        state_dir: dir.to_path_buf(),
+    }
+}
@@ -1,21 +1,40 @@
+//! Lunch Money API objects, see <https://lunchmoney.dev>.
+
 use std::fmt;
-use std::num::ParseFloatError;
 use std::str::FromStr;
-use std::time::UNIX_EPOCH;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, skip_serializing_none, DisplayFromStr};
 
-/// Tag object as described in https://lunchmoney.dev/#tags-object.
-#[derive(Debug, Serialize)]
+/// Tag object as described in <https://lunchmoney.dev/#tags-object>.
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Tag {
     pub id: u64,
     pub name: String,
-    pub description: String,
+    pub description: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// A tag to attach to an inserted transaction, by ID or by name. The insert endpoint creates tags
+/// given by name if they don't exist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum TagRef {
+    Id(u64),
+    Name(String),
+}
+
+impl TagRef {
+    pub fn id(&self) -> Option<u64> {
+        match *self {
+            TagRef::Id(id) => Some(id),
+            TagRef::Name(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum TransactionStatus {
     Cleared,
@@ -24,16 +43,16 @@ pub enum TransactionStatus {
     RecurringSuggested,
 }
 
-/// An f64 that serializes to a float up to 4 decimal places, as specified in the `Transaction`
-/// amount field description in https://lunchmoney.dev/#transaction-object.
+/// A decimal amount that serializes to a string with 4 decimal places, as specified in the
+/// `Transaction` amount field description in <https://lunchmoney.dev/#transaction-object>.
 #[derive(Debug)]
-pub struct Amount(pub f64);
+pub struct Amount(pub Decimal);
 
 impl FromStr for Amount {
-    type Err = ParseFloatError;
+    type Err = rust_decimal::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Amount(s.parse::<f64>()?))
+        Ok(Amount(s.parse::<Decimal>()?))
     }
 }
 
@@ -43,19 +62,20 @@ impl fmt::Display for Amount {
     }
 }
 
-impl From<f64> for Amount {
-    fn from(val: f64) -> Self {
+impl From<Decimal> for Amount {
+    fn from(val: Decimal) -> Self {
         Amount(val)
     }
 }
 
-/// Transaction object as defined in https://lunchmoney.dev/#transaction-object
+/// Transaction object as defined in <https://lunchmoney.dev/#transaction-object>
 #[serde_as]
 #[skip_serializing_none]
 #[derive(Debug, Serialize)]
 pub struct Transaction {
     pub id: Option<u64>,
-    pub date: DateTime<Utc>,
+    /// Lunch Money transactions only have a date, in the user's timezone.
+    pub date: NaiveDate,
     pub payee: Option<String>,
     #[serde_as(as = "DisplayFromStr")]
     pub amount: Amount,
@@ -67,7 +87,8 @@ pub struct Transaction {
     pub parent_id: Option<u64>,
     pub is_group: Option<bool>,
     pub group_id: Option<u64>,
-    pub tags: Option<Vec<Tag>>,
+    /// Tags to attach. The insert endpoint takes tag IDs or names rather than tag objects.
+    pub tags: Option<Vec<TagRef>>,
     pub external_id: Option<String>,
     pub original_name: Option<String>,
 }
@@ -76,9 +97,9 @@ impl Default for Transaction {
     fn default() -> Self {
         Self {
             id: None,
-            date: UNIX_EPOCH.into(),
+            date: NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
             payee: None,
-            amount: Amount(0.0),
+            amount: Amount(Decimal::ZERO),
             currency: None,
             notes: None,
             category_id: None,
@@ -95,7 +116,7 @@ impl Default for Transaction {
 }
 
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Asset {
     pub id: u64,
     #[serde(rename = "type_name")]
@@ -114,11 +135,74 @@ pub struct Asset {
     pub created_at: DateTime<Utc>,
 }
 
+/// Request body of <https://lunchmoney.dev/#create-asset>.
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct CreateAssetRequest {
+    /// E.g. `cash`, `credit`, or `other asset`.
+    pub type_name: String,
+    /// E.g. `digital wallet` for `cash`.
+    pub subtype_name: Option<String>,
+    pub name: String,
+    pub display_name: Option<String>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub balance: Amount,
+    /// Lowercase ISO currency code, defaults to the account's primary currency.
+    pub currency: Option<String>,
+    pub institution_name: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GetAllAssetsResponse {
     pub assets: Vec<Asset>,
 }
 
+/// Category object as described in <https://lunchmoney.dev/#categories-object>.
+#[derive(Debug, Deserialize)]
+pub struct Category {
+    pub id: u64,
+    pub name: String,
+    pub description: Option<String>,
+    pub is_income: bool,
+    pub exclude_from_budget: bool,
+    pub exclude_from_totals: bool,
+    pub is_group: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetAllCategoriesResponse {
+    pub categories: Vec<Category>,
+}
+
+/// A transaction as returned by <https://lunchmoney.dev/#get-all-transactions>, with only the
+/// fields needed to compare it to a synced Venmo transaction and list it.
+#[serde_as]
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExistingTransaction {
+    pub id: u64,
+    pub date: NaiveDate,
+    pub payee: Option<String>,
+    /// Unlike when inserting with `debit_as_negative`, expenses are positive.
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: Amount,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// `cleared`, `uncleared`, or `pending`.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<Tag>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetAllTransactionsResponse {
+    pub transactions: Vec<ExistingTransaction>,
+    #[serde(default)]
+    pub has_more: bool,
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Serialize)]
 pub struct InsertTransactionRequest {
@@ -134,3 +218,46 @@ pub struct InsertTransactionRequest {
 pub struct InsertTransactionResponse {
     pub ids: Vec<u64>,
 }
+
+/// The fields of a transaction to change, as taken by
+/// <https://lunchmoney.dev/#update-transaction>.
+#[skip_serializing_none]
+#[derive(Debug, Default, Serialize)]
+pub struct TransactionUpdate {
+    pub status: Option<TransactionStatus>,
+    pub notes: Option<String>,
+    /// Names of tags to set, replacing existing ones. Missing tags are created.
+    pub tags: Option<Vec<String>>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct UpdateTransactionRequest {
+    pub transaction: TransactionUpdate,
+    pub skip_balance_update: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTransactionResponse {
+    pub updated: bool,
+}
+
+/// Request body of <https://lunchmoney.dev/#create-transaction-group>.
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct CreateTransactionGroupRequest {
+    pub date: NaiveDate,
+    pub payee: String,
+    pub category_id: Option<u64>,
+    pub notes: Option<String>,
+    /// IDs of tags to attach.
+    pub tags: Option<Vec<u64>>,
+    /// IDs of the transactions to group.
+    pub transactions: Vec<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteTransactionGroupResponse {
+    /// IDs of the transactions that were in the group.
+    pub transactions: Vec<u64>,
+}
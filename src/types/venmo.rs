@@ -1,42 +1,157 @@
+//! Venmo statement records, and their conversion to Lunch Money transactions.
+
+use std::collections::BTreeMap;
 use std::fmt;
 use std::str::FromStr;
 
-use chrono::{offset::TimeZone, DateTime, NaiveDateTime, Utc};
+use chrono::offset::{Local, LocalResult, Offset, TimeZone};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
 use lazy_static::lazy_static;
 use regex::Regex;
+use rust_decimal::Decimal;
 use rusty_money::iso::Currency;
-use serde::Deserialize;
-use serde_with::{serde_as, DisplayFromStr};
-use thiserror::Error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr, SerializeDisplay};
 
 use super::lunchmoney;
 
-#[derive(Error, Debug)]
-pub enum Error {
+/// A Venmo statement, record, or statement format setting that failed to parse.
+#[allow(clippy::enum_variant_names)]
+#[derive(thiserror::Error, Debug)]
+pub enum StatementError {
     #[error("unexpected Venmo transaction type: {0}")]
     ParseTransactionTypeError(String),
     #[error("unexpected Venmo transaction status: {0}")]
     ParseStatusError(String),
     #[error("failed to parse Venmo amount: {0}")]
     ParseAmountError(String),
+    #[error("failed to parse Venmo datetime {0} with format {1}")]
+    ParseDatetimeError(String, String),
+    #[error("unknown timezone {0}, expected `local` or an IANA name like `America/New_York`")]
+    ParseTimezoneError(String),
+    #[error("unknown account type {0}, expected `personal` or `business`")]
+    ParseAccountTypeError(String),
+    #[error("invalid template {0:?}: {1}")]
+    ParseTemplateError(String, String),
     #[error("expected currency marker {0} for {1}, got {2} from Venmo")]
     WrongCurrencyError(String, String, String),
     #[error("expected field {0} to be defined on record {1:?}")]
-    InvalidRecord(String, TransactionRecord),
+    InvalidRecord(String, Box<TransactionRecord>),
     #[error("expected field {0} to be defined due to {1} on record {2:?}")]
-    InvalidTransaction(String, String, Transaction),
+    InvalidTransaction(String, String, Box<Transaction>),
+    #[error("Venmo changed their CSV statement format, missing columns: {0:?}, unrecognized columns: {1:?}")]
+    UnknownStatementFormat(Vec<String>, Vec<String>),
+    #[error("failed to read Venmo statement")]
+    ReadError(#[source] csv::Error),
+    #[error("failed to parse Venmo statement record")]
+    ParseRecordError(#[source] csv::Error),
+    #[error("failed to find the header row in Venmo statement:\n{0}")]
+    MissingHeaderRow(String),
+    #[error("expected there to be a {0} record, found none")]
+    MissingBalanceRecord(String),
+    #[error("transaction {0} has a type this tool doesn't support yet ({1}), pass --lenient to sync it like a payment")]
+    UnsupportedTransactionType(u64, String),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// A statement's `Transaction Type`. Serialized as it appears in statements.
+#[derive(Debug, Clone, PartialEq, Eq, SerializeDisplay)]
 pub enum TransactionType {
     Charge,
     Payment,
     StandardTransfer,
     MerchantTransaction,
+    DirectDeposit,
+    CreditCardPayment,
+    /// A purchase with the Venmo Credit Card, which doesn't touch the Venmo balance.
+    CreditCardPurchase,
+    Disbursement,
+    Refund,
+    Cashback,
+    /// A type this tool doesn't know about yet. Statements containing one are only parsed when
+    /// `StatementFormat::lenient` is set.
+    Unknown(String),
+}
+
+impl TransactionType {
+    /// Every type this tool knows about.
+    pub const KNOWN: &'static [TransactionType] = &[
+        TransactionType::Charge,
+        TransactionType::Payment,
+        TransactionType::StandardTransfer,
+        TransactionType::MerchantTransaction,
+        TransactionType::DirectDeposit,
+        TransactionType::CreditCardPayment,
+        TransactionType::CreditCardPurchase,
+        TransactionType::Disbursement,
+        TransactionType::Refund,
+        TransactionType::Cashback,
+    ];
+
+    pub fn is_known(&self) -> bool {
+        !matches!(self, TransactionType::Unknown(_))
+    }
+
+    /// Parses a type as given in a flag or config file, either as it appears in statements
+    /// (`Standard Transfer`) or as its variant name (`StandardTransfer`), ignoring case. Other
+    /// names are taken to be types this tool doesn't know about yet.
+    pub fn parse_name(name: &str) -> Result<Self, StatementError> {
+        let squashed = name.replace(' ', "");
+
+        match Self::KNOWN.iter().find(|type_| {
+            type_
+                .to_string()
+                .replace(' ', "")
+                .eq_ignore_ascii_case(&squashed)
+        }) {
+            Some(type_) => Ok(type_.clone()),
+            None => name.trim().parse(),
+        }
+    }
+}
+
+/// Which Venmo transactions to sync, see `sync.include_types`, `sync.exclude_types`,
+/// `sync.exclude_payee_regex`, `sync.exclude_note_regex`, `sync.min_amount`, and
+/// `sync.max_amount`.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    /// Only sync transactions of these types, unless empty.
+    pub include_types: Vec<TransactionType>,
+    /// Never sync transactions of these types.
+    pub exclude_types: Vec<TransactionType>,
+    /// Never sync transactions whose counterparty matches one of these, see
+    /// `Transaction::counterparty`.
+    pub exclude_payees: Vec<Regex>,
+    /// Never sync transactions whose note matches one of these.
+    pub exclude_notes: Vec<Regex>,
+    /// Never sync transactions for less than this, whether sent or received.
+    pub min_amount: Option<Decimal>,
+    /// Never sync transactions for more than this, whether sent or received.
+    pub max_amount: Option<Decimal>,
+}
+
+impl TransactionFilter {
+    /// Whether `transaction` is synced.
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        let type_ = &transaction.type_;
+        let amount = transaction.amount_total.val.abs();
+        let matches_any = |regexes: &[Regex], val: Option<&str>| {
+            val.is_some_and(|val| regexes.iter().any(|regex| regex.is_match(val)))
+        };
+
+        (self.include_types.is_empty() || self.include_types.contains(type_))
+            && !self.exclude_types.contains(type_)
+            && !matches_any(&self.exclude_payees, transaction.counterparty())
+            && !matches_any(&self.exclude_notes, transaction.note.as_deref())
+            && !matches!(self.min_amount, Some(min) if amount < min)
+            && !matches!(self.max_amount, Some(max) if amount > max)
+    }
 }
 
 impl FromStr for TransactionType {
-    type Err = Error;
+    type Err = StatementError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
@@ -44,41 +159,113 @@ impl FromStr for TransactionType {
             "Payment" => TransactionType::Payment,
             "Standard Transfer" => TransactionType::StandardTransfer,
             "Merchant Transaction" => TransactionType::MerchantTransaction,
-            _ => {
-                return Err(Error::ParseTransactionTypeError(s.to_string()));
+            "Direct Deposit" => TransactionType::DirectDeposit,
+            "Credit Card Payment" => TransactionType::CreditCardPayment,
+            "Credit Card Purchase" => TransactionType::CreditCardPurchase,
+            "Disbursement" => TransactionType::Disbursement,
+            "Refund" => TransactionType::Refund,
+            "Cashback" => TransactionType::Cashback,
+            "" => {
+                return Err(StatementError::ParseTransactionTypeError(s.to_string()));
             }
+            _ => TransactionType::Unknown(s.to_string()),
         })
     }
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransactionType::Charge => "Charge",
+            TransactionType::Payment => "Payment",
+            TransactionType::StandardTransfer => "Standard Transfer",
+            TransactionType::MerchantTransaction => "Merchant Transaction",
+            TransactionType::DirectDeposit => "Direct Deposit",
+            TransactionType::CreditCardPayment => "Credit Card Payment",
+            TransactionType::CreditCardPurchase => "Credit Card Purchase",
+            TransactionType::Disbursement => "Disbursement",
+            TransactionType::Refund => "Refund",
+            TransactionType::Cashback => "Cashback",
+            TransactionType::Unknown(type_) => type_,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum TransactionStatus {
     Complete,
     Issued,
+    Pending,
+    Failed,
+    Cancelled,
+}
+
+impl TransactionStatus {
+    /// Statuses of transactions that never moved money, and so aren't synced unless
+    /// `sync.skip_statuses` says otherwise.
+    pub const DEFAULT_SKIPPED: &'static [TransactionStatus] =
+        &[TransactionStatus::Failed, TransactionStatus::Cancelled];
+
+    /// The status to insert transactions with this status into Lunch Money as, unless
+    /// `sync.cleared_statuses` says otherwise. Lunch Money only takes `cleared` and `uncleared`
+    /// on insert, and cleared is for transactions the user has reviewed, so every status is
+    /// inserted uncleared.
+    pub fn to_lunchmoney_status(&self) -> lunchmoney::TransactionStatus {
+        match self {
+            TransactionStatus::Complete
+            | TransactionStatus::Issued
+            | TransactionStatus::Pending
+            | TransactionStatus::Failed
+            | TransactionStatus::Cancelled => lunchmoney::TransactionStatus::Uncleared,
+        }
+    }
 }
 
 impl FromStr for TransactionStatus {
-    type Err = Error;
+    type Err = StatementError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
             "Complete" => TransactionStatus::Complete,
             "Issued" => TransactionStatus::Issued,
+            "Pending" => TransactionStatus::Pending,
+            "Failed" => TransactionStatus::Failed,
+            // Venmo has used both spellings.
+            "Cancelled" | "Canceled" => TransactionStatus::Cancelled,
             _ => {
-                return Err(Error::ParseStatusError(s.to_string()));
+                return Err(StatementError::ParseStatusError(s.to_string()));
             }
         })
     }
 }
 
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransactionStatus::Complete => "Complete",
+            TransactionStatus::Issued => "Issued",
+            TransactionStatus::Pending => "Pending",
+            TransactionStatus::Failed => "Failed",
+            TransactionStatus::Cancelled => "Cancelled",
+        })
+    }
+}
+
 lazy_static! {
-    static ref VENMO_AMOUNT_RE: Regex = Regex::new(r"^([-+]?)[ ]?([^0-9])([0-9.,]+)$").unwrap();
+    /// A sign, a currency symbol before or after the number, and the number with optional
+    /// grouping separators (`,`, `'`, or spaces), e.g. `+ $1,234.56`, `- CA$ 5.00`, or
+    /// `- 1 234.56 kr`.
+    static ref VENMO_AMOUNT_RE: Regex = Regex::new(
+        r"^(?P<sign>[-+]?)\s*(?P<prefix>[^0-9\s.,+-]*)\s*(?P<number>[0-9][0-9,'\s]*(?:\.[0-9]+)?)\s*(?P<suffix>[^0-9\s.,+-]*)$"
+    )
+    .unwrap();
 }
 
-#[derive(Debug, Clone)]
+/// An amount as formatted in Venmo statements, e.g. `- $1,234.56`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Amount {
     pub currency: String,
-    pub val: f64,
+    pub val: Decimal,
 }
 
 impl fmt::Display for Amount {
@@ -94,26 +281,583 @@ impl fmt::Display for Amount {
 }
 
 impl FromStr for Amount {
-    type Err = Error;
+    type Err = StatementError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some(captures) = VENMO_AMOUNT_RE.captures(s) {
-            Ok(Amount {
-                currency: captures.get(2).unwrap().as_str().to_string(),
-                val: format!(
-                    "{}{}",
-                    captures.get(1).unwrap().as_str(),
-                    captures.get(3).unwrap().as_str().replace(",", "")
-                )
+        let captures = VENMO_AMOUNT_RE
+            .captures(s.trim())
+            .ok_or_else(|| StatementError::ParseAmountError(s.to_string()))?;
+
+        let currency = match (&captures["prefix"], &captures["suffix"]) {
+            (prefix, "") if !prefix.is_empty() => prefix,
+            ("", suffix) if !suffix.is_empty() => suffix,
+            _ => return Err(StatementError::ParseAmountError(s.to_string())),
+        };
+
+        let number: String = captures["number"]
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+
+        Ok(Amount {
+            currency: currency.to_string(),
+            val: format!("{}{}", &captures["sign"], number)
                 .parse()
-                .map_err(|_| Error::ParseAmountError(s.to_string()))?,
-            })
+                .map_err(|_| StatementError::ParseAmountError(s.to_string()))?,
+        })
+    }
+}
+
+/// Columns of each known version of Venmo's statement CSV, oldest first.
+const STATEMENT_SCHEMAS: &[(&str, &[&str])] = &[
+    (
+        "2022",
+        &[
+            "ID",
+            "Datetime",
+            "Type",
+            "Status",
+            "Note",
+            "From",
+            "To",
+            "Amount (total)",
+            "Amount (tip)",
+            "Amount (fee)",
+            "Funding Source",
+            "Destination",
+            "Beginning Balance",
+            "Ending Balance",
+            "Statement Period Venmo Fees",
+            "Terminal Location",
+            "Year to Date Venmo Fees",
+            "Disclaimer",
+        ],
+    ),
+    (
+        "2023",
+        &[
+            "ID",
+            "Datetime",
+            "Type",
+            "Status",
+            "Note",
+            "From",
+            "To",
+            "Amount (total)",
+            "Amount (tip)",
+            "Amount (tax)",
+            "Amount (fee)",
+            "Tax Rate",
+            "Tax Exempt",
+            "Funding Source",
+            "Destination",
+            "Beginning Balance",
+            "Ending Balance",
+            "Statement Period Venmo Fees",
+            "Terminal Location",
+            "Year to Date Venmo Fees",
+            "Disclaimer",
+        ],
+    ),
+    (
+        "2023-business",
+        &[
+            "ID",
+            "Datetime",
+            "Type",
+            "Status",
+            "Note",
+            "From",
+            "To",
+            "Amount (total)",
+            "Amount (tip)",
+            "Amount (tax)",
+            "Amount (fee)",
+            "Amount (purchase fee)",
+            "Amount (net)",
+            "Tax Rate",
+            "Tax Exempt",
+            "Funding Source",
+            "Destination",
+            "Beginning Balance",
+            "Ending Balance",
+            "Statement Period Venmo Fees",
+            "Terminal Location",
+            "Year to Date Venmo Fees",
+            "Disclaimer",
+        ],
+    ),
+];
+
+/// Columns a statement can't be parsed without.
+const REQUIRED_COLUMNS: &[&str] = &[
+    "ID",
+    "Datetime",
+    "Type",
+    "Status",
+    "Amount (total)",
+    "Beginning Balance",
+    "Ending Balance",
+];
+
+/// Whether `column` is part of any known statement format.
+pub fn is_known_column(column: &str) -> bool {
+    STATEMENT_SCHEMAS
+        .iter()
+        .any(|(_, columns)| columns.contains(&column))
+}
+
+/// Columns holding `Amount`s.
+const AMOUNT_COLUMNS: &[&str] = &[
+    "Amount (total)",
+    "Amount (tip)",
+    "Amount (tax)",
+    "Amount (fee)",
+    "Amount (purchase fee)",
+    "Amount (net)",
+    "Beginning Balance",
+    "Ending Balance",
+    "Statement Period Venmo Fees",
+    "Year to Date Venmo Fees",
+];
+
+/// Statement URL templates tried in order when `StatementFormat::endpoints` isn't set. Venmo has
+/// moved the statement between hosts before.
+pub const DEFAULT_STATEMENT_ENDPOINTS: &[&str] = &[
+    "https://venmo.com/transaction-history/statement?startDate={start_date}&endDate={end_date}&profileId={profile_id}&accountType={account_type}",
+    "https://account.venmo.com/api/statement/download?startDate={start_date_iso}&endDate={end_date_iso}&profileId={profile_id}&accountType={account_type}&csv=true",
+];
+
+/// Timezone of the datetimes in a statement, which don't say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    /// The timezone of the machine running this.
+    Local,
+    Named(Tz),
+}
+
+impl Timezone {
+    /// Attaches this timezone to `datetime`. Datetimes skipped by a DST change are taken as
+    /// UTC, and ones repeated by one as the earlier of the two.
+    pub fn localize(&self, datetime: &NaiveDateTime) -> DateTime<FixedOffset> {
+        fn localize<T: TimeZone>(tz: &T, datetime: &NaiveDateTime) -> DateTime<FixedOffset> {
+            match tz.from_local_datetime(datetime) {
+                LocalResult::Single(localized) | LocalResult::Ambiguous(localized, _) => {
+                    localized.with_timezone(&localized.offset().fix())
+                }
+                LocalResult::None => Utc.from_utc_datetime(datetime).into(),
+            }
+        }
+
+        match self {
+            Timezone::Local => localize(&Local, datetime),
+            Timezone::Named(tz) => localize(tz, datetime),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = StatementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Timezone::Local);
+        }
+
+        s.parse()
+            .map(Timezone::Named)
+            .map_err(|_| StatementError::ParseTimezoneError(s.to_string()))
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timezone::Local => f.write_str("local"),
+            Timezone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+/// Kind of Venmo profile a statement is for, business profiles (including charities) have their
+/// own statements with extra columns.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    #[default]
+    Personal,
+    Business,
+}
+
+impl FromStr for AccountType {
+    type Err = StatementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "personal" => Ok(AccountType::Personal),
+            "business" => Ok(AccountType::Business),
+            _ => Err(StatementError::ParseAccountTypeError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for AccountType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountType::Personal => f.write_str("personal"),
+            AccountType::Business => f.write_str("business"),
+        }
+    }
+}
+
+/// Locales Venmo statements can be downloaded in, each with its own column names, datetime
+/// format, and decimal separator, see `LocaleProfile`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "en-US")]
+    EnUs,
+    #[serde(rename = "en-GB")]
+    EnGb,
+    #[serde(rename = "es-ES")]
+    EsEs,
+    #[serde(rename = "fr-FR")]
+    FrFr,
+    #[serde(rename = "de-DE")]
+    DeDe,
+}
+
+/// How statements in a `Locale` differ from Venmo's usual (US English) format.
+#[derive(Debug)]
+pub struct LocaleProfile {
+    /// Translated column names, mapped to the names this tool expects.
+    pub headers: &'static [(&'static str, &'static str)],
+    /// strftime-style format of the `Datetime` column, ISO 8601 if `None`.
+    pub date_format: Option<&'static str>,
+    pub decimal_separator: char,
+}
+
+const SPANISH_HEADERS: &[(&str, &str)] = &[
+    ("Fecha y hora", "Datetime"),
+    ("Tipo", "Type"),
+    ("Estado", "Status"),
+    ("Nota", "Note"),
+    ("De", "From"),
+    ("Para", "To"),
+    ("Importe (total)", "Amount (total)"),
+    ("Importe (propina)", "Amount (tip)"),
+    ("Importe (impuesto)", "Amount (tax)"),
+    ("Importe (comisión)", "Amount (fee)"),
+    ("Tipo impositivo", "Tax Rate"),
+    ("Exento de impuestos", "Tax Exempt"),
+    ("Fuente de fondos", "Funding Source"),
+    ("Destino", "Destination"),
+    ("Saldo inicial", "Beginning Balance"),
+    ("Saldo final", "Ending Balance"),
+    (
+        "Comisiones de Venmo del período",
+        "Statement Period Venmo Fees",
+    ),
+    ("Ubicación del terminal", "Terminal Location"),
+    ("Comisiones de Venmo en el año", "Year to Date Venmo Fees"),
+    ("Aviso legal", "Disclaimer"),
+];
+
+const FRENCH_HEADERS: &[(&str, &str)] = &[
+    ("Date et heure", "Datetime"),
+    ("Statut", "Status"),
+    ("De", "From"),
+    ("À", "To"),
+    ("Montant (total)", "Amount (total)"),
+    ("Montant (pourboire)", "Amount (tip)"),
+    ("Montant (taxe)", "Amount (tax)"),
+    ("Montant (frais)", "Amount (fee)"),
+    ("Taux de taxe", "Tax Rate"),
+    ("Exonéré de taxe", "Tax Exempt"),
+    ("Source de financement", "Funding Source"),
+    ("Solde initial", "Beginning Balance"),
+    ("Solde final", "Ending Balance"),
+    ("Frais Venmo de la période", "Statement Period Venmo Fees"),
+    ("Emplacement du terminal", "Terminal Location"),
+    (
+        "Frais Venmo depuis le début de l'année",
+        "Year to Date Venmo Fees",
+    ),
+    ("Avertissement", "Disclaimer"),
+];
+
+const GERMAN_HEADERS: &[(&str, &str)] = &[
+    ("Datum und Uhrzeit", "Datetime"),
+    ("Typ", "Type"),
+    ("Notiz", "Note"),
+    ("Von", "From"),
+    ("An", "To"),
+    ("Betrag (gesamt)", "Amount (total)"),
+    ("Betrag (Trinkgeld)", "Amount (tip)"),
+    ("Betrag (Steuer)", "Amount (tax)"),
+    ("Betrag (Gebühr)", "Amount (fee)"),
+    ("Steuersatz", "Tax Rate"),
+    ("Steuerbefreit", "Tax Exempt"),
+    ("Zahlungsquelle", "Funding Source"),
+    ("Ziel", "Destination"),
+    ("Anfangssaldo", "Beginning Balance"),
+    ("Endsaldo", "Ending Balance"),
+    (
+        "Venmo-Gebühren im Abrechnungszeitraum",
+        "Statement Period Venmo Fees",
+    ),
+    ("Terminal-Standort", "Terminal Location"),
+    (
+        "Venmo-Gebühren seit Jahresbeginn",
+        "Year to Date Venmo Fees",
+    ),
+    ("Haftungsausschluss", "Disclaimer"),
+];
+
+impl Locale {
+    pub fn profile(&self) -> LocaleProfile {
+        match self {
+            Locale::EnUs => LocaleProfile {
+                headers: &[],
+                date_format: None,
+                decimal_separator: '.',
+            },
+            Locale::EnGb => LocaleProfile {
+                headers: &[],
+                date_format: Some("%d/%m/%Y %H:%M:%S"),
+                decimal_separator: '.',
+            },
+            Locale::EsEs => LocaleProfile {
+                headers: SPANISH_HEADERS,
+                date_format: Some("%d/%m/%Y %H:%M:%S"),
+                decimal_separator: ',',
+            },
+            Locale::FrFr => LocaleProfile {
+                headers: FRENCH_HEADERS,
+                date_format: Some("%d/%m/%Y %H:%M:%S"),
+                decimal_separator: ',',
+            },
+            Locale::DeDe => LocaleProfile {
+                headers: GERMAN_HEADERS,
+                date_format: Some("%d.%m.%Y %H:%M:%S"),
+                decimal_separator: ',',
+            },
+        }
+    }
+}
+
+/// Overrides for fetching and parsing statements that don't match Venmo's usual (US English)
+/// format, e.g. statements downloaded with a different locale or to work around Venmo renaming a
+/// column or moving the statement endpoint before a new release is out.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StatementFormat {
+    /// Renames statement columns before parsing, from the name in the statement to the name
+    /// this tool expects, e.g. `"Amount (Total)" = "Amount (total)"` or `"Fecha" = "Datetime"`.
+    /// Takes precedence over `locale`'s column names.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Locale the statements were downloaded in, e.g. `de-DE`, setting the column names,
+    /// `date_format`, and `decimal_separator` it uses. Defaults to `en-US`.
+    pub locale: Option<Locale>,
+    /// strftime-style format of the `Datetime` column, e.g. `%d/%m/%Y %H:%M:%S`. Defaults to
+    /// `locale`'s.
+    pub date_format: Option<String>,
+    /// Character separating whole and fractional amounts, e.g. `,` for `1.234,56`. Defaults to
+    /// `locale`'s.
+    pub decimal_separator: Option<char>,
+    /// Timezone of the `Datetime` column, `local` or an IANA name like `America/New_York`.
+    /// Defaults to `local`.
+    pub timezone: Option<String>,
+    /// Kind of Venmo profile to fetch statements for, `personal` or `business`. Defaults to
+    /// `personal`.
+    pub account_type: Option<AccountType>,
+    /// Statement URL templates to try in order, the first that returns a statement is used.
+    /// `{profile_id}`, `{account_type}`, `{start_date}`/`{end_date}` (`MM-DD-YYYY`), and
+    /// `{start_date_iso}`/`{end_date_iso}` (`YYYY-MM-DD`) are filled in. Defaults to
+    /// `DEFAULT_STATEMENT_ENDPOINTS`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<String>,
+    /// Parse transactions with a `Transaction Type` this tool doesn't know instead of failing
+    /// the whole statement. They're synced like payments, with a warning.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lenient: bool,
+    /// `User-Agent` sent with statement and login requests, e.g. a browser's if Venmo starts
+    /// treating clients differently by it.
+    pub user_agent: Option<String>,
+    /// Extra headers sent with statement and login requests, by name, replacing ones this tool
+    /// sets.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub request_headers: BTreeMap<String, String>,
+}
+
+impl StatementFormat {
+    /// The statement URL templates to try, in order.
+    pub fn endpoints(&self) -> Vec<&str> {
+        if self.endpoints.is_empty() {
+            DEFAULT_STATEMENT_ENDPOINTS.to_vec()
         } else {
-            Err(Error::ParseAmountError(s.to_string()))
+            self.endpoints.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// Parses `timezone`, defaulting to the local timezone.
+    pub fn timezone(&self) -> Result<Timezone, StatementError> {
+        self.timezone
+            .as_deref()
+            .map(Timezone::from_str)
+            .unwrap_or(Ok(Timezone::Local))
+    }
+
+    /// `user_agent` and `request_headers` as headers, and a problem for each that isn't a valid
+    /// header, which is left out.
+    pub fn request_headers(&self) -> (HeaderMap, Vec<String>) {
+        let mut headers = HeaderMap::new();
+        let mut problems = Vec::new();
+
+        let user_agent = self
+            .user_agent
+            .as_ref()
+            .map(|user_agent| (USER_AGENT.as_str(), user_agent));
+
+        for (name, value) in user_agent.into_iter().chain(
+            self.request_headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value)),
+        ) {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                (Err(_), _) => problems.push(format!("invalid header name '{}'", name)),
+                (_, Err(_)) => problems.push(format!("invalid value for header '{}'", name)),
+            }
+        }
+
+        (headers, problems)
+    }
+
+    /// Applies the configured column renames, then `locale`'s, to a statement's header row.
+    pub fn map_headers(&self, headers: &csv::StringRecord) -> csv::StringRecord {
+        let locale = self.locale.unwrap_or_default().profile();
+
+        headers
+            .iter()
+            .map(|header| {
+                self.headers
+                    .get(header)
+                    .map(String::as_str)
+                    .or_else(|| {
+                        locale
+                            .headers
+                            .iter()
+                            .find(|(translated, _)| *translated == header)
+                            .map(|(_, header)| *header)
+                    })
+                    .unwrap_or(header)
+            })
+            .collect()
+    }
+
+    /// Rewrites the datetime and amount fields of `record` from this format into the canonical
+    /// format `TransactionRecord` parses. `headers` must already be mapped with `map_headers`.
+    pub fn normalize_record(
+        &self,
+        headers: &csv::StringRecord,
+        record: csv::StringRecord,
+    ) -> Result<csv::StringRecord, StatementError> {
+        let locale = self.locale.unwrap_or_default().profile();
+        let date_format = self.date_format.as_deref().or(locale.date_format);
+        let decimal_separator = self.decimal_separator.unwrap_or(locale.decimal_separator);
+
+        if date_format.is_none() && decimal_separator == '.' {
+            return Ok(record);
         }
+
+        headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, field)| {
+                if field.is_empty() {
+                    return Ok(field.to_string());
+                }
+
+                match (header, date_format, decimal_separator) {
+                    ("Datetime", Some(date_format), _) => {
+                        NaiveDateTime::parse_from_str(field, date_format)
+                            .map(|datetime| datetime.format("%Y-%m-%dT%H:%M:%S").to_string())
+                            .map_err(|_| {
+                                StatementError::ParseDatetimeError(
+                                    field.to_string(),
+                                    date_format.to_string(),
+                                )
+                            })
+                    }
+                    (header, _, separator)
+                        if separator != '.' && AMOUNT_COLUMNS.contains(&header) =>
+                    {
+                        Ok(field
+                            .chars()
+                            .filter(|c| *c != '.')
+                            .map(|c| if c == separator { '.' } else { c })
+                            .collect())
+                    }
+                    _ => Ok(field.to_string()),
+                }
+            })
+            .collect()
     }
 }
 
+/// Result of fingerprinting a statement's header row against the known statement formats.
+#[derive(Debug)]
+pub struct StatementSchema {
+    /// The known format the header row matches exactly, if any.
+    pub version: Option<&'static str>,
+    /// Columns not present in any known format, these are ignored when parsing.
+    pub unknown_columns: Vec<String>,
+}
+
+/// Fingerprints a statement's header row, failing if any column we need to parse it is missing.
+pub fn detect_statement_schema(
+    headers: &csv::StringRecord,
+) -> Result<StatementSchema, StatementError> {
+    // Venmo's statements start with an unnamed column.
+    let headers: Vec<&str> = headers.iter().filter(|val| !val.is_empty()).collect();
+
+    let version = STATEMENT_SCHEMAS
+        .iter()
+        .find(|(_, columns)| *columns == headers.as_slice())
+        .map(|(version, _)| *version);
+
+    let unknown_columns: Vec<String> = headers
+        .iter()
+        .filter(|header| !is_known_column(header))
+        .map(|header| header.to_string())
+        .collect();
+
+    let missing_columns: Vec<String> = REQUIRED_COLUMNS
+        .iter()
+        .filter(|column| !headers.contains(column))
+        .map(|column| column.to_string())
+        .collect();
+
+    if !missing_columns.is_empty() {
+        return Err(StatementError::UnknownStatementFormat(
+            missing_columns,
+            unknown_columns,
+        ));
+    }
+
+    Ok(StatementSchema {
+        version,
+        unknown_columns,
+    })
+}
+
 /// Venmo transaction structure as found in their statement CSVs.
 #[serde_as]
 #[derive(Debug, Deserialize, Clone)]
@@ -136,9 +880,21 @@ pub struct TransactionRecord {
     #[serde(rename = "Amount (tip)")]
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub amount_tip: Option<Amount>,
+    #[serde(rename = "Amount (tax)")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub amount_tax: Option<Amount>,
     #[serde(rename = "Amount (fee)")]
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub amount_fee: Option<Amount>,
+    /// Venmo's fee on a purchase from a business profile, only in business statements.
+    #[serde(rename = "Amount (purchase fee)")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub amount_purchase_fee: Option<Amount>,
+    /// `amount_total` less fees, what a business profile's balance changed by. Only in business
+    /// statements.
+    #[serde(rename = "Amount (net)")]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub amount_net: Option<Amount>,
     #[serde(rename = "Funding Source")]
     pub funding_source: Option<String>,
     pub destination: Option<String>,
@@ -159,155 +915,481 @@ pub struct TransactionRecord {
     pub disclaimer: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+/// How statements name the Venmo Credit Card as a funding source.
+pub const CREDIT_CARD: &str = "Venmo Credit Card";
+
+/// Whether the funding source `account` is the Venmo Credit Card, e.g. `Venmo Credit Card *1234`.
+pub fn is_credit_card(account: &str) -> bool {
+    normalize_account(account).starts_with(&normalize_account(CREDIT_CARD))
+}
+
+/// A validated `TransactionRecord` for a single Venmo transaction.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Transaction {
+    /// Serialized as a string, Venmo IDs don't fit in a JSON (i.e. double precision) number.
+    #[serde_as(as = "DisplayFromStr")]
     pub id: u64,
-    pub datetime: DateTime<Utc>,
+    /// In the statement's timezone, see `StatementFormat::timezone`.
+    pub datetime: DateTime<FixedOffset>,
+    #[serde(rename = "type")]
     pub type_: TransactionType,
     pub status: TransactionStatus,
     pub note: Option<String>,
     pub from: Option<String>,
     pub to: Option<String>,
     pub amount_total: Amount,
+    /// Part of `amount_total` that was a tip, e.g. on payments to businesses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_tip: Option<Amount>,
+    /// Part of `amount_total` that was sales tax, only in business statements.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_tax: Option<Amount>,
+    /// Part of `amount_total` that was a Venmo fee, e.g. for instant transfers or, in business
+    /// statements, purchases.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_fee: Option<Amount>,
     pub funding_source: Option<String>,
     pub destination: Option<String>,
+    /// The Lunch Money `external_id` to insert this transaction with, instead of `id`. For other
+    /// sources whose transaction IDs aren't numbers, like PayPal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
 }
 
 impl TryFrom<TransactionRecord> for Transaction {
-    type Error = Error;
+    type Error = StatementError;
 
     fn try_from(val: TransactionRecord) -> Result<Self, Self::Error> {
         if val.id.is_none() {
-            return Err(Error::InvalidRecord("id".to_string(), val));
+            return Err(StatementError::InvalidRecord(
+                "id".to_string(),
+                Box::new(val),
+            ));
         }
 
         if val.datetime.is_none() {
-            return Err(Error::InvalidRecord("datetime".to_string(), val));
+            return Err(StatementError::InvalidRecord(
+                "datetime".to_string(),
+                Box::new(val),
+            ));
         }
 
         if val.type_.is_none() {
-            return Err(Error::InvalidRecord("type_".to_string(), val));
+            return Err(StatementError::InvalidRecord(
+                "type_".to_string(),
+                Box::new(val),
+            ));
         }
 
         if val.status.is_none() {
-            return Err(Error::InvalidRecord("status".to_string(), val));
+            return Err(StatementError::InvalidRecord(
+                "status".to_string(),
+                Box::new(val),
+            ));
         }
 
         if val.amount_total.is_none() {
-            return Err(Error::InvalidRecord("amount_total".to_string(), val));
+            return Err(StatementError::InvalidRecord(
+                "amount_total".to_string(),
+                Box::new(val),
+            ));
         }
 
+        // Purchases are paid with the card even when the statement doesn't say so.
+        // Business statements total purchases before Venmo's fees, which they split into their own
+        // column, but the balance moves by the net amount like with fees in personal statements.
+        let amount_fee = match (val.amount_fee, val.amount_purchase_fee) {
+            (Some(fee), Some(purchase_fee)) => Some(Amount {
+                val: fee.val + purchase_fee.val,
+                ..fee
+            }),
+            (fee, purchase_fee) => fee.or(purchase_fee),
+        };
+
+        let funding_source = match val.funding_source {
+            None if val.type_ == Some(TransactionType::CreditCardPurchase) => {
+                Some(CREDIT_CARD.to_string())
+            }
+            funding_source => funding_source,
+        };
+
         Ok(Self {
             id: val.id.unwrap(),
-            datetime: Utc.from_utc_datetime(&val.datetime.unwrap()),
+            // Taken as UTC until `parse_venmo_statement` applies the statement's timezone.
+            datetime: Utc.from_utc_datetime(&val.datetime.unwrap()).into(),
             type_: val.type_.unwrap(),
             status: val.status.unwrap(),
             note: val.note,
             from: val.from,
             to: val.to,
-            amount_total: val.amount_total.unwrap(),
-            funding_source: val.funding_source,
+            amount_total: val.amount_net.or(val.amount_total).unwrap(),
+            amount_tip: val.amount_tip.filter(|tip| !tip.val.is_zero()),
+            amount_tax: val.amount_tax.filter(|tax| !tax.val.is_zero()),
+            amount_fee: amount_fee.filter(|fee| !fee.val.is_zero()),
+            funding_source,
             destination: val.destination,
+            external_id: None,
         })
     }
 }
 
-#[derive(Debug)]
+/// A parsed Venmo statement for a date range.
+#[derive(Debug, Serialize)]
 pub struct Statement {
     pub beginning_balance: Amount,
     pub ending_balance: Amount,
     pub transactions: Vec<Transaction>,
 }
 
+/// How `Transaction::to_lunchmoney_transactions` converts Venmo transactions.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub transfers: Transfers,
+    /// Names the transactions it generates.
+    pub templates: Templates,
+    /// Split tips and Venmo fees out of transactions into their own, so fees are tracked as
+    /// spending.
+    pub split_tips_and_fees: bool,
+    /// Lunch Money asset ID of the Venmo Credit Card. Purchases and payments made with the card
+    /// are inserted into it instead, and card payments from the Venmo balance are paired with a
+    /// transfer into it.
+    pub credit_card_asset_id: Option<u64>,
+}
+
+/// How `Transaction::to_lunchmoney_transactions` records money moved between Venmo and other
+/// accounts.
+#[derive(Debug, Clone)]
+pub struct Transfers {
+    /// Generate "shadow" transfers for payments funded by, and money deposited to, other
+    /// accounts. Users whose bank is already in Lunch Money may not want these.
+    pub shadow_transactions: bool,
+    /// Lunch Money asset IDs of funding sources and destinations, by their name in statements
+    /// (e.g. `Chase Checking *1234`). The other side of transfers to or from them is inserted
+    /// into that asset.
+    pub asset_ids: BTreeMap<String, u64>,
+}
+
+impl Transfers {
+    /// The Lunch Money asset ID of the funding source or destination `account`, see `asset_ids`.
+    pub fn asset_id(&self, account: &str) -> Option<u64> {
+        let account = normalize_account(account);
+
+        self.asset_ids
+            .iter()
+            .find(|(name, _)| normalize_account(name) == account)
+            .map(|(_, &asset_id)| asset_id)
+    }
+}
+
+/// `account`, e.g. `Chase Checking *1234`, lowercased without spaces and punctuation, and without
+/// the `x` some statements mask account numbers with, e.g. `Chase Checking x1234`.
+fn normalize_account(account: &str) -> String {
+    let mut normalized = account
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect::<String>();
+
+    let number_start = normalized
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    if number_start < normalized.len() && normalized[..number_start].ends_with('x') {
+        normalized.remove(number_start - 1);
+    }
+
+    normalized
+}
+
+impl Default for Transfers {
+    fn default() -> Self {
+        Transfers {
+            shadow_transactions: true,
+            asset_ids: BTreeMap::new(),
+        }
+    }
+}
+
+/// Templates for the payees and notes of generated transactions, in place of the defaults, see
+/// `Template`.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    /// Payee of the transaction itself.
+    pub payee: Option<Template>,
+    /// Notes of the transaction itself.
+    pub notes: Option<Template>,
+    /// Payee of the transfers generated for it.
+    pub transfer_payee: Option<Template>,
+    /// Notes of the transfers generated for it.
+    pub transfer_notes: Option<Template>,
+}
+
+/// A payee or notes template, text with `{variable}`s filled in from the Venmo transaction, e.g.
+/// `Venmo: {note} ({type})`. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<TemplatePart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Text(String),
+    Variable(String),
+}
+
+impl Template {
+    /// Variables templates can use. `payee` and `notes` are the ones generated without the
+    /// template, `counterparty` is the payee of the transaction itself, even in transfers
+    /// generated for it.
+    pub const VARIABLES: &'static [&'static str] = &[
+        "payee",
+        "notes",
+        "counterparty",
+        "note",
+        "type",
+        "status",
+        "from",
+        "to",
+        "funding_source",
+        "destination",
+        "amount",
+        "id",
+    ];
+
+    /// Fills in the template, with `value` giving each variable's value. Variables without one
+    /// are left empty.
+    pub fn render(&self, value: impl Fn(&str) -> Option<String>) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.clone(),
+                TemplatePart::Variable(name) => value(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Template {
+    type Err = StatementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |message: String| StatementError::ParseTemplateError(s.to_string(), message);
+
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(err("unclosed '{'".to_string())),
+                        }
+                    }
+
+                    let name = name.trim();
+                    if !Template::VARIABLES.contains(&name) {
+                        return Err(err(format!(
+                            "unknown variable '{{{}}}', expected one of {}",
+                            name,
+                            Template::VARIABLES.join(", ")
+                        )));
+                    }
+
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(TemplatePart::Variable(name.to_string()));
+                }
+                '}' => {
+                    return Err(err(
+                        "unmatched '}', write '}}' for a literal one".to_string()
+                    ))
+                }
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+
+        Ok(Template(parts))
+    }
+}
+
 impl Transaction {
+    /// The Lunch Money `external_id` of the transaction itself, transfers generated for it
+    /// append a suffix to this.
+    pub fn external_id(&self) -> String {
+        self.external_id
+            .clone()
+            .unwrap_or_else(|| self.id.to_string())
+    }
+
+    /// How much this moves the Venmo balance: the amount, unless a bank funded the payment or
+    /// received the money, as the shadow transfers of `to_lunchmoney_transactions` account for.
+    pub fn balance_change(&self) -> Decimal {
+        let external = |account: &Option<String>| matches!(account.as_deref(), Some(account) if !account.is_empty() && account != "Venmo balance");
+
+        let mut change = self.amount_total.val;
+        if external(&self.funding_source) {
+            change -= self.amount_total.val;
+        }
+        if external(&self.destination) && self.type_ != TransactionType::StandardTransfer {
+            change -= self.amount_total.val;
+        }
+
+        change
+    }
+
+    /// Who the money went to or came from, which the payee is named after: the bank account for
+    /// transfers out of Venmo, `from` for money received, and `to` for money sent. Charges are
+    /// the other way around, since `to` is who requested the money. `None` if the statement
+    /// leaves it empty.
+    pub fn counterparty(&self) -> Option<&str> {
+        let received = self.amount_total.val.is_sign_positive();
+
+        let counterparty = match self.type_ {
+            TransactionType::StandardTransfer => &self.destination,
+            TransactionType::Charge if received => &self.to,
+            TransactionType::Charge => &self.from,
+            // Money Venmo pays out, or the card's bill paid.
+            TransactionType::DirectDeposit
+            | TransactionType::Disbursement
+            | TransactionType::Cashback => &self.from,
+            TransactionType::CreditCardPayment => &self.to,
+            _ if received => &self.from,
+            _ => &self.to,
+        };
+
+        counterparty.as_deref()
+    }
+
+    /// The payee for when the statement doesn't list the counterparty, or an error if it should.
+    fn missing_counterparty(&self) -> Result<String, StatementError> {
+        let received = self.amount_total.val.is_sign_positive();
+        let missing = |field: &str, condition: &str| {
+            Err(StatementError::InvalidTransaction(
+                field.to_string(),
+                condition.to_string(),
+                Box::new(self.clone()),
+            ))
+        };
+
+        Ok(match self.type_ {
+            TransactionType::StandardTransfer => {
+                return missing(
+                    "destination",
+                    "'Transaction Type' is set to 'Standard Transfer'",
+                )
+            }
+            TransactionType::Charge if received => {
+                return missing(
+                    "to",
+                    "'Transaction Type' is set to 'Charge' and 'Amount' is positive",
+                )
+            }
+            TransactionType::Charge => {
+                return missing(
+                    "from",
+                    "'Transaction Type' is set to 'Charge' and 'Amount' is negative",
+                )
+            }
+            TransactionType::Payment | TransactionType::MerchantTransaction if received => {
+                return missing(
+                    "from",
+                    "'Transaction Type' is set to 'Payment' or 'Merchant Transaction' and 'Amount' is positive",
+                )
+            }
+            TransactionType::Payment | TransactionType::MerchantTransaction => {
+                return missing(
+                    "to",
+                    "'Transaction Type' is set to 'Payment' or 'Merchant Transaction' and 'Amount' is negative",
+                )
+            }
+            TransactionType::DirectDeposit => "Direct Deposit".to_string(),
+            TransactionType::Disbursement => "Venmo".to_string(),
+            TransactionType::Cashback => "Venmo Cashback".to_string(),
+            TransactionType::CreditCardPayment | TransactionType::CreditCardPurchase => {
+                "Venmo Credit Card".to_string()
+            }
+            TransactionType::Refund => "Refund".to_string(),
+            TransactionType::Unknown(ref type_) => type_.clone(),
+        })
+    }
+
+    /// Converts this into Lunch Money transactions for asset `asset_id`: the transaction itself,
+    /// plus "shadow" transfers for money moved to or from a bank account, so the asset's balance
+    /// tracks the Venmo balance, as `options` says. Fails if the amount isn't in
+    /// `expected_currency`.
     pub fn to_lunchmoney_transactions(
         &self,
         expected_currency: Currency,
         asset_id: u64,
-    ) -> Result<Vec<lunchmoney::Transaction>, Error> {
+        options: &ConvertOptions,
+    ) -> Result<Vec<lunchmoney::Transaction>, StatementError> {
+        let transfers = &options.transfers;
         if self.amount_total.currency != expected_currency.symbol {
-            return Err(Error::WrongCurrencyError(
+            return Err(StatementError::WrongCurrencyError(
                 expected_currency.symbol.to_string(),
                 expected_currency.iso_alpha_code.to_string(),
                 self.amount_total.currency.clone(),
             ));
         }
 
-        let payee = match self.type_ {
-            TransactionType::StandardTransfer => self
-                .destination
-                .as_ref()
-                .map(|val| format!("TRANSFER TO {}", val))
-                .ok_or_else(|| {
-                    Error::InvalidTransaction(
-                        "destination".to_string(),
-                        "'Transaction Type' is set to 'Standard Transfer'".to_string(),
-                        self.clone(),
-                    )
-                })?,
-            TransactionType::Charge => {
-                if self.amount_total.val.is_sign_positive() {
-                    self.to.as_ref().cloned().ok_or_else(|| {
-                        Error::InvalidTransaction(
-                            "to".to_string(),
-                            "'Transaction Type' is set to 'Charge' and 'Amount' is positive"
-                                .to_string(),
-                            self.clone(),
-                        )
-                    })?
-                } else {
-                    self.from.as_ref().cloned().ok_or_else(|| {
-                        Error::InvalidTransaction(
-                            "from".to_string(),
-                            "'Transaction Type' is set to 'Charge' and 'Amount' is negative"
-                                .to_string(),
-                            self.clone(),
-                        )
-                    })?
-                }
-            }
-            TransactionType::Payment | TransactionType::MerchantTransaction => {
-                if self.amount_total.val.is_sign_positive() {
-                    self.from.as_ref().cloned().ok_or_else(|| {
-                        Error::InvalidTransaction(
-                            "from".to_string(),
-                            "'Transaction Type' is set to 'Payment' or 'Merchant Transaction' and 'Amount' is positive"
-                                .to_string(),
-                            self.clone(),
-                        )
-                    })?
-                } else {
-                    self.to.as_ref().cloned().ok_or_else(|| {
-                        Error::InvalidTransaction(
-                            "to".to_string(),
-                            "'Transaction Type' is set to 'Payment' or 'Merchant Transaction' and 'Amount' is negative"
-                                .to_string(),
-                            self.clone(),
-                        )
-                    })?
-                }
+        let payee = match (&self.type_, self.counterparty()) {
+            (TransactionType::StandardTransfer, Some(destination)) => {
+                format!("TRANSFER TO {}", destination)
             }
+            (_, Some(counterparty)) => counterparty.to_string(),
+            (_, None) => self.missing_counterparty()?,
         };
 
-        let transactions = {
+        let (tip, fee) = if options.split_tips_and_fees {
+            self.tip_and_fee()
+        } else {
+            (Decimal::ZERO, Decimal::ZERO)
+        };
+
+        // Spending on the Venmo Credit Card goes into its own asset, without shadow transfers.
+        let card_asset_id = options.credit_card_asset_id.filter(
+            |_| matches!(self.funding_source.as_deref(), Some(account) if is_credit_card(account)),
+        );
+
+        let mut transactions = {
             let mut txn = vec![lunchmoney::Transaction {
-                date: self.datetime,
-                payee: Some(payee),
-                amount: lunchmoney::Amount(self.amount_total.val),
+                date: self.datetime.naive_local().date(),
+                payee: Some(payee.clone()),
+                amount: lunchmoney::Amount(self.amount_total.val - tip - fee),
                 currency: Some(expected_currency.iso_alpha_code.to_string().to_lowercase()),
                 notes: self.note.as_ref().cloned(),
-                asset_id: Some(asset_id),
-                external_id: Some(self.id.to_string()),
-                status: lunchmoney::TransactionStatus::Uncleared,
+                asset_id: Some(card_asset_id.unwrap_or(asset_id)),
+                external_id: Some(self.external_id()),
+                status: self.status.to_lunchmoney_status(),
                 ..Default::default()
             }];
 
             if let Some(ref funding_source) = self.funding_source {
-                if !funding_source.is_empty() && funding_source != "Venmo balance" {
+                if transfers.shadow_transactions
+                    && card_asset_id.is_none()
+                    && !funding_source.is_empty()
+                    && funding_source != "Venmo balance"
+                {
                     // Create a "shadow" transaction to indicate we transfered money from one
                     // bank to our Venmo balance.
                     txn.push(lunchmoney::Transaction {
-                        date: self.datetime,
+                        date: self.datetime.naive_local().date(),
                         payee: Some(format!("TRANSFER FROM {}", funding_source)),
                         amount: lunchmoney::Amount(-self.amount_total.val),
                         currency: Some(expected_currency.iso_alpha_code.to_string().to_lowercase()),
@@ -316,10 +1398,28 @@ impl Transaction {
                             .as_ref()
                             .map(|val| format!("To fund Venmo transaction with note: '{}'", val)),
                         asset_id: Some(asset_id),
-                        external_id: Some(format!("{}T", self.id)),
-                        status: lunchmoney::TransactionStatus::Uncleared,
+                        external_id: Some(format!("{}T", self.external_id())),
+                        status: self.status.to_lunchmoney_status(),
                         ..Default::default()
                     });
+
+                    if let Some(other_asset_id) = transfers.asset_id(funding_source) {
+                        txn.push(lunchmoney::Transaction {
+                            date: self.datetime.naive_local().date(),
+                            payee: Some("TRANSFER TO Venmo".to_string()),
+                            amount: lunchmoney::Amount(self.amount_total.val),
+                            currency: Some(
+                                expected_currency.iso_alpha_code.to_string().to_lowercase(),
+                            ),
+                            notes: self.note.as_ref().map(|val| {
+                                format!("To fund Venmo transaction with note: '{}'", val)
+                            }),
+                            asset_id: Some(other_asset_id),
+                            external_id: Some(format!("{}TPAIR", self.external_id())),
+                            status: self.status.to_lunchmoney_status(),
+                            ..Default::default()
+                        });
+                    }
                 }
             }
 
@@ -327,12 +1427,13 @@ impl Transaction {
                 // It should never be possible to direct deposit a Venmo transaction to your bank
                 // account since Venmo always deposits it in your "Venmo balance" first... but just
                 // to cover our bases.
-                if !destination.is_empty()
+                if transfers.shadow_transactions
+                    && !destination.is_empty()
                     && destination != "Venmo balance"
                     && self.type_ != TransactionType::StandardTransfer
                 {
                     txn.push(lunchmoney::Transaction {
-                        date: self.datetime,
+                        date: self.datetime.naive_local().date(),
                         payee: Some(format!("TRANSFER TO {}", destination)),
                         amount: lunchmoney::Amount(-self.amount_total.val),
                         currency: Some(expected_currency.iso_alpha_code.to_string().to_lowercase()),
@@ -341,22 +1442,614 @@ impl Transaction {
                             .as_ref()
                             .map(|val| format!("From Venmo transaction with note: '{}'", val)),
                         asset_id: Some(asset_id),
-                        external_id: Some(format!("{}TDEPOSIT", self.id)),
-                        status: lunchmoney::TransactionStatus::Uncleared,
+                        external_id: Some(format!("{}TDEPOSIT", self.external_id())),
+                        status: self.status.to_lunchmoney_status(),
+                        ..Default::default()
+                    });
+                }
+
+                // The other side of money moved out of Venmo, for a transfer or a shadow one.
+                let moved_out = self.type_ == TransactionType::StandardTransfer
+                    || (transfers.shadow_transactions && destination != "Venmo balance");
+
+                if let (true, Some(other_asset_id)) = (moved_out, transfers.asset_id(destination)) {
+                    // The bank gets what's left after any fee.
+                    let val = if self.type_ == TransactionType::StandardTransfer {
+                        -(self.amount_total.val - fee)
+                    } else {
+                        self.amount_total.val
+                    };
+
+                    txn.push(lunchmoney::Transaction {
+                        date: self.datetime.naive_local().date(),
+                        payee: Some("TRANSFER FROM Venmo".to_string()),
+                        amount: lunchmoney::Amount(val),
+                        currency: Some(expected_currency.iso_alpha_code.to_string().to_lowercase()),
+                        notes: self.note.as_ref().cloned(),
+                        asset_id: Some(other_asset_id),
+                        external_id: Some(format!("{}TDEPOSITPAIR", self.external_id())),
+                        status: self.status.to_lunchmoney_status(),
                         ..Default::default()
                     });
                 }
             }
 
+            if let (TransactionType::CreditCardPayment, Some(card_asset_id)) =
+                (&self.type_, options.credit_card_asset_id)
+            {
+                txn.push(lunchmoney::Transaction {
+                    date: self.datetime.naive_local().date(),
+                    payee: Some("TRANSFER FROM Venmo".to_string()),
+                    amount: lunchmoney::Amount(-self.amount_total.val),
+                    currency: Some(expected_currency.iso_alpha_code.to_string().to_lowercase()),
+                    notes: self.note.as_ref().cloned(),
+                    asset_id: Some(card_asset_id),
+                    external_id: Some(format!("{}TCARD", self.external_id())),
+                    status: self.status.to_lunchmoney_status(),
+                    ..Default::default()
+                });
+            }
+
             txn
         };
 
+        let templates = &options.templates;
+        for (i, generated) in transactions.iter_mut().enumerate() {
+            // Only the first transaction is the payment itself, the rest are transfers.
+            let (payee_template, notes_template) = if i == 0 {
+                (&templates.payee, &templates.notes)
+            } else {
+                (&templates.transfer_payee, &templates.transfer_notes)
+            };
+
+            let value = |name: &str| self.template_value(name, &payee, generated);
+            let rendered_payee = payee_template
+                .as_ref()
+                .map(|template| template.render(value));
+            let rendered_notes = notes_template
+                .as_ref()
+                .map(|template| template.render(value));
+
+            if let Some(rendered) = rendered_payee {
+                generated.payee = Some(rendered);
+            }
+            if let Some(rendered) = rendered_notes {
+                generated.notes = Some(rendered).filter(|notes| !notes.is_empty());
+            }
+        }
+
+        for (suffix, name, val) in [("TIP", "tip", tip), ("FEE", "fee", fee)] {
+            if val.is_zero() {
+                continue;
+            }
+
+            transactions.push(lunchmoney::Transaction {
+                date: self.datetime.naive_local().date(),
+                payee: Some(format!("{} ({})", payee, name)),
+                amount: lunchmoney::Amount(val),
+                currency: Some(expected_currency.iso_alpha_code.to_string().to_lowercase()),
+                notes: self.note.as_ref().cloned(),
+                asset_id: Some(card_asset_id.unwrap_or(asset_id)),
+                external_id: Some(format!("{}{}", self.external_id(), suffix)),
+                status: self.status.to_lunchmoney_status(),
+                ..Default::default()
+            });
+        }
+
         Ok(transactions)
     }
+
+    /// The tip and fee included in `amount_total`, signed by how they move the Venmo balance: a
+    /// tip goes the same way as the payment, a fee is always paid.
+    fn tip_and_fee(&self) -> (Decimal, Decimal) {
+        let abs = |amount: &Option<Amount>| {
+            amount
+                .as_ref()
+                .map_or(Decimal::ZERO, |amount| amount.val.abs())
+        };
+
+        let tip = if self.amount_total.val.is_sign_negative() {
+            -abs(&self.amount_tip)
+        } else {
+            abs(&self.amount_tip)
+        };
+
+        (tip, -abs(&self.amount_fee))
+    }
+
+    /// The value of the template variable `name` for `generated`, one of the transactions this
+    /// converts into, see `Template::VARIABLES`.
+    fn template_value(
+        &self,
+        name: &str,
+        counterparty: &str,
+        generated: &lunchmoney::Transaction,
+    ) -> Option<String> {
+        match name {
+            "payee" => generated.payee.clone(),
+            "notes" => generated.notes.clone(),
+            "counterparty" => Some(counterparty.to_string()),
+            "note" => self.note.clone(),
+            "type" => Some(self.type_.to_string()),
+            "status" => Some(self.status.to_string()),
+            "from" => self.from.clone(),
+            "to" => self.to.clone(),
+            "funding_source" => self.funding_source.clone(),
+            "destination" => self.destination.clone(),
+            "amount" => Some(self.amount_total.val.to_string()),
+            "id" => Some(self.external_id()),
+            _ => None,
+        }
+    }
+
+    /// Whether `generated`, one of the transactions this converts into, is a transfer generated
+    /// for it, the other side of money moved to or from a bank account or the Venmo Credit Card,
+    /// rather than the payment itself or its tip or fee.
+    pub fn is_transfer(&self, generated: &lunchmoney::Transaction) -> bool {
+        let suffix = generated
+            .external_id
+            .as_deref()
+            .and_then(|generated_id| generated_id.strip_prefix(&self.external_id()));
+
+        matches!(
+            suffix,
+            Some("T" | "TPAIR" | "TDEPOSIT" | "TDEPOSITPAIR" | "TCARD")
+        )
+    }
+
+    /// The bank account `generated`, one of the transactions this converts into, moves money to
+    /// or from: the funding source for a shadow transfer funding a payment, the destination for a
+    /// shadow deposit or a withdrawal. `None` for payments themselves, and their tips and fees.
+    pub fn bank_account(&self, generated: &lunchmoney::Transaction) -> Option<&str> {
+        let external_id = self.external_id();
+        let generated_id = generated.external_id.as_deref().unwrap_or_default();
+        let split_out = generated_id == format!("{}TIP", external_id)
+            || generated_id == format!("{}FEE", external_id);
+
+        if generated_id == format!("{}T", external_id) {
+            self.funding_source.as_deref()
+        } else if generated_id == format!("{}TDEPOSIT", external_id)
+            || (self.type_ == TransactionType::StandardTransfer && !split_out)
+        {
+            self.destination.as_deref()
+        } else {
+            None
+        }
+    }
 }
 
+/// A Venmo account to fetch statements for.
 pub struct AccountRecord {
     pub profile_id: u64,
     pub api_token: String,
     pub currency: Currency,
 }
+
+/// The Venmo user an API token belongs to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    /// Profile ID, as a string.
+    pub id: String,
+    pub username: String,
+    pub display_name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn amount(s: &str) -> (String, Decimal) {
+        let amount = Amount::from_str(s).unwrap();
+        (amount.currency, amount.val)
+    }
+
+    #[test]
+    fn normalizes_localized_records() {
+        let format = StatementFormat {
+            locale: Some(Locale::FrFr),
+            ..Default::default()
+        };
+        let headers = format.map_headers(&csv::StringRecord::from(vec![
+            "",
+            "ID",
+            "Date et heure",
+            "Montant (total)",
+            "Note",
+        ]));
+        assert_eq!(
+            headers,
+            csv::StringRecord::from(vec!["", "ID", "Datetime", "Amount (total)", "Note"])
+        );
+
+        let record = format
+            .normalize_record(
+                &headers,
+                csv::StringRecord::from(vec!["", "1", "14/07/2023 08:00:00", "- $1.234,50", "1,5"]),
+            )
+            .unwrap();
+        assert_eq!(
+            record,
+            csv::StringRecord::from(vec!["", "1", "2023-07-14T08:00:00", "- $1234.50", "1,5"])
+        );
+    }
+
+    #[test]
+    fn prefers_configured_formats_over_locale() {
+        let format = StatementFormat {
+            locale: Some(Locale::DeDe),
+            headers: [("Datum und Uhrzeit".to_string(), "Note".to_string())].into(),
+            date_format: Some("%Y/%m/%d %H:%M".to_string()),
+            decimal_separator: Some('.'),
+            ..Default::default()
+        };
+        let headers = csv::StringRecord::from(vec!["Datum und Uhrzeit", "Betrag (gesamt)"]);
+        assert_eq!(
+            format.map_headers(&headers),
+            csv::StringRecord::from(vec!["Note", "Amount (total)"])
+        );
+
+        let headers = csv::StringRecord::from(vec!["Datetime", "Amount (total)"]);
+        let record = format
+            .normalize_record(
+                &headers,
+                csv::StringRecord::from(vec!["2023/07/14 08:00", "+ $1,234.50"]),
+            )
+            .unwrap();
+        assert_eq!(
+            record,
+            csv::StringRecord::from(vec!["2023-07-14T08:00:00", "+ $1,234.50"])
+        );
+
+        let err = format
+            .normalize_record(
+                &headers,
+                csv::StringRecord::from(vec!["14.07.2023 08:00:00", "+ $1.00"]),
+            )
+            .unwrap_err();
+        assert!(matches!(err, StatementError::ParseDatetimeError(..)));
+    }
+
+    #[test]
+    fn detects_statement_schemas() {
+        let mut headers = vec![""];
+        headers.extend(STATEMENT_SCHEMAS[0].1);
+        let schema = detect_statement_schema(&csv::StringRecord::from(headers.clone())).unwrap();
+        assert_eq!(schema.version, Some("2022"));
+        assert!(schema.unknown_columns.is_empty());
+
+        headers.push("Cashback");
+        let schema = detect_statement_schema(&csv::StringRecord::from(headers.clone())).unwrap();
+        assert_eq!(schema.version, None);
+        assert_eq!(schema.unknown_columns, vec!["Cashback".to_string()]);
+
+        headers.retain(|header| *header != "Datetime");
+        let err = detect_statement_schema(&csv::StringRecord::from(headers)).unwrap_err();
+        match err {
+            StatementError::UnknownStatementFormat(missing, unknown) => {
+                assert_eq!(missing, vec!["Datetime".to_string()]);
+                assert_eq!(unknown, vec!["Cashback".to_string()]);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn parses_statement_amounts() {
+        for (s, currency, val) in [
+            ("+ $25.00", "$", Decimal::new(25, 0)),
+            ("- $80.00", "$", Decimal::new(-80, 0)),
+            ("+ $1,234.56", "$", Decimal::new(123456, 2)),
+            ("- $12,345,678.90", "$", Decimal::new(-1234567890, 2)),
+            ("-$5", "$", Decimal::new(-5, 0)),
+            ("$0.25", "$", Decimal::new(25, 2)),
+            ("- CA$1,000.00", "CA$", Decimal::new(-1000, 0)),
+            ("+ R$ 12.50", "R$", Decimal::new(1250, 2)),
+            ("- 1 234.56 kr", "kr", Decimal::new(-123456, 2)),
+            ("+ 1'234.56 CHF", "CHF", Decimal::new(123456, 2)),
+            ("+ €5.00", "€", Decimal::new(5, 0)),
+        ] {
+            assert_eq!(amount(s), (currency.to_string(), val), "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for s in ["", "$", "25.00", "+ $", "$ 1.2.3", "$5 kr", "- $-5.00"] {
+            assert!(Amount::from_str(s).is_err(), "{}", s);
+        }
+    }
+
+    fn converted(
+        transaction: &Transaction,
+        options: &ConvertOptions,
+    ) -> Vec<(u64, String, Decimal)> {
+        transaction
+            .to_lunchmoney_transactions(*rusty_money::iso::USD, 1, options)
+            .unwrap()
+            .into_iter()
+            .map(|txn| (txn.asset_id.unwrap(), txn.payee.unwrap(), txn.amount.0))
+            .collect()
+    }
+
+    #[test]
+    fn records_transfers_as_configured() {
+        let mut payment = testing::transaction(1, TransactionType::Payment, Decimal::new(-80, 0));
+        payment.funding_source = Some("Chase Checking".to_string());
+        let mut withdrawal =
+            testing::transaction(1, TransactionType::StandardTransfer, Decimal::new(-40, 0));
+        withdrawal.destination = Some("Chase Checking".to_string());
+
+        assert_eq!(
+            converted(&payment, &ConvertOptions::default()),
+            vec![
+                (1, "John Smith".to_string(), Decimal::new(-80, 0)),
+                (
+                    1,
+                    "TRANSFER FROM Chase Checking".to_string(),
+                    Decimal::new(80, 0)
+                ),
+            ]
+        );
+
+        let no_shadows = ConvertOptions {
+            transfers: Transfers {
+                shadow_transactions: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(converted(&payment, &no_shadows).len(), 1);
+
+        let paired = ConvertOptions {
+            transfers: Transfers {
+                asset_ids: [("Chase Checking".to_string(), 2)].into_iter().collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            converted(&payment, &paired)[2],
+            (2, "TRANSFER TO Venmo".to_string(), Decimal::new(-80, 0))
+        );
+        let mut masked = payment.clone();
+        masked.funding_source = Some("CHASE CHECKING x1234".to_string());
+        let masked_pair = ConvertOptions {
+            transfers: Transfers {
+                asset_ids: [("Chase Checking *1234".to_string(), 2)]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(converted(&masked, &masked_pair)[2].0, 2);
+        assert_eq!(masked_pair.transfers.asset_id("Chase Savings *1234"), None);
+
+        assert_eq!(
+            converted(&withdrawal, &paired),
+            vec![
+                (
+                    1,
+                    "TRANSFER TO Chase Checking".to_string(),
+                    Decimal::new(-40, 0)
+                ),
+                (2, "TRANSFER FROM Venmo".to_string(), Decimal::new(40, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn routes_credit_card_transactions() {
+        let mut purchase =
+            testing::transaction(1, TransactionType::CreditCardPurchase, Decimal::new(-30, 0));
+        purchase.to = Some("Coffee Shop".to_string());
+        purchase.funding_source = Some(CREDIT_CARD.to_string());
+        let mut card_payment =
+            testing::transaction(1, TransactionType::CreditCardPayment, Decimal::new(-200, 0));
+        card_payment.to = None;
+
+        assert_eq!(purchase.balance_change(), Decimal::ZERO);
+        assert_eq!(
+            converted(&purchase, &ConvertOptions::default())[1],
+            (
+                1,
+                "TRANSFER FROM Venmo Credit Card".to_string(),
+                Decimal::new(30, 0)
+            )
+        );
+
+        let card = ConvertOptions {
+            credit_card_asset_id: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            converted(&purchase, &card),
+            vec![(3, "Coffee Shop".to_string(), Decimal::new(-30, 0))]
+        );
+        assert_eq!(
+            converted(&card_payment, &card),
+            vec![
+                (1, "Venmo Credit Card".to_string(), Decimal::new(-200, 0)),
+                (3, "TRANSFER FROM Venmo".to_string(), Decimal::new(200, 0)),
+            ]
+        );
+        assert!(is_credit_card("Venmo Credit Card *1234"));
+        assert!(!is_credit_card("Chase Checking *1234"));
+    }
+
+    #[test]
+    fn splits_tips_and_fees() {
+        let mut payment = testing::transaction(
+            1,
+            TransactionType::MerchantTransaction,
+            Decimal::new(-60, 0),
+        );
+        payment.amount_tip = Some(testing::amount(Decimal::new(10, 0)));
+        let mut instant =
+            testing::transaction(1, TransactionType::StandardTransfer, Decimal::new(-100, 0));
+        instant.destination = Some("Chase Checking".to_string());
+        instant.amount_fee = Some(testing::amount(Decimal::new(-175, 2)));
+
+        assert_eq!(converted(&payment, &ConvertOptions::default()).len(), 1);
+
+        let split = ConvertOptions {
+            transfers: Transfers {
+                asset_ids: [("Chase Checking".to_string(), 2)].into_iter().collect(),
+                ..Default::default()
+            },
+            split_tips_and_fees: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            converted(&payment, &split),
+            vec![
+                (1, "John Smith".to_string(), Decimal::new(-50, 0)),
+                (1, "John Smith (tip)".to_string(), Decimal::new(-10, 0)),
+            ]
+        );
+        assert_eq!(
+            converted(&instant, &split),
+            vec![
+                (
+                    1,
+                    "TRANSFER TO Chase Checking".to_string(),
+                    Decimal::new(-9825, 2)
+                ),
+                (2, "TRANSFER FROM Venmo".to_string(), Decimal::new(9825, 2)),
+                (
+                    1,
+                    "TRANSFER TO Chase Checking (fee)".to_string(),
+                    Decimal::new(-175, 2)
+                ),
+            ]
+        );
+
+        // Only the bank's side of the transfer is a generated transfer, not the fee.
+        let generated = instant
+            .to_lunchmoney_transactions(*rusty_money::iso::USD, 1, &split)
+            .unwrap();
+        assert_eq!(
+            generated
+                .iter()
+                .map(|generated| instant.is_transfer(generated))
+                .collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+    }
+
+    #[test]
+    fn applies_templates() {
+        let mut payment = testing::transaction(1, TransactionType::Payment, Decimal::new(-80, 0));
+        payment.funding_source = Some("Chase Checking".to_string());
+
+        let options = ConvertOptions {
+            templates: Templates {
+                payee: Some("{counterparty} via Venmo".parse().unwrap()),
+                notes: Some("Venmo: {note} ({type}) {{{id}}}".parse().unwrap()),
+                transfer_payee: None,
+                transfer_notes: Some("{counterparty}: {note}".parse().unwrap()),
+            },
+            ..Default::default()
+        };
+        let generated = payment
+            .to_lunchmoney_transactions(*rusty_money::iso::USD, 1, &options)
+            .unwrap();
+
+        assert_eq!(generated[0].payee.as_deref(), Some("John Smith via Venmo"));
+        assert_eq!(
+            generated[0].notes.as_deref(),
+            Some("Venmo: Dinner (Payment) {1}")
+        );
+        assert_eq!(
+            generated[1].payee.as_deref(),
+            Some("TRANSFER FROM Chase Checking")
+        );
+        assert_eq!(generated[1].notes.as_deref(), Some("John Smith: Dinner"));
+
+        assert!(Template::from_str("{note").is_err());
+        assert!(Template::from_str("note}").is_err());
+        assert!(Template::from_str("{memo}").is_err());
+    }
+
+    #[test]
+    fn filters_transactions() {
+        assert_eq!(
+            TransactionType::parse_name("standardtransfer").unwrap(),
+            TransactionType::StandardTransfer
+        );
+        assert_eq!(
+            TransactionType::parse_name("Standard Transfer").unwrap(),
+            TransactionType::StandardTransfer
+        );
+        assert_eq!(
+            TransactionType::parse_name("Crypto Purchase").unwrap(),
+            TransactionType::Unknown("Crypto Purchase".to_string())
+        );
+        assert!(TransactionType::parse_name(" ").is_err());
+
+        let transaction = |type_, note: &str| Transaction {
+            note: Some(note.to_string()),
+            ..testing::transaction(1, type_, Decimal::new(-20, 0))
+        };
+
+        let filter = TransactionFilter {
+            include_types: vec![TransactionType::Payment, TransactionType::Charge],
+            exclude_types: vec![TransactionType::Charge],
+            ..Default::default()
+        };
+        assert!(filter.matches(&transaction(TransactionType::Payment, "Dinner")));
+        assert!(!filter.matches(&transaction(TransactionType::Charge, "Dinner")));
+        assert!(!filter.matches(&transaction(TransactionType::Refund, "Dinner")));
+
+        let filter = TransactionFilter {
+            exclude_payees: vec![Regex::new("(?i)^john").unwrap()],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&transaction(TransactionType::Payment, "Dinner")));
+        // Charges are named after `from` when paid, like their payee.
+        let charge = transaction(TransactionType::Charge, "Dinner");
+        assert_eq!(charge.counterparty(), Some("Jane Doe"));
+        assert!(filter.matches(&charge));
+
+        let filter = TransactionFilter {
+            exclude_notes: vec![Regex::new("(?i)rent").unwrap()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&transaction(TransactionType::Payment, "Dinner")));
+        assert!(!filter.matches(&transaction(TransactionType::Payment, "July rent")));
+
+        let filter = TransactionFilter {
+            min_amount: Some(Decimal::new(1, 0)),
+            max_amount: Some(Decimal::new(20, 0)),
+            ..Default::default()
+        };
+        assert!(filter.matches(&transaction(TransactionType::Payment, "Dinner")));
+        let mut test_payment = transaction(TransactionType::Payment, "Test");
+        test_payment.amount_total.val = Decimal::new(-1, 2);
+        assert!(!filter.matches(&test_payment));
+        test_payment.amount_total.val = Decimal::new(2001, 2);
+        assert!(!filter.matches(&test_payment));
+
+        assert!(TransactionFilter::default().matches(&transaction(TransactionType::Refund, "")));
+    }
+
+    #[test]
+    fn localizes_datetimes() {
+        let datetime =
+            NaiveDateTime::parse_from_str("2022-07-01T23:30:00", "%Y-%m-%dT%H:%M:%S").unwrap();
+        let new_york = Timezone::from_str("America/New_York").unwrap();
+
+        let localized = new_york.localize(&datetime);
+        assert_eq!(localized.to_rfc3339(), "2022-07-01T23:30:00-04:00");
+        // Still July 1st in New York, though it's July 2nd in UTC.
+        assert_eq!(
+            localized.naive_local().date(),
+            chrono::NaiveDate::from_ymd_opt(2022, 7, 1).unwrap()
+        );
+
+        assert_eq!(new_york.to_string(), "America/New_York");
+        assert_eq!(Timezone::from_str("Local").unwrap(), Timezone::Local);
+        assert!(Timezone::from_str("Mars/Olympus_Mons").is_err());
+    }
+}
@@ -1,191 +1,1550 @@
-use std::io::BufRead;
+//! Client for the unofficial Venmo API: fetching and parsing statements, and getting API
+//! tokens.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use dialoguer::{Confirm, Input, Password};
-use hyper::header::{AUTHORIZATION, CONTENT_TYPE, COOKIE};
-use hyper::{body, body::Buf, Method, Request, StatusCode};
+use dialoguer::{Confirm, Input, Password, Select};
+use futures::stream::{self, Stream};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, COOKIE};
+use hyper::{body, Method, Request, StatusCode};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use tracing::{debug, warn};
 
-use crate::types::venmo::{AccountRecord, Statement, TransactionRecord};
-use crate::types::HttpsClient;
+use crate::http::{request_following_redirects, BoxError, HttpTransport};
+use crate::paths::{ensure_dir, Paths};
+use crate::progress;
+use crate::redact;
+use crate::types::venmo::{
+    detect_statement_schema, AccountRecord, AccountType, Amount, Statement, StatementError,
+    StatementFormat, Timezone, Transaction, TransactionRecord, User,
+};
 
-async fn fetch_venmo_statement(
-    client: &HttpsClient,
+/// A statement to fetch: whose, of which account type, and for which dates.
+struct StatementRequest<'a> {
+    account: &'a AccountRecord,
+    account_type: AccountType,
+    start_date: &'a DateTime<Utc>,
+    end_date: &'a DateTime<Utc>,
+}
+
+impl StatementRequest<'_> {
+    /// Fills in the placeholders of statement URL `template`, see `StatementFormat::endpoints`.
+    fn url(&self, template: &str) -> String {
+        template
+            .replace("{profile_id}", &self.account.profile_id.to_string())
+            .replace("{account_type}", &self.account_type.to_string())
+            .replace(
+                "{start_date_iso}",
+                &self.start_date.format("%Y-%m-%d").to_string(),
+            )
+            .replace(
+                "{end_date_iso}",
+                &self.end_date.format("%Y-%m-%d").to_string(),
+            )
+            .replace(
+                "{start_date}",
+                &self.start_date.format("%m-%d-%Y").to_string(),
+            )
+            .replace("{end_date}", &self.end_date.format("%m-%d-%Y").to_string())
+    }
+}
+
+/// An error from the Venmo API. Getting an API token prompts on the terminal, and fails with
+/// plain `anyhow` errors instead.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Venmo rejected the API token, e.g. because it expired.
+    #[error("{0}")]
+    AuthError(String),
+    /// Venmo rate limited a request.
+    #[error("{0}")]
+    RateLimited(String),
+    /// Venmo served an HTML page instead of a statement, e.g. because the statement endpoint
+    /// moved, or is down for maintenance.
+    #[error("{0}")]
+    EndpointMoved(String),
+    /// A response didn't have the expected format.
+    #[error("{message}")]
+    ParseError {
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+    /// Venmo responded with an unexpected status.
+    #[error("Failed to {action}, code {status}, err:\n{body}")]
+    ApiError {
+        action: String,
+        status: StatusCode,
+        body: String,
+    },
+    /// No statement endpoint returned a statement, e.g. during an outage.
+    #[error("{0}")]
+    Unavailable(String),
+    /// The request failed to send, or the response failed to arrive.
+    #[error("Failed to send Venmo request")]
+    Http(#[source] BoxError),
+    /// `--from-cache` was passed without a directory to read cached statements from.
+    #[error("--from-cache needs a cache directory, pass --cache-dir")]
+    MissingCacheDir,
+    /// Reading or writing a cached or archived statement failed.
+    #[error("{message}")]
+    Io {
+        message: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl Error {
+    fn http(e: impl Into<BoxError>) -> Self {
+        Error::Http(e.into())
+    }
+
+    fn io(message: String) -> impl FnOnce(io::Error) -> Self {
+        |source| Error::Io { message, source }
+    }
+}
+
+/// Bytes at the start of a statement response checked for an error page, see
+/// `unavailable_reason`, before the rest is streamed.
+const SNIFF_BYTES: usize = 64;
+
+/// A statement response body, read a chunk at a time so the statement isn't held in memory whole.
+struct StatementBody {
+    /// The start of the body, already read to check for an error page.
+    head: body::Bytes,
+    rest: Option<body::Body>,
+}
+
+impl StatementBody {
+    /// The next chunk of the body, or `None` at its end.
+    async fn next_chunk(&mut self) -> Result<Option<body::Bytes>, Error> {
+        if !self.head.is_empty() {
+            return Ok(Some(std::mem::take(&mut self.head)));
+        }
+
+        match &mut self.rest {
+            Some(rest) => rest.data().await.transpose().map_err(Error::http),
+            None => Ok(None),
+        }
+    }
+}
+
+/// GETs the statement at `url`, following redirects.
+async fn fetch_venmo_statement_from(
+    client: &impl HttpTransport,
     account: &AccountRecord,
-    start_date: &DateTime<Utc>,
-    end_date: &DateTime<Utc>,
-) -> Result<body::Bytes> {
-    let request = Request::builder()
+    url: &str,
+    headers: &HeaderMap,
+) -> Result<StatementBody, Error> {
+    let mut request = Request::builder()
         .method(Method::GET)
-        .uri(
-            format!(
-                "https://venmo.com/transaction-history/statement?startDate={}&endDate={}&profileId={}&accountType=personal", 
-                start_date.format("%m-%d-%Y"), 
-                end_date.format("%m-%d-%Y"), 
-                account.profile_id
-            )
-        )
-        .header(COOKIE, format!("api_access_token={}", account.api_token)) 
+        .uri(url)
+        .header(COOKIE, format!("api_access_token={}", account.api_token))
         .body(body::Body::empty())
         .unwrap();
+    request.headers_mut().extend(headers.clone());
 
-    let response = client.request(request).await?;
+    let response = request_following_redirects(client, request)
+        .await
+        .map_err(Error::http)?;
+    let status = response.status();
 
-    if response.status() != StatusCode::OK {
-        bail!(
-            "Failed to get Venmo statement, code {}, err:\n{:#?}",
-            response.status(),
-            response
+    if status != StatusCode::OK {
+        let response_headers = redact::headers(response.headers());
+        let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+        debug!(
+            "Venmo statement response, code {}, headers:\n{}\nbody:\n{}",
+            status,
+            response_headers,
+            redact::bytes(&bytes)
         );
+
+        return Err(match (status, unavailable_reason(&bytes)) {
+            (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN, _) => Error::AuthError(format!(
+                "Venmo rejected the API token, code {}, err:\n{}",
+                status,
+                redact::bytes(&bytes)
+            )),
+            (StatusCode::TOO_MANY_REQUESTS, _) => Error::RateLimited(format!(
+                "Venmo rate limited the statement request, code {}, retry later",
+                status
+            )),
+            (_, Some(reason)) => unavailable(reason, format!("({}, code {})", reason, status)),
+            (_, None) => Error::ApiError {
+                action: "get Venmo statement".to_string(),
+                status,
+                body: redact::bytes(&bytes),
+            },
+        });
     }
 
-    let bytes = body::to_bytes(response).await?;
+    let mut rest = response.into_body();
+    let mut head = Vec::new();
+    while head.trim_ascii_start().len() < SNIFF_BYTES {
+        match rest.data().await {
+            Some(chunk) => head.extend_from_slice(&chunk.map_err(Error::http)?),
+            None => break,
+        }
+    }
+
+    // Venmo serves errors and maintenance pages with a 200 too.
+    if let Some(reason) = unavailable_reason(&head) {
+        let rest = body::to_bytes(rest).await.map_err(Error::http)?;
+        head.extend_from_slice(&rest);
+        debug!("Venmo statement response body:\n{}", redact::bytes(&head));
+        return Err(unavailable(reason, format!("({})", reason)));
+    }
+
+    Ok(StatementBody {
+        head: head.into(),
+        rest: Some(rest),
+    })
+}
+
+/// An HTML page, as opposed to a message saying a statement is unavailable.
+const HTML_PAGE: &str = "it returned an HTML page instead of a statement CSV";
+
+/// Describes why `bytes` is an error page rather than a statement, if it is one. Moved endpoints
+/// and outages tend to serve a login, error, or maintenance page rather than failing.
+fn unavailable_reason(bytes: &[u8]) -> Option<&'static str> {
+    let bytes = bytes.trim_ascii_start();
 
     if bytes.starts_with(b"Unable to fetch transaction history") {
-        bail!("Venmo transaction history request failed: {:#?}", bytes);
+        Some("it was unable to fetch transaction history")
+    } else if bytes.starts_with(b"<") {
+        Some(HTML_PAGE)
+    } else {
+        None
+    }
+}
+
+/// The concise error for a statement endpoint that returned an error page for `reason`, with
+/// `details` about it.
+fn unavailable(reason: &str, details: String) -> Error {
+    if reason == HTML_PAGE {
+        Error::EndpointMoved(format!(
+            "Venmo statement endpoint unavailable {}, retry later, or list a new statement URL \
+             in `statement_format.endpoints` if this persists",
+            details
+        ))
+    } else {
+        Error::Unavailable(format!(
+            "Venmo statement endpoint unavailable {}, retry later",
+            details
+        ))
     }
+}
+
+/// Fetches the statement for `request`, trying each of `endpoints` in order, with `headers`.
+/// Returns the statement's body and the index of the endpoint that returned it.
+async fn fetch_venmo_statement(
+    client: &impl HttpTransport,
+    request: &StatementRequest<'_>,
+    endpoints: &[&str],
+    headers: &HeaderMap,
+) -> Result<(StatementBody, usize), Error> {
+    let mut errors = Vec::new();
+    let mut unauthorized = false;
+    let mut moved = true;
+
+    for (i, template) in endpoints.iter().enumerate() {
+        let url = request.url(template);
+
+        match fetch_venmo_statement_from(client, request.account, &url, headers).await {
+            Ok(body) => {
+                if !errors.is_empty() {
+                    warn!(
+                        "Fetched Venmo statement from {} after earlier endpoints failed, consider listing it first in `statement_format.endpoints`.",
+                        template
+                    );
+                }
+
+                return Ok((body, i));
+            }
+            Err(e) => {
+                unauthorized |= matches!(e, Error::AuthError(_));
+                moved &= matches!(e, Error::EndpointMoved(_));
+
+                // Through `anyhow` to include the causes.
+                let e = anyhow::Error::from(e);
+                warn!("Venmo statement endpoint {} failed: {:#}", template, e);
+                errors.push(format!("{}: {:#}", template, e));
+            }
+        }
+    }
+
+    if unauthorized {
+        return Err(Error::AuthError(format!(
+            "Venmo rejected the API token, it may have expired, get a new one with `get-venmo-api-token`:\n{}",
+            errors.join("\n")
+        )));
+    }
+    if moved {
+        return Err(Error::EndpointMoved(format!(
+            "Every Venmo statement endpoint returned an HTML page instead of a statement:\n{}",
+            errors.join("\n")
+        )));
+    }
+    Err(Error::Unavailable(format!(
+        "Failed to get Venmo statement from any endpoint:\n{}",
+        errors.join("\n")
+    )))
+}
+
+/// Returns the user `api_token` belongs to, or `None` if Venmo rejects it as invalid or expired.
+pub async fn get_venmo_user(
+    client: &impl HttpTransport,
+    api_token: &str,
+) -> Result<Option<User>, Error> {
+    #[derive(Deserialize)]
+    struct Response {
+        data: Data,
+    }
+
+    #[derive(Deserialize)]
+    struct Data {
+        user: User,
+    }
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("https://api.venmo.com/v1/me")
+        .header(AUTHORIZATION, format!("Bearer {}", api_token))
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = request_following_redirects(client, request)
+        .await
+        .map_err(Error::http)?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await.map_err(Error::http)?;
+
+    if status == StatusCode::UNAUTHORIZED {
+        return Ok(None);
+    }
+
+    if status != StatusCode::OK {
+        return Err(Error::ApiError {
+            action: "get Venmo user".to_string(),
+            status,
+            body: redact::bytes(&bytes),
+        });
+    }
+
+    let response: Response = serde_json::from_slice(&bytes).map_err(|e| Error::ParseError {
+        message: format!("Failed to parse Venmo user from {}", redact::bytes(&bytes)),
+        source: e.into(),
+    })?;
+
+    Ok(Some(response.data.user))
+}
+
+/// The configured headers sent with statement and login requests, see
+/// `StatementFormat::request_headers`. Invalid ones are left out with a warning.
+pub fn request_headers(format: &StatementFormat) -> HeaderMap {
+    let (headers, problems) = format.request_headers();
+
+    for problem in problems {
+        warn!(
+            "Ignoring venmo.statement_format.request_headers: {}",
+            problem
+        );
+    }
+
+    headers
+}
+
+/// Longest date range, in days, Venmo returns a full statement for. Longer ranges are silently
+/// truncated.
+const MAX_STATEMENT_DAYS: i64 = 90;
+
+/// Splits `start_date..=end_date` into consecutive windows of at most `MAX_STATEMENT_DAYS` days.
+/// Statements are requested by day, so windows start on the day after the previous one ends.
+fn statement_windows(
+    start_date: &DateTime<Utc>,
+    end_date: &DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut windows = Vec::new();
+    let mut window_start = *start_date;
+
+    loop {
+        let window_end = window_start + chrono::Duration::days(MAX_STATEMENT_DAYS - 1);
+
+        if window_end >= *end_date {
+            windows.push((window_start, *end_date));
+            return windows;
+        }
+
+        windows.push((window_start, window_end));
+        window_start = window_end + chrono::Duration::days(1);
+    }
+}
 
-    Ok(bytes)
+/// Where fetched statements are kept on disk, besides being parsed. Set from the command line,
+/// not the config file.
+#[derive(Debug, Clone, Default)]
+pub struct StatementStore {
+    /// Directory to save fetched statement CSVs in, one file per profile and date window, to
+    /// replay them with `from_cache`.
+    pub cache_dir: Option<PathBuf>,
+    /// Read statements from `cache_dir` instead of fetching them from Venmo, e.g. to iterate on
+    /// category rules without refetching.
+    pub from_cache: bool,
+    /// Directory to keep a copy of every fetched statement in, as the raw CSV and the parsed
+    /// transactions as JSON, named by profile, date window, and when it was fetched.
+    pub archive_dir: Option<PathBuf>,
 }
 
+/// Fetches and parses the Venmo statement for `start_date` to `end_date`. Ranges longer than
+/// Venmo allows are fetched as multiple statements and stitched together. This holds the whole
+/// statement in memory, for commands that need all of it at once, e.g. to sort or export it; syncs
+/// to Lunch Money read it as a `StatementStream` instead.
+#[tracing::instrument(skip_all, fields(profile_id = account.profile_id, %start_date, %end_date))]
 pub async fn fetch_venmo_transactions(
-    client: &HttpsClient,
+    client: &impl HttpTransport,
     account: &AccountRecord,
+    format: &StatementFormat,
+    store: &StatementStore,
     start_date: &DateTime<Utc>,
     end_date: &DateTime<Utc>,
-) -> Result<Statement> {
-    let bytes = fetch_venmo_statement(client, account, start_date, end_date).await?;
-    let bytes_clone = bytes.clone();
+) -> Result<Statement, Error> {
+    let mut stream = StatementStream::new(client, account, format, store, start_date, end_date);
+    let mut transactions = Vec::new();
 
-    let reader = {
-        let mut reader = bytes.reader();
-        let mut dummy_buf = String::new();
+    while let Some(transaction) = stream.next().await? {
+        transactions.push(transaction);
+    }
 
-        reader.read_line(&mut dummy_buf).with_context(|| {
-            anyhow!(
-                "Failed to skip first line in Venmo statement:\n{:#?}",
-                bytes_clone
-            )
-        })?;
-        reader.read_line(&mut dummy_buf).with_context(|| {
-            anyhow!(
-                "Failed to skip second line in Venmo statement:\n{:#?}",
-                bytes_clone
-            )
+    let (beginning_balance, ending_balance) = stream
+        .balances()
+        .expect("there is always at least one statement window");
+
+    Ok(Statement {
+        beginning_balance: beginning_balance.clone(),
+        ending_balance: ending_balance.clone(),
+        transactions,
+    })
+}
+
+/// Like `fetch_venmo_transactions`, but returns the transactions as they're parsed, so they can be
+/// processed without holding the whole statement in memory. Statements are fetched one window at
+/// a time as the stream is polled. A failed window ends the stream after its error. Use
+/// `StatementStream` directly to also get the statement's balances.
+pub fn fetch_venmo_transactions_stream<'a>(
+    client: &'a impl HttpTransport,
+    account: &'a AccountRecord,
+    format: &'a StatementFormat,
+    store: &'a StatementStore,
+    start_date: &DateTime<Utc>,
+    end_date: &DateTime<Utc>,
+) -> impl Stream<Item = Result<Transaction, Error>> + 'a {
+    let stream = StatementStream::new(client, account, format, store, start_date, end_date);
+
+    stream::try_unfold(stream, |mut stream| async move {
+        Ok(stream
+            .next()
+            .await?
+            .map(|transaction| (transaction, stream)))
+    })
+}
+
+/// Transactions of the Venmo statements for a date range, fetched one window (see
+/// `statement_windows`) at a time and parsed as their bodies arrive. Transactions repeated across
+/// windows are only returned once.
+pub struct StatementStream<'a, C> {
+    client: &'a C,
+    account: &'a AccountRecord,
+    format: &'a StatementFormat,
+    store: &'a StatementStore,
+    endpoints: Vec<&'a str>,
+    headers: HeaderMap,
+    windows: std::vec::IntoIter<(DateTime<Utc>, DateTime<Utc>)>,
+    window: Option<StatementWindow<'a>>,
+    /// Parsed transactions not returned yet.
+    ready: VecDeque<Transaction>,
+    seen: HashSet<u64>,
+    /// Of the first window, once it's parsed.
+    beginning_balance: Option<Amount>,
+    /// Of the last window parsed.
+    ending_balance: Option<Amount>,
+    progress: progress::Progress,
+}
+
+/// The statement window a `StatementStream` is reading.
+struct StatementWindow<'a> {
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+    body: StatementBody,
+    parser: StatementParser<'a>,
+    /// Where the statement is cached once fully read, and the file it's written to until then.
+    cache: Option<(PathBuf, PathBuf, fs::File)>,
+    /// Where the statement is archived, if it is.
+    archive: Option<StatementArchive>,
+}
+
+impl<'a, C: HttpTransport> StatementStream<'a, C> {
+    pub fn new(
+        client: &'a C,
+        account: &'a AccountRecord,
+        format: &'a StatementFormat,
+        store: &'a StatementStore,
+        start_date: &DateTime<Utc>,
+        end_date: &DateTime<Utc>,
+    ) -> Self {
+        let windows = statement_windows(start_date, end_date);
+
+        Self {
+            client,
+            account,
+            format,
+            store,
+            endpoints: format.endpoints(),
+            headers: request_headers(format),
+            progress: progress::bar("Fetching Venmo statements", windows.len(), "windows"),
+            windows: windows.into_iter(),
+            window: None,
+            ready: VecDeque::new(),
+            seen: HashSet::new(),
+            beginning_balance: None,
+            ending_balance: None,
+        }
+    }
+
+    /// The next transaction, or `None` once every window is read.
+    pub async fn next(&mut self) -> Result<Option<Transaction>, Error> {
+        loop {
+            if let Some(transaction) = self.ready.pop_front() {
+                return Ok(Some(transaction));
+            }
+
+            let window = match &mut self.window {
+                Some(window) => window,
+                None => match self.windows.next() {
+                    Some((start_date, end_date)) => {
+                        self.window = Some(self.open(start_date, end_date).await?);
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+
+            let chunk = match window.body.next_chunk().await? {
+                Some(chunk) => chunk,
+                None => {
+                    let window = self.window.take().expect("a window is being read");
+                    self.close(window)?;
+                    continue;
+                }
+            };
+
+            if let Some((path, _, file)) = &mut window.cache {
+                let message = format!("Failed to cache Venmo statement to {:?}", path);
+                file.write_all(&chunk).map_err(Error::io(message))?;
+            }
+
+            let transactions = window
+                .parser
+                .push(&chunk)
+                .map_err(parse_error(&window.start_date, &window.end_date))?;
+
+            if let Some(archive) = &mut window.archive {
+                archive.write(&chunk, &transactions)?;
+            }
+
+            for transaction in transactions {
+                if self.seen.insert(transaction.id) {
+                    self.ready.push_back(transaction);
+                }
+            }
+        }
+    }
+
+    /// Beginning balance of the first window and ending balance of the last one, once every
+    /// window is read.
+    pub fn balances(&self) -> Option<(&Amount, &Amount)> {
+        match (self.windows.len(), &self.window) {
+            (0, None) => self
+                .beginning_balance
+                .as_ref()
+                .zip(self.ending_balance.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Starts reading the statement for `start_date` to `end_date`, from the cache or Venmo.
+    async fn open(
+        &mut self,
+        start_date: DateTime<Utc>,
+        end_date: DateTime<Utc>,
+    ) -> Result<StatementWindow<'a>, Error> {
+        let (format, store) = (self.format, self.store);
+        let cached = store
+            .cache_dir
+            .as_deref()
+            .map(|dir| cached_statement(dir, self.account, &start_date, &end_date));
+
+        let (body, cache) = match (cached, store.from_cache) {
+            (Some(path), true) => {
+                let bytes = fs::read(&path).map_err(Error::io(format!(
+                    "Failed to read cached Venmo statement {:?}, fetch it first without --from-cache",
+                    path
+                )))?;
+                let body = StatementBody {
+                    head: bytes.into(),
+                    rest: None,
+                };
+
+                (body, None)
+            }
+            (None, true) => return Err(Error::MissingCacheDir),
+            (cached, false) => {
+                let request = StatementRequest {
+                    account: self.account,
+                    account_type: format.account_type.unwrap_or_default(),
+                    start_date: &start_date,
+                    end_date: &end_date,
+                };
+                let (body, endpoint) =
+                    fetch_venmo_statement(self.client, &request, &self.endpoints, &self.headers)
+                        .await?;
+                // Try the endpoint that worked first for the remaining windows.
+                self.endpoints[..=endpoint].rotate_right(1);
+
+                // Written next to the cached statement, then moved over it once complete, so a
+                // failed fetch doesn't leave a partial statement to replay.
+                let cache = match cached {
+                    Some(path) => {
+                        let partial = path.with_extension("csv.part");
+                        let message = format!("Failed to cache Venmo statement to {:?}", path);
+                        let file = fs::create_dir_all(
+                            path.parent().expect("cached statements are in a directory"),
+                        )
+                        .and_then(|()| create_private(&partial))
+                        .map_err(Error::io(message))?;
+
+                        Some((path, partial, file))
+                    }
+                    None => None,
+                };
+
+                (body, cache)
+            }
+        };
+
+        let archive = match &store.archive_dir {
+            Some(dir) if !store.from_cache => Some(StatementArchive::create(
+                dir,
+                self.account,
+                (&start_date, &end_date),
+            )?),
+            _ => None,
+        };
+
+        Ok(StatementWindow {
+            start_date,
+            end_date,
+            body,
+            parser: StatementParser::new(format).map_err(parse_error(&start_date, &end_date))?,
+            cache,
+            archive,
+        })
+    }
+
+    /// Finishes reading `window`, once its whole body is read, warning if its beginning balance
+    /// doesn't line up with the previous window's ending balance, which means transactions
+    /// between the windows were missed.
+    fn close(&mut self, window: StatementWindow<'a>) -> Result<(), Error> {
+        let (transactions, beginning_balance, ending_balance) = window
+            .parser
+            .finish()
+            .map_err(parse_error(&window.start_date, &window.end_date))?;
+
+        if let Some((path, partial, file)) = window.cache {
+            let message = format!("Failed to cache Venmo statement to {:?}", path);
+            drop(file);
+            fs::rename(&partial, &path).map_err(Error::io(message))?;
+        }
+
+        if let Some(mut archive) = window.archive {
+            archive.write(&[], &transactions)?;
+            archive.finish(&beginning_balance, &ending_balance)?;
+        }
+
+        match &self.ending_balance {
+            Some(previous) if previous.val != beginning_balance.val => warn!(
+                "Venmo statement ending balance {} does not match the next statement's beginning balance {}, some transactions may be missing.",
+                previous, beginning_balance
+            ),
+            Some(_) => {}
+            None => self.beginning_balance = Some(beginning_balance),
+        }
+        self.ending_balance = Some(ending_balance);
+
+        for transaction in transactions {
+            if self.seen.insert(transaction.id) {
+                self.ready.push_back(transaction);
+            }
+        }
+        self.progress.inc(1);
+
+        Ok(())
+    }
+}
+
+/// Wraps an error parsing the statement for `start_date` to `end_date`.
+fn parse_error(
+    start_date: &DateTime<Utc>,
+    end_date: &DateTime<Utc>,
+) -> impl FnOnce(StatementError) -> Error {
+    let message = format!(
+        "Failed to parse Venmo statement for {} to {}",
+        start_date.format("%Y-%m-%d"),
+        end_date.format("%Y-%m-%d")
+    );
+
+    |e| Error::ParseError {
+        message,
+        source: e.into(),
+    }
+}
+
+/// Path in `dir` the statement of `account` for `start_date` to `end_date` is cached at.
+fn cached_statement(
+    dir: &Path,
+    account: &AccountRecord,
+    start_date: &DateTime<Utc>,
+    end_date: &DateTime<Utc>,
+) -> PathBuf {
+    dir.join(format!(
+        "{}-{}-{}.csv",
+        account.profile_id,
+        start_date.format("%Y-%m-%d"),
+        end_date.format("%Y-%m-%d")
+    ))
+}
+
+/// Creates or truncates `path`, only readable by the current user on Unix, as statements list
+/// who the user paid and why.
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
+
+/// The fetched statement of an account for a window, written to an archive directory as its
+/// bytes arrive: the raw CSV, and the parsed statement in JSON.
+struct StatementArchive {
+    csv: (PathBuf, io::BufWriter<fs::File>),
+    json: (PathBuf, io::BufWriter<fs::File>),
+    /// Whether a transaction has been written to the JSON yet.
+    started: bool,
+}
+
+impl StatementArchive {
+    fn create(
+        dir: &Path,
+        account: &AccountRecord,
+        (start_date, end_date): (&DateTime<Utc>, &DateTime<Utc>),
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(dir).map_err(Error::io(format!(
+            "Failed to create archive directory {:?}",
+            dir
+        )))?;
+
+        let name = format!(
+            "{}-{}-{}-fetched-{}",
+            account.profile_id,
+            start_date.format("%Y-%m-%d"),
+            end_date.format("%Y-%m-%d"),
+            Utc::now().format("%Y%m%dT%H%M%SZ")
+        );
+        let create = |extension: &str| {
+            let path = dir.join(format!("{}.{}", name, extension));
+            let file = create_private(&path).map_err(Error::io(format!(
+                "Failed to archive Venmo statement to {:?}",
+                path
+            )))?;
+
+            Ok::<_, Error>((path, io::BufWriter::new(file)))
+        };
+
+        let mut archive = Self {
+            csv: create("csv")?,
+            json: create("json")?,
+            started: false,
+        };
+        archive.write_json(b"{\"transactions\":[")?;
+
+        Ok(archive)
+    }
+
+    /// Appends `chunk` of the statement, and the `transactions` parsed from it.
+    fn write(&mut self, chunk: &[u8], transactions: &[Transaction]) -> Result<(), Error> {
+        let (path, file) = &mut self.csv;
+        file.write_all(chunk).map_err(Error::io(format!(
+            "Failed to archive Venmo statement to {:?}",
+            path
+        )))?;
+
+        for transaction in transactions {
+            if std::mem::replace(&mut self.started, true) {
+                self.write_json(b",")?;
+            }
+            let json = serde_json::to_vec(transaction).expect("transactions serialize to JSON");
+            self.write_json(&json)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the statement's balances, once all of it is written.
+    fn finish(mut self, beginning_balance: &Amount, ending_balance: &Amount) -> Result<(), Error> {
+        let balances = format!(
+            "],\"beginning_balance\":{},\"ending_balance\":{}}}",
+            json!(beginning_balance),
+            json!(ending_balance)
+        );
+        self.write_json(balances.as_bytes())?;
+
+        for (path, file) in [&mut self.csv, &mut self.json] {
+            file.flush().map_err(Error::io(format!(
+                "Failed to archive Venmo statement to {:?}",
+                path
+            )))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let (path, file) = &mut self.json;
+        file.write_all(bytes).map_err(Error::io(format!(
+            "Failed to archive Venmo statement to {:?}",
+            path
+        )))
+    }
+}
+
+/// Parses a Venmo statement CSV. Tolerates a UTF-8 BOM, CRLF line endings, quoted fields
+/// containing commas or newlines, and any number of preamble lines before the header row.
+#[tracing::instrument(skip_all, fields(bytes = bytes.len()))]
+pub fn parse_venmo_statement(bytes: &[u8], format: &StatementFormat) -> Result<Statement, Error> {
+    let parse = || {
+        let mut parser = StatementParser::new(format)?;
+        let mut transactions = parser.push(bytes)?;
+        let (rest, beginning_balance, ending_balance) = parser.finish()?;
+        transactions.extend(rest);
+
+        Ok::<_, StatementError>((transactions, beginning_balance, ending_balance))
+    };
+    let (transactions, beginning_balance, ending_balance) =
+        parse().map_err(|e| Error::ParseError {
+            message: "Failed to parse Venmo statement".to_string(),
+            source: e.into(),
         })?;
 
-        reader
+    Ok(Statement {
+        beginning_balance,
+        ending_balance,
+        transactions,
+    })
+}
+
+/// Parses a Venmo statement CSV as its bytes arrive, a record at a time, see
+/// `parse_venmo_statement`. Only the record being received is buffered.
+struct StatementParser<'a> {
+    format: &'a StatementFormat,
+    timezone: Timezone,
+    /// Bytes received but not parsed yet, the start of a record.
+    buf: Vec<u8>,
+    /// How far into `buf` records have been looked for, and whether that's in a quoted field.
+    scanned: usize,
+    in_quotes: bool,
+    /// Whether a leading UTF-8 BOM was looked for yet.
+    started: bool,
+    /// Records before the header row, for the error if there is none.
+    preamble: Vec<String>,
+    headers: Option<csv::StringRecord>,
+    beginning_balance: Option<Amount>,
+    /// The latest record, held back until the next one shows it isn't the ending balance record.
+    last: Option<TransactionRecord>,
+}
+
+impl<'a> StatementParser<'a> {
+    fn new(format: &'a StatementFormat) -> Result<Self, StatementError> {
+        Ok(Self {
+            format,
+            timezone: format.timezone()?,
+            buf: Vec::new(),
+            scanned: 0,
+            in_quotes: false,
+            started: false,
+            preamble: Vec::new(),
+            headers: None,
+            beginning_balance: None,
+            last: None,
+        })
+    }
+
+    /// Takes the next `bytes` of the statement, returning the transactions of the records they
+    /// complete.
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<Transaction>, StatementError> {
+        self.buf.extend_from_slice(bytes);
+
+        if !self.started {
+            if self.buf.len() < BOM.len() {
+                return Ok(Vec::new());
+            }
+            self.strip_bom();
+        }
+
+        // Records end at newlines outside quoted fields, escaped quotes toggle twice.
+        let mut end = None;
+        for (i, byte) in self.buf.iter().enumerate().skip(self.scanned) {
+            match byte {
+                b'"' => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => end = Some(i + 1),
+                _ => {}
+            }
+        }
+        self.scanned = self.buf.len();
+
+        match end {
+            Some(end) => {
+                let records = self.buf.drain(..end).collect::<Vec<_>>();
+                self.scanned -= end;
+                self.parse_records(&records)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Parses what's left at the end of the statement, returning the transactions of the last
+    /// records and the statement's beginning and ending balances.
+    fn finish(mut self) -> Result<(Vec<Transaction>, Amount, Amount), StatementError> {
+        if !self.started {
+            self.strip_bom();
+        }
+
+        let rest = std::mem::take(&mut self.buf);
+        let transactions = self.parse_records(&rest)?;
+
+        if self.headers.is_none() {
+            return Err(StatementError::MissingHeaderRow(self.preamble.join("\n")));
+        }
+
+        let beginning_balance = self
+            .beginning_balance
+            .ok_or_else(|| StatementError::MissingBalanceRecord("beginning balance".to_string()))?;
+        let last = self
+            .last
+            .ok_or_else(|| StatementError::MissingBalanceRecord("ending balance".to_string()))?;
+        let ending_balance = match &last.ending_balance {
+            Some(ending_balance) => ending_balance.clone(),
+            None => {
+                return Err(StatementError::InvalidRecord(
+                    "Ending Balance".to_string(),
+                    Box::new(last),
+                ))
+            }
+        };
+
+        Ok((transactions, beginning_balance, ending_balance))
+    }
+
+    fn strip_bom(&mut self) {
+        if self.buf.starts_with(BOM) {
+            self.buf.drain(..BOM.len());
+        }
+        self.started = true;
+    }
+
+    /// Parses complete `records`, returning their transactions.
+    fn parse_records(&mut self, records: &[u8]) -> Result<Vec<Transaction>, StatementError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            // Preamble lines don't have as many fields as the header row.
+            .flexible(true)
+            .from_reader(records);
+
+        let mut transactions = Vec::new();
+        for record in rdr.records() {
+            let record = record.map_err(StatementError::ReadError)?;
+
+            if let Some(transaction) = self.parse_record(record)? {
+                transactions.push(transaction);
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    /// Parses `record`, returning the transaction of the record before it, if that wasn't the
+    /// beginning balance record.
+    fn parse_record(
+        &mut self,
+        record: csv::StringRecord,
+    ) -> Result<Option<Transaction>, StatementError> {
+        let headers = match &self.headers {
+            Some(headers) => headers,
+            None => {
+                let headers = self.format.map_headers(&record);
+
+                if headers.iter().any(|header| header == "ID")
+                    && headers.iter().any(|header| header == "Datetime")
+                {
+                    warn_about_schema(&headers)?;
+                    self.headers = Some(headers);
+                } else {
+                    self.preamble
+                        .push(record.iter().collect::<Vec<_>>().join(","));
+                }
+
+                return Ok(None);
+            }
+        };
+
+        if record.iter().all(|field| field.trim().is_empty()) {
+            return Ok(None);
+        }
+
+        let record: TransactionRecord = self
+            .format
+            .normalize_record(headers, record)?
+            .deserialize(Some(headers))
+            .map_err(StatementError::ParseRecordError)?;
+
+        if self.beginning_balance.is_none() {
+            match &record.beginning_balance {
+                Some(beginning_balance) => self.beginning_balance = Some(beginning_balance.clone()),
+                None => {
+                    return Err(StatementError::InvalidRecord(
+                        "Beginning Balance".to_string(),
+                        Box::new(record),
+                    ))
+                }
+            }
+
+            return Ok(None);
+        }
+
+        self.last
+            .replace(record)
+            .map(|record| self.transaction(record))
+            .transpose()
+    }
+
+    /// Converts `record`, which isn't the beginning or ending balance record, to a transaction.
+    fn transaction(&self, record: TransactionRecord) -> Result<Transaction, StatementError> {
+        let mut transaction: Transaction = record.try_into()?;
+        transaction.datetime = self.timezone.localize(&transaction.datetime.naive_utc());
+
+        if !transaction.type_.is_known() {
+            if !self.format.lenient {
+                return Err(StatementError::UnsupportedTransactionType(
+                    transaction.id,
+                    transaction.type_.to_string(),
+                ));
+            }
+
+            warn!(
+                "Transaction {} has unknown type '{}', syncing it like a payment",
+                transaction.id, transaction.type_
+            );
+        }
+
+        Ok(transaction)
+    }
+}
+
+/// UTF-8 byte order mark some statements start with.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Fingerprints a statement's header row, warning if it isn't a known format and failing if
+/// columns needed to parse it are missing.
+fn warn_about_schema(headers: &csv::StringRecord) -> Result<(), StatementError> {
+    let schema = detect_statement_schema(headers)?;
+
+    if schema.version.is_none() {
+        warn!("Unrecognized Venmo statement format, Venmo may have changed their CSV format.");
+
+        if !schema.unknown_columns.is_empty() {
+            warn!(
+                "Unrecognized columns (ignored): {:?}. If a column was renamed, map it to its old name in `venmo.statement_format.headers` in the config file.",
+                schema.unknown_columns
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the device ID to identify ourselves to Venmo with. Venmo only asks for 2FA when it
+/// hasn't seen a device before, so the ID is persisted in the state directory on first use and
+/// reused for every login and logout after. `device_id_override` replaces the persisted ID.
+fn device_id(paths: &Paths, device_id_override: Option<String>) -> Result<String> {
+    let path = paths.state_dir.join("device-id");
+    let stored = fs::read_to_string(&path)
+        .ok()
+        .map(|device_id| device_id.trim().to_string())
+        .filter(|device_id| !device_id.is_empty());
+
+    let device_id = match (device_id_override, &stored) {
+        (Some(device_id), _) => device_id,
+        (None, Some(stored)) => return Ok(stored.clone()),
+        // Before the ID was persisted the machine ID was used, keep using it so upgrading doesn't
+        // make Venmo see a new device and ask for 2FA again.
+        (None, None) => {
+            machine_uid::get().unwrap_or_else(|_| uuid::Uuid::new_v4().simple().to_string())
+        }
     };
 
-    let mut rdr = csv::Reader::from_reader(reader);
+    if stored.as_deref() != Some(device_id.as_str()) {
+        ensure_dir(&paths.state_dir)?;
+        fs::write(&path, &device_id)
+            .with_context(|| anyhow!("Failed to persist device ID to {:?}", path))?;
+    }
 
-    let mut transactions = Vec::new();
+    Ok(device_id)
+}
 
-    let mut records_iter = rdr.deserialize().peekable();
+/// How Venmo delivers 2FA codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TwoFactorMethod {
+    /// Texted to the account's phone number.
+    Sms,
+    /// Emailed to the account's email address.
+    Email,
+    /// Generated by an authenticator app (TOTP), nothing is sent.
+    App,
+}
 
-    let beginning_record: TransactionRecord = records_iter.next().ok_or_else(|| {
-        anyhow!(
-            "Expected there to be a beginning balance record, found none in response:\n{:#?}",
-            bytes_clone
-        )
-    })??;
+impl TwoFactorMethod {
+    /// Value of `via` when requesting a code, `None` for methods Venmo doesn't send codes for.
+    fn via(self) -> Option<&'static str> {
+        match self {
+            TwoFactorMethod::Sms => Some("sms"),
+            TwoFactorMethod::Email => Some("email"),
+            TwoFactorMethod::App => None,
+        }
+    }
+}
 
-    let beginning_balance = beginning_record.beginning_balance.ok_or_else(|| {
-        anyhow!(
-            "Expected 'Beginning Balance' to be set for the first record, got response:\n{:#?}",
-            bytes_clone
-        )
-    })?;
+impl FromStr for TwoFactorMethod {
+    type Err = anyhow::Error;
 
-    let ending_balance = loop {
-        let record: TransactionRecord = records_iter.next().ok_or_else(|| {
-            anyhow!(
-                "Expected there to be an ending balance record, found none in response:\n{:#?}",
-                bytes_clone
-            )
-        })??;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "sms" | "text" => TwoFactorMethod::Sms,
+            "email" => TwoFactorMethod::Email,
+            "app" | "totp" => TwoFactorMethod::App,
+            _ => bail!("Unknown 2FA method '{}', expected sms, email, or app", s),
+        })
+    }
+}
 
-        // We're at our last record, meaning this should be the ending balance record.
-        if records_iter.peek().is_none() {
-            break record.ending_balance.ok_or_else(|| {
-                anyhow!(
-                    "Expected 'Ending Balance' to be set for the last record, got response:\n{:#?}",
-                    bytes_clone
-                )
-            })?;
+impl fmt::Display for TwoFactorMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TwoFactorMethod::Sms => "text message",
+            TwoFactorMethod::Email => "email",
+            TwoFactorMethod::App => "authenticator app",
+        })
+    }
+}
+
+/// Returns the 2FA methods offered in the `error.data.options` of a 2FA challenge `response`,
+/// each either a method name or an object with a `via` or `type` field. Unknown methods are
+/// skipped.
+fn twofa_methods(response: &Value) -> Vec<TwoFactorMethod> {
+    let options = response
+        .get("error")
+        .and_then(|error| error.get("data"))
+        .and_then(|data| data.get("options"))
+        .and_then(|options| options.as_array());
+
+    let mut methods = Vec::new();
+
+    for option in options.into_iter().flatten() {
+        let name = option
+            .as_str()
+            .or_else(|| option.get("via").and_then(|via| via.as_str()))
+            .or_else(|| option.get("type").and_then(|type_| type_.as_str()));
+
+        if let Some(method) = name.and_then(|name| name.parse().ok()) {
+            if !methods.contains(&method) {
+                methods.push(method);
+            }
         }
+    }
 
-        let record_clone = record.clone();
-        transactions.push(record.try_into().with_context(|| {
-            anyhow!(
-                "Failed to convert TransactionRecord to Transaction: {:#?}",
-                record_clone
-            )
-        })?);
+    methods
+}
+
+/// Picks the 2FA method to use from those offered in the challenge, asking which one when there
+/// are several and we're running in a terminal. Falls back to text message, which Venmo always
+/// offered before it supported anything else.
+fn choose_twofa_method(offered: &[TwoFactorMethod], interactive: bool) -> Result<TwoFactorMethod> {
+    match offered {
+        [] => Ok(TwoFactorMethod::Sms),
+        [method] => Ok(*method),
+        _ if interactive => {
+            let choice = Select::new()
+                .with_prompt("Get a 2FA code by")
+                .items(offered)
+                .default(0)
+                .interact()?;
+
+            Ok(offered[choice])
+        }
+        _ => Ok(offered
+            .iter()
+            .copied()
+            .find(|method| *method == TwoFactorMethod::Sms)
+            .unwrap_or(offered[0])),
+    }
+}
+
+/// The device login requests are sent from, identified to Venmo by `device_id` (see
+/// `device_id`), and the extra `headers` sent with each of them.
+#[derive(Debug, Clone, Copy)]
+struct LoginDevice<'a> {
+    device_id: &'a str,
+    headers: &'a HeaderMap,
+}
+
+impl LoginDevice<'_> {
+    /// A JSON `POST` of `body` to `uri` from this device.
+    fn post(&self, uri: &str, body: body::Body) -> Request<body::Body> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header("device-id", self.device_id)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap();
+        request.headers_mut().extend(self.headers.clone());
+
+        request
+    }
+}
+
+/// Has Venmo send a 2FA code for the login identified by `otp_secret` using `method`. Does
+/// nothing for authenticator apps.
+async fn request_twofa_code(
+    client: &impl HttpTransport,
+    device: LoginDevice<'_>,
+    otp_secret: &HeaderValue,
+    method: TwoFactorMethod,
+) -> Result<()> {
+    let via = match method.via() {
+        Some(via) => via,
+        None => {
+            println!("Two-factor auth required, using authenticator app...");
+            return Ok(());
+        }
     };
 
-    Ok(Statement {
-        beginning_balance,
-        ending_balance,
-        transactions,
-    })
+    println!("Two-factor auth required, using {}...", method);
+
+    let twofa_request = json!({
+        "via": via
+    });
+
+    let mut twofa_request = device.post(
+        "https://api.venmo.com/v1/account/two-factor/token",
+        serde_json::to_vec(&twofa_request)?.into(),
+    );
+    twofa_request
+        .headers_mut()
+        .insert("venmo-otp-secret", otp_secret.clone());
+
+    let twofa_response = request_following_redirects(client, twofa_request).await?;
+    let twofa_bytes = body::to_bytes(twofa_response).await?;
+    let twofa_response: Value = serde_json::from_slice(&twofa_bytes)?;
+
+    if let Some(val) = twofa_response
+        .get("data")
+        .and_then(|data| data.get("status"))
+    {
+        if val != "sent" {
+            bail!(
+                "Failed to request 2FA code, response was: {:?}",
+                redact::json(&twofa_response)
+            );
+        }
+    } else {
+        bail!(
+            "Failed to request 2FA code, response was: {:?}",
+            redact::json(&twofa_response)
+        );
+    }
+
+    Ok(())
+}
+
+/// Times the login is retried while waiting for the device to be confirmed when not running in
+/// a terminal. Each retry sends the password again, so there are only a few, further and further
+/// apart.
+const DEVICE_CONFIRMATION_RETRIES: u32 = 4;
+
+/// Wait before the first of those retries, doubled for each one after it.
+const DEVICE_CONFIRMATION_FIRST_DELAY: Duration = Duration::from_secs(30);
+
+/// A challenge to confirm the device logging in by following a link Venmo emailed, which Venmo
+/// sometimes responds to logins from new devices with instead of asking for a 2FA code.
+#[derive(Debug, PartialEq, Eq)]
+struct DeviceConfirmation {
+    /// Where Venmo sent the link, usually partly masked, if it says.
+    email: Option<String>,
+}
+
+impl DeviceConfirmation {
+    /// What to do to confirm the device.
+    fn instructions(&self, interactive: bool) -> String {
+        let mut instructions = format!(
+            "Venmo wants to confirm this device before logging in, and emailed a link to {}. \
+             Open the email and follow the link (on any device) to approve this login.",
+            self.email
+                .as_deref()
+                .unwrap_or("your account's email address")
+        );
+
+        if interactive {
+            instructions += " Press Enter once you have.";
+        } else {
+            let delays = device_confirmation_delays();
+            instructions += &format!(
+                " Retrying the login {} times over the next {}...",
+                delays.len(),
+                humantime::format_duration(delays.iter().sum())
+            );
+        }
+
+        instructions
+    }
+}
+
+/// How long to wait before each retry while waiting for the device to be confirmed when not
+/// running in a terminal.
+fn device_confirmation_delays() -> Vec<Duration> {
+    (0..DEVICE_CONFIRMATION_RETRIES)
+        .map(|retry| DEVICE_CONFIRMATION_FIRST_DELAY * 2u32.pow(retry))
+        .collect()
+}
+
+/// Parses a login `response` asking to confirm the device by email, `None` for any other
+/// response, including 2FA challenges and other errors that mention email.
+fn device_confirmation(response: &Value) -> Option<DeviceConfirmation> {
+    let data = response.get("error")?.get("data")?;
+
+    let kind = data
+        .get("verification_type")
+        .or_else(|| data.get("type"))
+        .and_then(Value::as_str)?;
+    if !kind.eq_ignore_ascii_case("device_confirmation") {
+        return None;
+    }
+
+    let email = data
+        .get("masked_email")
+        .or_else(|| data.get("email"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    Some(DeviceConfirmation { email })
+}
+
+/// Asks Venmo for an API token with `username` and `password` from `device`, returning the
+/// response and its `venmo-otp-secret` header.
+async fn request_access_token(
+    client: &impl HttpTransport,
+    device: LoginDevice<'_>,
+    username: &str,
+    password: &str,
+) -> Result<(Option<HeaderValue>, Value)> {
+    let request = json!({
+        "phone_email_or_username": username,
+        "client_id": "1",
+        "password": password,
+    });
+
+    let request = device.post(
+        "https://api.venmo.com/v1/oauth/access_token",
+        serde_json::to_vec(&request)?.into(),
+    );
+
+    let response = request_following_redirects(client, request).await?;
+
+    let otp_secret = response.headers().get("venmo-otp-secret").cloned();
+    let bytes = body::to_bytes(response).await?;
+    let response: Value = serde_json::from_slice(&bytes)?;
+
+    Ok((otp_secret, response))
+}
+
+/// Retries logging in after each of `delays` until Venmo stops asking to confirm the device,
+/// returning the first other response, e.g. an API token or a 2FA challenge.
+async fn retry_device_confirmation(
+    client: &impl HttpTransport,
+    device: LoginDevice<'_>,
+    (username, password): (&str, &str),
+    delays: &[Duration],
+) -> Result<(Option<HeaderValue>, Value)> {
+    for delay in delays {
+        tokio::time::sleep(*delay).await;
+
+        let (otp_secret, response) =
+            request_access_token(client, device, username, password).await?;
+        if device_confirmation(&response).is_none() {
+            return Ok((otp_secret, response));
+        }
+
+        debug!("Device not confirmed yet");
+    }
+
+    bail!(
+        "Venmo didn't confirm the device, follow the link in Venmo's email and run \
+         get-venmo-api-token again"
+    );
+}
+
+/// Credentials and answers for `cmd_get_venmo_api_token`, anything left unset is prompted for.
+#[derive(Debug, Default)]
+pub struct LoginOptions {
+    /// Device ID to log in with, see `device_id`.
+    pub device_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// 2FA code to submit instead of having one sent. If a code is sent and stdin isn't a
+    /// terminal, it is read as a line from stdin.
+    pub otp_code: Option<String>,
+    /// How to get a 2FA code, chosen from what Venmo offers if not set.
+    pub twofa_method: Option<TwoFactorMethod>,
+    /// Skip confirming the risks of API tokens.
+    pub accept_risk: bool,
+    /// Extra headers to send with login requests, see `request_headers`.
+    pub headers: HeaderMap,
+}
+
+/// An API token and the profile it belongs to, from logging in with `log_in`.
+#[derive(Debug)]
+pub struct VenmoLogin {
+    pub profile_id: String,
+    pub api_token: String,
+}
+
+/// Logs in to Venmo and prints an API token and profile ID for syncing, prompting for anything
+/// not given in `options`.
+pub async fn cmd_get_venmo_api_token(
+    client: &impl HttpTransport,
+    paths: &Paths,
+    options: LoginOptions,
+) -> Result<()> {
+    let login = log_in(client, paths, options).await?;
+
+    println!("Venmo profile ID: {}", login.profile_id);
+    println!("Venmo API token: {}", login.api_token);
+
+    Ok(())
 }
 
-pub async fn cmd_get_venmo_api_token(client: &HttpsClient) -> Result<()> {
+/// Logs in to Venmo for a new API token, prompting for anything not given in `options`.
+pub async fn log_in(
+    client: &impl HttpTransport,
+    paths: &Paths,
+    options: LoginOptions,
+) -> Result<VenmoLogin> {
     println!("** TREAT VENMO API TOKENS LIKE YOUR VENMO PASSWORD, DO NOT SHARE IT WITH ANYONE AND KEEP IT SECURE. ANYONE WITH THIS API TOKEN HAS FULL ACCESS TO YOUR ACCOUNT, INCLUDING SENDING TRANSACTIONS. API TOKENS ARE NOT AUTOMATICALLY INVALIDATED, YOU MUST USE `logout-venmo-api-token` TO INVALIDATE THEM WHEN YOU ARE DONE WITH THEM. **\n");
 
-    if !Confirm::new()
-        .with_prompt("Do you understand the risk?")
-        .default(false)
-        .wait_for_newline(true)
-        .interact()?
-    {
-        bail!("Risk was not acknowledged.");
+    let interactive = io::stdin().is_terminal();
+
+    if !options.accept_risk {
+        if !interactive {
+            bail!("Pass --accept-risk to acknowledge the risk when not running in a terminal.");
+        }
+
+        if !Confirm::new()
+            .with_prompt("Do you understand the risk?")
+            .default(false)
+            .wait_for_newline(true)
+            .interact()?
+        {
+            bail!("Risk was not acknowledged.");
+        }
     }
 
-    let username: String = Input::new()
-        .with_prompt("Venmo email/phone number")
-        .interact_text()?;
-    let password: String = Password::new().with_prompt("Venmo password").interact()?;
+    let username: String = match options.username {
+        Some(username) => username,
+        None if interactive => Input::new()
+            .with_prompt("Venmo email/phone number")
+            .interact_text()?,
+        None => bail!("--username must be set when not running in a terminal"),
+    };
+    let password: String = match options.password {
+        Some(password) => password,
+        None if interactive => Password::new().with_prompt("Venmo password").interact()?,
+        None => bail!("--password must be set when not running in a terminal"),
+    };
 
-    let machine_id = machine_uid::get().unwrap();
+    let machine_id = device_id(paths, options.device_id)?;
+    let device = LoginDevice {
+        device_id: &machine_id,
+        headers: &options.headers,
+    };
 
-    let request = json!({
-        "phone_email_or_username": username,
-        "client_id": "1",
-        "password": password,
-    });
+    let (mut otp_secret, mut response) =
+        request_access_token(client, device, &username, &password).await?;
 
-    let request = Request::builder()
-        .method(Method::POST)
-        .uri("https://api.venmo.com/v1/oauth/access_token")
-        .header("device-id", machine_id.clone())
-        .header(CONTENT_TYPE, "application/json")
-        .body(serde_json::to_vec(&request)?.into())
-        .unwrap();
+    if let Some(challenge) = device_confirmation(&response) {
+        println!("{}", challenge.instructions(interactive));
 
-    let response = client.request(request).await?;
+        let delays = if interactive {
+            io::stdin()
+                .read_line(&mut String::new())
+                .context("Failed to wait for Enter")?;
+            vec![Duration::ZERO]
+        } else {
+            device_confirmation_delays()
+        };
+        (otp_secret, response) =
+            retry_device_confirmation(client, device, (&username, &password), &delays).await?;
 
-    let otp_secret = response.headers().get("venmo-otp-secret").cloned();
-    let bytes = body::to_bytes(response).await?;
-    let response: Value = serde_json::from_slice(&bytes)?;
+        println!("Device confirmed.");
+    }
 
     let api_token_response = if let Some(error) = response.get("error") {
         let message = if let Some(message) = error.get("message") {
             message.as_str().ok_or_else(|| {
                 anyhow!(
                     "Failed to parse 'message' field, response was: {:?}",
-                    response
+                    redact::json(&response)
                 )
             })?
         } else {
             bail!(
                 "Failed to get 'message' field, response was: {:?}",
-                response
+                redact::json(&response)
             );
         };
 
@@ -194,69 +1553,54 @@ pub async fn cmd_get_venmo_api_token(client: &HttpsClient) -> Result<()> {
         }
 
         if message != "Additional authentication is required." {
-            bail!("Unknown response: {:?}", response);
+            bail!("Unknown response: {:?}", redact::json(&response));
         }
 
         let otp_secret = otp_secret.ok_or_else(|| {
             anyhow!("2FA required, but did not get venmo-otp-secret in header...")
         })?;
 
-        println!("Two-factor auth required, using text message...");
-
-        let twofa_request = json!({
-            "via": "sms"
-        });
+        let twofa_code = match options.otp_code {
+            Some(twofa_code) => twofa_code,
+            None => {
+                let method = match options.twofa_method {
+                    Some(method) => method,
+                    None => choose_twofa_method(&twofa_methods(&response), interactive)?,
+                };
 
-        let twofa_request = Request::builder()
-            .method(Method::POST)
-            .uri("https://api.venmo.com/v1/account/two-factor/token")
-            .header("device-id", machine_id.clone())
-            .header(CONTENT_TYPE, "application/json")
-            .header("venmo-otp-secret", otp_secret.clone())
-            .body(serde_json::to_vec(&twofa_request)?.into())
-            .unwrap();
+                request_twofa_code(client, device, &otp_secret, method).await?;
 
-        let twofa_response = client.request(twofa_request).await?;
-        let twofa_bytes = body::to_bytes(twofa_response).await?;
-        let twofa_response: Value = serde_json::from_slice(&twofa_bytes)?;
+                if interactive {
+                    Input::new().with_prompt("2FA code").interact_text()?
+                } else {
+                    println!("Reading 2FA code from stdin...");
 
-        if let Some(val) = twofa_response
-            .get("data")
-            .and_then(|data| data.get("status"))
-        {
-            if val != "sent" {
-                bail!(
-                    "Failed to request 2FA code, response was: {:?}",
-                    twofa_response
-                );
+                    let mut line = String::new();
+                    io::stdin()
+                        .read_line(&mut line)
+                        .context("Failed to read 2FA code from stdin")?;
+                    line.trim().to_string()
+                }
             }
-        } else {
-            bail!(
-                "Failed to request 2FA code, response was: {:?}",
-                twofa_response
-            );
-        }
-
-        let twofa_code: String = Input::new().with_prompt("2FA code").interact_text()?;
+        };
 
-        let twofa_submit_request = Request::builder()
-            .method(Method::POST)
-            .uri("https://api.venmo.com/v1/oauth/access_token?client_id=1")
-            .header("device-id", machine_id)
-            .header(CONTENT_TYPE, "application/json")
-            .header("venmo-otp-secret", otp_secret)
-            .header("Venmo-Otp", twofa_code)
-            .body(body::Body::empty())
-            .unwrap();
+        let mut twofa_submit_request = device.post(
+            "https://api.venmo.com/v1/oauth/access_token?client_id=1",
+            body::Body::empty(),
+        );
+        let submit_headers = twofa_submit_request.headers_mut();
+        submit_headers.insert("venmo-otp-secret", otp_secret);
+        submit_headers.insert("Venmo-Otp", HeaderValue::from_str(&twofa_code)?);
 
-        let twofa_submit_response = client.request(twofa_submit_request).await?;
+        let twofa_submit_response =
+            request_following_redirects(client, twofa_submit_request).await?;
         let twofa_submit_bytes = body::to_bytes(twofa_submit_response).await?;
         let twofa_submit_response: Value = serde_json::from_slice(&twofa_submit_bytes)?;
 
         if let Some(_error) = twofa_submit_response.get("error") {
             bail!(
                 "Failed to confirm 2FA code, response was: {:?}",
-                twofa_submit_response
+                redact::json(&twofa_submit_response)
             );
         }
 
@@ -269,13 +1613,13 @@ pub async fn cmd_get_venmo_api_token(client: &HttpsClient) -> Result<()> {
         token.as_str().ok_or_else(|| {
             anyhow!(
                 "Failed to parse 'access_token' field, response was: {:?}",
-                api_token_response
+                redact::json(&api_token_response)
             )
         })?
     } else {
         bail!(
             "Did not get error but no 'access_token' field was found, response was: {:?}",
-            api_token_response
+            redact::json(&api_token_response)
         );
     };
 
@@ -286,34 +1630,872 @@ pub async fn cmd_get_venmo_api_token(client: &HttpsClient) -> Result<()> {
         id.as_str().ok_or_else(|| {
             anyhow!(
                 "Failed to parse user.id, response was: {:?}",
-                api_token_response
+                redact::json(&api_token_response)
             )
         })?
     } else {
         bail!(
             "Did not get error but no 'user.id' field was found, response was: {:?}",
-            api_token_response
+            redact::json(&api_token_response)
         );
     };
 
-    println!("Venmo profile ID: {}", profile_id);
-    println!("Venmo API token: {}", access_token);
-
-    Ok(())
+    Ok(VenmoLogin {
+        profile_id: profile_id.to_string(),
+        api_token: access_token.to_string(),
+    })
 }
 
-pub async fn cmd_logout_venmo_api_token(client: &HttpsClient, api_token: &str) -> Result<()> {
+/// Invalidates Venmo API token `api_token`, identifying ourselves with the same device ID as
+/// `cmd_get_venmo_api_token`.
+pub async fn cmd_logout_venmo_api_token(
+    client: &impl HttpTransport,
+    paths: &Paths,
+    api_token: &str,
+    device_id_override: Option<String>,
+) -> Result<()> {
+    let machine_id = device_id(paths, device_id_override)?;
+
     let request = Request::builder()
         .method(Method::DELETE)
         .uri("https://api.venmo.com/v1/oauth/access_token")
+        .header("device-id", machine_id)
         .header(AUTHORIZATION, api_token)
         .body(body::Body::empty())
         .unwrap();
 
-    let response = client.request(request).await?;
+    let response = request_following_redirects(client, request).await?;
     let bytes = body::to_bytes(response).await?;
     let response: Value = serde_json::from_slice(&bytes)?;
 
-    println!("Response: {:?}", response);
+    println!("Response: {:?}", redact::json(&response));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::http::fake::FakeTransport;
+    use crate::testing;
+    use crate::types::venmo::{ConvertOptions, Locale, TransactionStatus, TransactionType};
+
+    fn parse_fixture(bytes: &[u8]) -> Statement {
+        parse_venmo_statement(bytes, &StatementFormat::default()).unwrap()
+    }
+
+    fn assert_basic_statement(statement: &Statement) {
+        assert_eq!(statement.beginning_balance.val, Decimal::new(1205, 1));
+        assert_eq!(statement.ending_balance.val, Decimal::new(1055, 1));
+        assert_eq!(
+            statement
+                .transactions
+                .iter()
+                .map(|transaction| transaction.id)
+                .collect::<Vec<_>>(),
+            vec![
+                3581234567890123456,
+                3581234567890123457,
+                3581234567890123458
+            ]
+        );
+        assert_eq!(
+            statement.transactions[1].amount_total.val,
+            Decimal::new(-80, 0)
+        );
+        assert_eq!(
+            statement.transactions[1].funding_source.as_deref(),
+            Some("Chase Checking")
+        );
+    }
+
+    #[test]
+    fn parses_basic_statement() {
+        let statement = parse_fixture(include_bytes!("../tests/fixtures/statement_basic.csv"));
+
+        assert_basic_statement(&statement);
+        assert_eq!(statement.transactions[0].note.as_deref(), Some("Dinner"));
+    }
+
+    #[test]
+    fn parses_statement_with_bom_and_crlf() {
+        let statement = parse_fixture(include_bytes!("../tests/fixtures/statement_bom_crlf.csv"));
+
+        assert_basic_statement(&statement);
+        assert_eq!(statement.transactions[0].note.as_deref(), Some("Dinner"));
+    }
+
+    #[test]
+    fn parses_statement_with_quoted_note() {
+        let statement = parse_fixture(include_bytes!(
+            "../tests/fixtures/statement_quoted_note.csv"
+        ));
+
+        assert_basic_statement(&statement);
+        assert_eq!(
+            statement.transactions[0].note.as_deref(),
+            Some("Dinner, drinks, and \"dessert\"\nat Luigi's")
+        );
+    }
+
+    #[test]
+    fn parses_statements_as_they_arrive() {
+        let format = StatementFormat::default();
+
+        for fixture in [
+            &include_bytes!("../tests/fixtures/statement_bom_crlf.csv")[..],
+            &include_bytes!("../tests/fixtures/statement_quoted_note.csv")[..],
+        ] {
+            // Chunk boundaries fall inside the BOM, quoted fields, and CRLFs.
+            let mut parser = StatementParser::new(&format).unwrap();
+            let mut transactions = Vec::new();
+            for chunk in fixture.chunks(2) {
+                transactions.extend(parser.push(chunk).unwrap());
+            }
+            let (rest, beginning_balance, ending_balance) = parser.finish().unwrap();
+            transactions.extend(rest);
+
+            assert_eq!(
+                transactions[0].note,
+                parse_fixture(fixture).transactions[0].note
+            );
+            assert_basic_statement(&Statement {
+                beginning_balance,
+                ending_balance,
+                transactions,
+            });
+        }
+    }
+
+    #[test]
+    fn parses_localized_statement() {
+        let fixture: &[u8] = include_bytes!("../tests/fixtures/statement_de.csv");
+
+        let statement = parse_venmo_statement(
+            fixture,
+            &StatementFormat {
+                locale: Some(Locale::DeDe),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_basic_statement(&statement);
+        assert_eq!(
+            statement.transactions[0].datetime.naive_local(),
+            NaiveDate::from_ymd_opt(2022, 7, 1)
+                .unwrap()
+                .and_hms_opt(18, 30, 12)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_statement_with_renamed_columns() {
+        let fixture = include_str!("../tests/fixtures/statement_basic.csv")
+            .replace(",Amount (total),", ",Total Amount,")
+            .replace(",Funding Source,", ",Paid With,");
+
+        let err =
+            parse_venmo_statement(fixture.as_bytes(), &StatementFormat::default()).unwrap_err();
+        assert!(matches!(err, Error::ParseError { .. }), "{}", err);
+
+        let statement = parse_venmo_statement(
+            fixture.as_bytes(),
+            &StatementFormat {
+                headers: [
+                    ("Total Amount".to_string(), "Amount (total)".to_string()),
+                    ("Paid With".to_string(), "Funding Source".to_string()),
+                ]
+                .into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_basic_statement(&statement);
+    }
+
+    #[test]
+    fn parses_statement_with_extra_preamble() {
+        let statement = parse_fixture(include_bytes!(
+            "../tests/fixtures/statement_extra_preamble.csv"
+        ));
+
+        assert_basic_statement(&statement);
+    }
+
+    #[test]
+    fn parses_other_transaction_types_when_lenient() {
+        let fixture: &[u8] = include_bytes!("../tests/fixtures/statement_new_types.csv");
+
+        let err = parse_venmo_statement(fixture, &StatementFormat::default()).unwrap_err();
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("Crypto Purchase"));
+
+        let statement = parse_venmo_statement(
+            fixture,
+            &StatementFormat {
+                lenient: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let payees = statement
+            .transactions
+            .iter()
+            .map(|transaction| {
+                transaction
+                    .to_lunchmoney_transactions(
+                        *rusty_money::iso::USD,
+                        1,
+                        &ConvertOptions::default(),
+                    )
+                    .unwrap()[0]
+                    .payee
+                    .clone()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            payees,
+            vec![
+                "ACME Payroll",
+                "Venmo Credit Card",
+                "Shoe Store",
+                "Venmo Cashback",
+                "Crypto Purchase"
+            ]
+        );
+        assert_eq!(
+            statement.transactions[4].type_,
+            TransactionType::Unknown("Crypto Purchase".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_typed_errors() {
+        let err =
+            parse_venmo_statement(b"Account Statement\nnot,a,statement\n", &Default::default())
+                .unwrap_err();
+        let source = match &err {
+            Error::ParseError { source, .. } => source.downcast_ref::<StatementError>(),
+            _ => None,
+        };
+        assert!(
+            matches!(source, Some(StatementError::MissingHeaderRow(_))),
+            "{:?}",
+            err
+        );
+
+        let store = StatementStore {
+            from_cache: true,
+            ..Default::default()
+        };
+        let err = fetch_venmo_transactions(
+            &FakeTransport::with_responses(vec![]),
+            &account(),
+            &StatementFormat::default(),
+            &store,
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::MissingCacheDir), "{:?}", err);
+    }
+
+    #[test]
+    fn parses_business_statement() {
+        let statement = parse_fixture(include_bytes!("../tests/fixtures/statement_business.csv"));
+
+        assert_eq!(statement.beginning_balance.val, Decimal::new(50, 0));
+        assert_eq!(statement.ending_balance.val, Decimal::new(2049, 2));
+
+        let purchase = &statement.transactions[0];
+        assert_eq!(purchase.amount_total.val, Decimal::new(1049, 2));
+        assert_eq!(
+            purchase.amount_tax.as_ref().map(|tax| tax.val),
+            Some(Decimal::new(80, 2))
+        );
+        assert_eq!(
+            purchase.amount_fee.as_ref().map(|fee| fee.val),
+            Some(Decimal::new(-31, 2))
+        );
+        assert!(purchase.amount_tip.is_none());
+
+        let transfer = &statement.transactions[1];
+        assert_eq!(transfer.amount_total.val, Decimal::new(-40, 0));
+        assert!(transfer.amount_fee.is_none());
+
+        let date = date("2023-03-01T00:00:00Z");
+        let request = StatementRequest {
+            account: &account(),
+            account_type: AccountType::Business,
+            start_date: &date,
+            end_date: &date,
+        };
+        assert!(request
+            .url(crate::types::venmo::DEFAULT_STATEMENT_ENDPOINTS[0])
+            .ends_with("profileId=1234567890&accountType=business"));
+    }
+
+    #[test]
+    fn parses_other_transaction_statuses() {
+        let statement = parse_fixture(include_bytes!("../tests/fixtures/statement_statuses.csv"));
+
+        assert_eq!(
+            statement
+                .transactions
+                .iter()
+                .map(|transaction| transaction.status.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                TransactionStatus::Pending,
+                TransactionStatus::Failed,
+                TransactionStatus::Cancelled
+            ]
+        );
+        assert!(TransactionStatus::DEFAULT_SKIPPED.contains(&statement.transactions[2].status));
+    }
+
+    #[test]
+    fn rejects_statement_without_header_row() {
+        assert!(parse_venmo_statement(
+            b"Unable to fetch transaction history\n",
+            &StatementFormat::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn splits_long_ranges_into_statement_windows() {
+        let start_date = DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let end_date = start_date + chrono::Duration::days(200);
+
+        let windows = statement_windows(&start_date, &end_date);
+
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0].0, start_date);
+        assert_eq!(windows[2].1, end_date);
+
+        for (window_start, window_end) in &windows {
+            assert!(*window_end - *window_start < chrono::Duration::days(MAX_STATEMENT_DAYS));
+        }
+
+        for pair in windows.windows(2) {
+            assert_eq!(pair[1].0 - pair[0].1, chrono::Duration::days(1));
+        }
+
+        assert_eq!(
+            statement_windows(&start_date, &(start_date + chrono::Duration::days(30))),
+            vec![(start_date, start_date + chrono::Duration::days(30))]
+        );
+    }
+
+    #[test]
+    fn parses_offered_twofa_methods() {
+        let challenge = json!({
+            "error": {
+                "message": "Additional authentication is required.",
+                "data": {"options": ["sms", {"via": "email"}, {"type": "totp"}, "carrier-pigeon", "sms"]},
+            }
+        });
+
+        assert_eq!(
+            twofa_methods(&challenge),
+            vec![
+                TwoFactorMethod::Sms,
+                TwoFactorMethod::Email,
+                TwoFactorMethod::App
+            ]
+        );
+        assert_eq!(twofa_methods(&json!({"error": {}})), vec![]);
+
+        assert_eq!(
+            choose_twofa_method(&[], false).unwrap(),
+            TwoFactorMethod::Sms
+        );
+        assert_eq!(
+            choose_twofa_method(&[TwoFactorMethod::App, TwoFactorMethod::Sms], false).unwrap(),
+            TwoFactorMethod::Sms
+        );
+    }
+
+    #[test]
+    fn parses_device_confirmations() {
+        let challenge = json!({
+            "error": {
+                "message": "Please confirm this device using the link we emailed you.",
+                "data": {"verification_type": "device_confirmation", "masked_email": "a***@example.com"},
+            }
+        });
+        assert_eq!(
+            device_confirmation(&challenge),
+            Some(DeviceConfirmation {
+                email: Some("a***@example.com".to_string())
+            })
+        );
+
+        let challenge = json!({
+            "error": {"message": "Verification required.", "data": {"type": "DEVICE_CONFIRMATION"}}
+        });
+        assert_eq!(
+            device_confirmation(&challenge),
+            Some(DeviceConfirmation { email: None })
+        );
+
+        let twofa = json!({
+            "error": {
+                "message": "Additional authentication is required.",
+                "data": {"options": ["sms", "email"]},
+            }
+        });
+        assert_eq!(device_confirmation(&twofa), None);
+        let unverified_email = json!({
+            "error": {"message": "Please confirm your email address before logging in."}
+        });
+        assert_eq!(device_confirmation(&unverified_email), None);
+        assert_eq!(device_confirmation(&json!({"access_token": "token"})), None);
+    }
+
+    #[tokio::test]
+    async fn retries_until_device_is_confirmed() {
+        let challenge = br#"{"error": {"data": {"type": "DEVICE_CONFIRMATION"}}}"#;
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::BAD_REQUEST, challenge),
+            (
+                StatusCode::OK,
+                br#"{"access_token": "secret-venmo-token", "user": {"id": "1234"}}"#,
+            ),
+        ]);
+        let headers = HeaderMap::new();
+        let device = LoginDevice {
+            device_id: "device",
+            headers: &headers,
+        };
+        let credentials = ("user@example.com", "password");
+
+        let (_, response) =
+            retry_device_confirmation(&transport, device, credentials, &[Duration::ZERO; 3])
+                .await
+                .unwrap();
+
+        assert_eq!(response["access_token"], "secret-venmo-token");
+        {
+            let requests = transport.requests.lock().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert_eq!(requests[1].headers["device-id"], "device");
+        }
+
+        let transport =
+            FakeTransport::with_responses(vec![(StatusCode::BAD_REQUEST, challenge); 2]);
+        let err = retry_device_confirmation(&transport, device, credentials, &[Duration::ZERO; 2])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("didn't confirm the device"));
+        assert_eq!(transport.requests.lock().unwrap().len(), 2);
+
+        let delays = device_confirmation_delays();
+        assert_eq!(delays.len(), DEVICE_CONFIRMATION_RETRIES as usize);
+        assert_eq!(delays[1], delays[0] * 2);
+    }
+
+    #[test]
+    fn persists_device_id() {
+        let state_dir = testing::temp_dir();
+        let paths = testing::paths(state_dir.path());
+
+        let generated = device_id(&paths, None).unwrap();
+        assert_eq!(device_id(&paths, None).unwrap(), generated);
+        // Upgrading from versions that didn't persist the ID keeps the same one.
+        if let Ok(machine_id) = machine_uid::get() {
+            assert_eq!(generated, machine_id);
+        }
+
+        assert_eq!(
+            device_id(&paths, Some("my-device".to_string())).unwrap(),
+            "my-device"
+        );
+        assert_eq!(device_id(&paths, None).unwrap(), "my-device");
+    }
+
+    fn account() -> AccountRecord {
+        AccountRecord {
+            profile_id: 1234567890,
+            api_token: "secret-venmo-token".to_string(),
+            currency: *rusty_money::iso::find("USD").unwrap(),
+        }
+    }
+
+    fn date(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[tokio::test]
+    async fn fetches_statement() {
+        let transport = FakeTransport::with_responses(vec![(
+            StatusCode::OK,
+            include_bytes!("../tests/fixtures/statement_basic.csv"),
+        )]);
+
+        let statement = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &StatementStore::default(),
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+
+        assert_basic_statement(&statement);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0]
+            .uri
+            .contains("startDate=07-01-2022&endDate=07-31-2022&profileId=1234567890"));
+    }
+
+    #[tokio::test]
+    async fn stitches_statements_for_long_ranges() {
+        let fixture: &[u8] = include_bytes!("../tests/fixtures/statement_basic.csv");
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, fixture),
+            (StatusCode::OK, fixture),
+            (StatusCode::OK, fixture),
+        ]);
+
+        let statement = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &StatementStore::default(),
+            &date("2022-01-01T00:00:00Z"),
+            &date("2022-07-20T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(transport.requests.lock().unwrap().len(), 3);
+        // Transactions repeated across windows are only kept once.
+        assert_basic_statement(&statement);
+    }
+
+    #[tokio::test]
+    async fn streams_transactions() {
+        use futures::TryStreamExt;
+
+        let fixture: &[u8] = include_bytes!("../tests/fixtures/statement_basic.csv");
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::OK, fixture),
+            (StatusCode::OK, fixture),
+            (StatusCode::NOT_FOUND, b"Not Found"),
+            (StatusCode::NOT_FOUND, b"Not Found"),
+        ]);
+        let (account, format, store) = (
+            account(),
+            StatementFormat::default(),
+            StatementStore::default(),
+        );
+
+        let mut stream = Box::pin(fetch_venmo_transactions_stream(
+            &transport,
+            &account,
+            &format,
+            &store,
+            &date("2022-01-01T00:00:00Z"),
+            &date("2022-09-20T00:00:00Z"),
+        ));
+
+        // Windows are only fetched as the stream is polled.
+        let first = stream.try_next().await.unwrap().unwrap();
+        assert_eq!(first.id, 3581234567890123456);
+        assert_eq!(transport.requests.lock().unwrap().len(), 1);
+
+        let mut ids = vec![first.id];
+        let error = loop {
+            match stream.try_next().await {
+                Ok(Some(transaction)) => ids.push(transaction.id),
+                Ok(None) => panic!("the third window fails"),
+                Err(e) => break e,
+            }
+        };
+
+        // Transactions repeated across windows are only returned once.
+        assert_eq!(
+            ids,
+            vec![
+                3581234567890123456,
+                3581234567890123457,
+                3581234567890123458
+            ]
+        );
+        assert!(matches!(error, Error::Unavailable(_)));
+        assert!(stream.try_next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn replays_cached_statements() {
+        let cache_dir = testing::temp_dir();
+        let mut store = StatementStore {
+            cache_dir: Some(cache_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let transport = FakeTransport::with_responses(vec![(
+            StatusCode::OK,
+            include_bytes!("../tests/fixtures/statement_basic.csv"),
+        )]);
+
+        fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &store,
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+        let cached = cache_dir
+            .path()
+            .join("1234567890-2022-07-01-2022-07-31.csv");
+        assert!(cached.exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&cached).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store.from_cache = true;
+        let transport = FakeTransport::with_responses(vec![]);
+        let statement = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &store,
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+
+        assert_basic_statement(&statement);
+        assert!(transport.requests.lock().unwrap().is_empty());
+
+        // Windows that weren't fetched before aren't fetched now either.
+        assert!(fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &store,
+            &date("2022-08-01T00:00:00Z"),
+            &date("2022-08-31T00:00:00Z"),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn archives_fetched_statements() {
+        let archive_dir = testing::temp_dir();
+        let fixture: &[u8] = include_bytes!("../tests/fixtures/statement_basic.csv");
+        let transport = FakeTransport::with_responses(vec![(StatusCode::OK, fixture)]);
+
+        let statement = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &StatementStore {
+                archive_dir: Some(archive_dir.path().to_path_buf()),
+                ..Default::default()
+            },
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+
+        let mut archived = fs::read_dir(archive_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        archived.sort();
+
+        assert_eq!(archived.len(), 2);
+        assert!(archived[0]
+            .to_str()
+            .unwrap()
+            .contains("1234567890-2022-07-01-2022-07-31-fetched-"));
+        assert_eq!(fs::read(&archived[0]).unwrap(), fixture);
+        let json: Value = serde_json::from_slice(&fs::read(&archived[1]).unwrap()).unwrap();
+        assert_eq!(json, json!(statement));
+        #[cfg(unix)]
+        for path in &archived {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn summarizes_error_pages() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                b"<!DOCTYPE html><html><body>Down for maintenance</body></html>",
+            ),
+            (StatusCode::OK, b"Unable to fetch transaction history"),
+        ]);
+
+        let error = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat {
+                endpoints: vec![
+                    "https://venmo.test/maintenance".to_string(),
+                    "https://venmo.test/statement".to_string(),
+                ],
+                ..Default::default()
+            },
+            &StatementStore::default(),
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap_err();
+        let message = format!("{:#}", error);
+
+        assert!(message.contains("unavailable (it returned an HTML page"));
+        assert!(message.contains("code 503 Service Unavailable"));
+        assert!(message.contains("unavailable (it was unable to fetch transaction history)"));
+        assert!(!message.contains("Down for maintenance"));
+        assert!(matches!(error, Error::Unavailable(_)));
+    }
+
+    #[tokio::test]
+    async fn sends_configured_request_headers() {
+        let transport = FakeTransport::with_responses(vec![(
+            StatusCode::OK,
+            include_bytes!("../tests/fixtures/statement_basic.csv"),
+        )]);
+        let format = StatementFormat {
+            user_agent: Some("Mozilla/5.0".to_string()),
+            request_headers: [
+                ("X-Requested-With".to_string(), "XMLHttpRequest".to_string()),
+                ("Bad Header".to_string(), "ignored".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(format.request_headers().1.len(), 1);
+
+        fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &format,
+            &StatementStore::default(),
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0].headers["user-agent"], "Mozilla/5.0");
+        assert_eq!(requests[0].headers["x-requested-with"], "XMLHttpRequest");
+        assert!(requests[0].headers.contains_key(COOKIE));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_other_statement_endpoints() {
+        let fixture: &[u8] = include_bytes!("../tests/fixtures/statement_basic.csv");
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::NOT_FOUND, b"Not Found"),
+            (StatusCode::OK, fixture),
+            (StatusCode::OK, fixture),
+        ]);
+
+        let statement = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &StatementStore::default(),
+            &date("2022-01-01T00:00:00Z"),
+            &date("2022-04-30T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+
+        assert_basic_statement(&statement);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].uri.starts_with("https://venmo.com/"));
+        assert!(requests[1].uri.starts_with("https://account.venmo.com/"));
+        assert!(requests[1].uri.contains("startDate=2022-01-01"));
+        // The endpoint that worked is tried first for the next window.
+        assert!(requests[2].uri.starts_with("https://account.venmo.com/"));
+    }
+
+    #[tokio::test]
+    async fn redacts_failed_statement_requests() {
+        let error: &[u8] = br#"{"error": "invalid", "access_token": "secret-venmo-token"}"#;
+        let transport = FakeTransport::with_responses(vec![
+            (StatusCode::UNAUTHORIZED, error),
+            (StatusCode::UNAUTHORIZED, error),
+        ]);
+
+        let err = fetch_venmo_transactions(
+            &transport,
+            &account(),
+            &StatementFormat::default(),
+            &StatementStore::default(),
+            &date("2022-07-01T00:00:00Z"),
+            &date("2022-07-31T00:00:00Z"),
+        )
+        .await
+        .unwrap_err();
+
+        let message = format!("{:#}", err);
+        assert!(message.contains("401"));
+        assert!(!message.contains("secret-venmo-token"));
+        assert!(matches!(err, Error::AuthError(_)));
+    }
+
+    #[tokio::test]
+    async fn checks_venmo_token() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"data": {"user": {"id": "1234567890", "username": "jsmith", "display_name": "John Smith"}}}"#,
+            ),
+            (
+                StatusCode::UNAUTHORIZED,
+                br#"{"error": {"message": "Invalid access token."}}"#,
+            ),
+        ]);
+
+        let user = get_venmo_user(&transport, "secret-venmo-token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id, "1234567890");
+        assert_eq!(user.username, "jsmith");
+
+        assert!(get_venmo_user(&transport, "expired-venmo-token")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
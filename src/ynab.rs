@@ -0,0 +1,480 @@
+//! Client for the [YNAB API](https://api.ynab.com), to sync transactions to a YNAB account
+//! instead of Lunch Money.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{body, Method, Request, StatusCode};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rusty_money::iso::Currency;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::{Config, PayeeConfig};
+use crate::http::{request_following_redirects, HttpTransport};
+use crate::payees::Payees;
+use crate::redact;
+use crate::sink::TransactionSink;
+use crate::sync::{balance_drift, SyncSummary};
+use crate::types::lunchmoney::{self, TransactionStatus as LunchMoneyStatus};
+use crate::types::venmo::{ConvertOptions, Statement, TransactionFilter, TransactionStatus};
+use crate::types::HttpsClient;
+
+const YNAB_API: &str = "https://api.ynab.com/v1";
+
+/// YNAB's limits on the lengths of a transaction's fields.
+const MAX_PAYEE_LEN: usize = 50;
+const MAX_MEMO_LEN: usize = 200;
+
+/// The YNAB account to insert transactions into.
+pub struct YnabDestination {
+    pub api_token: String,
+    /// A budget ID, or `last-used`.
+    pub budget_id: String,
+    pub account_id: String,
+    pub payees: PayeeConfig,
+    /// Venmo transactions with these statuses aren't synced.
+    pub skip_statuses: Vec<TransactionStatus>,
+    /// Venmo transactions of types it doesn't match aren't synced.
+    pub filter: TransactionFilter,
+    /// Venmo transactions with these statuses are inserted as cleared.
+    pub cleared_statuses: Vec<TransactionStatus>,
+    /// Only `transfers.shadow_transactions` applies, the other sides of transfers aren't inserted,
+    /// and credit card payments aren't split out.
+    pub convert: ConvertOptions,
+}
+
+impl YnabDestination {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let api_token = config
+            .ynab
+            .api_token
+            .clone()
+            .ok_or_else(|| anyhow!("--ynab-api-token or ynab.api_token must be set"))?;
+        let account_id = config
+            .ynab
+            .account_id
+            .clone()
+            .ok_or_else(|| anyhow!("--ynab-account-id or ynab.account_id must be set"))?;
+
+        Ok(YnabDestination {
+            api_token,
+            budget_id: config
+                .ynab
+                .budget_id
+                .clone()
+                .unwrap_or_else(|| "last-used".to_string()),
+            account_id,
+            payees: config.payees.clone(),
+            skip_statuses: config.sync.skip_statuses()?,
+            filter: config.sync.filter()?,
+            cleared_statuses: config.sync.cleared_statuses()?,
+            convert: ConvertOptions {
+                credit_card_asset_id: None,
+                ..config.convert_options()?
+            },
+        })
+    }
+}
+
+/// A transaction to create, see <https://api.ynab.com/v1#/Transactions/createTransaction>.
+#[derive(Debug, Serialize)]
+struct SaveTransaction {
+    account_id: String,
+    date: NaiveDate,
+    /// In milliunits, e.g. -12340 for -12.34.
+    amount: i64,
+    payee_name: Option<String>,
+    memo: Option<String>,
+    cleared: &'static str,
+    approved: bool,
+    /// YNAB skips transactions with an `import_id` already in the account.
+    import_id: Option<String>,
+}
+
+impl SaveTransaction {
+    fn new(transaction: &lunchmoney::Transaction, account_id: &str) -> Result<Self> {
+        let truncate =
+            |s: &Option<String>, len: usize| s.as_ref().map(|s| s.chars().take(len).collect());
+
+        Ok(SaveTransaction {
+            account_id: account_id.to_string(),
+            date: transaction.date,
+            amount: (transaction.amount.0 * Decimal::from(1000))
+                .round()
+                .to_i64()
+                .ok_or_else(|| anyhow!("Amount {} is too large for YNAB", transaction.amount.0))?,
+            payee_name: truncate(&transaction.payee, MAX_PAYEE_LEN),
+            memo: truncate(&transaction.notes, MAX_MEMO_LEN),
+            cleared: match transaction.status {
+                LunchMoneyStatus::Cleared => "cleared",
+                _ => "uncleared",
+            },
+            approved: false,
+            import_id: transaction.external_id.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionsResponse<T> {
+    data: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountTransactions {
+    transactions: Vec<AccountTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountTransaction {
+    import_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatedTransactions {
+    #[serde(default)]
+    transaction_ids: Vec<String>,
+    #[serde(default)]
+    duplicate_import_ids: Vec<String>,
+}
+
+/// Fails with YNAB's error for non-2xx responses.
+fn check_response(status: StatusCode, bytes: &[u8], action: &str) -> Result<()> {
+    if !status.is_success() {
+        bail!(
+            "Failed to {}, code {}, err:\n{}",
+            action,
+            status,
+            redact::bytes(bytes)
+        );
+    }
+
+    Ok(())
+}
+
+/// Lists the `import_id`s of the transactions in YNAB account `account_id` since `since_date`.
+async fn get_import_ids(
+    client: &impl HttpTransport,
+    destination: &YnabDestination,
+    since_date: NaiveDate,
+) -> Result<Vec<String>> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{}/budgets/{}/accounts/{}/transactions?since_date={}",
+            YNAB_API,
+            destination.budget_id,
+            destination.account_id,
+            since_date.format("%Y-%m-%d")
+        ))
+        .header(AUTHORIZATION, format!("Bearer {}", destination.api_token))
+        .body(body::Body::empty())
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+    check_response(status, &bytes, "get YNAB transactions")?;
+
+    let response: TransactionsResponse<AccountTransactions> = serde_json::from_slice(&bytes)
+        .with_context(|| anyhow!("Failed to parse YNAB transactions"))?;
+
+    Ok(response
+        .data
+        .transactions
+        .into_iter()
+        .filter_map(|transaction| transaction.import_id)
+        .collect())
+}
+
+/// Creates `transactions` in one request. Returns the IDs of the created transactions, and the
+/// `import_id`s YNAB skipped as duplicates.
+async fn create_transactions(
+    client: &impl HttpTransport,
+    destination: &YnabDestination,
+    transactions: &[SaveTransaction],
+) -> Result<CreatedTransactions> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!(
+            "{}/budgets/{}/transactions",
+            YNAB_API, destination.budget_id
+        ))
+        .header(AUTHORIZATION, format!("Bearer {}", destination.api_token))
+        .header(CONTENT_TYPE, "application/json")
+        .body(body::Body::from(serde_json::to_vec(
+            &json!({ "transactions": transactions }),
+        )?))
+        .unwrap();
+
+    let response = request_following_redirects(client, request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response).await?;
+    check_response(status, &bytes, "create YNAB transactions")?;
+
+    let response: TransactionsResponse<CreatedTransactions> = serde_json::from_slice(&bytes)
+        .with_context(|| anyhow!("Failed to parse created YNAB transactions"))?;
+
+    Ok(response.data)
+}
+
+/// Converts `statement` into Lunch Money transactions in asset 0, which stands for the YNAB
+/// account, as configured by `destination`. Returns them, the number of Venmo transactions
+/// skipped because of their status, the number left out because of their type, and the number
+/// of generated transfers.
+fn convert_statement(
+    destination: &YnabDestination,
+    currency: &Currency,
+    statement: &Statement,
+) -> Result<(Vec<lunchmoney::Transaction>, usize, usize, usize)> {
+    let payees = Payees::new(&destination.payees)?;
+    let mut converted = Vec::new();
+    let mut skipped = 0;
+    let mut filtered = 0;
+    let mut transfers = 0;
+
+    for transaction in &statement.transactions {
+        if destination.skip_statuses.contains(&transaction.status) {
+            skipped += 1;
+            continue;
+        }
+
+        if !destination.filter.matches(transaction) {
+            filtered += 1;
+            continue;
+        }
+
+        let mut transactions = transaction
+            .to_lunchmoney_transactions(*currency, 0, &destination.convert)?
+            .into_iter()
+            .filter(|transaction| transaction.asset_id == Some(0))
+            .collect::<Vec<_>>();
+
+        if destination.cleared_statuses.contains(&transaction.status) {
+            for transaction in &mut transactions {
+                transaction.status = LunchMoneyStatus::Cleared;
+            }
+        }
+
+        // Only the first transaction is the payment itself, the rest are transfers.
+        if let Some(primary) = transactions.first_mut() {
+            primary.payee = primary.payee.as_deref().map(|payee| payees.rewrite(payee));
+        }
+
+        transfers += transactions
+            .iter()
+            .filter(|generated| transaction.is_transfer(generated))
+            .count();
+        converted.extend(transactions);
+    }
+
+    Ok((converted, skipped, filtered, transfers))
+}
+
+/// Inserts `statement` into the YNAB account, skipping transactions whose `import_id` is already
+/// there. Category rules and tags are Lunch Money only.
+async fn insert_statement(
+    client: &impl HttpTransport,
+    destination: &YnabDestination,
+    currency: &Currency,
+    statement: Statement,
+    dry_run: bool,
+) -> Result<SyncSummary> {
+    let (transactions, skipped_by_status, filtered, transfers) =
+        convert_statement(destination, currency, &statement)?;
+    let venmo_transactions = statement.transactions.len();
+    let generated = transactions.len();
+
+    let existing = match transactions
+        .iter()
+        .map(|transaction| transaction.date)
+        .min()
+    {
+        Some(since_date) => get_import_ids(client, destination, since_date).await?,
+        None => Vec::new(),
+    };
+    let (synced, new): (Vec<_>, Vec<_>) = transactions.into_iter().partition(|transaction| {
+        matches!(&transaction.external_id, Some(external_id) if existing.contains(external_id))
+    });
+
+    let mut summary = SyncSummary {
+        beginning_balance: statement.beginning_balance.val,
+        ending_balance: statement.ending_balance.val,
+        venmo_transactions,
+        skipped_by_status,
+        filtered,
+        lunch_money_transactions: generated,
+        transfers,
+        new: new.len(),
+        already_synced: synced.len(),
+        changed: Vec::new(),
+        inserted: None,
+        inserted_ids: None,
+        grouped: 0,
+        would_insert: None,
+        net_amount: synced
+            .iter()
+            .chain(&new)
+            .map(|transaction| transaction.amount.0)
+            .sum(),
+        balance_drift: balance_drift(&statement, &destination.skip_statuses),
+        currency: *currency,
+    };
+
+    if dry_run {
+        summary.would_insert = Some(new);
+        return Ok(summary);
+    }
+
+    summary.inserted = Some(0);
+    if !new.is_empty() {
+        let save = new
+            .iter()
+            .map(|transaction| SaveTransaction::new(transaction, &destination.account_id))
+            .collect::<Result<Vec<_>>>()?;
+        let created = create_transactions(client, destination, &save).await?;
+
+        // Inserted since they were listed, e.g. by a concurrent sync.
+        summary.new -= created.duplicate_import_ids.len();
+        summary.already_synced += created.duplicate_import_ids.len();
+        summary.inserted = Some(created.transaction_ids.len());
+    }
+
+    Ok(summary)
+}
+
+#[async_trait]
+impl TransactionSink for YnabDestination {
+    fn name(&self) -> String {
+        format!("YNAB account {}", self.account_id)
+    }
+
+    async fn insert_statement(
+        &self,
+        client: &HttpsClient,
+        currency: &Currency,
+        statement: Statement,
+        dry_run: bool,
+    ) -> Result<SyncSummary> {
+        insert_statement(client, self, currency, statement, dry_run).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::fake::FakeTransport;
+    use crate::testing::{amount, transaction};
+    use crate::types::venmo::TransactionType;
+
+    fn destination() -> YnabDestination {
+        YnabDestination {
+            api_token: "token".to_string(),
+            budget_id: "last-used".to_string(),
+            account_id: "account".to_string(),
+            payees: PayeeConfig::default(),
+            skip_statuses: TransactionStatus::DEFAULT_SKIPPED.to_vec(),
+            filter: TransactionFilter::default(),
+            cleared_statuses: vec![TransactionStatus::Complete],
+            convert: ConvertOptions::default(),
+        }
+    }
+
+    fn statement() -> Statement {
+        let transaction = |id, val| transaction(id, TransactionType::Payment, val);
+
+        Statement {
+            beginning_balance: amount(Decimal::new(100, 0)),
+            ending_balance: amount(Decimal::new(5950, 2)),
+            transactions: vec![
+                transaction(1, Decimal::new(-2050, 2)),
+                transaction(2, Decimal::new(-20, 0)),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn inserts_new_transactions() {
+        let transport = FakeTransport::with_responses(vec![
+            (
+                StatusCode::OK,
+                br#"{"data": {"transactions": [{"import_id": "2"}, {"import_id": null}]}}"#,
+            ),
+            (
+                StatusCode::CREATED,
+                br#"{"data": {"transaction_ids": ["abc"], "duplicate_import_ids": []}}"#,
+            ),
+        ]);
+
+        let summary = insert_statement(
+            &transport,
+            &destination(),
+            rusty_money::iso::USD,
+            statement(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.new, 1);
+        assert_eq!(summary.already_synced, 1);
+        assert_eq!(summary.inserted, Some(1));
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(
+            requests[0].uri,
+            "https://api.ynab.com/v1/budgets/last-used/accounts/account/transactions?since_date=2022-07-01"
+        );
+        assert_eq!(requests[1].method, Method::POST);
+        assert_eq!(requests[1].headers[AUTHORIZATION], "Bearer token");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[1].body).unwrap(),
+            json!({
+                "transactions": [{
+                    "account_id": "account",
+                    "date": "2022-07-01",
+                    "amount": -20500,
+                    "payee_name": "John Smith",
+                    "memo": "Dinner",
+                    "cleared": "cleared",
+                    "approved": false,
+                    "import_id": "1",
+                }]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_transactions_by_type() {
+        let transport = FakeTransport::with_responses(Vec::new());
+        let destination = YnabDestination {
+            filter: TransactionFilter {
+                exclude_types: vec![TransactionType::Payment],
+                ..Default::default()
+            },
+            ..destination()
+        };
+
+        let summary = insert_statement(
+            &transport,
+            &destination,
+            rusty_money::iso::USD,
+            statement(),
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.filtered, 2);
+        assert_eq!(summary.lunch_money_transactions, 0);
+        assert_eq!(summary.transfers, 0);
+        assert!(summary.balance_drift.is_zero());
+        assert!(transport.requests.lock().unwrap().is_empty());
+    }
+}